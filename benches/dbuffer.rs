@@ -0,0 +1,75 @@
+//! throughput of [`DBuffer`] compared to reading a request one byte per `read` call
+//!
+//! run with `cargo bench --bench dbuffer`
+use rsweb::dbuffer::DBuffer;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const ITERATIONS: usize = 200;
+
+/// build a request with a few typical headers and a body of `body_size` bytes
+fn request(body_size: usize) -> Vec<u8> {
+    let mut req = format!(
+        "POST /upload HTTP/1.1\r\nHost: localhost\r\nUser-Agent: rsweb-bench\r\nAccept: */*\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n",
+        body_size
+    )
+    .into_bytes();
+    req.extend(std::iter::repeat_n(b'a', body_size));
+    req
+}
+
+/// read the head byte by byte until an empty line and then the body, again byte by byte
+fn read_bytewise<T: Read>(r: &mut T, body_size: usize) -> usize {
+    let mut buf: Vec<u8> = Vec::new();
+    let mut tmp = [0u8];
+    while !buf.ends_with(b"\r\n\r\n") {
+        if r.read(&mut tmp).unwrap() != 1 {
+            break;
+        }
+        buf.push(tmp[0]);
+    }
+    for _ in 0..body_size {
+        if r.read(&mut tmp).unwrap() != 1 {
+            break;
+        }
+        buf.push(tmp[0]);
+    }
+    buf.len()
+}
+
+/// send `req` over a socket pair `ITERATIONS` times and measure how long `read` takes to read it
+fn run<F: FnMut(&mut UnixStream) -> usize>(req: &[u8], mut read: F) -> Duration {
+    let (mut server, mut client) = UnixStream::pair().unwrap();
+    let data = req.to_vec();
+    let writer = thread::spawn(move || {
+        for _ in 0..ITERATIONS {
+            client.write_all(&data).unwrap();
+        }
+    });
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        assert_eq!(read(&mut server), req.len());
+    }
+    let elapsed = start.elapsed();
+    writer.join().unwrap();
+    elapsed
+}
+
+fn main() {
+    for body_size in [0, 4096, 65536] {
+        let req = request(body_size);
+        let bytewise = run(&req, |s| read_bytewise(s, body_size));
+        let mut buf = DBuffer::new();
+        let chunked = run(&req, |s| buf.read_http_request(s).unwrap());
+        let mbps = |d: Duration| (req.len() * ITERATIONS) as f64 / d.as_secs_f64() / 1e6;
+        println!(
+            "body {:>6} B: byte-wise {:>8.2} MB/s, DBuffer {:>8.2} MB/s ({:.1}x)",
+            body_size,
+            mbps(bytewise),
+            mbps(chunked),
+            bytewise.as_secs_f64() / chunked.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,396 @@
+use crate::config::AccessLogConfig;
+use crate::http::response::HTTPResponse;
+use crate::json::JsonValue;
+use crate::logfile::{LogFile, Rotation};
+use chrono::prelude::*;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// the Common Log Format
+pub const COMMON_FORMAT: &str = "%h %l %u %t \"%r\" %>s %b";
+
+/// the Combined Log Format (Common Log Format with referer and user agent)
+pub const COMBINED_FORMAT: &str = "%h %l %u %t \"%r\" %>s %b \"%{Referer}i\" \"%{User-Agent}i\"";
+
+/// a part of a custom access log format
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FormatToken {
+    /// text copied to the log
+    Literal(String),
+    /// `%h`: IP address of the client
+    Client,
+    /// `%l`: identity of the client (always `-`)
+    Ident,
+    /// `%u`: subject of the client certificate
+    User,
+    /// `%t`: time the request was received, e.g. `[10/Oct/2000:13:55:36 -0700]`
+    Time,
+    /// `%r`: the request line
+    RequestLine,
+    /// `%m`: the request method
+    Method,
+    /// `%U`: the requested path
+    Path,
+    /// `%H`: the request protocol
+    Protocol,
+    /// `%s` or `%>s`: the response status
+    Status,
+    /// `%b`: size of the response body, `-` if it's empty
+    Bytes,
+    /// `%B`: size of the response body
+    BytesZero,
+    /// `%D`: time taken to answer the request in microseconds
+    Micros,
+    /// `%T`: time taken to answer the request in seconds
+    Seconds,
+    /// `%{Name}i`: a request header
+    RequestHeader(String),
+    /// `%{Name}o`: a response header
+    ResponseHeader(String),
+}
+
+/// the format of access log lines
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AccessLogFormat {
+    Common,
+    Combined,
+    /// one JSON object per line
+    Json,
+    Custom(Vec<FormatToken>),
+}
+
+impl AccessLogFormat {
+    /// get the format named `string` (`common`, `combined` or `json`) or parse a custom format
+    /// string using Apache style directives. Unknown directives are written literally
+    pub fn from_string(string: String) -> AccessLogFormat {
+        match string.as_str() {
+            "common" => return AccessLogFormat::Common,
+            "combined" => return AccessLogFormat::Combined,
+            "json" => return AccessLogFormat::Json,
+            _ => (),
+        }
+        let mut tokens = Vec::new();
+        let mut literal = String::new();
+        let mut chars = string.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+            let mut directive = String::from("%");
+            if chars.peek() == Some(&'>') {
+                directive.push(chars.next().unwrap_or('>'));
+            }
+            let mut name = None;
+            if chars.peek() == Some(&'{') {
+                directive.push(chars.next().unwrap_or('{'));
+                let mut n = String::new();
+                for c in chars.by_ref() {
+                    directive.push(c);
+                    if c == '}' {
+                        break;
+                    }
+                    n.push(c);
+                }
+                name = Some(n);
+            }
+            let c = match chars.next() {
+                Some(n) => n,
+                None => {
+                    literal.push_str(directive.as_str());
+                    break;
+                }
+            };
+            let token = match (c, name) {
+                ('%', None) => {
+                    literal.push('%');
+                    continue;
+                }
+                ('h', None) => FormatToken::Client,
+                ('l', None) => FormatToken::Ident,
+                ('u', None) => FormatToken::User,
+                ('t', None) => FormatToken::Time,
+                ('r', None) => FormatToken::RequestLine,
+                ('m', None) => FormatToken::Method,
+                ('U', None) => FormatToken::Path,
+                ('H', None) => FormatToken::Protocol,
+                ('s', None) => FormatToken::Status,
+                ('b', None) => FormatToken::Bytes,
+                ('B', None) => FormatToken::BytesZero,
+                ('D', None) => FormatToken::Micros,
+                ('T', None) => FormatToken::Seconds,
+                ('i', Some(n)) => FormatToken::RequestHeader(n),
+                ('o', Some(n)) => FormatToken::ResponseHeader(n),
+                (c, _) => {
+                    literal.push_str(directive.as_str());
+                    literal.push(c);
+                    continue;
+                }
+            };
+            if !literal.is_empty() {
+                tokens.push(FormatToken::Literal(std::mem::take(&mut literal)));
+            }
+            tokens.push(token);
+        }
+        if !literal.is_empty() {
+            tokens.push(FormatToken::Literal(literal));
+        }
+        AccessLogFormat::Custom(tokens)
+    }
+}
+
+/// a completed request as written to the access log
+#[derive(Clone, Debug)]
+pub struct AccessEntry {
+    /// IP address of the client
+    pub client: Option<IpAddr>,
+    /// time the request was received
+    pub time: DateTime<Local>,
+    /// the request line, e.g. `GET / HTTP/1.1`. Empty if the request couldn't be read
+    pub request_line: String,
+    /// request headers as (name, value)
+    pub request_headers: Vec<(String, String)>,
+    /// subject of the client certificate
+    pub user: Option<String>,
+    /// the response status
+    pub status: u16,
+    /// size of the response body
+    pub bytes: usize,
+    /// response headers as (name, value)
+    pub response_headers: Vec<(String, String)>,
+    /// time taken to answer the request
+    pub duration: Duration,
+}
+
+impl AccessEntry {
+    /// create the entry for answering `request` (the request head as received) with `resp`.
+    /// `started` is when the connection was accepted
+    pub fn new(
+        client: Option<IpAddr>,
+        request: &str,
+        resp: &HTTPResponse,
+        started: Instant,
+    ) -> AccessEntry {
+        let duration = started.elapsed();
+        let mut lines = request.lines();
+        let request_line = lines.next().unwrap_or("").trim().to_string();
+        let request_headers = lines
+            .take_while(|n| !n.is_empty())
+            .filter_map(|n| n.split_once(':'))
+            .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+            .collect();
+        let response_headers = resp
+            .get_header()
+            .iter()
+            .filter_map(|n| {
+                let n = n.to_string();
+                n.split_once(':')
+                    .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+            })
+            .collect();
+        AccessEntry {
+            client,
+            time: Local::now()
+                - chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::zero()),
+            request_line,
+            request_headers,
+            user: None,
+            status: resp.get_status().code(),
+            bytes: resp.get_body().len(),
+            response_headers,
+            duration,
+        }
+    }
+
+    fn request_part(&self, index: usize) -> &str {
+        self.request_line.split(' ').nth(index).unwrap_or("")
+    }
+
+    /// get the value of the request header `name`
+    pub fn request_header(&self, name: &str) -> Option<&str> {
+        self.request_headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// get the value of the response header `name`
+    pub fn response_header(&self, name: &str) -> Option<&str> {
+        self.response_headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn token(&self, token: &FormatToken) -> String {
+        let or_dash = |n: Option<&str>| match n {
+            Some(n) if !n.is_empty() => n.replace('"', "\\\""),
+            _ => String::from("-"),
+        };
+        match token {
+            FormatToken::Literal(n) => n.clone(),
+            FormatToken::Client => or_dash(self.client.map(|n| n.to_string()).as_deref()),
+            FormatToken::Ident => String::from("-"),
+            FormatToken::User => or_dash(self.user.as_deref()),
+            FormatToken::Time => format!("[{}]", self.time.format("%d/%b/%Y:%H:%M:%S %z")),
+            FormatToken::RequestLine => or_dash(Some(self.request_line.as_str())),
+            FormatToken::Method => or_dash(Some(self.request_part(0))),
+            FormatToken::Path => or_dash(Some(self.request_part(1))),
+            FormatToken::Protocol => or_dash(Some(self.request_part(2))),
+            FormatToken::Status => self.status.to_string(),
+            FormatToken::Bytes if self.bytes == 0 => String::from("-"),
+            FormatToken::Bytes | FormatToken::BytesZero => self.bytes.to_string(),
+            FormatToken::Micros => self.duration.as_micros().to_string(),
+            FormatToken::Seconds => self.duration.as_secs().to_string(),
+            FormatToken::RequestHeader(n) => or_dash(self.request_header(n)),
+            FormatToken::ResponseHeader(n) => or_dash(self.response_header(n)),
+        }
+    }
+
+    /// format the entry as one line of the access log (without a line break)
+    pub fn format(&self, format: &AccessLogFormat) -> String {
+        let custom = |string: &str| match AccessLogFormat::from_string(string.to_string()) {
+            AccessLogFormat::Custom(n) => n,
+            _ => Vec::new(),
+        };
+        let tokens = match format {
+            AccessLogFormat::Common => custom(COMMON_FORMAT),
+            AccessLogFormat::Combined => custom(COMBINED_FORMAT),
+            AccessLogFormat::Custom(n) => n.clone(),
+            AccessLogFormat::Json => return self.to_json().to_string(),
+        };
+        tokens.iter().map(|n| self.token(n)).collect()
+    }
+
+    /// get the entry as a JSON object
+    pub fn to_json(&self) -> JsonValue {
+        let optional = |n: Option<&str>| match n {
+            Some(n) => JsonValue::from(n),
+            None => JsonValue::Null,
+        };
+        JsonValue::Object(vec![
+            (
+                String::from("time"),
+                JsonValue::from(self.time.to_rfc3339_opts(SecondsFormat::Millis, false)),
+            ),
+            (
+                String::from("client"),
+                optional(self.client.map(|n| n.to_string()).as_deref()),
+            ),
+            (String::from("user"), optional(self.user.as_deref())),
+            (
+                String::from("method"),
+                JsonValue::from(self.request_part(0)),
+            ),
+            (String::from("path"), JsonValue::from(self.request_part(1))),
+            (
+                String::from("protocol"),
+                JsonValue::from(self.request_part(2)),
+            ),
+            (
+                String::from("status"),
+                JsonValue::Number(self.status as f64),
+            ),
+            (String::from("bytes"), JsonValue::from(self.bytes)),
+            (
+                String::from("duration_ms"),
+                JsonValue::Number(self.duration.as_micros() as f64 / 1000.0),
+            ),
+            (
+                String::from("referer"),
+                optional(self.request_header("Referer")),
+            ),
+            (
+                String::from("user_agent"),
+                optional(self.request_header("User-Agent")),
+            ),
+            (
+                String::from("request_id"),
+                optional(self.response_header("X-Request-Id")),
+            ),
+        ])
+    }
+}
+
+/// a log of all answered requests, separate from the error log
+#[derive(Clone, Debug)]
+pub struct AccessLog {
+    path: String,
+    format: AccessLogFormat,
+    rotation: Rotation,
+    file: Option<Arc<Mutex<LogFile>>>,
+}
+
+impl AccessLog {
+    /// create an access log writing to `path` in `format`. The file is opened when the server
+    /// starts (see [`AccessLog::open`])
+    pub fn new(path: &str, format: AccessLogFormat) -> AccessLog {
+        AccessLog {
+            path: path.to_string(),
+            format,
+            rotation: Rotation::default(),
+            file: None,
+        }
+    }
+
+    /// create an access log from the `access_log` setting of a config section. The Combined Log
+    /// Format is used by default
+    pub fn from_config(conf: &AccessLogConfig) -> AccessLog {
+        let format = match &conf.format {
+            Some(n) => AccessLogFormat::from_string(n.clone()),
+            None => AccessLogFormat::Combined,
+        };
+        let mut log = AccessLog::new(conf.path.as_str(), format);
+        if let Some(n) = &conf.rotation {
+            log.set_rotation(Rotation::from_config(n));
+        }
+        log
+    }
+
+    /// set when the log file is rotated
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        if let Some(file) = &self.file {
+            if let Ok(mut n) = file.lock() {
+                n.set_rotation(rotation.clone());
+            }
+        }
+        self.rotation = rotation;
+    }
+
+    /// open the log file for appending. Called by the servers before they accept connections
+    pub fn open(&mut self) -> Result<(), std::io::Error> {
+        if self.file.is_some() {
+            return Ok(());
+        }
+        let file = LogFile::open(self.path.as_str(), self.rotation.clone())?;
+        self.file = Some(Arc::new(Mutex::new(file)));
+        Ok(())
+    }
+
+    /// write `entry` to the log
+    pub fn log(&self, entry: &AccessEntry) {
+        let mut line = entry.format(&self.format);
+        line.push('\n');
+        if let Some(file) = &self.file {
+            if let Ok(mut n) = file.lock() {
+                let _ = n.write(line.as_bytes());
+            }
+        }
+    }
+
+    /// write the entry for answering `request` with `resp` to the log. `user` is the subject of
+    /// the client certificate
+    pub fn record(
+        &self,
+        client: Option<IpAddr>,
+        user: Option<String>,
+        request: &str,
+        resp: &HTTPResponse,
+        started: Instant,
+    ) {
+        let mut entry = AccessEntry::new(client, request, resp, started);
+        entry.user = user;
+        self.log(&entry);
+    }
+}
@@ -0,0 +1,1139 @@
+use crate::certgen::{generate_key, generate_self_signed, write_file, write_pem};
+use crate::config::AcmeConfig;
+use crate::error::ServerError;
+use crate::http::body::Body;
+use crate::http::header::HTTPResponseHeaders;
+use crate::http::request::HTTPRequest;
+use crate::http::response::HTTPResponse;
+use crate::http::{MimeType, StatusCode};
+use crate::json::JsonValue;
+use crate::log::Logger;
+use crate::tls::{certificate_names, pending_file};
+use crate::{error, msg, RSWEB_SERVER_STR, RSWEB_VERSION};
+use openssl::asn1::Asn1Time;
+use openssl::base64::encode_block;
+use openssl::ec::EcKey;
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::{hash, MessageDigest};
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::ssl::{select_next_proto, AlpnError, SslAcceptor, SslConnector, SslMethod};
+use openssl::stack::Stack;
+use openssl::x509::extension::SubjectAlternativeName;
+use openssl::x509::{X509Extension, X509NameBuilder, X509Req, X509};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{IpAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// directory of the Let's Encrypt production CA
+pub const LETS_ENCRYPT_DIRECTORY: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
+/// path prefix of HTTP-01 challenge requests
+pub const HTTP_CHALLENGE_PATH: &str = "/.well-known/acme-challenge/";
+
+/// ALPN protocol of TLS-ALPN-01 validation connections (RFC 8737)
+const ACME_TLS_ALPN: &[u8] = b"\x0aacme-tls/1";
+
+/// default directory for account keys and certificates
+const DEFAULT_STORAGE: &str = "/var/lib/rsweb/acme";
+
+/// default number of days before expiry a certificate is renewed
+const DEFAULT_RENEW_DAYS: u32 = 30;
+
+/// default time between checks whether the certificate needs to be renewed
+const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// time after which a failed issuance is retried at the earliest
+const RETRY_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// number of times orders and authorizations are polled before giving up
+const POLL_ATTEMPTS: usize = 60;
+
+/// time between polls of orders and authorizations unless the CA asks for another one
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// number of times a request rejected because of a bad nonce is sent again
+const NONCE_RETRIES: usize = 3;
+
+/// longest time between polls a `Retry-After` header can ask for
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// the challenge used to prove control over a domain
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChallengeType {
+    /// a file served by the plain HTTP server on port 80
+    Http01,
+
+    /// a special certificate served by the HTTPS server on port 443
+    TlsAlpn01,
+}
+
+impl ChallengeType {
+    pub fn from_string(string: String) -> Option<ChallengeType> {
+        match string.as_str() {
+            "http-01" => Some(ChallengeType::Http01),
+            "tls-alpn-01" => Some(ChallengeType::TlsAlpn01),
+            _ => None,
+        }
+    }
+
+    /// get the name used by the ACME protocol
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChallengeType::Http01 => "http-01",
+            ChallengeType::TlsAlpn01 => "tls-alpn-01",
+        }
+    }
+}
+
+/// encode `data` as unpadded base64url
+fn base64url(data: &[u8]) -> String {
+    encode_block(data)
+        .trim_end_matches('=')
+        .replace('+', "-")
+        .replace('/', "_")
+}
+
+fn acme_error(msg: &str) -> ServerError {
+    ServerError::new(format!("ACME: {}", msg).as_str())
+}
+
+/// challenges that are currently being validated. Shared between the ACME client and the
+/// servers answering the validation requests
+#[derive(Clone, Default)]
+pub struct AcmeChallenges {
+    http: Arc<RwLock<HashMap<String, String>>>,
+    tls: Arc<RwLock<HashMap<String, Arc<SslAcceptor>>>>,
+}
+
+impl AcmeChallenges {
+    /// create an empty set of challenges
+    pub fn new() -> AcmeChallenges {
+        AcmeChallenges::default()
+    }
+
+    /// answer the HTTP-01 challenge for `token` with `key_authorization`
+    pub fn add_http(&self, token: &str, key_authorization: &str) {
+        if let Ok(mut n) = self.http.write() {
+            n.insert(token.to_string(), key_authorization.to_string());
+        }
+    }
+
+    /// stop answering the HTTP-01 challenge for `token`
+    pub fn remove_http(&self, token: &str) {
+        if let Ok(mut n) = self.http.write() {
+            n.remove(token);
+        }
+    }
+
+    /// answer TLS-ALPN-01 validation connections for `domain` with `acceptor`
+    pub fn add_tls(&self, domain: &str, acceptor: SslAcceptor) {
+        if let Ok(mut n) = self.tls.write() {
+            n.insert(domain.to_lowercase(), Arc::new(acceptor));
+        }
+    }
+
+    /// stop answering TLS-ALPN-01 validation connections for `domain`
+    pub fn remove_tls(&self, domain: &str) {
+        if let Ok(mut n) = self.tls.write() {
+            n.remove(&domain.to_lowercase());
+        }
+    }
+
+    /// answer `req` if it asks for the key authorization of a pending HTTP-01 challenge
+    pub fn http_response(&self, req: &HTTPRequest) -> Option<HTTPResponse> {
+        let path = req.get_path();
+        let token = path.strip_prefix(HTTP_CHALLENGE_PATH)?;
+        let key_authorization = match self.http.read() {
+            Ok(n) => n.get(token)?.clone(),
+            Err(_) => return None,
+        };
+        let body = Body::new(key_authorization);
+        Some(HTTPResponse::new(
+            StatusCode::Ok,
+            vec![
+                HTTPResponseHeaders::Server(RSWEB_SERVER_STR.to_string()),
+                HTTPResponseHeaders::ContentType(MimeType::Plaintext),
+                HTTPResponseHeaders::ContentLength(body.get_bytes().len()),
+            ],
+            body,
+        ))
+    }
+
+    /// get the acceptor for `stream` if it is a TLS-ALPN-01 validation connection for a pending
+    /// challenge. The connection is only inspected while a challenge is pending
+    pub fn tls_acceptor(&self, stream: &TcpStream) -> Option<Arc<SslAcceptor>> {
+        match self.tls.read() {
+            Ok(n) if !n.is_empty() => (),
+            _ => return None,
+        }
+        let hello = peek_client_hello(stream)?;
+        if hello.alpn != ["acme-tls/1"] {
+            return None;
+        }
+        let name = hello.server_name?.to_lowercase();
+        match self.tls.read() {
+            Ok(n) => n.get(&name).cloned(),
+            Err(_) => None,
+        }
+    }
+}
+
+/// the parts of a TLS ClientHello needed to route a connection
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ClientHello {
+    /// the host name sent with SNI
+    pub server_name: Option<String>,
+
+    /// the protocols offered with ALPN
+    pub alpn: Vec<String>,
+}
+
+fn be16(data: &[u8], pos: usize) -> Option<usize> {
+    let n = data.get(pos..pos + 2)?;
+    Some(((n[0] as usize) << 8) | n[1] as usize)
+}
+
+/// parse the first TLS record of a connection
+///
+/// # Returns
+/// `None` if `data` doesn't start with a complete ClientHello
+pub fn parse_client_hello(data: &[u8]) -> Option<ClientHello> {
+    // record header: content type (handshake), version, length
+    if *data.first()? != 0x16 {
+        return None;
+    }
+    let record = data.get(5..5 + be16(data, 3)?)?;
+    // handshake header: type (client hello), length
+    if *record.first()? != 1 {
+        return None;
+    }
+    // version and random
+    let mut pos = 4 + 2 + 32;
+    pos += 1 + *record.get(pos)? as usize;
+    pos += 2 + be16(record, pos)?;
+    pos += 1 + *record.get(pos)? as usize;
+    let extensions = record.get(pos + 2..pos + 2 + be16(record, pos)?)?;
+
+    let mut hello = ClientHello::default();
+    let mut pos = 0;
+    while pos + 4 <= extensions.len() {
+        let kind = be16(extensions, pos)?;
+        let data = extensions.get(pos + 4..pos + 4 + be16(extensions, pos + 2)?)?;
+        pos += 4 + data.len();
+        match kind {
+            // server_name: list of (type, name), type 0 is a host name
+            0 => {
+                let list = data.get(2..2 + be16(data, 0)?)?;
+                let mut i = 0;
+                while i + 3 <= list.len() {
+                    let len = be16(list, i + 1)?;
+                    let name = list.get(i + 3..i + 3 + len)?;
+                    if list[i] == 0 {
+                        hello.server_name = Some(String::from_utf8_lossy(name).to_string());
+                    }
+                    i += 3 + len;
+                }
+            }
+            // application_layer_protocol_negotiation: list of length prefixed names
+            16 => {
+                let list = data.get(2..2 + be16(data, 0)?)?;
+                let mut i = 0;
+                while i < list.len() {
+                    let len = list[i] as usize;
+                    let name = list.get(i + 1..i + 1 + len)?;
+                    hello.alpn.push(String::from_utf8_lossy(name).to_string());
+                    i += 1 + len;
+                }
+            }
+            _ => (),
+        }
+    }
+    Some(hello)
+}
+
+/// read the ClientHello of `stream` without consuming it
+pub fn peek_client_hello(stream: &TcpStream) -> Option<ClientHello> {
+    let mut buf = vec![0; 5 + 16384];
+    for _ in 0..100 {
+        let n = stream.peek(&mut buf).ok()?;
+        if n == 0 {
+            return None;
+        }
+        if n >= 5 && n >= 5 + be16(&buf, 3)? {
+            return parse_client_hello(&buf[..n]);
+        }
+        // the rest of the record hasn't arrived yet
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    None
+}
+
+/// create the acceptor answering TLS-ALPN-01 validation connections for `domain`. It presents a
+/// self-signed certificate carrying the digest of `key_authorization` (RFC 8737)
+pub fn challenge_acceptor(
+    domain: &str,
+    key_authorization: &str,
+) -> Result<SslAcceptor, ServerError> {
+    let build = || -> Result<SslAcceptor, openssl::error::ErrorStack> {
+        let key = generate_key()?;
+        let mut subject = X509NameBuilder::new()?;
+        subject.append_entry_by_nid(Nid::COMMONNAME, domain)?;
+        let subject = subject.build();
+        let mut cert = X509::builder()?;
+        cert.set_version(2)?;
+        cert.set_subject_name(&subject)?;
+        cert.set_issuer_name(&subject)?;
+        cert.set_pubkey(&key)?;
+        cert.set_not_before(Asn1Time::days_from_now(0)?.as_ref())?;
+        cert.set_not_after(Asn1Time::days_from_now(7)?.as_ref())?;
+        let san = SubjectAlternativeName::new()
+            .dns(domain)
+            .build(&cert.x509v3_context(None, None))?;
+        cert.append_extension(san)?;
+        // id-pe-acmeIdentifier: an OCTET STRING holding the SHA-256 digest
+        let digest = hash(MessageDigest::sha256(), key_authorization.as_bytes())?;
+        let value = digest
+            .iter()
+            .map(|n| format!("{:02X}", n))
+            .collect::<Vec<String>>()
+            .join(":");
+        let ext = X509Extension::new(
+            None,
+            Some(&cert.x509v3_context(None, None)),
+            "1.3.6.1.5.5.7.1.31",
+            format!("critical,DER:04:20:{}", value).as_str(),
+        )?;
+        cert.append_extension(ext)?;
+        cert.sign(&key, MessageDigest::sha256())?;
+
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())?;
+        acceptor.set_private_key(&key)?;
+        acceptor.set_certificate(&cert.build())?;
+        acceptor.set_alpn_select_callback(|_, client| {
+            select_next_proto(ACME_TLS_ALPN, client).ok_or(AlpnError::ALERT_FATAL)
+        });
+        Ok(acceptor.build())
+    };
+    match build() {
+        Ok(n) => Ok(n),
+        Err(e) => Err(acme_error(
+            format!("failed to create the challenge certificate: {}", e).as_str(),
+        )),
+    }
+}
+
+/// a response of an ACME server
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn json(&self) -> Result<JsonValue, ServerError> {
+        JsonValue::parse(String::from_utf8_lossy(&self.body).as_ref())
+    }
+
+    /// get the time to wait before polling again from the `Retry-After` header. Only delays in
+    /// seconds are understood
+    fn retry_after(&self) -> Option<Duration> {
+        let secs = self.header("Retry-After")?.trim().parse::<u64>().ok()?;
+        Some(Duration::from_secs(secs).min(MAX_RETRY_AFTER))
+    }
+}
+
+/// decode a body sent with `Transfer-Encoding: chunked`
+fn dechunk(mut data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    while let Some(end) = data.windows(2).position(|n| n == b"\r\n") {
+        let size = String::from_utf8_lossy(&data[..end]);
+        let size = size.split(';').next().unwrap_or("").trim();
+        let size = match usize::from_str_radix(size, 16) {
+            Ok(n) => n,
+            Err(_) => break,
+        };
+        if size == 0 || data.len() < end + 2 + size {
+            break;
+        }
+        out.extend_from_slice(&data[end + 2..end + 2 + size]);
+        data = &data[(end + 4 + size).min(data.len())..];
+    }
+    out
+}
+
+/// a minimal HTTPS client for talking to an ACME server
+struct Client {
+    connector: SslConnector,
+}
+
+impl Client {
+    /// create a client trusting the system CAs and the CAs in `ca_file` (e.g. the root of a
+    /// Pebble test server)
+    fn new(ca_file: Option<&str>) -> Result<Client, ServerError> {
+        let mut builder = match SslConnector::builder(SslMethod::tls()) {
+            Ok(n) => n,
+            Err(e) => return Err(acme_error(e.to_string().as_str())),
+        };
+        if let Some(n) = ca_file {
+            if builder.set_ca_file(n).is_err() {
+                return Err(acme_error(
+                    format!("error loading CA file '{}'", n).as_str(),
+                ));
+            }
+        }
+        Ok(Client {
+            connector: builder.build(),
+        })
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        url: &str,
+        body: Option<&str>,
+    ) -> Result<Response, ServerError> {
+        let rest = match url.strip_prefix("https://") {
+            Some(n) => n,
+            None => return Err(acme_error(format!("not an https URL: '{}'", url).as_str())),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(n) => (&rest[..n], &rest[n..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rfind(':') {
+            Some(n) if !authority[n..].contains(']') => match authority[n + 1..].parse::<u16>() {
+                Ok(port) => (&authority[..n], port),
+                Err(_) => return Err(acme_error(format!("invalid URL: '{}'", url).as_str())),
+            },
+            _ => (authority, 443),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let io_error = |e: std::io::Error| acme_error(format!("{}: {}", url, e).as_str());
+
+        let stream = TcpStream::connect((host, port)).map_err(io_error)?;
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(30)));
+        let _ = stream.set_write_timeout(Some(Duration::from_secs(30)));
+        let mut config = match self.connector.configure() {
+            Ok(n) => n,
+            Err(e) => return Err(acme_error(e.to_string().as_str())),
+        };
+        if host.parse::<IpAddr>().is_ok() {
+            config.set_use_server_name_indication(false);
+        }
+        let mut stream = match config.connect(host, stream) {
+            Ok(n) => n,
+            Err(e) => return Err(acme_error(format!("{}: {}", url, e).as_str())),
+        };
+
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: rsweb/{}\r\nAccept: */*\r\nConnection: close\r\n",
+            method, path, authority, RSWEB_VERSION
+        );
+        if let Some(n) = body {
+            request.push_str(
+                format!(
+                    "Content-Type: application/jose+json\r\nContent-Length: {}\r\n",
+                    n.len()
+                )
+                .as_str(),
+            );
+        }
+        request.push_str("\r\n");
+        request.push_str(body.unwrap_or(""));
+        stream.write_all(request.as_bytes()).map_err(io_error)?;
+
+        let mut data = Vec::new();
+        let mut buf = [0; 8192];
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => data.extend_from_slice(&buf[..n]),
+                // servers often close the connection without a TLS close_notify
+                Err(_) if !data.is_empty() => break,
+                Err(e) => return Err(io_error(e)),
+            }
+        }
+
+        let end = match data.windows(4).position(|n| n == b"\r\n\r\n") {
+            Some(n) => n,
+            None => return Err(acme_error(format!("{}: invalid response", url).as_str())),
+        };
+        let head = String::from_utf8_lossy(&data[..end]).to_string();
+        let mut lines = head.split("\r\n");
+        let status = match lines
+            .next()
+            .and_then(|n| n.split(' ').nth(1))
+            .and_then(|n| n.parse::<u16>().ok())
+        {
+            Some(n) => n,
+            None => return Err(acme_error(format!("{}: invalid response", url).as_str())),
+        };
+        let headers: Vec<(String, String)> = lines
+            .filter_map(|n| n.split_once(':'))
+            .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+            .collect();
+        let mut response = Response {
+            status,
+            headers,
+            body: data[end + 4..].to_vec(),
+        };
+        if response
+            .header("Transfer-Encoding")
+            .map(|n| n.eq_ignore_ascii_case("chunked"))
+            .unwrap_or(false)
+        {
+            response.body = dechunk(&response.body);
+        } else if let Some(n) = response
+            .header("Content-Length")
+            .and_then(|n| n.parse::<usize>().ok())
+        {
+            response.body.truncate(n);
+        }
+        Ok(response)
+    }
+}
+
+/// the key of an ACME account (P-256, signing with ES256)
+pub struct AccountKey {
+    key: EcKey<Private>,
+}
+
+impl AccountKey {
+    /// generate a new account key
+    pub fn generate() -> Result<AccountKey, ServerError> {
+        match generate_key().and_then(|n| n.ec_key()) {
+            Ok(key) => Ok(AccountKey { key }),
+            Err(e) => Err(acme_error(e.to_string().as_str())),
+        }
+    }
+
+    /// load the account key from `path` or create it there if it doesn't exist
+    pub fn load_or_create(path: &Path) -> Result<AccountKey, ServerError> {
+        if path.exists() {
+            let pem = match std::fs::read(path) {
+                Ok(n) => n,
+                Err(e) => {
+                    return Err(acme_error(
+                        format!("failed to read '{}': {}", path.display(), e).as_str(),
+                    ));
+                }
+            };
+            return match EcKey::private_key_from_pem(&pem) {
+                Ok(key) => Ok(AccountKey { key }),
+                Err(_) => Err(acme_error(
+                    format!("invalid account key: '{}'", path.display()).as_str(),
+                )),
+            };
+        }
+        let key = AccountKey::generate()?;
+        let pem =
+            match PKey::from_ec_key(key.key.clone()).and_then(|n| n.private_key_to_pem_pkcs8()) {
+                Ok(n) => n,
+                Err(e) => return Err(acme_error(e.to_string().as_str())),
+            };
+        write_file(path, &pem, true)?;
+        Ok(key)
+    }
+
+    /// get the public key as a JSON Web Key. The members are in the order required for the
+    /// thumbprint
+    pub fn jwk(&self) -> Result<JsonValue, ServerError> {
+        let coordinates = || -> Result<(Vec<u8>, Vec<u8>), openssl::error::ErrorStack> {
+            let mut ctx = openssl::bn::BigNumContext::new()?;
+            let mut x = openssl::bn::BigNum::new()?;
+            let mut y = openssl::bn::BigNum::new()?;
+            self.key.public_key().affine_coordinates_gfp(
+                self.key.group(),
+                &mut x,
+                &mut y,
+                &mut ctx,
+            )?;
+            Ok((x.to_vec_padded(32)?, y.to_vec_padded(32)?))
+        };
+        let (x, y) = match coordinates() {
+            Ok(n) => n,
+            Err(e) => return Err(acme_error(e.to_string().as_str())),
+        };
+        Ok(JsonValue::Object(vec![
+            (String::from("crv"), JsonValue::from("P-256")),
+            (String::from("kty"), JsonValue::from("EC")),
+            (String::from("x"), JsonValue::from(base64url(&x))),
+            (String::from("y"), JsonValue::from(base64url(&y))),
+        ]))
+    }
+
+    /// get the JWK thumbprint (RFC 7638) used in key authorizations
+    pub fn thumbprint(&self) -> Result<String, ServerError> {
+        let jwk = self.jwk()?.to_string();
+        match hash(MessageDigest::sha256(), jwk.as_bytes()) {
+            Ok(n) => Ok(base64url(&n)),
+            Err(e) => Err(acme_error(e.to_string().as_str())),
+        }
+    }
+
+    /// create a JWS in flattened JSON serialization. An empty `payload` makes a POST-as-GET
+    /// request
+    pub fn sign(&self, protected: &JsonValue, payload: &str) -> Result<String, ServerError> {
+        let protected = base64url(protected.to_string().as_bytes());
+        let payload = base64url(payload.as_bytes());
+        let input = format!("{}.{}", protected, payload);
+        let signature = hash(MessageDigest::sha256(), input.as_bytes())
+            .and_then(|n| EcdsaSig::sign(&n, &self.key))
+            .and_then(|n| {
+                let mut signature = n.r().to_vec_padded(32)?;
+                signature.extend(n.s().to_vec_padded(32)?);
+                Ok(signature)
+            });
+        let signature = match signature {
+            Ok(n) => n,
+            Err(e) => return Err(acme_error(e.to_string().as_str())),
+        };
+        Ok(JsonValue::Object(vec![
+            (String::from("protected"), JsonValue::from(protected)),
+            (String::from("payload"), JsonValue::from(payload)),
+            (
+                String::from("signature"),
+                JsonValue::from(base64url(&signature)),
+            ),
+        ])
+        .to_string())
+    }
+}
+
+/// a conversation with an ACME server
+struct Session<'a> {
+    acme: &'a Acme,
+    client: Client,
+    key: AccountKey,
+    directory: JsonValue,
+    nonce: Option<String>,
+    kid: Option<String>,
+}
+
+impl<'a> Session<'a> {
+    fn new(acme: &'a Acme) -> Result<Session<'a>, ServerError> {
+        let client = Client::new(acme.ca_file.as_deref())?;
+        let key = AccountKey::load_or_create(&acme.storage.join("account.pem"))?;
+        let resp = client.request("GET", acme.directory.as_str(), None)?;
+        if resp.status != 200 {
+            return Err(acme_error(
+                format!("failed to fetch directory: status {}", resp.status).as_str(),
+            ));
+        }
+        Ok(Session {
+            acme,
+            client,
+            key,
+            directory: resp.json()?,
+            nonce: None,
+            kid: None,
+        })
+    }
+
+    /// get the URL of the directory entry `name`
+    fn url(&self, name: &str) -> Result<String, ServerError> {
+        match self.directory.get(name).and_then(|n| n.as_str()) {
+            Some(n) => Ok(n.to_string()),
+            None => Err(acme_error(
+                format!("directory has no '{}' entry", name).as_str(),
+            )),
+        }
+    }
+
+    fn nonce(&mut self) -> Result<String, ServerError> {
+        if let Some(n) = self.nonce.take() {
+            return Ok(n);
+        }
+        let resp = self
+            .client
+            .request("HEAD", self.url("newNonce")?.as_str(), None)?;
+        match resp.header("Replay-Nonce") {
+            Some(n) => Ok(n.to_string()),
+            None => Err(acme_error("server sent no nonce")),
+        }
+    }
+
+    /// send a signed request to `url`. `None` as the payload makes a POST-as-GET request
+    fn post(&mut self, url: &str, payload: Option<&JsonValue>) -> Result<Response, ServerError> {
+        let payload = payload.map(|n| n.to_string()).unwrap_or_default();
+        // a bad nonce is answered with a fresh one, so the request is tried again with it
+        for attempt in 0..=NONCE_RETRIES {
+            let mut protected = vec![(String::from("alg"), JsonValue::from("ES256"))];
+            match &self.kid {
+                Some(n) => protected.push((String::from("kid"), JsonValue::from(n.as_str()))),
+                None => protected.push((String::from("jwk"), self.key.jwk()?)),
+            }
+            protected.push((String::from("nonce"), JsonValue::from(self.nonce()?)));
+            protected.push((String::from("url"), JsonValue::from(url)));
+            let body = self
+                .key
+                .sign(&JsonValue::Object(protected), payload.as_str())?;
+            let resp = self.client.request("POST", url, Some(body.as_str()))?;
+            self.nonce = resp.header("Replay-Nonce").map(|n| n.to_string());
+            if resp.status < 400 {
+                return Ok(resp);
+            }
+            let problem = resp.json().unwrap_or(JsonValue::Null);
+            let kind = problem.get("type").and_then(|n| n.as_str()).unwrap_or("");
+            if kind == "urn:ietf:params:acme:error:badNonce" && attempt < NONCE_RETRIES {
+                continue;
+            }
+            return Err(acme_error(
+                format!(
+                    "{} (status {}): {}",
+                    kind,
+                    resp.status,
+                    problem.get("detail").and_then(|n| n.as_str()).unwrap_or("")
+                )
+                .as_str(),
+            ));
+        }
+        Err(acme_error("server keeps rejecting nonces"))
+    }
+
+    /// register the account key or look up its existing account
+    fn account(&mut self) -> Result<(), ServerError> {
+        let mut payload = vec![(String::from("termsOfServiceAgreed"), JsonValue::from(true))];
+        if !self.acme.contact.is_empty() {
+            payload.push((
+                String::from("contact"),
+                JsonValue::Array(
+                    self.acme
+                        .contact
+                        .iter()
+                        .map(|n| JsonValue::from(n.as_str()))
+                        .collect(),
+                ),
+            ));
+        }
+        let url = self.url("newAccount")?;
+        let resp = self.post(url.as_str(), Some(&JsonValue::Object(payload)))?;
+        match resp.header("Location") {
+            Some(n) => {
+                self.kid = Some(n.to_string());
+                Ok(())
+            }
+            None => Err(acme_error("server sent no account URL")),
+        }
+    }
+
+    /// request `url` until its status is `status`, waiting as long as the CA asks for in between
+    fn poll(&mut self, url: &str, status: &str) -> Result<JsonValue, ServerError> {
+        for _ in 0..POLL_ATTEMPTS {
+            let resp = self.post(url, None)?;
+            let wait = resp.retry_after().unwrap_or(POLL_INTERVAL);
+            let resp = resp.json()?;
+            match resp.get("status").and_then(|n| n.as_str()) {
+                Some(n) if n == status => return Ok(resp),
+                Some("invalid") => {
+                    let detail = resp
+                        .get("challenges")
+                        .and_then(|n| n.as_array())
+                        .into_iter()
+                        .flatten()
+                        .chain(std::iter::once(&resp))
+                        .find_map(|n| n.get("error")?.get("detail")?.as_str())
+                        .unwrap_or("no details");
+                    return Err(acme_error(
+                        format!("{} became invalid: {}", url, detail).as_str(),
+                    ));
+                }
+                _ => std::thread::sleep(wait),
+            }
+        }
+        Err(acme_error(
+            format!("timed out waiting for {} to become {}", url, status).as_str(),
+        ))
+    }
+
+    /// prove control over the identifier of the authorization at `url`
+    fn authorize(&mut self, url: &str) -> Result<(), ServerError> {
+        let authz = self.post(url, None)?.json()?;
+        if authz.get("status").and_then(|n| n.as_str()) == Some("valid") {
+            return Ok(());
+        }
+        let domain = authz
+            .get("identifier")
+            .and_then(|n| n.get("value"))
+            .and_then(|n| n.as_str())
+            .unwrap_or("")
+            .to_string();
+        let challenge = authz
+            .get("challenges")
+            .and_then(|n| n.as_array())
+            .into_iter()
+            .flatten()
+            .find(|n| n.get("type").and_then(|n| n.as_str()) == Some(self.acme.challenge.name()));
+        let (token, challenge_url) = match challenge.and_then(|n| {
+            Some((
+                n.get("token")?.as_str()?.to_string(),
+                n.get("url")?.as_str()?.to_string(),
+            ))
+        }) {
+            Some(n) => n,
+            None => {
+                return Err(acme_error(
+                    format!(
+                        "no {} challenge offered for '{}'",
+                        self.acme.challenge.name(),
+                        domain
+                    )
+                    .as_str(),
+                ));
+            }
+        };
+        let key_authorization = format!("{}.{}", token, self.key.thumbprint()?);
+        match self.acme.challenge {
+            ChallengeType::Http01 => self
+                .acme
+                .challenges
+                .add_http(token.as_str(), key_authorization.as_str()),
+            ChallengeType::TlsAlpn01 => self.acme.challenges.add_tls(
+                domain.as_str(),
+                challenge_acceptor(domain.as_str(), key_authorization.as_str())?,
+            ),
+        }
+        let res = self
+            .post(challenge_url.as_str(), Some(&JsonValue::Object(Vec::new())))
+            .and_then(|_| self.poll(url, "valid"));
+        match self.acme.challenge {
+            ChallengeType::Http01 => self.acme.challenges.remove_http(token.as_str()),
+            ChallengeType::TlsAlpn01 => self.acme.challenges.remove_tls(domain.as_str()),
+        }
+        res.map(|_| ())
+    }
+
+    /// order a certificate for `key`
+    ///
+    /// # Returns
+    /// the certificate chain as PEM
+    fn order(&mut self, key: &PKey<Private>) -> Result<Vec<u8>, ServerError> {
+        let identifiers = self
+            .acme
+            .domains
+            .iter()
+            .map(|n| {
+                let kind = if n.parse::<IpAddr>().is_ok() {
+                    "ip"
+                } else {
+                    "dns"
+                };
+                JsonValue::Object(vec![
+                    (String::from("type"), JsonValue::from(kind)),
+                    (String::from("value"), JsonValue::from(n.as_str())),
+                ])
+            })
+            .collect();
+        let url = self.url("newOrder")?;
+        let resp = self.post(
+            url.as_str(),
+            Some(&JsonValue::Object(vec![(
+                String::from("identifiers"),
+                JsonValue::Array(identifiers),
+            )])),
+        )?;
+        let order_url = match resp.header("Location") {
+            Some(n) => n.to_string(),
+            None => return Err(acme_error("server sent no order URL")),
+        };
+        let order = resp.json()?;
+        let authorizations: Vec<String> = order
+            .get("authorizations")
+            .and_then(|n| n.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|n| n.as_str().map(|n| n.to_string()))
+            .collect();
+        for n in authorizations {
+            self.authorize(n.as_str())?;
+        }
+        // the order becomes ready once the CA processed all authorizations
+        self.poll(order_url.as_str(), "ready")?;
+
+        let finalize = match order.get("finalize").and_then(|n| n.as_str()) {
+            Some(n) => n.to_string(),
+            None => return Err(acme_error("order has no finalize URL")),
+        };
+        let csr = certificate_request(key, &self.acme.domains)?;
+        self.post(
+            finalize.as_str(),
+            Some(&JsonValue::Object(vec![(
+                String::from("csr"),
+                JsonValue::from(base64url(&csr)),
+            )])),
+        )?;
+        let order = self.poll(order_url.as_str(), "valid")?;
+        let certificate = match order.get("certificate").and_then(|n| n.as_str()) {
+            Some(n) => n.to_string(),
+            None => return Err(acme_error("order has no certificate URL")),
+        };
+        Ok(self.post(certificate.as_str(), None)?.body)
+    }
+}
+
+/// create a DER encoded certificate signing request for `domains`
+fn certificate_request(key: &PKey<Private>, domains: &[String]) -> Result<Vec<u8>, ServerError> {
+    let build = || -> Result<Vec<u8>, openssl::error::ErrorStack> {
+        let mut req = X509Req::builder()?;
+        let mut subject = X509NameBuilder::new()?;
+        if let Some(n) = domains.first() {
+            subject.append_entry_by_nid(Nid::COMMONNAME, n)?;
+        }
+        req.set_subject_name(&subject.build())?;
+        req.set_pubkey(key)?;
+        let mut san = SubjectAlternativeName::new();
+        for n in domains {
+            match n.parse::<IpAddr>() {
+                Ok(_) => san.ip(n),
+                Err(_) => san.dns(n),
+            };
+        }
+        let mut extensions = Stack::new()?;
+        extensions.push(san.build(&req.x509v3_context(None))?)?;
+        req.add_extensions(&extensions)?;
+        req.sign(key, MessageDigest::sha256())?;
+        req.build().to_der()
+    };
+    match build() {
+        Ok(n) => Ok(n),
+        Err(e) => Err(acme_error(
+            format!("failed to create the certificate request: {}", e).as_str(),
+        )),
+    }
+}
+
+/// obtains and renews a certificate from an ACME CA (RFC 8555)
+///
+/// The account key is kept in `account.pem` and the certificate in `<domain>/key.pem` and
+/// `<domain>/certs.pem` below the storage directory, where `<domain>` is the first domain.
+pub struct Acme {
+    /// URL of the directory of the CA
+    pub directory: String,
+
+    /// domains (and IP addresses) the certificate is issued for
+    pub domains: Vec<String>,
+
+    /// contact URLs of the account, e.g. `mailto:admin@example.com`
+    pub contact: Vec<String>,
+
+    /// directory the account key and the certificate are stored in
+    pub storage: PathBuf,
+
+    /// the challenge used to prove control over the domains
+    pub challenge: ChallengeType,
+
+    /// PEM file with additional CAs to trust when connecting to the directory
+    pub ca_file: Option<String>,
+
+    /// the certificate is renewed this many days before it expires
+    pub renew_days: u32,
+
+    /// time between checks whether the certificate needs to be renewed
+    pub check_interval: Duration,
+
+    challenges: AcmeChallenges,
+}
+
+impl Acme {
+    /// create a client for `domains` using the CA at `directory` and HTTP-01 challenges
+    pub fn new(directory: &str, domains: Vec<String>, storage: &Path) -> Acme {
+        Acme {
+            directory: directory.to_string(),
+            domains,
+            contact: Vec::new(),
+            storage: storage.to_path_buf(),
+            challenge: ChallengeType::Http01,
+            ca_file: None,
+            renew_days: DEFAULT_RENEW_DAYS,
+            check_interval: DEFAULT_CHECK_INTERVAL,
+            challenges: AcmeChallenges::new(),
+        }
+    }
+
+    /// create a client from the `acme` setting of the `ssl` config section
+    pub fn from_config(conf: &AcmeConfig) -> Result<Acme, ServerError> {
+        if conf.accept_terms != Some(true) {
+            return Err(acme_error(
+                "the terms of service of the CA have to be accepted (accept_terms = true)",
+            ));
+        }
+        if conf.domains.is_empty() {
+            return Err(acme_error("no domains configured"));
+        }
+        let mut acme = Acme::new(
+            conf.directory.as_deref().unwrap_or(LETS_ENCRYPT_DIRECTORY),
+            conf.domains.clone(),
+            Path::new(conf.storage.as_deref().unwrap_or(DEFAULT_STORAGE)),
+        );
+        if let Some(n) = &conf.challenge {
+            acme.challenge = match ChallengeType::from_string(n.to_lowercase()) {
+                Some(n) => n,
+                None => {
+                    return Err(acme_error(
+                        format!("unknown challenge type: '{}'", n).as_str(),
+                    ));
+                }
+            };
+        }
+        acme.contact = conf.contact.clone().unwrap_or_default();
+        acme.ca_file = conf.ca_file.clone();
+        if let Some(n) = conf.renew_days {
+            acme.renew_days = n;
+        }
+        if let Some(n) = conf.check_interval {
+            acme.check_interval = Duration::from_secs(n);
+        }
+        Ok(acme)
+    }
+
+    /// get the challenges the servers have to answer while a certificate is being issued
+    pub fn challenges(&self) -> AcmeChallenges {
+        self.challenges.clone()
+    }
+
+    /// get the paths of the private key and the certificate chain
+    pub fn files(&self) -> (PathBuf, PathBuf) {
+        let dir = self.storage.join(
+            self.domains
+                .first()
+                .map(|n| n.as_str())
+                .unwrap_or("default"),
+        );
+        (dir.join("key.pem"), dir.join("certs.pem"))
+    }
+
+    /// get the paths of the private key and the certificate chain for the `ssl` config section.
+    /// If no certificate has been issued yet a self-signed placeholder is created so the server
+    /// can start and answer TLS-ALPN-01 challenges
+    pub fn certificate_files(&self) -> Result<(String, String), ServerError> {
+        let (keyfile, certfile) = self.files();
+        if !keyfile.exists() || !certfile.exists() {
+            if let Some(dir) = keyfile.parent() {
+                if let Err(e) = std::fs::create_dir_all(dir) {
+                    return Err(acme_error(
+                        format!("failed to create '{}': {}", dir.display(), e).as_str(),
+                    ));
+                }
+            }
+            let (key, cert) = generate_self_signed(&self.domains)?;
+            write_pem(&key, &[&cert], &keyfile, &certfile)?;
+        }
+        Ok((
+            keyfile.to_string_lossy().to_string(),
+            certfile.to_string_lossy().to_string(),
+        ))
+    }
+
+    /// check whether the certificate is missing, a placeholder, doesn't cover all domains or
+    /// expires within `renew_days`
+    pub fn needs_renewal(&self) -> bool {
+        let cert = match std::fs::read(self.files().1)
+            .ok()
+            .and_then(|n| X509::from_pem(&n).ok())
+        {
+            Some(n) => n,
+            None => return true,
+        };
+        // the placeholder is self-signed
+        if cert.subject_name().to_der().ok() == cert.issuer_name().to_der().ok() {
+            return true;
+        }
+        let names = certificate_names(&cert);
+        if !self
+            .domains
+            .iter()
+            .filter(|n| n.parse::<IpAddr>().is_err())
+            .all(|n| names.iter().any(|m| m.eq_ignore_ascii_case(n)))
+        {
+            return true;
+        }
+        match Asn1Time::days_from_now(self.renew_days) {
+            Ok(n) => cert.not_after() < n,
+            Err(_) => true,
+        }
+    }
+
+    /// obtain a new certificate and store it. The servers have to answer the challenges while
+    /// this runs
+    pub fn obtain(&self) -> Result<(), ServerError> {
+        if let Err(e) = std::fs::create_dir_all(&self.storage) {
+            return Err(acme_error(
+                format!("failed to create '{}': {}", self.storage.display(), e).as_str(),
+            ));
+        }
+        let mut session = Session::new(self)?;
+        session.account()?;
+        let key = match generate_key() {
+            Ok(n) => n,
+            Err(e) => return Err(acme_error(e.to_string().as_str())),
+        };
+        let chain = session.order(&key)?;
+        if X509::stack_from_pem(&chain)
+            .map(|n| n.is_empty())
+            .unwrap_or(true)
+        {
+            return Err(acme_error("server sent no certificate"));
+        }
+        let pem = match key.private_key_to_pem_pkcs8() {
+            Ok(n) => n,
+            Err(e) => return Err(acme_error(e.to_string().as_str())),
+        };
+
+        // write both files before replacing the old ones one after the other. The watcher of the
+        // server certificates skips reloads while a pending file is left, a reload racing with
+        // both renames can still load a mismatched pair but rejects it and keeps the old one
+        let (keyfile, certfile) = self.files();
+        if let Some(dir) = keyfile.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        let keytmp = pending_file(&keyfile);
+        let certtmp = pending_file(&certfile);
+        write_file(&keytmp, &pem, true)?;
+        write_file(&certtmp, &chain, false)?;
+        match std::fs::rename(&keytmp, &keyfile).and_then(|_| std::fs::rename(&certtmp, &certfile))
+        {
+            Ok(_) => Ok(()),
+            Err(e) => Err(acme_error(
+                format!("failed to store the certificate: {}", e).as_str(),
+            )),
+        }
+    }
+
+    /// renew the certificate in a background thread whenever it needs to be. `on_renew` is
+    /// called after a new certificate was stored (e.g. to reload the server certificates).
+    /// Results are logged to `logger`
+    pub fn watch<F>(self, on_renew: F, logger: Logger) -> JoinHandle<()>
+    where
+        F: Fn() -> Result<(), ServerError> + Send + 'static,
+    {
+        std::thread::spawn(move || loop {
+            let mut wait = self.check_interval;
+            if self.needs_renewal() {
+                match self.obtain().and_then(|_| on_renew()) {
+                    Ok(_) => msg!(
+                        logger,
+                        "obtained a certificate for {}",
+                        self.domains.join(", ")
+                    ),
+                    Err(e) => {
+                        error!(logger, "failed to obtain a certificate: {}", e);
+                        wait = wait.min(RETRY_INTERVAL);
+                    }
+                }
+            }
+            std::thread::sleep(wait);
+        })
+    }
+}
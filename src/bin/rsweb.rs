@@ -1,215 +1,262 @@
-use rsweb::cli::Arguments;
-use rsweb::config::{load_config, Config};
+use rsweb::acme::Acme;
+use rsweb::certgen::{configured_certificate, generate_certificates};
+use rsweb::cli::{Arguments, Command};
+use rsweb::config::Config;
+use rsweb::health::Health;
+use rsweb::listener::{http_listeners, ssl_listeners};
+use rsweb::log::Logger;
+#[cfg(unix)]
+use rsweb::logfile::reopen_on_sigusr1;
+use rsweb::metrics::Metrics;
 use rsweb::resource::ResourceLoader;
 use rsweb::route::Router;
 use rsweb::server::Server;
 use rsweb::ssl::SSLServer;
+use rsweb::validate::validate_file;
+use rsweb::vhost::VirtualHosts;
+use std::net::SocketAddr;
 use std::process::exit;
-use std::str::FromStr;
+use std::sync::mpsc::channel;
 use std::thread;
 
 fn main() {
     let arguments = Arguments::load();
+    if let Some(Command::GenerateCertificate { dir, names }) = &arguments.command {
+        match generate_certificates(std::path::Path::new(dir), names) {
+            Ok(files) => {
+                eprintln!(
+                    "issued a certificate for {}\n  private_key = \"{}\"\n  certificate_chain = \"{}\"\nclients have to trust the CA in {}",
+                    names.join(", "),
+                    files.private_key.display(),
+                    files.certificate_chain.display(),
+                    files.ca_certificate.display(),
+                );
+                exit(0);
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        }
+    }
     let path: String = arguments
         .configfile
         .unwrap_or_else(|| String::from("/etc/rsweb/rsweb.config.toml"));
-    let conf: Config = match load_config(&path) {
+    let validation = match validate_file(&path) {
+        Ok(n) => n,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            exit(1);
+        }
+    };
+    for n in &validation.diagnostics {
+        match n.position {
+            Some(_) => eprintln!("{}:{}", path, n),
+            None => eprintln!("{}: {}", path, n),
+        }
+    }
+    if arguments.check_config {
+        if validation.has_errors() {
+            exit(1);
+        }
+        eprintln!("{}: config is valid", path);
+        exit(0);
+    }
+    let conf: Config = match validation.config {
+        Some(n) if !validation.has_errors() => n,
+        _ => {
+            eprintln!("invalid config file. Exiting...");
+            exit(1);
+        }
+    };
+    // all servers share one logger writing to the first configured log file
+    let logfile: String = arguments
+        .logfile
+        .clone()
+        .or_else(|| conf.http.as_ref().and_then(|n| n.logfile.clone()))
+        .or_else(|| conf.ssl.as_ref().and_then(|n| n.logfile.clone()))
+        .unwrap_or_else(|| String::from("/var/log/rsweb/latest.log"));
+    let logger = match Logger::from_config(conf.log.as_ref(), logfile.as_str()) {
         Ok(n) => n,
-        Err(_) => {
-            eprintln!("failed to parse config file. Exiting...");
+        Err(e) => {
+            eprintln!("{}", e);
             exit(1);
         }
     };
-    let mut http: bool = false;
-    let mut http_handle: Option<std::thread::JoinHandle<()>> = None;
-    let mut ssl: bool = false;
-    let mut ssl_handle: Option<std::thread::JoinHandle<()>> = None;
+    #[cfg(unix)]
+    if let Err(e) = reopen_on_sigusr1() {
+        eprintln!("failed to handle SIGUSR1: {}", e);
+    }
+    // all servers record into the same metrics and report to the same health checks
+    let metrics = conf.metrics.as_ref().map(Metrics::from_config);
+    let health = conf.health.as_ref().map(Health::from_config);
+    // metrics and health checks configured for the same admin address share its listener
+    let shared = match (&metrics, &health) {
+        (Some(m), Some(h)) => m.listen().is_some() && m.listen() == h.listen(),
+        _ => false,
+    };
+    if let Some(n) = &health {
+        let with = metrics.clone().filter(|_| shared);
+        if let Err(e) = n.serve(logger.clone(), with) {
+            eprintln!("failed to serve health checks: {}", e);
+            exit(1);
+        }
+    }
+    if let Some(n) = metrics.as_ref().filter(|_| !shared) {
+        if let Err(e) = n.serve(logger.clone()) {
+            eprintln!("failed to serve metrics: {}", e);
+            exit(1);
+        }
+    }
+    let acme = match conf.ssl.as_ref().and_then(|n| n.acme.as_ref()) {
+        Some(n) => match Acme::from_config(n) {
+            Ok(n) => Some(n),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        },
+        None => None,
+    };
+    // both servers answer requests for the virtual hosts
+    let vhosts = match VirtualHosts::from_config(conf.vhost.as_ref()) {
+        Ok(n) => n,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+    // servers report when their listeners are bound, ACME validation needs them
+    let (bound, all_bound) = channel();
+    let mut http_server: Option<Server> = None;
     if let Some(n) = conf.clone().http {
-        http = true;
-        let index_page = n
-            .resources
-            .index
-            .unwrap_or_else(|| String::from("/index.html"));
-        let mut router = Router::new(index_page);
-        let threads: usize = n.threads.unwrap_or(4);
-        let port: usize = n.port;
-        let logfile: String = arguments.logfile.clone().unwrap_or_else(|| {
-            n.logfile
-                .unwrap_or_else(|| String::from("/var/log/rsweb/latest.log"))
+        let listeners = http_listeners(&n).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            exit(1);
         });
+        let router = Router::from_config(&n.resources, n.routes.as_ref(), n.aliases.as_ref());
+        let threads: usize = n.threads.unwrap_or(4);
         let use_cache: bool = n.resources.resource_cache.unwrap_or(true);
         let cache_cap: usize = n.resources.cache_capacity.unwrap_or(10);
         let root: String = n.resources.root;
-        let routes: Vec<(String, String)> = n
-            .resources
-            .routes
-            .unwrap_or(Vec::new())
+        // replaced by the listeners, servers listening only on unix sockets have no address
+        let addr = listeners
             .iter()
-            .map(|x| {
-                let lh = x.split_once(':').unwrap_or(("", "")).0;
-                let rh = x.split_once(':').unwrap_or(("", "")).1;
-                (lh.to_string(), rh.to_string())
-            })
-            .filter(|x| {
-                let (lh, rh) = x;
-                !lh.is_empty() && !rh.is_empty()
-            })
-            .collect();
-        for (lh, rh) in routes {
-            router.route(lh, rh);
-        }
-        let aliases: Vec<(String, String)> = n
-            .resources
-            .aliases
-            .unwrap_or(Vec::new())
-            .iter()
-            .map(|x| {
-                let mut parts = x.split(':');
-                let lh = parts.next().unwrap_or("");
-                let rh = parts.next().unwrap_or("");
-                (lh.to_string(), rh.to_string())
-            })
-            .filter(|x| {
-                let (lh, rh) = x;
-                !lh.is_empty() && !rh.is_empty()
-            })
-            .collect();
-        for (lh, rh) in aliases {
-            router.alias(lh, rh);
-        }
-        let addr = match std::net::IpAddr::from_str(n.ip.as_str()) {
-            Ok(n) => n,
-            Err(_) => {
-                eprintln!("failed to parse ip address");
-                exit(1);
-            }
-        };
+            .find_map(|n| n.tcp_addr())
+            .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0)));
         let mut server = Server::new(
             threads,
             ResourceLoader::new(cache_cap, root, use_cache),
             router.clone(),
-            port,
-            addr,
+            addr.port() as usize,
+            addr.ip(),
             conf.clone(),
         );
-        if let Some(_) = conf.ssl {
-            http_handle = Some(thread::spawn(move || match server.run(logfile.as_str()) {
-                Ok(_) => (),
-                Err(e) => eprintln!("runtime error: {}", e),
-            }));
-        } else {
-            match server.run(logfile.as_str()) {
-                Ok(_) => (),
-                Err(e) => eprintln!("runtime error: {}", e),
-            }
-        }
+        server.set_listeners(listeners);
+        server.set_acme_challenges(acme.as_ref().map(|n| n.challenges()));
+        server.set_logger(logger.clone());
+        server.set_vhosts(vhosts.clone());
+        server.set_metrics(metrics.clone());
+        server.set_health(health.clone());
+        server.set_bound_notifier(Some(bound.clone()));
+        http_server = Some(server);
     }
 
+    let mut ssl_server: Option<SSLServer> = None;
     if let Some(n) = conf.clone().ssl {
-        ssl = true;
-        if !http {
+        if http_server.is_none() {
             // remember: there are people not using encryption out there
             eprintln!("warning: using an SSLServer without an HTTP server may lead to compatibility issues.");
         }
-        let index_page = n
-            .resources
-            .index
-            .unwrap_or_else(|| String::from("/index.html"));
-        let mut router = Router::new(index_page);
-        let threads: usize = n.threads.unwrap_or(4);
-        let port: usize = n.port;
-        let logfile: String = arguments.logfile.clone().unwrap_or_else(|| {
-            n.logfile
-                .unwrap_or_else(|| String::from("/var/log/rsweb/latest.log"))
+        if acme.is_none() && n.private_key.is_none() && n.certificate_chain.is_none() {
+            eprintln!("warning: no certificate configured, using an ephemeral self-signed one.");
+        }
+        let (privkey, cert_chain) = match &acme {
+            Some(acme) => acme.certificate_files(),
+            None => configured_certificate(&n),
+        }
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            exit(1);
         });
+        let listeners = ssl_listeners(&n).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            exit(1);
+        });
+        let router = Router::from_config(&n.resources, n.routes.as_ref(), n.aliases.as_ref());
+        let threads: usize = n.threads.unwrap_or(4);
         let use_cache: bool = n.resources.resource_cache.unwrap_or(true);
         let cache_cap: usize = n.resources.cache_capacity.unwrap_or(10);
-        let routes: Vec<(String, String)> = n
-            .resources
-            .routes
-            .unwrap_or(Vec::new())
-            .iter()
-            .map(|x| {
-                let lh = x.split_once(':').unwrap_or(("", "")).0;
-                let rh = x.split_once(':').unwrap_or(("", "")).1;
-                (lh.to_string(), rh.to_string())
-            })
-            .filter(|x| {
-                let (lh, rh) = x;
-                !lh.is_empty() && !rh.is_empty()
-            })
-            .collect();
-        for (lh, rh) in routes {
-            router.route(lh, rh);
-        }
-        let aliases: Vec<(String, String)> = n
-            .resources
-            .aliases
-            .unwrap_or(Vec::new())
-            .iter()
-            .map(|x| {
-                let mut parts = x.split(':');
-                let lh = parts.next().unwrap_or("");
-                let rh = parts.next().unwrap_or("");
-                (lh.to_string(), rh.to_string())
-            })
-            .filter(|x| {
-                let (lh, rh) = x;
-                !lh.is_empty() && !rh.is_empty()
-            })
-            .collect();
-        for (lh, rh) in aliases {
-            router.alias(lh, rh);
-        }
-        let addr = match std::net::IpAddr::from_str(n.ip.as_str()) {
-            Ok(n) => n,
-            Err(_) => {
-                eprintln!("failed to parse ip address");
-                exit(1);
-            }
-        };
-        let privkey = n.private_key;
-        let cert_chain = n.certificate_chain;
         let root = n.resources.root;
+        // replaced by the listeners, servers listening only on unix sockets have no address
+        let addr = listeners
+            .iter()
+            .find_map(|n| n.tcp_addr())
+            .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0)));
         let mut server = SSLServer::new(
             threads,
             ResourceLoader::new(cache_cap, root, use_cache),
             router,
-            port,
-            addr,
+            addr.port() as usize,
+            addr.ip(),
             privkey,
             cert_chain,
-	    conf.clone(),
+            conf.clone(),
         )
-        .unwrap_or_else(|_| {
-            eprintln!("failed to create SSLServer. Exiting...");
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
             exit(1);
         });
-        if http {
-            ssl_handle = Some(thread::spawn(move || match server.run(logfile.as_str()) {
-                Ok(_) => (),
-                Err(e) => eprintln!("runtime error: {}", e),
-            }));
-        } else {
-            match server.run(logfile.as_str()) {
-                Ok(_) => (),
-                Err(e) => eprintln!("runtime error: {}", e),
-            }
+        server.set_listeners(listeners);
+        server.set_logger(logger.clone());
+        server.set_vhosts(vhosts.clone());
+        server.set_metrics(metrics.clone());
+        server.set_health(health.clone());
+        server.set_bound_notifier(Some(bound.clone()));
+        if let Some(acme) = &acme {
+            server.set_acme_challenges(Some(acme.challenges()));
         }
+        ssl_server = Some(server);
     }
 
-    if !http && !ssl {
+    // TLS listeners of the http section use the certificates of the ssl section
+    if let (Some(http), Some(ssl)) = (&mut http_server, &ssl_server) {
+        http.set_certificates(Some(ssl.certificates()));
+    }
+
+    if http_server.is_none() && ssl_server.is_none() {
         eprintln!("config used doesn't specify any servers. exiting normally...");
         exit(0);
     }
 
-    if let Some(handle) = http_handle {
-        if let Err(_) = handle.join() {
-            eprintln!("error joining threads");
-        }
+    // start obtaining certificates once every server bound its listeners or failed to
+    drop(bound);
+    if let (Some(acme), Some(server)) = (acme, &ssl_server) {
+        let certificates = server.certificates();
+        let logger = logger.clone();
+        thread::spawn(move || {
+            while all_bound.recv().is_ok() {}
+            acme.watch(move || certificates.reload(), logger);
+        });
+    }
+
+    let mut handles: Vec<thread::JoinHandle<()>> = Vec::new();
+    if let Some(mut server) = http_server {
+        let logfile = logfile.clone();
+        handles.push(thread::spawn(move || match server.run(logfile.as_str()) {
+            Ok(_) => (),
+            Err(e) => eprintln!("runtime error: {}", e),
+        }));
+    }
+    if let Some(mut server) = ssl_server {
+        let logfile = logfile.clone();
+        handles.push(thread::spawn(move || match server.run(logfile.as_str()) {
+            Ok(_) => (),
+            Err(e) => eprintln!("runtime error: {}", e),
+        }));
     }
-    if let Some(handle) = ssl_handle {
-        if let Err(_) = handle.join() {
+    for handle in handles {
+        if handle.join().is_err() {
             eprintln!("error joining threads");
         }
     }
@@ -0,0 +1,326 @@
+use crate::config::SslConfig;
+use crate::error::ServerError;
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::x509::extension::{
+    AuthorityKeyIdentifier, BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectAlternativeName,
+    SubjectKeyIdentifier,
+};
+use openssl::x509::{X509NameBuilder, X509};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// days a generated CA is valid
+pub const CA_VALIDITY_DAYS: u32 = 3650;
+
+/// days a generated leaf certificate is valid. Browsers reject server certificates that are valid
+/// for longer than 825 days
+pub const LEAF_VALIDITY_DAYS: u32 = 825;
+
+/// names a certificate is generated for if none are given
+pub const DEFAULT_NAMES: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
+
+fn gen_error(e: ErrorStack) -> ServerError {
+    ServerError::new(format!("failed to generate certificate: {}", e).as_str())
+}
+
+/// generate a P-256 key
+pub(crate) fn generate_key() -> Result<PKey<Private>, ErrorStack> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    PKey::from_ec_key(EcKey::generate(&group)?)
+}
+
+/// build and sign a certificate for `key`. Self-signed if `issuer` is `None`
+fn build_certificate(
+    key: &PKey<Private>,
+    common_name: &str,
+    names: &[String],
+    issuer: Option<(&PKey<Private>, &X509)>,
+    ca: bool,
+    days: u32,
+) -> Result<X509, ErrorStack> {
+    let mut subject = X509NameBuilder::new()?;
+    subject.append_entry_by_nid(Nid::ORGANIZATIONNAME, "rsweb development")?;
+    subject.append_entry_by_nid(Nid::COMMONNAME, common_name)?;
+    let subject = subject.build();
+
+    let mut serial = BigNum::new()?;
+    serial.rand(127, MsbOption::MAYBE_ZERO, false)?;
+
+    let mut cert = X509::builder()?;
+    cert.set_version(2)?;
+    cert.set_serial_number(serial.to_asn1_integer()?.as_ref())?;
+    cert.set_subject_name(&subject)?;
+    match issuer {
+        Some((_, n)) => cert.set_issuer_name(n.subject_name())?,
+        None => cert.set_issuer_name(&subject)?,
+    }
+    cert.set_pubkey(key)?;
+    cert.set_not_before(Asn1Time::days_from_now(0)?.as_ref())?;
+    cert.set_not_after(Asn1Time::days_from_now(days)?.as_ref())?;
+
+    if ca {
+        cert.append_extension(BasicConstraints::new().critical().ca().build()?)?;
+        cert.append_extension(
+            KeyUsage::new()
+                .critical()
+                .key_cert_sign()
+                .crl_sign()
+                .build()?,
+        )?;
+    } else {
+        cert.append_extension(BasicConstraints::new().critical().build()?)?;
+        cert.append_extension(
+            KeyUsage::new()
+                .critical()
+                .digital_signature()
+                .key_encipherment()
+                .build()?,
+        )?;
+        cert.append_extension(ExtendedKeyUsage::new().server_auth().build()?)?;
+    }
+    let ski = SubjectKeyIdentifier::new().build(&cert.x509v3_context(None, None))?;
+    cert.append_extension(ski)?;
+    if let Some((_, n)) = issuer {
+        let aki = AuthorityKeyIdentifier::new()
+            .keyid(false)
+            .build(&cert.x509v3_context(Some(n), None))?;
+        cert.append_extension(aki)?;
+    }
+    if !names.is_empty() {
+        let mut san = SubjectAlternativeName::new();
+        for n in names {
+            match n.parse::<IpAddr>() {
+                Ok(_) => san.ip(n),
+                Err(_) => san.dns(n),
+            };
+        }
+        let san = san.build(&cert.x509v3_context(issuer.map(|n| n.1.as_ref()), None))?;
+        cert.append_extension(san)?;
+    }
+    cert.sign(issuer.map(|n| n.0).unwrap_or(key), MessageDigest::sha256())?;
+    Ok(cert.build())
+}
+
+/// write `data` to `path`. The file is only readable by the owner if `private` is set
+pub(crate) fn write_file(path: &Path, data: &[u8], private: bool) -> Result<(), ServerError> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    match options.open(path).and_then(|mut n| n.write_all(data)) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ServerError::new(
+            format!("failed to write '{}': {}", path.display(), e).as_str(),
+        )),
+    }
+}
+
+/// read a PEM file
+fn read_file(path: &Path) -> Result<Vec<u8>, ServerError> {
+    match std::fs::read(path) {
+        Ok(n) => Ok(n),
+        Err(e) => Err(ServerError::new(
+            format!("failed to read '{}': {}", path.display(), e).as_str(),
+        )),
+    }
+}
+
+/// a certificate authority for local development that issues server certificates
+pub struct CertificateAuthority {
+    key: PKey<Private>,
+    cert: X509,
+}
+
+impl CertificateAuthority {
+    /// generate a new CA called `name`
+    pub fn generate(name: &str) -> Result<CertificateAuthority, ServerError> {
+        let key = generate_key().map_err(gen_error)?;
+        let cert =
+            build_certificate(&key, name, &[], None, true, CA_VALIDITY_DAYS).map_err(gen_error)?;
+        Ok(CertificateAuthority { key, cert })
+    }
+
+    /// load a CA from the PEM files `keyfile` and `certfile`
+    pub fn load(keyfile: &Path, certfile: &Path) -> Result<CertificateAuthority, ServerError> {
+        let key = match PKey::private_key_from_pem(&read_file(keyfile)?) {
+            Ok(n) => n,
+            Err(_) => {
+                return Err(ServerError::new(
+                    format!("invalid private key: '{}'", keyfile.display()).as_str(),
+                ));
+            }
+        };
+        let cert = match X509::from_pem(&read_file(certfile)?) {
+            Ok(n) => n,
+            Err(_) => {
+                return Err(ServerError::new(
+                    format!("invalid certificate: '{}'", certfile.display()).as_str(),
+                ));
+            }
+        };
+        Ok(CertificateAuthority { key, cert })
+    }
+
+    /// get the certificate of the CA. Clients have to trust it to accept the issued certificates
+    pub fn certificate(&self) -> &X509 {
+        &self.cert
+    }
+
+    /// issue a server certificate for `names` (host names, wildcards like `*.example.test` or IP
+    /// addresses). The first name becomes the common name
+    pub fn issue(&self, names: &[String]) -> Result<(PKey<Private>, X509), ServerError> {
+        let common_name = match names.first() {
+            Some(n) => n,
+            None => return Err(ServerError::new("no names to issue a certificate for")),
+        };
+        let key = generate_key().map_err(gen_error)?;
+        let cert = build_certificate(
+            &key,
+            common_name,
+            names,
+            Some((&self.key, &self.cert)),
+            false,
+            LEAF_VALIDITY_DAYS,
+        )
+        .map_err(gen_error)?;
+        Ok((key, cert))
+    }
+
+    /// write the CA to the PEM files `keyfile` and `certfile`
+    pub fn write(&self, keyfile: &Path, certfile: &Path) -> Result<(), ServerError> {
+        write_pem(&self.key, &[&self.cert], keyfile, certfile)
+    }
+}
+
+/// generate a self-signed server certificate for `names`
+pub fn generate_self_signed(names: &[String]) -> Result<(PKey<Private>, X509), ServerError> {
+    let common_name = match names.first() {
+        Some(n) => n,
+        None => return Err(ServerError::new("no names to issue a certificate for")),
+    };
+    let key = generate_key().map_err(gen_error)?;
+    let cert = build_certificate(&key, common_name, names, None, false, LEAF_VALIDITY_DAYS)
+        .map_err(gen_error)?;
+    Ok((key, cert))
+}
+
+/// write `key` (PKCS#8, only readable by the owner) and the certificate chain `chain` to PEM
+/// files
+pub fn write_pem(
+    key: &PKey<Private>,
+    chain: &[&X509],
+    keyfile: &Path,
+    certfile: &Path,
+) -> Result<(), ServerError> {
+    let key = key.private_key_to_pem_pkcs8().map_err(gen_error)?;
+    let mut certs = Vec::new();
+    for n in chain {
+        certs.extend(n.to_pem().map_err(gen_error)?);
+    }
+    write_file(keyfile, &key, true)?;
+    write_file(certfile, &certs, false)
+}
+
+/// files written by [`generate_certificates`]
+pub struct GeneratedFiles {
+    /// certificate of the CA to install in browsers and clients
+    pub ca_certificate: PathBuf,
+    /// private key of the CA
+    pub ca_private_key: PathBuf,
+    /// private key of the server certificate (`private_key` in the `ssl` section)
+    pub private_key: PathBuf,
+    /// server certificate followed by the CA certificate (`certificate_chain` in the `ssl`
+    /// section)
+    pub certificate_chain: PathBuf,
+}
+
+/// issue a server certificate for `names` from the CA in `dir` and write it to `dir`. The CA
+/// (`ca.pem` and `ca-key.pem`) is created on the first call and reused afterwards so clients only
+/// have to trust it once. The server certificate is written to `key.pem` and `certs.pem`
+pub fn generate_certificates(dir: &Path, names: &[String]) -> Result<GeneratedFiles, ServerError> {
+    if let Err(e) = std::fs::create_dir_all(dir) {
+        return Err(ServerError::new(
+            format!("failed to create '{}': {}", dir.display(), e).as_str(),
+        ));
+    }
+    let files = GeneratedFiles {
+        ca_certificate: dir.join("ca.pem"),
+        ca_private_key: dir.join("ca-key.pem"),
+        private_key: dir.join("key.pem"),
+        certificate_chain: dir.join("certs.pem"),
+    };
+    let ca = if files.ca_certificate.exists() && files.ca_private_key.exists() {
+        CertificateAuthority::load(&files.ca_private_key, &files.ca_certificate)?
+    } else {
+        let ca = CertificateAuthority::generate("rsweb development CA")?;
+        ca.write(&files.ca_private_key, &files.ca_certificate)?;
+        ca
+    };
+    let (key, cert) = ca.issue(names)?;
+    write_pem(
+        &key,
+        &[&cert, ca.certificate()],
+        &files.private_key,
+        &files.certificate_chain,
+    )?;
+    Ok(files)
+}
+
+/// generate a self-signed certificate for `names` that only lives as long as the process and
+/// write it to a private temporary directory
+///
+/// # Returns
+/// the paths of the private key and the certificate
+pub fn ephemeral_certificate(names: &[String]) -> Result<(String, String), ServerError> {
+    let dir = std::env::temp_dir().join(format!("rsweb-ephemeral-{}", std::process::id()));
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    if let Err(e) = builder.create(&dir) {
+        return Err(ServerError::new(
+            format!("failed to create '{}': {}", dir.display(), e).as_str(),
+        ));
+    }
+    let (key, cert) = generate_self_signed(names)?;
+    let keyfile = dir.join("key.pem");
+    let certfile = dir.join("certs.pem");
+    write_pem(&key, &[&cert], &keyfile, &certfile)?;
+    Ok((
+        keyfile.to_string_lossy().to_string(),
+        certfile.to_string_lossy().to_string(),
+    ))
+}
+
+/// get the private key and certificate chain files of the `ssl` config section. If neither is
+/// configured an ephemeral self-signed certificate for `self_signed_names` is generated
+pub fn configured_certificate(conf: &SslConfig) -> Result<(String, String), ServerError> {
+    match (&conf.private_key, &conf.certificate_chain) {
+        (Some(key), Some(chain)) => Ok((key.clone(), chain.clone())),
+        (None, None) => {
+            let names = match &conf.self_signed_names {
+                Some(n) => n.clone(),
+                None => DEFAULT_NAMES.iter().map(|n| n.to_string()).collect(),
+            };
+            ephemeral_certificate(&names)
+        }
+        _ => Err(ServerError::new(
+            "private_key and certificate_chain have to be configured together",
+        )),
+    }
+}
@@ -1,8 +1,17 @@
 use std::env::args;
 
+/// subcommands of `rsweb`. Without one the server is started
+pub enum Command {
+    /// issue a certificate for `names` from a local CA kept in `dir`
+    GenerateCertificate { dir: String, names: Vec<String> },
+}
+
 pub struct Arguments {
     pub logfile: Option<String>,
     pub configfile: Option<String>,
+    /// validate the config, print problems and exit
+    pub check_config: bool,
+    pub command: Option<Command>,
 }
 
 impl Arguments {
@@ -12,7 +21,11 @@ impl Arguments {
         let mut out = Arguments {
             logfile: None,
             configfile: None,
+            check_config: false,
+            command: None,
         };
+        // skip the program name
+        arguments.next();
         while let Some(n) = arguments.next() {
             match n.as_str() {
                 "-h" | "--help" => {
@@ -20,12 +33,20 @@ impl Arguments {
                         "
 USAGE
   rsweb [OPTIONS]
+  rsweb gen-cert [-o,--out <dir>] [NAME...]
 
 OPTIONS
   -h,--help: print this help and exit
   -l,--logfile <logfile>: log to <logfile> instead of default or configured logfile
   -c,--config <config>: use <config> as a config file instead of default
+  --check-config: validate the config file, print errors and warnings and exit
   -v,--version: print the version and exit
+
+COMMANDS
+  gen-cert: issue a certificate for the host names and IP addresses NAME (default: localhost,
+    127.0.0.1 and ::1) from a local CA. The CA is created in <dir> (default: .) as ca.pem and
+    ca-key.pem on the first run and reused afterwards. The certificate is written to key.pem
+    and certs.pem
                             "
                     );
                     std::process::exit(0);
@@ -46,10 +67,35 @@ OPTIONS
                         std::process::exit(1);
                     }
                 }
+                "--check-config" => out.check_config = true,
                 "-v" | "--version" => {
                     eprintln!("rsweb: version {}", crate::RSWEB_VERSION);
                     std::process::exit(0);
                 }
+                "gen-cert" => {
+                    let mut dir = String::from(".");
+                    let mut names = Vec::new();
+                    while let Some(n) = arguments.next() {
+                        match n.as_str() {
+                            "-o" | "--out" => {
+                                if let Some(d) = arguments.next() {
+                                    dir = d;
+                                } else {
+                                    eprintln!("no output directory provided");
+                                    std::process::exit(1);
+                                }
+                            }
+                            _ => names.push(n),
+                        }
+                    }
+                    if names.is_empty() {
+                        names = crate::certgen::DEFAULT_NAMES
+                            .iter()
+                            .map(|n| n.to_string())
+                            .collect();
+                    }
+                    out.command = Some(Command::GenerateCertificate { dir, names });
+                }
                 _ => (),
             }
         }
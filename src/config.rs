@@ -1,36 +1,205 @@
-use serde_derive::Deserialize;
+use crate::log::LogFormat;
+use crate::logfile::RotateInterval;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs::read_to_string;
 use std::io::Error;
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Config {
     pub http: Option<HTTPConfig>,
     pub ssl: Option<SslConfig>,
+    pub log: Option<LogConfig>,
+    pub metrics: Option<MetricsConfig>,
+    pub health: Option<HealthConfig>,
+    pub vhost: Option<Vec<VhostConfig>>,
 }
 
-#[derive(Deserialize, Clone)]
+/// a virtual host answering requests whose `Host` header matches one of `names`
+#[derive(Deserialize, Serialize, Clone)]
+pub struct VhostConfig {
+    /// exact names (`example.com`), wildcards (`*.example.com`) or regular expressions starting
+    /// with `~` (`~^www[0-9]+\.example\.com$`)
+    pub names: Vec<String>,
+    /// answer requests for hosts no virtual host is named after
+    pub default: Option<bool>,
+    pub resources: Resource,
+    pub routes: Option<Vec<RouteConfig>>,
+    pub aliases: Option<Vec<RouteConfig>>,
+    /// pages sent instead of the default ones by status code, e.g. `404 = "/404.html"`
+    pub error_pages: Option<BTreeMap<String, String>>,
+    /// headers added to all responses
+    pub headers: Option<BTreeMap<String, String>>,
+}
+
+/// liveness and readiness endpoints shared by all servers
+#[derive(Deserialize, Serialize, Clone)]
+pub struct HealthConfig {
+    /// path liveness is reported on. Defaults to `/healthz`
+    pub liveness_path: Option<String>,
+    /// path readiness is reported on. Defaults to `/readyz`
+    pub readiness_path: Option<String>,
+    /// report health on this admin address (e.g. `127.0.0.1:9101`) instead of the servers
+    pub listen: Option<String>,
+    /// connections that may wait for a worker before a server isn't ready. Defaults to the
+    /// number of threads
+    pub max_queued: Option<usize>,
+}
+
+/// Prometheus metrics shared by all servers
+#[derive(Deserialize, Serialize, Clone)]
+pub struct MetricsConfig {
+    /// path metrics are served on. Defaults to `/metrics`
+    pub path: Option<String>,
+    /// serve metrics on this address (e.g. `127.0.0.1:9100`) instead of the servers
+    pub listen: Option<String>,
+}
+
+/// settings of the logger shared by all servers
+#[derive(Deserialize, Serialize, Clone)]
+pub struct LogConfig {
+    /// minimum level of logged messages, optionally per module, e.g. `"info,rsweb::acme=debug"`
+    pub level: Option<String>,
+    /// messages that can be queued before new ones are dropped
+    pub queue_size: Option<usize>,
+    pub rotation: Option<RotationConfig>,
+    /// log to the terminal. Defaults to `true`
+    pub terminal: Option<bool>,
+    /// `"text"` (default) or `"json"`
+    pub terminal_format: Option<LogFormat>,
+    /// `"text"` (default) or `"json"`
+    pub file_format: Option<LogFormat>,
+    pub syslog: Option<SyslogConfig>,
+    pub journald: Option<JournaldConfig>,
+}
+
+/// sending log messages to the local syslog daemon (RFC 5424)
+#[derive(Deserialize, Serialize, Clone)]
+pub struct SyslogConfig {
+    /// defaults to `/dev/log`
+    pub socket: Option<String>,
+    /// e.g. `"daemon"` (default) or `"local0"`
+    pub facility: Option<String>,
+    /// defaults to `rsweb`
+    pub app_name: Option<String>,
+}
+
+/// sending log messages to the systemd journal
+#[derive(Deserialize, Serialize, Clone)]
+pub struct JournaldConfig {
+    /// defaults to `/run/systemd/journal/socket`
+    pub socket: Option<String>,
+    /// `SYSLOG_IDENTIFIER` of the messages. Defaults to `rsweb`
+    pub identifier: Option<String>,
+}
+
+/// rotation of a log file. Without `max_size` and `interval` the file is never rotated
+#[derive(Deserialize, Serialize, Clone)]
+pub struct RotationConfig {
+    /// rotate once the file grows larger than this many bytes
+    pub max_size: Option<u64>,
+    /// `"hourly"`, `"daily"` or `"weekly"`
+    pub interval: Option<RotateInterval>,
+    /// compress rotated files with gzip. Defaults to `true`
+    pub compress: Option<bool>,
+    /// number of rotated files to keep
+    pub keep: Option<usize>,
+    /// days rotated files are kept
+    pub max_age: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
 pub struct HTTPConfig {
-    pub port: usize,
-    pub ip: String,
+    /// without `port` the server only listens on the `listen` addresses
+    pub port: Option<usize>,
+    /// address `port` is bound on. Defaults to `0.0.0.0`
+    pub ip: Option<String>,
+    pub listen: Option<Vec<ListenConfig>>,
     pub threads: Option<usize>,
     pub resources: Resource,
+    pub routes: Option<Vec<RouteConfig>>,
+    pub aliases: Option<Vec<RouteConfig>>,
     pub logfile: Option<String>,
     pub allowed_methods: Option<Vec<String>>,
+    pub path_methods: Option<Vec<PathMethodsConfig>>,
+    pub cors: Option<Vec<CorsConfig>>,
+    pub timeouts: Option<TimeoutConfig>,
+    pub limits: Option<LimitConfig>,
+    pub https_redirect: Option<HttpsRedirectConfig>,
+    pub access_log: Option<AccessLogConfig>,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct SslConfig {
-    pub private_key: String,
-    pub certificate_chain: String,
-    pub port: usize,
-    pub ip: String,
+    /// without `private_key` and `certificate_chain` an ephemeral self-signed certificate is
+    /// generated at startup
+    pub private_key: Option<String>,
+    pub certificate_chain: Option<String>,
+    /// names the ephemeral certificate is generated for. Defaults to `localhost`, `127.0.0.1`
+    /// and `::1`
+    pub self_signed_names: Option<Vec<String>>,
+    pub certificates: Option<Vec<CertificateConfig>>,
+    /// seconds between checks of the certificate files for changes. `0` disables reloading
+    pub reload_interval: Option<u64>,
+    pub client_auth: Option<ClientAuthConfig>,
+    /// `"intermediate"` (default) or `"modern"`
+    pub profile: Option<String>,
+    pub min_version: Option<String>,
+    pub max_version: Option<String>,
+    pub ciphers: Option<String>,
+    pub ciphersuites: Option<String>,
+    pub curves: Option<String>,
+    pub alpn: Option<Vec<String>>,
+    pub session_cache: Option<bool>,
+    pub session_cache_size: Option<usize>,
+    pub session_tickets: Option<bool>,
+    /// file containing a DER encoded OCSP response to staple
+    pub ocsp_response: Option<String>,
+    pub hsts: Option<HstsConfig>,
+    pub acme: Option<AcmeConfig>,
+    /// without `port` the server only listens on the `listen` addresses
+    pub port: Option<usize>,
+    /// address `port` is bound on. Defaults to `0.0.0.0`
+    pub ip: Option<String>,
+    pub listen: Option<Vec<ListenConfig>>,
     pub threads: Option<usize>,
     pub resources: Resource,
+    pub routes: Option<Vec<RouteConfig>>,
+    pub aliases: Option<Vec<RouteConfig>>,
     pub logfile: Option<String>,
     pub allowed_methods: Option<Vec<String>>,
+    pub path_methods: Option<Vec<PathMethodsConfig>>,
+    pub cors: Option<Vec<CorsConfig>>,
+    pub timeouts: Option<TimeoutConfig>,
+    pub limits: Option<LimitConfig>,
+    pub access_log: Option<AccessLogConfig>,
+}
+
+/// an address a server listens on in addition to `ip` and `port`
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ListenConfig {
+    /// e.g. `"0.0.0.0:8080"`, `"[::]:8080"` or a unix socket like `"unix:/run/rsweb.sock"`
+    pub address: String,
+    /// serve TLS on the address. Defaults to `false` in the `http` and `true` in the `ssl`
+    /// section. TLS listeners of the `http` section use the certificates of the `ssl` section
+    pub tls: Option<bool>,
+    /// set `SO_REUSEADDR`. Defaults to `true`
+    pub reuse_address: Option<bool>,
+    /// set `SO_REUSEPORT` so several processes can listen on the address. Defaults to `false`
+    pub reuse_port: Option<bool>,
+    /// only accept IPv6 connections on an IPv6 address (`IPV6_V6ONLY`). Defaults to `true` if
+    /// the section also listens on `0.0.0.0` with the same port and to the system setting
+    /// otherwise
+    pub ipv6_only: Option<bool>,
+    /// permissions of a unix socket in octal, e.g. `"0660"`
+    pub mode: Option<String>,
+    /// user owning a unix socket, by name or id
+    pub owner: Option<String>,
+    /// group owning a unix socket, by name or id
+    pub group: Option<String>,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Resource {
     pub root: String,
     pub index: Option<String>,
@@ -41,6 +210,151 @@ pub struct Resource {
     pub notfound_page: Option<String>,
 }
 
+/// a route redirecting or an alias serving `to` for all paths matching `from`. Aliases don't
+/// have a `status`
+#[derive(Deserialize, Serialize, Clone)]
+pub struct RouteConfig {
+    pub from: String,
+    pub to: String,
+    /// status of the redirect: 301 (default), 302, 303, 307 or 308
+    pub status: Option<u16>,
+    /// methods the route applies to. Without them it applies to all methods
+    pub methods: Option<Vec<String>>,
+    /// headers added to the response
+    pub headers: Option<BTreeMap<String, String>>,
+}
+
+/// a certificate served to clients asking for one of `names` (SNI). Without `names` the names
+/// the certificate is valid for are used
+#[derive(Deserialize, Serialize, Clone)]
+pub struct CertificateConfig {
+    pub names: Option<Vec<String>>,
+    pub private_key: String,
+    pub certificate_chain: String,
+}
+
+/// log of all answered requests
+#[derive(Deserialize, Serialize, Clone)]
+pub struct AccessLogConfig {
+    pub path: String,
+    /// `"common"`, `"combined"` (default), `"json"` or a custom format string like
+    /// `"%h %t \"%r\" %>s %b %D"`
+    pub format: Option<String>,
+    pub rotation: Option<RotationConfig>,
+}
+
+/// redirect of plain HTTP requests to HTTPS
+#[derive(Deserialize, Serialize, Clone)]
+pub struct HttpsRedirectConfig {
+    /// HTTPS port to redirect to. Defaults to the port of the `ssl` section
+    pub port: Option<usize>,
+    /// `301` (default) or `308`
+    pub status: Option<u16>,
+    /// paths that aren't redirected. Defaults to `["/.well-known/acme-challenge/*"]`
+    pub exclude: Option<Vec<String>>,
+    /// host used if a request has no `Host` header
+    pub host: Option<String>,
+}
+
+/// automatic certificate issuance via ACME
+#[derive(Deserialize, Serialize, Clone)]
+pub struct AcmeConfig {
+    /// directory URL of the CA. Defaults to Let's Encrypt
+    pub directory: Option<String>,
+    pub domains: Vec<String>,
+    /// e.g. `["mailto:admin@example.com"]`
+    pub contact: Option<Vec<String>>,
+    /// has to be `true` to agree to the terms of service of the CA
+    pub accept_terms: Option<bool>,
+    /// defaults to `/var/lib/rsweb/acme`
+    pub storage: Option<String>,
+    /// `"http-01"` (default) or `"tls-alpn-01"`
+    pub challenge: Option<String>,
+    /// additional CAs to trust when connecting to the directory (e.g. of a Pebble test server)
+    pub ca_file: Option<String>,
+    /// days before expiry the certificate is renewed. Defaults to 30
+    pub renew_days: Option<u32>,
+    /// seconds between checks whether the certificate needs to be renewed
+    pub check_interval: Option<u64>,
+}
+
+/// `Strict-Transport-Security` header sent with TLS responses
+#[derive(Deserialize, Serialize, Clone)]
+pub struct HstsConfig {
+    pub max_age: u64,
+    pub include_subdomains: Option<bool>,
+    pub preload: Option<bool>,
+}
+
+/// verification of client certificates (mutual TLS)
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ClientAuthConfig {
+    /// `"required"` (default) or `"optional"`
+    pub mode: Option<String>,
+    pub ca_file: String,
+    /// directory of CRLs named by the hash of their issuer (see `openssl rehash`)
+    pub crl_dir: Option<String>,
+    pub rules: Option<Vec<ClientAccessConfig>>,
+}
+
+/// clients allowed to access paths matching `path`. A client has to match one of the subjects,
+/// names or fingerprints, without any of them every verified client is allowed
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ClientAccessConfig {
+    pub path: String,
+    pub subjects: Option<Vec<String>>,
+    pub sans: Option<Vec<String>>,
+    pub fingerprints: Option<Vec<String>>,
+}
+
+/// methods allowed on all paths matching `path`
+#[derive(Deserialize, Serialize, Clone)]
+pub struct PathMethodsConfig {
+    pub path: String,
+    pub allowed_methods: Vec<String>,
+}
+
+/// CORS policy for all paths matching `path`
+#[derive(Deserialize, Serialize, Clone)]
+pub struct CorsConfig {
+    pub path: String,
+    pub origins: Vec<String>,
+    pub methods: Option<Vec<String>>,
+    pub headers: Option<Vec<String>>,
+    pub credentials: Option<bool>,
+    pub max_age: Option<usize>,
+    pub expose_headers: Option<Vec<String>>,
+}
+
+/// timeouts for client connections in seconds. `0` disables a timeout
+#[derive(Deserialize, Serialize, Clone)]
+pub struct TimeoutConfig {
+    pub header_read: Option<u64>,
+    pub body_read: Option<u64>,
+    pub write: Option<u64>,
+    pub idle: Option<u64>,
+    /// minimum transfer rate in bytes per second
+    pub min_rate: Option<usize>,
+}
+
+/// size limits for requests in bytes (or number of fields for `header_count`). `0` disables a
+/// limit
+#[derive(Deserialize, Serialize, Clone)]
+pub struct LimitConfig {
+    pub request_line: Option<usize>,
+    pub header_count: Option<usize>,
+    pub header_size: Option<usize>,
+    pub body_size: Option<usize>,
+    pub routes: Option<Vec<RouteLimitConfig>>,
+}
+
+/// a body size limit for all paths matching `path`
+#[derive(Deserialize, Serialize, Clone)]
+pub struct RouteLimitConfig {
+    pub path: String,
+    pub body_size: usize,
+}
+
 /// load a config from a file
 pub fn load_config(path: &str) -> Result<Config, Error> {
     let contents: String = match read_to_string(path) {
@@ -51,9 +365,6 @@ pub fn load_config(path: &str) -> Result<Config, Error> {
     };
     match toml::from_str(contents.as_str()) {
         Ok(n) => Ok(n),
-        Err(e) => Err(Error::new(
-            std::io::ErrorKind::Other,
-            format!("failed to parse config file: {}", e),
-        )),
+        Err(e) => Err(Error::other(format!("failed to parse config file: {}", e))),
     }
 }
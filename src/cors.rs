@@ -0,0 +1,259 @@
+use crate::config::CorsConfig;
+use crate::http::body::Body;
+use crate::http::header::{HTTPRequestHeaders, HTTPResponseHeaders};
+use crate::http::request::{HTTPMethod, HTTPRequest};
+use crate::http::response::HTTPResponse;
+use crate::http::StatusCode;
+use crate::RSWEB_SERVER_STR;
+use wildmatch::WildMatch;
+
+/// a CORS policy for a set of paths
+#[derive(Clone, Debug)]
+pub struct CorsPolicy {
+    /// origins allowed to access the resources. Entries are wildcard patterns (e.g.
+    /// `https://*.example.com`), `*` allows every origin unless `credentials` are allowed
+    pub origins: Vec<String>,
+
+    /// methods allowed in cross-origin requests. `None` allows the methods the server answers on
+    /// the requested path
+    pub methods: Option<Vec<HTTPMethod>>,
+
+    /// request headers allowed in cross-origin requests. `*` allows every header
+    pub headers: Vec<String>,
+
+    /// whether credentials (cookies, authorization headers) may be sent
+    pub credentials: bool,
+
+    /// time in seconds browsers may cache the result of a preflight request
+    pub max_age: Option<usize>,
+
+    /// response headers scripts on other origins may read
+    pub expose_headers: Vec<String>,
+}
+
+impl CorsPolicy {
+    /// create a new policy allowing `origins` to send simple requests
+    pub fn new(origins: Vec<String>) -> CorsPolicy {
+        CorsPolicy {
+            origins,
+            methods: None,
+            headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+            expose_headers: Vec::new(),
+        }
+    }
+
+    /// create a policy from a config entry. Unknown method names are ignored
+    pub fn from_config(conf: &CorsConfig) -> CorsPolicy {
+        CorsPolicy {
+            origins: conf.origins.clone(),
+            methods: conf.methods.as_ref().map(|methods| {
+                methods
+                    .iter()
+                    .filter_map(|n| HTTPMethod::from_string(n.to_uppercase()))
+                    .collect()
+            }),
+            headers: conf.headers.clone().unwrap_or_default(),
+            credentials: conf.credentials.unwrap_or(false),
+            max_age: conf.max_age,
+            expose_headers: conf.expose_headers.clone().unwrap_or_default(),
+        }
+    }
+
+    /// check whether `origin` may access the resources. `*` is ignored if credentials are
+    /// allowed, it would let every site make requests with the user's cookies
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        self.origins
+            .iter()
+            .filter(|n| !(self.credentials && n.as_str() == "*"))
+            .any(|n| WildMatch::new(n.as_str()).matches(origin))
+    }
+
+    /// get the value of `Access-Control-Allow-Origin` for a request from `origin`. Credentialed
+    /// requests don't accept `*` so the matched origin is echoed in that case
+    fn allow_origin(&self, origin: &str) -> String {
+        if !self.credentials && self.origins.iter().any(|n| n == "*") {
+            String::from("*")
+        } else {
+            origin.to_string()
+        }
+    }
+
+    /// create the response to a preflight request from `origin`. `allowed` are the methods the
+    /// server answers on the requested path
+    ///
+    /// # Returns
+    /// `None` if the origin, the requested method or one of the requested headers is not allowed
+    pub fn preflight(
+        &self,
+        origin: &str,
+        method: &HTTPMethod,
+        headers: &[String],
+        allowed: &[HTTPMethod],
+    ) -> Option<HTTPResponse> {
+        if !self.allows_origin(origin) {
+            return None;
+        }
+        let methods: Vec<HTTPMethod> = match &self.methods {
+            Some(n) => n.iter().filter(|m| allowed.contains(m)).cloned().collect(),
+            None => allowed.to_vec(),
+        };
+        if !methods.contains(method) {
+            return None;
+        }
+        let any_header = self.headers.iter().any(|n| n == "*");
+        let allowed_headers = if any_header {
+            headers.to_vec()
+        } else {
+            if !headers
+                .iter()
+                .all(|n| self.headers.iter().any(|m| m.eq_ignore_ascii_case(n)))
+            {
+                return None;
+            }
+            self.headers.clone()
+        };
+
+        let mut resp = HTTPResponse::new(
+            StatusCode::NoContent,
+            vec![
+                HTTPResponseHeaders::Server(RSWEB_SERVER_STR.to_string()),
+                HTTPResponseHeaders::ContentLength(0),
+                HTTPResponseHeaders::AccessControlAllowMethods(methods),
+            ],
+            Body::new(String::new()),
+        );
+        if !allowed_headers.is_empty() {
+            resp.add_header(HTTPResponseHeaders::AccessControlAllowHeaders(
+                allowed_headers,
+            ));
+        }
+        if let Some(n) = self.max_age {
+            resp.add_header(HTTPResponseHeaders::AccessControlMaxAge(n));
+        }
+        self.apply(origin, &mut resp);
+        Some(resp)
+    }
+
+    /// add the CORS headers for a request from `origin` to `resp`
+    pub fn apply(&self, origin: &str, resp: &mut HTTPResponse) {
+        resp.add_header(HTTPResponseHeaders::Vary(String::from("Origin")));
+        if !self.allows_origin(origin) {
+            return;
+        }
+        resp.add_header(HTTPResponseHeaders::AccessControlAllowOrigin(
+            self.allow_origin(origin),
+        ));
+        if self.credentials {
+            resp.add_header(HTTPResponseHeaders::AccessControlAllowCredentials(true));
+        }
+        if !self.expose_headers.is_empty() {
+            resp.add_header(HTTPResponseHeaders::AccessControlExposeHeaders(
+                self.expose_headers.clone(),
+            ));
+        }
+    }
+}
+
+/// CORS policies for paths matching wildcard patterns
+///
+/// Can be used by the static servers (see the `cors` config setting) and as middleware for
+/// [`crate::server::FuncServer`] and [`crate::ssl::SSLFuncServer`].
+#[derive(Clone, Debug, Default)]
+pub struct Cors {
+    routes: Vec<(String, CorsPolicy)>,
+}
+
+impl Cors {
+    /// create a new set of policies without any routes. No CORS headers are sent
+    pub fn new() -> Cors {
+        Cors { routes: Vec::new() }
+    }
+
+    /// create the policies from the `cors` setting of a config section
+    pub fn from_config(conf: Option<&Vec<CorsConfig>>) -> Cors {
+        let mut cors = Cors::new();
+        for n in conf.into_iter().flatten() {
+            cors.route(n.path.clone(), CorsPolicy::from_config(n));
+        }
+        cors
+    }
+
+    /// use `policy` for all paths matching `pattern`. The first matching pattern wins
+    pub fn route(&mut self, pattern: String, policy: CorsPolicy) {
+        self.routes.push((pattern, policy));
+    }
+
+    /// get the policy for `path`
+    pub fn lookup(&self, path: &str) -> Option<&CorsPolicy> {
+        self.routes
+            .iter()
+            .find(|(pattern, _)| WildMatch::new(pattern.as_str()).matches(path))
+            .map(|(_, policy)| policy)
+    }
+
+    /// answer `req` if it is a preflight request for a path with a CORS policy. `allowed` are the
+    /// methods the server answers on the requested path
+    pub fn preflight(&self, req: &HTTPRequest, allowed: &[HTTPMethod]) -> Option<HTTPResponse> {
+        if req.get_method() != HTTPMethod::Options {
+            return None;
+        }
+        let policy = self.lookup(req.get_path().as_str())?;
+        let mut origin = None;
+        let mut method = None;
+        let mut headers = Vec::new();
+        for header in req.get_header() {
+            match header {
+                HTTPRequestHeaders::Origin(n) => origin = Some(n),
+                HTTPRequestHeaders::AccessControlRequestMethod(n) => method = Some(n),
+                HTTPRequestHeaders::AccessControlRequestHeaders(n) => headers = n,
+                _ => (),
+            }
+        }
+        let (origin, method) = origin.zip(method)?;
+        match policy.preflight(&origin, &method, &headers, allowed) {
+            Some(n) => Some(n),
+            // reject the preflight without CORS headers so the browser blocks the request
+            None => Some(HTTPResponse::new(
+                StatusCode::NoContent,
+                vec![
+                    HTTPResponseHeaders::Server(RSWEB_SERVER_STR.to_string()),
+                    HTTPResponseHeaders::ContentLength(0),
+                    HTTPResponseHeaders::Vary(String::from("Origin")),
+                ],
+                Body::new(String::new()),
+            )),
+        }
+    }
+
+    /// add the CORS headers for `req` to `resp` if the requested path has a CORS policy
+    pub fn apply(&self, req: &HTTPRequest, resp: &mut HTTPResponse) {
+        let policy = match self.lookup(req.get_path().as_str()) {
+            Some(n) => n,
+            None => return,
+        };
+        let origin = req.get_header().into_iter().find_map(|n| match n {
+            HTTPRequestHeaders::Origin(n) => Some(n),
+            _ => None,
+        });
+        match origin {
+            Some(origin) => policy.apply(&origin, resp),
+            None => resp.add_header(HTTPResponseHeaders::Vary(String::from("Origin"))),
+        }
+    }
+
+    /// answer `req` using `func` and add the CORS headers. Preflight requests are answered
+    /// without calling `func`, allowing all methods that aren't restricted by the policy
+    pub fn handle<F>(&self, req: HTTPRequest, func: F) -> HTTPResponse
+    where
+        F: FnOnce(HTTPRequest) -> HTTPResponse,
+    {
+        if let Some(resp) = self.preflight(&req, &crate::methods::ALL_METHODS) {
+            return resp;
+        }
+        let mut resp = func(req.clone());
+        self.apply(&req, &mut resp);
+        resp
+    }
+}
@@ -1,21 +1,46 @@
 use crate::http::header::HTTPRequestHeaders;
 use crate::http::request::HTTPRequest;
-use std::io::Read;
+use crate::limits::{LimitExceeded, Limits};
+use crate::timeout::{map_timeout_error, Deadline, SetTimeout, Timeouts};
+use std::io::{ErrorKind, Read};
+use std::time::Duration;
 
+/// maximum amount of bytes requested from a reader with a single `read` call
+const CHUNK_SIZE: usize = 8192;
+
+/// A buffer that reads from streams in chunks.
+///
+/// Bytes that were read past the end of the current message (e.g. a pipelined request) are kept
+/// and used as the start of the next message that gets read.
 pub struct DBuffer {
     buffer: Vec<u8>,
+    /// length of the current message
+    len: usize,
+    /// length of the current message including its terminator
+    consumed: usize,
+    /// length of the head of the current HTTP request
+    head_len: usize,
+}
+
+impl Default for DBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DBuffer {
     /// create a new DBuffer
     pub fn new() -> DBuffer {
-        DBuffer { buffer: Vec::new() }
+        DBuffer::with_capacity(0)
     }
 
     /// create a new DBuffer with `cap` as its capacity
     pub fn with_capacity(cap: usize) -> DBuffer {
         DBuffer {
             buffer: Vec::with_capacity(cap),
+            len: 0,
+            consumed: 0,
+            head_len: 0,
         }
     }
 
@@ -24,26 +49,22 @@ impl DBuffer {
     /// # Returns
     /// This function returns a result that if Ok holds the amount of bytes read
     pub fn read_until_zero<T: Read>(&mut self, r: &mut T) -> std::io::Result<usize> {
-        let mut tmp_buf = [0u8];
-        let mut counter: usize = 0;
+        self.discard();
+        let mut r = NoTimeout(r);
+        let mut scanned: usize = 0;
         loop {
-            match r.read(&mut tmp_buf) {
-                Ok(n) => {
-                    if n != 1 {
-                        break;
-                    }
-                    if tmp_buf[0] == 0 {
-                        break;
-                    }
-                    self.buffer.push(tmp_buf[0]);
-                    counter += 1;
-                }
-                Err(e) => {
-                    return Err(e);
-                }
+            if let Some(pos) = self.buffer[scanned..].iter().position(|n| *n == 0) {
+                self.len = scanned + pos;
+                self.consumed = self.len + 1;
+                return Ok(self.len);
+            }
+            scanned = self.buffer.len();
+            if self.fill(&mut r, CHUNK_SIZE, &mut None)? == 0 {
+                self.len = self.buffer.len();
+                self.consumed = self.len;
+                return Ok(self.len);
             }
         }
-        Ok(counter)
     }
 
     /// read a HTTP request from `r`
@@ -51,49 +72,126 @@ impl DBuffer {
     /// # Returns
     /// This function returns a result that if Ok holds the amount of bytes read
     pub fn read_http_request<T: Read>(&mut self, r: &mut T) -> std::io::Result<usize> {
-        let mut tmp_buf = [0u8];
-        let mut header_size: usize = 0;
-        let mut crlf_counter: u8 = 0;
-        let mut cr: bool = false;
-        loop {
-            match r.read(&mut tmp_buf) {
-                Ok(n) => {
-                    if n != 1 {
-                        break;
-                    }
-                    if tmp_buf[0] == 0xD {
-                        cr = true;
-                    } else if cr && tmp_buf[0] == 0xA {
-                        crlf_counter += 1;
-                    } else {
-                        crlf_counter = 0;
-                        cr = false;
-                    }
-                    self.buffer.push(tmp_buf[0]);
-                    header_size += 1;
-                    if crlf_counter == 2 {
-                        break;
-                    }
-                }
-                Err(e) => {
-                    return Err(e);
-                }
+        self.read_request(&mut NoTimeout(r), None, &Limits::none())
+    }
+
+    /// read a HTTP request from `r` and enforce `timeouts` while doing so
+    ///
+    /// # Returns
+    /// This function returns a result that if Ok holds the amount of bytes read. If the client
+    /// doesn't send the request in time an error of kind [`std::io::ErrorKind::TimedOut`] is
+    /// returned
+    pub fn read_http_request_timeout<T: Read + SetTimeout>(
+        &mut self,
+        r: &mut T,
+        timeouts: &Timeouts,
+    ) -> std::io::Result<usize> {
+        self.read_http_request_limited(r, timeouts, &Limits::none())
+    }
+
+    /// read a HTTP request from `r` and enforce `timeouts` and `limits` while doing so. Reading
+    /// stops as soon as a limit is exceeded so no more than the limit gets buffered
+    ///
+    /// # Returns
+    /// This function returns a result that if Ok holds the amount of bytes read. If the client
+    /// doesn't send the request in time an error of kind [`std::io::ErrorKind::TimedOut`] is
+    /// returned. If a limit is exceeded the error wraps a [`LimitExceeded`] (see
+    /// [`crate::limits::limit_exceeded`])
+    pub fn read_http_request_limited<T: Read + SetTimeout>(
+        &mut self,
+        r: &mut T,
+        timeouts: &Timeouts,
+        limits: &Limits,
+    ) -> std::io::Result<usize> {
+        let mut deadline = Deadline::new(timeouts);
+        let res = self.read_request(r, Some(&mut deadline), limits);
+        let _ = r.set_read_timeout(None);
+        res
+    }
+
+    /// drop the current message and move the bytes read past it to the front of the buffer
+    fn discard(&mut self) {
+        self.buffer.drain(..self.consumed);
+        self.len = 0;
+        self.consumed = 0;
+        self.head_len = 0;
+    }
+
+    /// read up to `max` bytes from `r` with a single `read` call and append them to the buffer.
+    /// If `deadline` is given the read timeout of `r` gets adjusted before reading
+    ///
+    /// # Returns
+    /// the amount of bytes read. `0` means the reader hit EOF
+    fn fill<T: Read + SetTimeout>(
+        &mut self,
+        r: &mut T,
+        max: usize,
+        deadline: &mut Option<&mut Deadline>,
+    ) -> std::io::Result<usize> {
+        if let Some(deadline) = deadline {
+            deadline.apply(r)?;
+        }
+        let start = self.buffer.len();
+        self.buffer.resize(start + max, 0);
+        let n = match r.read(&mut self.buffer[start..]) {
+            Ok(n) => n,
+            Err(e) => {
+                self.buffer.truncate(start);
+                return Err(map_timeout_error(e));
             }
+        };
+        self.buffer.truncate(start + n);
+        if let Some(deadline) = deadline {
+            deadline.record(n);
         }
-        let string = match self.to_string() {
+        Ok(n)
+    }
+
+    fn read_request<T: Read + SetTimeout>(
+        &mut self,
+        r: &mut T,
+        mut deadline: Option<&mut Deadline>,
+        limits: &Limits,
+    ) -> std::io::Result<usize> {
+        self.discard();
+        let mut parser = HeadParser::new(limits);
+        // request line, header fields and their line breaks
+        let max_head = limits
+            .request_line
+            .zip(limits.header_size)
+            .map(|(line, header)| line + header + 4);
+        let mut scanned: usize = 0;
+        let head_len = loop {
+            if let Some(n) = parser.feed(&self.buffer[scanned..])? {
+                break scanned + n;
+            }
+            scanned = self.buffer.len();
+            let want = match max_head {
+                Some(n) => CHUNK_SIZE.min(n + 1 - scanned.min(n)),
+                None => CHUNK_SIZE,
+            };
+            if self.fill(r, want, &mut deadline)? == 0 {
+                break self.buffer.len();
+            }
+        };
+        self.head_len = head_len;
+        self.len = head_len;
+        self.consumed = head_len;
+
+        let head = match std::str::from_utf8(self.head()) {
             Ok(n) => n,
             Err(_) => {
                 return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
+                    ErrorKind::InvalidData,
                     "failed to convert bytes to string",
                 ));
             }
         };
-        let request = match HTTPRequest::from_string(string) {
+        let request = match HTTPRequest::from_string(head.to_string()) {
             Ok(n) => n,
             Err(_) => {
                 return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
+                    ErrorKind::InvalidData,
                     "failed to parse header of HTTP request",
                 ));
             }
@@ -107,31 +205,147 @@ impl DBuffer {
             }
         }
 
-        if length == 0 {
-            return Ok(header_size);
-        } else {
-            let mut bytes_read: usize = 0;
-            let mut buf = [0u8];
-            while bytes_read < length {
-                match r.read(&mut buf) {
-                    Ok(n) => {
-                        if n != 1 {
-                            break;
-                        }
-                        self.buffer.push(buf[0]);
-                        bytes_read += 1;
-                    }
-                    Err(_) => {
-                        break;
-                    }
-                }
+        if limits
+            .body_limit(request.get_path().as_str())
+            .is_some_and(|n| length > n)
+        {
+            return Err(LimitExceeded::Body.into());
+        }
+
+        let end = head_len + length;
+        if self.buffer.len() < end {
+            if let Some(deadline) = &mut deadline {
+                deadline.start_body();
             }
-            return Ok(header_size + bytes_read);
         }
+        while self.buffer.len() < end {
+            let want = CHUNK_SIZE.min(end - self.buffer.len());
+            // a truncated body must not be mistaken for a complete one
+            if self.fill(r, want, &mut deadline)? == 0 {
+                return Err(std::io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "connection closed before the body was complete",
+                ));
+            }
+        }
+        self.len = end.min(self.buffer.len());
+        self.consumed = self.len;
+        Ok(self.len)
+    }
+
+    /// get the current message as bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+
+    /// get the head (request line and header fields) of the current HTTP request
+    pub fn head(&self) -> &[u8] {
+        &self.buffer[..self.head_len]
+    }
+
+    /// get the body of the current HTTP request
+    pub fn body(&self) -> &[u8] {
+        &self.buffer[self.head_len..self.len]
     }
 
-    /// try to convert the internal buffer to a string
+    /// get the bytes that were read past the current message. These are used as the start of the
+    /// next message that gets read
+    pub fn leftover(&self) -> &[u8] {
+        &self.buffer[self.consumed..]
+    }
+
+    /// try to convert the current message to a string
     pub fn to_string(&self) -> Result<String, std::string::FromUtf8Error> {
-        String::from_utf8(self.buffer.clone())
+        String::from_utf8(self.as_bytes().to_vec())
+    }
+}
+
+/// incremental parser finding the end of the head of a HTTP request while enforcing size limits
+struct HeadParser<'a> {
+    limits: &'a Limits,
+    size: usize,
+    cr: bool,
+    crlf_counter: u8,
+    request_line_size: usize,
+    fields: usize,
+    line_length: usize,
+}
+
+impl HeadParser<'_> {
+    fn new(limits: &Limits) -> HeadParser<'_> {
+        HeadParser {
+            limits,
+            size: 0,
+            cr: false,
+            crlf_counter: 0,
+            request_line_size: 0,
+            fields: 0,
+            line_length: 0,
+        }
+    }
+
+    /// feed the next bytes of the request to the parser
+    ///
+    /// # Returns
+    /// the position in `bytes` right after the head if it is complete
+    fn feed(&mut self, bytes: &[u8]) -> Result<Option<usize>, LimitExceeded> {
+        for (i, byte) in bytes.iter().enumerate() {
+            if *byte == 0xD {
+                self.cr = true;
+            } else if self.cr && *byte == 0xA {
+                self.crlf_counter += 1;
+                if self.request_line_size == 0 {
+                    self.request_line_size = self.size + 1;
+                } else if self.line_length > 0 {
+                    self.fields += 1;
+                }
+                self.line_length = 0;
+            } else {
+                self.crlf_counter = 0;
+                self.cr = false;
+                self.line_length += 1;
+            }
+            if self.request_line_size == 0 {
+                if self
+                    .limits
+                    .request_line
+                    .is_some_and(|n| self.line_length > n)
+                {
+                    return Err(LimitExceeded::RequestLine);
+                }
+            } else if self.limits.header_count.is_some_and(|n| self.fields > n) {
+                return Err(LimitExceeded::HeaderCount);
+            } else if self
+                .limits
+                .header_size
+                .is_some_and(|n| self.size + 1 - self.request_line_size > n)
+            {
+                return Err(LimitExceeded::HeaderSize);
+            }
+            self.size += 1;
+            if self.crlf_counter == 2 {
+                return Ok(Some(i + 1));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// wrapper for readers that have no notion of timeouts
+struct NoTimeout<'a, T: Read>(&'a mut T);
+
+impl<T: Read> Read for NoTimeout<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<T: Read> SetTimeout for NoTimeout<'_, T> {
+    fn set_read_timeout(&self, _dur: Option<Duration>) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _dur: Option<Duration>) -> std::io::Result<()> {
+        Ok(())
     }
 }
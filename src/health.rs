@@ -0,0 +1,332 @@
+use crate::config::HealthConfig;
+use crate::http::body::Body;
+use crate::http::header::HTTPResponseHeaders;
+use crate::http::request::HTTPRequest;
+use crate::http::response::HTTPResponse;
+use crate::http::{MimeType, StatusCode};
+use crate::json::JsonValue;
+use crate::log::Logger;
+use crate::metrics::Metrics;
+use crate::server::{read_only_response, serve_admin};
+use crate::tls::ReloadableAcceptor;
+use crate::tp::PoolStats;
+use crate::RSWEB_SERVER_STR;
+use openssl::asn1::Asn1Time;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+/// path liveness is reported on by default
+pub const LIVENESS_PATH: &str = "/healthz";
+
+/// path readiness is reported on by default
+pub const READINESS_PATH: &str = "/readyz";
+
+/// the result of a readiness check
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Check {
+    /// e.g. `http_listener`
+    pub name: String,
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: String, ok: bool, detail: String) -> Check {
+        Check { name, ok, detail }
+    }
+}
+
+/// what the servers registered to be checked for readiness
+#[derive(Default)]
+struct Components {
+    /// servers by name and the address they are bound to once they are
+    listeners: Vec<(String, Option<String>)>,
+    roots: Vec<(String, String)>,
+    pools: Vec<(String, PoolStats)>,
+    tls: Vec<(String, Arc<ReloadableAcceptor>)>,
+}
+
+/// liveness and readiness of the servers, reported as JSON on `/healthz` and `/readyz`. Clones
+/// share their state, so one instance can check several servers
+#[derive(Clone)]
+pub struct Health {
+    components: Arc<Mutex<Components>>,
+    started: Instant,
+    liveness_path: String,
+    readiness_path: String,
+    listen: Option<String>,
+    max_queued: Option<usize>,
+}
+
+impl Default for Health {
+    fn default() -> Health {
+        Health::new()
+    }
+}
+
+/// create a response with `json` as body
+fn json_response(status: StatusCode, json: JsonValue) -> HTTPResponse {
+    let body = Body::new(json.to_string());
+    HTTPResponse::new(
+        status,
+        vec![
+            HTTPResponseHeaders::Server(RSWEB_SERVER_STR.to_string()),
+            HTTPResponseHeaders::ContentType(MimeType::Other(String::from("application/json"))),
+            HTTPResponseHeaders::ContentLength(body.len()),
+        ],
+        body,
+    )
+}
+
+impl Health {
+    /// create health endpoints on [`LIVENESS_PATH`] and [`READINESS_PATH`] of the servers using
+    /// them
+    pub fn new() -> Health {
+        Health {
+            components: Arc::new(Mutex::new(Components::default())),
+            started: Instant::now(),
+            liveness_path: String::from(LIVENESS_PATH),
+            readiness_path: String::from(READINESS_PATH),
+            listen: None,
+            max_queued: None,
+        }
+    }
+
+    /// create health endpoints as configured in the `health` section
+    pub fn from_config(conf: &HealthConfig) -> Health {
+        let mut health = Health::new();
+        if let Some(n) = &conf.liveness_path {
+            health.liveness_path = n.clone();
+        }
+        if let Some(n) = &conf.readiness_path {
+            health.readiness_path = n.clone();
+        }
+        health.set_listen(conf.listen.clone());
+        health.set_max_queued(conf.max_queued);
+        health
+    }
+
+    /// set the paths liveness and readiness are reported on
+    pub fn set_paths(&mut self, liveness: &str, readiness: &str) {
+        self.liveness_path = liveness.to_string();
+        self.readiness_path = readiness.to_string();
+    }
+
+    /// report health on a separate admin address (e.g. `127.0.0.1:9101`, see [`Health::serve`])
+    /// instead of the servers using it. `None` reports it on the servers
+    pub fn set_listen(&mut self, listen: Option<String>) {
+        self.listen = listen;
+    }
+
+    /// get the address of the separate admin listener, if any
+    pub fn listen(&self) -> Option<&str> {
+        self.listen.as_deref()
+    }
+
+    /// set how many connections may wait for a worker before a server isn't ready anymore.
+    /// `None` allows as many as the thread pool has workers
+    pub fn set_max_queued(&mut self, max_queued: Option<usize>) {
+        self.max_queued = max_queued;
+    }
+
+    fn components(&self) -> MutexGuard<'_, Components> {
+        match self.components.lock() {
+            Ok(n) => n,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    /// check the listener of `server` (e.g. `http`). It isn't ready until
+    /// [`Health::listener_bound`] is called
+    pub fn register_listener(&self, server: &str) {
+        self.components().listeners.push((server.to_string(), None));
+    }
+
+    /// check `count` listeners of `server`, replacing the ones registered before. The checks
+    /// are named `http_listener`, `http_listener_2` and so on
+    pub fn register_listeners(&self, server: &str, count: usize) {
+        let mut components = self.components();
+        components.listeners.retain(|(name, _)| name != server);
+        for _ in 0..count {
+            components.listeners.push((server.to_string(), None));
+        }
+    }
+
+    /// mark the next listener of `server` that isn't bound yet as bound to `addr`
+    pub fn listener_bound(&self, server: &str, addr: &str) {
+        for (name, bound) in self.components().listeners.iter_mut() {
+            if name == server && bound.is_none() {
+                *bound = Some(addr.to_string());
+                return;
+            }
+        }
+    }
+
+    /// check that the resource root of `server` is readable
+    pub fn register_root(&self, server: &str, root: &str) {
+        self.components()
+            .roots
+            .push((server.to_string(), root.to_string()));
+    }
+
+    /// check that the thread pool of `server` isn't saturated
+    pub fn register_pool(&self, server: &str, stats: PoolStats) {
+        self.components().pools.push((server.to_string(), stats));
+    }
+
+    /// check that `server` has a valid certificate loaded
+    pub fn register_tls(&self, server: &str, acceptor: Arc<ReloadableAcceptor>) {
+        self.components().tls.push((server.to_string(), acceptor));
+    }
+
+    /// run all readiness checks
+    pub fn checks(&self) -> Vec<Check> {
+        let components = self.components();
+        let mut checks = Vec::new();
+        for (i, (server, bound)) in components.listeners.iter().enumerate() {
+            let index = components.listeners[..i]
+                .iter()
+                .filter(|(name, _)| name == server)
+                .count();
+            let name = match index {
+                0 => format!("{}_listener", server),
+                n => format!("{}_listener_{}", server, n + 1),
+            };
+            checks.push(match bound {
+                Some(addr) => Check::new(name, true, format!("bound to {}", addr)),
+                None => Check::new(name, false, String::from("not bound")),
+            });
+        }
+        for (server, root) in &components.roots {
+            checks.push(match std::fs::read_dir(root) {
+                Ok(_) => Check::new(
+                    format!("{}_resource_root", server),
+                    true,
+                    format!("{} is readable", root),
+                ),
+                Err(e) => Check::new(
+                    format!("{}_resource_root", server),
+                    false,
+                    format!("{}: {}", root, e),
+                ),
+            });
+        }
+        for (server, stats) in &components.pools {
+            let max = self.max_queued.unwrap_or_else(|| stats.size());
+            checks.push(Check::new(
+                format!("{}_thread_pool", server),
+                stats.queued() <= max,
+                format!(
+                    "{} of {} workers busy, {} connections queued",
+                    stats.busy(),
+                    stats.size(),
+                    stats.queued()
+                ),
+            ));
+        }
+        for (server, acceptor) in &components.tls {
+            let acceptor = acceptor.acceptor();
+            let name = format!("{}_tls", server);
+            checks.push(match acceptor.context().certificate() {
+                Some(cert) => {
+                    let valid = match Asn1Time::days_from_now(0) {
+                        Ok(now) => cert.not_after() > now,
+                        Err(_) => false,
+                    };
+                    let detail = match valid {
+                        true => format!("certificate valid until {}", cert.not_after()),
+                        false => format!("certificate expired on {}", cert.not_after()),
+                    };
+                    Check::new(name, valid, detail)
+                }
+                None => Check::new(name, false, String::from("no certificate loaded")),
+            });
+        }
+        checks
+    }
+
+    /// create the response reporting liveness. The process is alive as long as it can answer
+    pub fn liveness(&self) -> HTTPResponse {
+        json_response(
+            StatusCode::Ok,
+            JsonValue::Object(vec![
+                (String::from("status"), JsonValue::from("ok")),
+                (
+                    String::from("uptime_seconds"),
+                    JsonValue::Number(self.started.elapsed().as_secs() as f64),
+                ),
+            ]),
+        )
+    }
+
+    /// create the response reporting readiness: `200 OK` if all checks pass, otherwise
+    /// `503 Service Unavailable`
+    pub fn readiness(&self) -> HTTPResponse {
+        let checks = self.checks();
+        let ready = checks.iter().all(|n| n.ok);
+        let checks = checks
+            .into_iter()
+            .map(|n| {
+                (
+                    n.name,
+                    JsonValue::Object(vec![
+                        (String::from("ok"), JsonValue::from(n.ok)),
+                        (String::from("detail"), JsonValue::from(n.detail)),
+                    ]),
+                )
+            })
+            .collect();
+        let (status, text) = match ready {
+            true => (StatusCode::Ok, "ready"),
+            false => (StatusCode::ServiceUnavailable, "not ready"),
+        };
+        json_response(
+            status,
+            JsonValue::Object(vec![
+                (String::from("status"), JsonValue::from(text)),
+                (String::from("checks"), JsonValue::Object(checks)),
+            ]),
+        )
+    }
+
+    /// answer `req` if it asks for liveness or readiness
+    pub(crate) fn answer(&self, req: &HTTPRequest) -> Option<HTTPResponse> {
+        let path = req.get_path();
+        if path == self.liveness_path {
+            Some(read_only_response(&req.get_method(), || self.liveness()))
+        } else if path == self.readiness_path {
+            Some(read_only_response(&req.get_method(), || self.readiness()))
+        } else {
+            None
+        }
+    }
+
+    /// answer `req` if it asks for liveness or readiness and they are reported by the servers
+    /// instead of a separate admin listener. Routes and aliases don't apply to these paths
+    pub fn handle(&self, req: &HTTPRequest) -> Option<HTTPResponse> {
+        match self.listen {
+            Some(_) => None,
+            None => self.answer(req),
+        }
+    }
+
+    /// report health on the admin address set with [`Health::set_listen`] in a background
+    /// thread. If `metrics` are given they are served on the same address. Does nothing if no
+    /// address is set
+    pub fn serve(&self, logger: Logger, metrics: Option<Metrics>) -> Result<(), std::io::Error> {
+        let addr = match &self.listen {
+            Some(n) => n.clone(),
+            None => return Ok(()),
+        };
+        let health = self.clone();
+        let name = match metrics {
+            Some(_) => "health checks and metrics",
+            None => "health checks",
+        };
+        serve_admin(addr.as_str(), name, logger, move |req| {
+            health
+                .answer(req)
+                .or_else(|| metrics.as_ref().and_then(|n| n.answer(req)))
+        })
+    }
+}
@@ -48,6 +48,16 @@ impl Body {
         self.content.clone()
     }
 
+    /// get the size of the (possibly encoded) content in bytes
+    pub fn len(&self) -> usize {
+        self.content.len()
+    }
+
+    /// check whether the body has no content
+    pub fn is_empty(&self) -> bool {
+        self.content.is_empty()
+    }
+
     /// try converting the raw bytes content to a string
     pub fn try_to_string(&self) -> Result<String, std::string::FromUtf8Error> {
         String::from_utf8(self.content.clone())
@@ -56,9 +66,10 @@ impl Body {
     /// decode the content and put it in memory.
     ///
     /// **WARNING**: encoded content might be a large file. Use this function with caution
+    #[allow(clippy::unused_io_amount)]
     pub fn decode_into_memory(&self) -> Result<Vec<u8>, Error> {
         if self.encoding.is_none() {
-            return Ok(self.content.clone());
+            Ok(self.content.clone())
         } else {
             match self.encoding.clone().unwrap() {
                 ContentEncodingMethod::Gzip => {
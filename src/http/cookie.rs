@@ -19,8 +19,16 @@ pub enum CookieAttributes {
 }
 
 impl CookieAttributes {
+    /// convert a cookie attribute to the string used in Set-Cookie headers
+    #[allow(clippy::inherent_to_string_shadow_display)]
     pub fn to_string(&self) -> String {
-        match self {
+        format!("{}", self)
+    }
+}
+
+impl std::fmt::Display for CookieAttributes {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let string = match self {
             CookieAttributes::Secure => String::from("Secure"),
             CookieAttributes::HTTPOnly => String::from("HttpOnly"),
             CookieAttributes::Path(n) => format!("Path={}", n),
@@ -32,7 +40,8 @@ impl CookieAttributes {
             },
             CookieAttributes::MaxAge(n) => format!("Max-Age={}", n),
             CookieAttributes::Expires(n) => format!("Expires={}", n),
-        }
+        };
+        write!(f, "{}", string)
     }
 }
 
@@ -71,14 +80,8 @@ impl Cookie {
             }
         };
         let mut other_parts = string.split("; ");
-        let left = match other_parts.next() {
-            Some(n) => n,
-            None => return None,
-        };
-        let value = match left.split('=').nth(1) {
-            Some(n) => n,
-            None => return None,
-        };
+        let left = other_parts.next()?;
+        let value = left.split('=').nth(1)?;
         let mut attributes: Vec<CookieAttributes> = Vec::new();
         for i in string.split("; ").skip(1) {
             if i.contains('=') {
@@ -126,14 +129,21 @@ impl Cookie {
     }
 
     /// convert a cookie to a string that can be sent in responses or requests
+    #[allow(clippy::inherent_to_string_shadow_display)]
     pub fn to_string(&self) -> String {
-        let mut out: String = format!("{}={}", self.name, self.value);
+        format!("{}", self)
+    }
+}
+
+impl std::fmt::Display for Cookie {
+    /// convert a cookie to a string that can be sent in responses or requests
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "{}={}", self.name, self.value)?;
         if let Some(n) = &self.attributes {
             for attr in n {
-                out.push_str(format!("; {}", attr.to_string()).as_str());
+                write!(f, "; {}", attr)?;
             }
         }
-
-        out
+        Ok(())
     }
 }
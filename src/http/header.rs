@@ -1,4 +1,5 @@
 use super::cookie::Cookie;
+use super::request::HTTPMethod;
 use super::MimeType;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -28,13 +29,22 @@ impl ContentEncodingMethod {
         }
     }
 
+    /// convert a content encoding method to its header value
+    #[allow(clippy::inherent_to_string_shadow_display)]
     pub fn to_string(&self) -> String {
-        match self {
+        format!("{}", self)
+    }
+}
+
+impl std::fmt::Display for ContentEncodingMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let string = match self {
             ContentEncodingMethod::Gzip => String::from("gzip"),
             ContentEncodingMethod::Compress => String::from("compress"),
             ContentEncodingMethod::Deflate => String::from("deflate"),
             ContentEncodingMethod::Br => String::from("br"),
-        }
+        };
+        write!(f, "{}", string)
     }
 }
 
@@ -48,70 +58,148 @@ pub enum HTTPResponseHeaders {
     SetCookie(Cookie),
     Location(String),
     AccessControlAllowOrigin(String),
+    Connection(String),
+    Allow(Vec<HTTPMethod>),
+    AccessControlAllowMethods(Vec<HTTPMethod>),
+    AccessControlAllowHeaders(Vec<String>),
+    AccessControlAllowCredentials(bool),
+    AccessControlMaxAge(usize),
+    AccessControlExposeHeaders(Vec<String>),
+    Vary(String),
+    StrictTransportSecurity(String),
+    RequestId(String),
+    /// any other header by name and value
+    Other(String, String),
+}
+
+/// split a comma separated header value into its trimmed, non-empty items
+fn list_from_string(string: &str) -> Vec<String> {
+    string
+        .split(',')
+        .map(|n| n.trim())
+        .filter(|n| !n.is_empty())
+        .map(|n| n.to_string())
+        .collect()
+}
+
+/// join `items` to a comma separated header value
+fn list_to_string<T: std::fmt::Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|n| n.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
 }
 
 impl HTTPResponseHeaders {
     /// read the header from a string
     pub fn from_string(string: String) -> Option<HTTPResponseHeaders> {
-        let mut parts = string.split(':').map(|x| x.trim_end().trim_start());
-        let left: &str = match parts.next() {
-            Some(n) => n,
-            None => return None,
-        };
-        let right: &str = match parts.next() {
-            Some(n) => n,
-            None => return None,
-        };
+        let mut parts = string.splitn(2, ':').map(|x| x.trim_end().trim_start());
+        let left: &str = parts.next()?;
+        let right: &str = parts.next()?;
         match left {
             "Content-Type" => {
-                if let Some(n) = MimeType::from_string(right.to_string()) {
-                    Some(HTTPResponseHeaders::ContentType(n))
-                } else {
-                    None
-                }
+                MimeType::from_string(right.to_string()).map(HTTPResponseHeaders::ContentType)
             }
             "Content-Length" => {
-                if let Err(_) = right.parse::<usize>() {
+                if right.parse::<usize>().is_err() {
                     None
                 } else {
                     Some(HTTPResponseHeaders::ContentLength(right.parse().unwrap()))
                 }
             }
-            "Content-Encoding" => {
-                if let Some(n) = ContentEncodingMethod::from_string(right.to_string()) {
-                    Some(HTTPResponseHeaders::ContentEncoding(n))
-                } else {
-                    None
-                }
-            }
+            "Content-Encoding" => ContentEncodingMethod::from_string(right.to_string())
+                .map(HTTPResponseHeaders::ContentEncoding),
             "Server" => Some(HTTPResponseHeaders::Server(right.to_string())),
-            "Set-Cookie" => match Cookie::from_string(right.to_string()) {
-                Some(n) => Some(HTTPResponseHeaders::SetCookie(n)),
-                None => None,
-            },
+            "Set-Cookie" => {
+                Cookie::from_string(right.to_string()).map(HTTPResponseHeaders::SetCookie)
+            }
             "Location" => Some(HTTPResponseHeaders::Location(right.to_string())),
             "Access-Control-Allow-Origin" => Some(HTTPResponseHeaders::AccessControlAllowOrigin(
                 right.to_string(),
             )),
+            "Connection" => Some(HTTPResponseHeaders::Connection(right.to_string())),
+            "Allow" => Some(HTTPResponseHeaders::Allow(
+                list_from_string(right)
+                    .into_iter()
+                    .filter_map(HTTPMethod::from_string)
+                    .collect(),
+            )),
+            "Access-Control-Allow-Methods" => Some(HTTPResponseHeaders::AccessControlAllowMethods(
+                list_from_string(right)
+                    .into_iter()
+                    .filter_map(HTTPMethod::from_string)
+                    .collect(),
+            )),
+            "Access-Control-Allow-Headers" => Some(HTTPResponseHeaders::AccessControlAllowHeaders(
+                list_from_string(right),
+            )),
+            "Access-Control-Allow-Credentials" => Some(
+                HTTPResponseHeaders::AccessControlAllowCredentials(right == "true"),
+            ),
+            "Access-Control-Max-Age" => right
+                .parse::<usize>()
+                .ok()
+                .map(HTTPResponseHeaders::AccessControlMaxAge),
+            "Access-Control-Expose-Headers" => Some(
+                HTTPResponseHeaders::AccessControlExposeHeaders(list_from_string(right)),
+            ),
+            "Vary" => Some(HTTPResponseHeaders::Vary(right.to_string())),
+            "Strict-Transport-Security" => Some(HTTPResponseHeaders::StrictTransportSecurity(
+                right.to_string(),
+            )),
+            "X-Request-Id" => Some(HTTPResponseHeaders::RequestId(right.to_string())),
             _ => None,
         }
     }
 
     /// convert a HTTPResponseHeaders instance to a string
+    #[allow(clippy::inherent_to_string_shadow_display)]
     pub fn to_string(&self) -> String {
-        match self {
-            HTTPResponseHeaders::ContentType(n) => format!("Content-Type: {}", n.to_string()),
+        format!("{}", self)
+    }
+}
+
+impl std::fmt::Display for HTTPResponseHeaders {
+    /// convert a HTTPResponseHeaders instance to a string
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let string = match self {
+            HTTPResponseHeaders::ContentType(n) => format!("Content-Type: {}", n),
             HTTPResponseHeaders::ContentLength(n) => format!("Content-Length: {}", n),
             HTTPResponseHeaders::ContentEncoding(n) => {
-                format!("Content-Encoding: {}", n.to_string())
+                format!("Content-Encoding: {}", n)
             }
             HTTPResponseHeaders::Server(n) => format!("Server: {}", n),
-            HTTPResponseHeaders::SetCookie(n) => format!("Set-Cookie: {}", n.to_string()),
+            HTTPResponseHeaders::SetCookie(n) => format!("Set-Cookie: {}", n),
             HTTPResponseHeaders::Location(n) => format!("Location: {}", n),
             HTTPResponseHeaders::AccessControlAllowOrigin(n) => {
                 format!("Access-Control-Allow-Origin: {}", n)
             }
-        }
+            HTTPResponseHeaders::Connection(n) => format!("Connection: {}", n),
+            HTTPResponseHeaders::Allow(n) => format!("Allow: {}", list_to_string(n)),
+            HTTPResponseHeaders::AccessControlAllowMethods(n) => {
+                format!("Access-Control-Allow-Methods: {}", list_to_string(n))
+            }
+            HTTPResponseHeaders::AccessControlAllowHeaders(n) => {
+                format!("Access-Control-Allow-Headers: {}", list_to_string(n))
+            }
+            HTTPResponseHeaders::AccessControlAllowCredentials(n) => {
+                format!("Access-Control-Allow-Credentials: {}", n)
+            }
+            HTTPResponseHeaders::AccessControlMaxAge(n) => {
+                format!("Access-Control-Max-Age: {}", n)
+            }
+            HTTPResponseHeaders::AccessControlExposeHeaders(n) => {
+                format!("Access-Control-Expose-Headers: {}", list_to_string(n))
+            }
+            HTTPResponseHeaders::Vary(n) => format!("Vary: {}", n),
+            HTTPResponseHeaders::StrictTransportSecurity(n) => {
+                format!("Strict-Transport-Security: {}", n)
+            }
+            HTTPResponseHeaders::RequestId(n) => format!("X-Request-Id: {}", n),
+            HTTPResponseHeaders::Other(name, value) => format!("{}: {}", name, value),
+        };
+        write!(f, "{}", string)
     }
 }
 
@@ -124,11 +212,15 @@ pub enum HTTPRequestHeaders {
     UserAgent(String),
     Cookie(Vec<Cookie>),
     Host(String),
+    Origin(String),
+    AccessControlRequestMethod(HTTPMethod),
+    AccessControlRequestHeaders(Vec<String>),
+    RequestId(String),
 }
 
 impl HTTPRequestHeaders {
     pub fn from_string(string: String) -> Option<HTTPRequestHeaders> {
-        let mut parts = string.split(':');
+        let mut parts = string.splitn(2, ':');
         let key = match parts.next() {
             Some(n) => n,
             None => {
@@ -143,29 +235,22 @@ impl HTTPRequestHeaders {
         };
         let value = value.trim_end();
         let value = value.trim_start();
-        match key {
-            "Content-Length" => {
-                if let Err(_) = value.parse::<usize>() {
+        // header names are case-insensitive
+        match key.to_ascii_lowercase().as_str() {
+            "content-length" => {
+                if value.parse::<usize>().is_err() {
                     return None;
                 }
                 Some(HTTPRequestHeaders::ContentLength(value.parse().unwrap()))
             }
-            "Content-Type" => {
-                if let Some(m) = MimeType::from_string(value.to_string()) {
-                    return Some(HTTPRequestHeaders::ContentType(m));
-                } else {
-                    return None;
-                }
+            "content-type" => {
+                MimeType::from_string(value.to_string()).map(HTTPRequestHeaders::ContentType)
             }
-            "Accept" => {
-                if let Some(m) = MimeType::from_string(value.to_string()) {
-                    return Some(HTTPRequestHeaders::ContentType(m));
-                } else {
-                    return None;
-                }
+            "accept" => {
+                MimeType::from_string(value.to_string()).map(HTTPRequestHeaders::ContentType)
             }
-            "User-Agent" => Some(HTTPRequestHeaders::UserAgent(value.to_string())),
-            "Cookie" => {
+            "user-agent" => Some(HTTPRequestHeaders::UserAgent(value.to_string())),
+            "cookie" => {
                 let parts: Vec<String> = value.split("; ").map(|x| x.to_string()).collect();
                 let mut cookies: Vec<Cookie> = Vec::new();
                 for part in parts {
@@ -175,7 +260,14 @@ impl HTTPRequestHeaders {
                 }
                 Some(HTTPRequestHeaders::Cookie(cookies))
             }
-            "Host" => Some(HTTPRequestHeaders::Host(value.to_string())),
+            "host" => Some(HTTPRequestHeaders::Host(value.to_string())),
+            "origin" => Some(HTTPRequestHeaders::Origin(value.to_string())),
+            "access-control-request-method" => HTTPMethod::from_string(value.to_string())
+                .map(HTTPRequestHeaders::AccessControlRequestMethod),
+            "access-control-request-headers" => Some(
+                HTTPRequestHeaders::AccessControlRequestHeaders(list_from_string(value)),
+            ),
+            "x-request-id" => Some(HTTPRequestHeaders::RequestId(value.to_string())),
             _ => None,
         }
     }
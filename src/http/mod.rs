@@ -10,21 +10,100 @@ pub mod response;
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum StatusCode {
     Ok,
+    NoContent,
+    BadRequest,
+    Forbidden,
+    MethodNotAllowed,
     NotFound,
     InternalServerError,
     MovedPermanently,
+    Found,
+    SeeOther,
+    TemporaryRedirect,
+    PermanentRedirect,
+    RequestTimeout,
+    PayloadTooLarge,
+    URITooLong,
+    RequestHeaderFieldsTooLarge,
+    ServiceUnavailable,
 }
 
 impl StatusCode {
-    /// convert an HTTP status code to a string
-    pub fn to_string(&self) -> String {
+    /// get the numeric status code
+    pub fn code(&self) -> u16 {
         match self {
-            StatusCode::Ok => String::from("HTTP/1.1 200 OK"),
-            StatusCode::NotFound => String::from("HTTP/1.1 404 NOT FOUND"),
-            StatusCode::InternalServerError => String::from("HTTP/1.1 500 INTERNAL SERVER ERROR"),
-            StatusCode::MovedPermanently => String::from("HTTP/1.1 301 MOVED PERMANENTLY"),
+            StatusCode::Ok => 200,
+            StatusCode::NoContent => 204,
+            StatusCode::MovedPermanently => 301,
+            StatusCode::Found => 302,
+            StatusCode::SeeOther => 303,
+            StatusCode::TemporaryRedirect => 307,
+            StatusCode::PermanentRedirect => 308,
+            StatusCode::BadRequest => 400,
+            StatusCode::Forbidden => 403,
+            StatusCode::NotFound => 404,
+            StatusCode::MethodNotAllowed => 405,
+            StatusCode::RequestTimeout => 408,
+            StatusCode::PayloadTooLarge => 413,
+            StatusCode::URITooLong => 414,
+            StatusCode::RequestHeaderFieldsTooLarge => 431,
+            StatusCode::InternalServerError => 500,
+            StatusCode::ServiceUnavailable => 503,
+        }
+    }
+
+    /// get the redirect status with the numeric code `code`
+    pub fn redirect(code: u16) -> Option<StatusCode> {
+        match code {
+            301 => Some(StatusCode::MovedPermanently),
+            302 => Some(StatusCode::Found),
+            303 => Some(StatusCode::SeeOther),
+            307 => Some(StatusCode::TemporaryRedirect),
+            308 => Some(StatusCode::PermanentRedirect),
+            _ => None,
         }
     }
+
+    /// get the reason phrase of the status code
+    pub fn reason(&self) -> &'static str {
+        match self {
+            StatusCode::Ok => "OK",
+            StatusCode::NoContent => "No Content",
+            StatusCode::MovedPermanently => "Moved Permanently",
+            StatusCode::Found => "Found",
+            StatusCode::SeeOther => "See Other",
+            StatusCode::TemporaryRedirect => "Temporary Redirect",
+            StatusCode::PermanentRedirect => "Permanent Redirect",
+            StatusCode::BadRequest => "Bad Request",
+            StatusCode::Forbidden => "Forbidden",
+            StatusCode::NotFound => "Not Found",
+            StatusCode::MethodNotAllowed => "Method Not Allowed",
+            StatusCode::RequestTimeout => "Request Timeout",
+            StatusCode::PayloadTooLarge => "Payload Too Large",
+            StatusCode::URITooLong => "URI Too Long",
+            StatusCode::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            StatusCode::InternalServerError => "Internal Server Error",
+            StatusCode::ServiceUnavailable => "Service Unavailable",
+        }
+    }
+
+    /// convert an HTTP status code to a string
+    #[allow(clippy::inherent_to_string_shadow_display)]
+    pub fn to_string(&self) -> String {
+        format!("{}", self)
+    }
+}
+
+impl std::fmt::Display for StatusCode {
+    /// convert an HTTP status code to a status line
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "HTTP/1.1 {} {}",
+            self.code(),
+            self.reason().to_uppercase()
+        )
+    }
 }
 
 /// enum for supported mime types
@@ -59,14 +138,11 @@ impl MimeType {
             n => {
                 if n.contains("*") {
                     let mut parts = n.split('/');
-                    let l = parts.next();
-                    let r = parts.next();
-                    if l == None || r == None {
-                        None
-                    } else {
-                        let l = l.unwrap();
-                        let r = r.unwrap();
-                        Some(MimeType::Wildcard(l.to_string(), r.to_string()))
+                    match (parts.next(), parts.next()) {
+                        (Some(l), Some(r)) => {
+                            Some(MimeType::Wildcard(l.to_string(), r.to_string()))
+                        }
+                        _ => None,
                     }
                 } else if n.contains('/') {
                     Some(MimeType::Other(n.to_string()))
@@ -78,8 +154,16 @@ impl MimeType {
     }
 
     /// stringify a mime type
+    #[allow(clippy::inherent_to_string_shadow_display)]
     pub fn to_string(&self) -> String {
-        match self {
+        format!("{}", self)
+    }
+}
+
+impl std::fmt::Display for MimeType {
+    /// stringify a mime type
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let string = match self {
             MimeType::Html => String::from("text/html"),
             MimeType::Javascript => String::from("text/javascript"),
             MimeType::Css => String::from("text/css"),
@@ -91,7 +175,8 @@ impl MimeType {
             MimeType::WWWFormUrlencoded => String::from("application/x-www-form-urlencoded"),
             MimeType::Other(n) => n.to_string(),
             MimeType::Wildcard(l, r) => format!("{}/{}", l, r),
-        }
+        };
+        write!(f, "{}", string)
     }
 }
 
@@ -1,4 +1,6 @@
 use super::header::HTTPRequestHeaders;
+use crate::listener::PeerCredentials;
+use crate::mtls::PeerIdentity;
 
 #[derive(Debug)]
 pub struct HTTPRequestParsingError;
@@ -23,6 +25,28 @@ pub enum HTTPMethod {
     Options,
     Trace,
     Patch,
+    /// a method the server doesn't know (e.g. WebDAV's `PROPFIND`), kept so the request can be
+    /// answered with `405 Method Not Allowed`
+    Other(String),
+}
+
+impl HTTPMethod {
+    /// parse a method from its name. Returns `None` for unknown methods, requests with them use
+    /// [`HTTPMethod::Other`]
+    pub fn from_string(string: String) -> Option<HTTPMethod> {
+        match string.as_str() {
+            "GET" => Some(HTTPMethod::Get),
+            "POST" => Some(HTTPMethod::Post),
+            "HEAD" => Some(HTTPMethod::Head),
+            "DELETE" => Some(HTTPMethod::Delete),
+            "PUT" => Some(HTTPMethod::Put),
+            "CONNECT" => Some(HTTPMethod::Connect),
+            "OPTIONS" => Some(HTTPMethod::Options),
+            "TRACE" => Some(HTTPMethod::Trace),
+            "PATCH" => Some(HTTPMethod::Patch),
+            _ => None,
+        }
+    }
 }
 
 impl std::fmt::Display for HTTPMethod {
@@ -37,11 +61,20 @@ impl std::fmt::Display for HTTPMethod {
             HTTPMethod::Options => String::from("OPTIONS"),
             HTTPMethod::Trace => String::from("TRACE"),
             HTTPMethod::Patch => String::from("PATCH"),
+            HTTPMethod::Other(n) => n.clone(),
         };
         write!(f, "{}", string)
     }
 }
 
+/// check whether `string` is a token (RFC 9110), the syntax of method names
+fn is_token(string: &str) -> bool {
+    !string.is_empty()
+        && string
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+}
+
 /// An HTTP request
 #[derive(Clone, Debug)]
 pub struct HTTPRequest {
@@ -49,6 +82,9 @@ pub struct HTTPRequest {
     path: String,
     header: Vec<HTTPRequestHeaders>,
     body: Option<String>,
+    peer: Option<PeerIdentity>,
+    credentials: Option<PeerCredentials>,
+    id: Option<String>,
 }
 
 impl HTTPRequest {
@@ -60,11 +96,18 @@ impl HTTPRequest {
         body: Option<String>,
     ) -> HTTPRequest {
         // TODO: proper reading of header from arguments
+        let id = header.iter().find_map(|n| match n {
+            HTTPRequestHeaders::RequestId(n) => Some(n.clone()),
+            _ => None,
+        });
         HTTPRequest {
             method,
             path,
             body,
             header,
+            peer: None,
+            credentials: None,
+            id,
         }
     }
 
@@ -90,17 +133,10 @@ impl HTTPRequest {
             }
         };
 
-        let method: HTTPMethod = match method {
-            "GET" => HTTPMethod::Get,
-            "POST" => HTTPMethod::Post,
-            "HEAD" => HTTPMethod::Head,
-            "DELETE" => HTTPMethod::Delete,
-            "PUT" => HTTPMethod::Put,
-            "CONNECT" => HTTPMethod::Connect,
-            "OPTIONS" => HTTPMethod::Options,
-            "TRACE" => HTTPMethod::Trace,
-            "PATCH" => HTTPMethod::Patch,
-            _ => {
+        let method: HTTPMethod = match HTTPMethod::from_string(method.to_string()) {
+            Some(n) => n,
+            None if is_token(method) => HTTPMethod::Other(method.to_string()),
+            None => {
                 return Err(HTTPRequestParsingError);
             }
         };
@@ -110,10 +146,7 @@ impl HTTPRequest {
                 out_headers.push(n);
             }
         }
-        let body: Option<String> = match path.split("\r\n\r\n").nth(1) {
-            Some(n) => Some(n.to_string()),
-            None => None,
-        };
+        let body: Option<String> = path.split("\r\n\r\n").nth(1).map(|n| n.to_string());
         Ok(HTTPRequest::new(
             method,
             path.to_string(),
@@ -137,8 +170,47 @@ impl HTTPRequest {
         self.header.clone()
     }
 
+    /// get the value of the `Host` header
+    pub fn get_host(&self) -> Option<String> {
+        self.header.iter().find_map(|n| match n {
+            HTTPRequestHeaders::Host(n) => Some(n.clone()),
+            _ => None,
+        })
+    }
+
     /// get the body
     pub fn get_body(&self) -> Option<String> {
         self.body.clone()
     }
+
+    /// get the identity of the client if it presented a verified TLS client certificate
+    pub fn get_peer_identity(&self) -> Option<PeerIdentity> {
+        self.peer.clone()
+    }
+
+    /// set the identity of the client
+    pub fn set_peer_identity(&mut self, peer: Option<PeerIdentity>) {
+        self.peer = peer;
+    }
+
+    /// get the user, group and process id of the client if it connected through a unix socket
+    pub fn get_peer_credentials(&self) -> Option<PeerCredentials> {
+        self.credentials
+    }
+
+    /// set the credentials of the client process
+    pub fn set_peer_credentials(&mut self, credentials: Option<PeerCredentials>) {
+        self.credentials = credentials;
+    }
+
+    /// get the id of the request. The servers set it to the `X-Request-Id` sent by the client or
+    /// a generated id before the request is handled
+    pub fn get_request_id(&self) -> Option<String> {
+        self.id.clone()
+    }
+
+    /// set the id of the request
+    pub fn set_request_id(&mut self, id: Option<String>) {
+        self.id = id;
+    }
 }
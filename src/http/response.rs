@@ -18,19 +18,34 @@ impl HTTPResponse {
         }
     }
 
+    /// get the status of the response
+    pub fn get_status(&self) -> StatusCode {
+        self.status.clone()
+    }
+
+    /// get the headers of the response
+    pub fn get_header(&self) -> Vec<HTTPResponseHeaders> {
+        self.header.clone()
+    }
+
+    /// get the body of the response
+    pub fn get_body(&self) -> &Body {
+        &self.body
+    }
+
+    /// add a header to the response
+    pub fn add_header(&mut self, header: HTTPResponseHeaders) {
+        self.header.push(header);
+    }
+
     /// try to convert a response to a string
     pub fn try_to_string(&self) -> Result<String, std::string::FromUtf8Error> {
         let mut header = String::new();
         for i in &self.header {
-            header.push_str(format!("{}\r\n", i.to_string()).as_str());
+            header.push_str(format!("{}\r\n", i).as_str());
         }
         match self.body.try_to_string() {
-            Ok(n) => Ok(format!(
-                "{}\r\n{}\r\n{}",
-                self.status.to_string(),
-                header,
-                n
-            )),
+            Ok(n) => Ok(format!("{}\r\n{}\r\n{}", self.status, header, n)),
             Err(e) => Err(e),
         }
     }
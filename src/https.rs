@@ -0,0 +1,146 @@
+use crate::config::{HstsConfig, HttpsRedirectConfig};
+use crate::http::body::Body;
+use crate::http::header::HTTPResponseHeaders;
+use crate::http::request::HTTPRequest;
+use crate::http::response::HTTPResponse;
+use crate::http::MimeType;
+use crate::http::StatusCode;
+use crate::RSWEB_SERVER_STR;
+use wildmatch::WildMatch;
+
+/// paths that are served over plain HTTP by default. ACME HTTP-01 challenges have to be answered
+/// without TLS
+pub const DEFAULT_REDIRECT_EXCLUDE: &str = "/.well-known/acme-challenge/*";
+
+/// redirects plain HTTP requests to the same host and path on an HTTPS port
+#[derive(Clone, Debug)]
+pub struct HttpsRedirect {
+    /// the HTTPS port to redirect to
+    pub port: usize,
+
+    /// `MovedPermanently` (301) or `PermanentRedirect` (308, keeps the request method and body)
+    pub status: StatusCode,
+
+    /// wildcard patterns of paths that are served normally instead of being redirected
+    pub exclude: Vec<String>,
+
+    /// host to redirect to if the request has no `Host` header
+    pub host: Option<String>,
+}
+
+impl HttpsRedirect {
+    /// create a redirect to `port` using `301 Moved Permanently` that excludes ACME challenges
+    pub fn new(port: usize) -> HttpsRedirect {
+        HttpsRedirect {
+            port,
+            status: StatusCode::MovedPermanently,
+            exclude: vec![DEFAULT_REDIRECT_EXCLUDE.to_string()],
+            host: None,
+        }
+    }
+
+    /// create a redirect from the `https_redirect` setting of the `http` config section.
+    /// `https_port` is used if the setting doesn't name a port
+    pub fn from_config(conf: &HttpsRedirectConfig, https_port: Option<usize>) -> HttpsRedirect {
+        let mut redirect = HttpsRedirect::new(conf.port.or(https_port).unwrap_or(443));
+        if conf.status == Some(308) {
+            redirect.status = StatusCode::PermanentRedirect;
+        }
+        if let Some(n) = &conf.exclude {
+            redirect.exclude = n.clone();
+        }
+        redirect.host = conf.host.clone();
+        redirect
+    }
+
+    /// get the location `req` gets redirected to. `fallback_host` is used if neither the request
+    /// nor the redirect names a host
+    pub fn location(&self, req: &HTTPRequest, fallback_host: &str) -> String {
+        let host = req
+            .get_host()
+            .or_else(|| self.host.clone())
+            .unwrap_or_else(|| fallback_host.to_string());
+        // remove the port of the plain HTTP listener (IPv6 addresses are in brackets)
+        let host = match host.rfind(':') {
+            Some(n) if !host[n..].contains(']') => &host[..n],
+            _ => host.as_str(),
+        };
+        if self.port == 443 {
+            format!("https://{}{}", host, req.get_path())
+        } else {
+            format!("https://{}:{}{}", host, self.port, req.get_path())
+        }
+    }
+
+    /// create the redirect for `req`
+    ///
+    /// # Returns
+    /// `None` if the requested path is excluded from redirection
+    pub fn response(&self, req: &HTTPRequest, fallback_host: &str) -> Option<HTTPResponse> {
+        let path = req.get_path();
+        if self
+            .exclude
+            .iter()
+            .any(|n| WildMatch::new(n.as_str()).matches(path.as_str()))
+        {
+            return None;
+        }
+        let location = self.location(req, fallback_host);
+        let body = Body::new(format!("<a href=\"{}\">{}</a>", location, location));
+        Some(HTTPResponse::new(
+            self.status.clone(),
+            vec![
+                HTTPResponseHeaders::Server(RSWEB_SERVER_STR.to_string()),
+                HTTPResponseHeaders::Location(location),
+                HTTPResponseHeaders::ContentType(MimeType::Html),
+                HTTPResponseHeaders::ContentLength(body.get_bytes().len()),
+            ],
+            body,
+        ))
+    }
+}
+
+/// HTTP Strict Transport Security: tells browsers to only use HTTPS for the host
+#[derive(Clone, Debug)]
+pub struct Hsts {
+    /// seconds browsers remember to only use HTTPS
+    pub max_age: u64,
+
+    /// whether the policy covers all subdomains too
+    pub include_subdomains: bool,
+
+    /// whether the host may be included in the preload lists of browsers
+    pub preload: bool,
+}
+
+impl Hsts {
+    /// create a policy that lasts `max_age` seconds
+    pub fn new(max_age: u64) -> Hsts {
+        Hsts {
+            max_age,
+            include_subdomains: false,
+            preload: false,
+        }
+    }
+
+    /// create a policy from the `hsts` setting of the `ssl` config section
+    pub fn from_config(conf: &HstsConfig) -> Hsts {
+        Hsts {
+            max_age: conf.max_age,
+            include_subdomains: conf.include_subdomains.unwrap_or(false),
+            preload: conf.preload.unwrap_or(false),
+        }
+    }
+
+    /// get the `Strict-Transport-Security` header
+    pub fn header(&self) -> HTTPResponseHeaders {
+        let mut value = format!("max-age={}", self.max_age);
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            value.push_str("; preload");
+        }
+        HTTPResponseHeaders::StrictTransportSecurity(value)
+    }
+}
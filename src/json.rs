@@ -0,0 +1,297 @@
+use crate::error::ServerError;
+use std::fmt;
+
+/// a JSON value
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    /// members in the order they appear in the document
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    /// parse a JSON document
+    pub fn parse(string: &str) -> Result<JsonValue, ServerError> {
+        let mut parser = Parser {
+            chars: string.chars().collect(),
+            pos: 0,
+        };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.pos != parser.chars.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    /// get the member `key` of an object
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(n) => n.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(n) => Some(n.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<JsonValue>> {
+        match self {
+            JsonValue::Array(n) => Some(n),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for JsonValue {
+    fn from(string: &str) -> JsonValue {
+        JsonValue::String(string.to_string())
+    }
+}
+
+impl From<String> for JsonValue {
+    fn from(string: String) -> JsonValue {
+        JsonValue::String(string)
+    }
+}
+
+impl From<bool> for JsonValue {
+    fn from(b: bool) -> JsonValue {
+        JsonValue::Bool(b)
+    }
+}
+
+impl From<usize> for JsonValue {
+    fn from(n: usize) -> JsonValue {
+        JsonValue::Number(n as f64)
+    }
+}
+
+/// escape `string` for use inside a JSON string literal
+pub fn escape(string: &str) -> String {
+    let mut out = String::with_capacity(string.len());
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(format!("\\u{:04x}", c as u32).as_str()),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+impl fmt::Display for JsonValue {
+    /// write the value as compact JSON
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JsonValue::Null => write!(f, "null"),
+            JsonValue::Bool(n) => write!(f, "{}", n),
+            JsonValue::Number(n) if n.is_finite() => write!(f, "{}", n),
+            JsonValue::Number(_) => write!(f, "null"),
+            JsonValue::String(n) => write!(f, "\"{}\"", escape(n)),
+            JsonValue::Array(n) => {
+                write!(f, "[")?;
+                for (i, v) in n.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, "]")
+            }
+            JsonValue::Object(n) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in n.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "\"{}\":{}", escape(k), v)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error(&self, msg: &str) -> ServerError {
+        ServerError::new(format!("invalid JSON at offset {}: {}", self.pos, msg).as_str())
+    }
+
+    fn whitespace(&mut self) {
+        while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ServerError> {
+        self.whitespace();
+        if self.peek() != Some(c) {
+            return Err(self.error(format!("expected '{}'", c).as_str()));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str, value: JsonValue) -> Result<JsonValue, ServerError> {
+        let end = self.pos + word.len();
+        if end <= self.chars.len() && self.chars[self.pos..end].iter().copied().eq(word.chars()) {
+            self.pos = end;
+            Ok(value)
+        } else {
+            Err(self.error("unexpected token"))
+        }
+    }
+
+    fn value(&mut self) -> Result<JsonValue, ServerError> {
+        self.whitespace();
+        match self.peek() {
+            Some('n') => self.literal("null", JsonValue::Null),
+            Some('t') => self.literal("true", JsonValue::Bool(true)),
+            Some('f') => self.literal("false", JsonValue::Bool(false)),
+            Some('"') => Ok(JsonValue::String(self.string()?)),
+            Some('[') => {
+                self.pos += 1;
+                let mut values = Vec::new();
+                self.whitespace();
+                if self.peek() == Some(']') {
+                    self.pos += 1;
+                    return Ok(JsonValue::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    self.whitespace();
+                    match self.peek() {
+                        Some(',') => self.pos += 1,
+                        Some(']') => {
+                            self.pos += 1;
+                            return Ok(JsonValue::Array(values));
+                        }
+                        _ => return Err(self.error("expected ',' or ']'")),
+                    }
+                }
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut members = Vec::new();
+                self.whitespace();
+                if self.peek() == Some('}') {
+                    self.pos += 1;
+                    return Ok(JsonValue::Object(members));
+                }
+                loop {
+                    self.whitespace();
+                    let key = self.string()?;
+                    self.expect(':')?;
+                    members.push((key, self.value()?));
+                    self.whitespace();
+                    match self.peek() {
+                        Some(',') => self.pos += 1,
+                        Some('}') => {
+                            self.pos += 1;
+                            return Ok(JsonValue::Object(members));
+                        }
+                        _ => return Err(self.error("expected ',' or '}'")),
+                    }
+                }
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let start = self.pos;
+                while let Some(c) = self.peek() {
+                    if c.is_ascii_digit() || "+-.eE".contains(c) {
+                        self.pos += 1;
+                    } else {
+                        break;
+                    }
+                }
+                let number: String = self.chars[start..self.pos].iter().collect();
+                match number.parse::<f64>() {
+                    Ok(n) => Ok(JsonValue::Number(n)),
+                    Err(_) => Err(self.error("invalid number")),
+                }
+            }
+            _ => Err(self.error("unexpected token")),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, ServerError> {
+        if self.pos + 4 > self.chars.len() {
+            return Err(self.error("invalid escape"));
+        }
+        let hex: String = self.chars[self.pos..self.pos + 4].iter().collect();
+        self.pos += 4;
+        match u32::from_str_radix(hex.as_str(), 16) {
+            Ok(n) => Ok(n),
+            Err(_) => Err(self.error("invalid escape")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ServerError> {
+        if self.peek() != Some('"') {
+            return Err(self.error("expected string"));
+        }
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return Err(self.error("unterminated string")),
+            };
+            self.pos += 1;
+            match c {
+                '"' => return Ok(out),
+                '\\' => {
+                    let c = match self.peek() {
+                        Some(c) => c,
+                        None => return Err(self.error("unterminated string")),
+                    };
+                    self.pos += 1;
+                    match c {
+                        'n' => out.push('\n'),
+                        'r' => out.push('\r'),
+                        't' => out.push('\t'),
+                        'b' => out.push('\u{8}'),
+                        'f' => out.push('\u{c}'),
+                        'u' => {
+                            let mut code = self.hex4()?;
+                            // surrogate pair
+                            if (0xd800..0xdc00).contains(&code)
+                                && self.chars.get(self.pos) == Some(&'\\')
+                                && self.chars.get(self.pos + 1) == Some(&'u')
+                            {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                code =
+                                    0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00));
+                            }
+                            out.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                        }
+                        c => out.push(c),
+                    }
+                }
+                c => out.push(c),
+            }
+        }
+    }
+}
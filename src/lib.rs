@@ -8,7 +8,7 @@
 //! use rsweb::server::Server;
 //! use rsweb::config::Config;
 //!
-//! let conf = Config {http: None, ssl: None}; // just a config so this example works. In reality you would load a config
+//! let conf = Config {http: None, ssl: None, log: None, metrics: None, health: None, vhost: None}; // just a config so this example works. In reality you would load a config
 //! let mut server = Server::new(
 //!     10, // number of threads
 //!     ResourceLoader::new(10, ".".to_string(), true), // create a new resource loader with capacity 10
@@ -27,7 +27,7 @@
 //! use rsweb::ssl::SSLServer;
 //! use rsweb::config::Config;
 //!
-//! let conf = Config {http: None, ssl: None}; // just a config so this example works.
+//! let conf = Config {http: None, ssl: None, log: None, metrics: None, health: None, vhost: None}; // just a config so this example works.
 //!
 //! let mut server = SSLServer::new(
 //!     10, // number of threads
@@ -41,6 +41,15 @@
 //! );
 //! ```
 
+/// access log of answered requests in Common, Combined, JSON or custom formats
+pub mod access;
+
+/// automatic certificates from ACME CAs (e.g. Let's Encrypt)
+pub mod acme;
+
+/// generation of certificates for local development
+pub mod certgen;
+
 /// the cli of `rsweb`
 pub mod cli;
 
@@ -50,28 +59,77 @@ pub mod config;
 /// a dynamic buffer implementation used to read from streams
 pub mod dbuffer;
 
+/// cross-origin resource sharing (CORS) policies
+pub mod cors;
+
 /// errors for `rsweb`
 pub mod error;
 pub mod http;
 
+/// redirects from HTTP to HTTPS and HTTP Strict Transport Security
+pub mod https;
+
+/// minimal JSON encoding and decoding
+pub mod json;
+
+/// size limits for requests
+pub mod limits;
+
+/// listen addresses with socket options and TLS, and the connections accepted on them
+pub mod listener;
+
 /// logging functions for `rsweb`
 pub mod log;
 
+/// log files with size and time based rotation
+pub mod logfile;
+
+/// Prometheus metrics
+pub mod metrics;
+
+/// allowlists for HTTP methods
+pub mod methods;
+
+/// mutual TLS (client certificate verification)
+pub mod mtls;
+
 /// resource handler and cache storage
 pub mod resource;
 
 /// router for requests
 pub mod route;
 
+/// ids to correlate requests with their log messages
+pub mod request_id;
+
+/// liveness and readiness endpoints
+pub mod health;
+
 /// basic HTTP server implementation
 pub mod server;
 
 /// basic HTTPS server implementation
 pub mod ssl;
 
-/// Threadpool implementation included in the logs produced by [`log`]
+/// syslog and systemd journal log outputs
+#[cfg(unix)]
+pub mod syslog;
+
+/// read/write timeouts for client connections
+pub mod timeout;
+
+/// TLS setup (certificates, SNI and protocol settings)
+pub mod tls;
+
+/// Threadpool implementation used by the servers
 pub mod tp;
 
+/// validation of the config with errors and warnings located by line and column
+pub mod validate;
+
+/// virtual hosts selected by the `Host` header
+pub mod vhost;
+
 pub use tp::ThreadPool;
 /// version str of rsweb. Used for logging and CLI
 pub const RSWEB_VERSION: &str = "0.8.10";
@@ -94,6 +152,283 @@ mod tests {
 	}
     }
 
+    mod log {
+        use crate::json::JsonValue;
+        use crate::log::{Filter, Level, LogFormat, Logger};
+        use crate::{debug, error, info, msg, trace, warn};
+
+        #[test]
+        fn filter() {
+            let filter =
+                Filter::from_string("warn, rsweb::tls=debug,rsweb::tls::ocsp=error").unwrap();
+            assert!(filter.enabled(Level::Warn, "rsweb::server"));
+            assert!(!filter.enabled(Level::Info, "rsweb::server"));
+            assert!(filter.enabled(Level::Debug, "rsweb::tls"));
+            assert!(!filter.enabled(Level::Trace, "rsweb::tls"));
+            assert!(!filter.enabled(Level::Warn, "rsweb::tls::ocsp"));
+            // only whole module names match
+            assert!(!filter.enabled(Level::Debug, "rsweb::tlsx"));
+            assert_eq!(Filter::from_string("").unwrap(), Filter::default());
+            assert!(Filter::from_string("info,rsweb=loud").is_err());
+        }
+
+        #[test]
+        fn shared_logger() {
+            let path = std::env::temp_dir().join(format!("rsweb-log-{}.log", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let logger = Logger::new();
+            logger.set_logfile(path.to_str().unwrap()).unwrap();
+            let mut filter = Filter::new(Level::Info);
+            filter.module(module_path!(), Level::Debug);
+            logger.set_filter(filter);
+
+            let handle = logger.clone();
+            std::thread::spawn(move || warn!(handle, "from {}", "a thread"))
+                .join()
+                .unwrap();
+            trace!(logger, "hidden");
+            debug!(logger, "shown");
+            info!(logger, "info");
+            msg!(logger, "message {}", 1);
+            error!(logger, "error");
+            logger.log(Level::Debug, "other::module", "hidden");
+            logger.flush();
+
+            let content = std::fs::read_to_string(&path).unwrap();
+            let lines: Vec<&str> = content
+                .lines()
+                .map(|n| n.split_once("]: ").unwrap().1)
+                .collect();
+            assert_eq!(
+                lines,
+                vec![
+                    "Warning: from a thread",
+                    "Debug: rsweb::tests::log: shown",
+                    "info",
+                    "message 1",
+                    "Error: error",
+                ]
+            );
+            let _ = std::fs::remove_file(&path);
+        }
+
+        #[test]
+        fn json() {
+            let path = std::env::temp_dir().join(format!("rsweb-json-{}.log", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let logger = Logger::new();
+            logger.set_logfile(path.to_str().unwrap()).unwrap();
+            logger.set_file_format(LogFormat::Json);
+
+            let handle = logger.clone();
+            std::thread::Builder::new()
+                .name(String::from("worker"))
+                .spawn(move || {
+                    info!(handle, {"request_id": "abc", "status": 200, "level": "x"}, "answered")
+                })
+                .unwrap()
+                .join()
+                .unwrap();
+            warn!(logger, "line\nbreak");
+            logger.flush();
+
+            let content = std::fs::read_to_string(&path).unwrap();
+            let lines: Vec<JsonValue> = content
+                .lines()
+                .map(|n| JsonValue::parse(n).unwrap())
+                .collect();
+            assert_eq!(lines.len(), 2);
+            let first = &lines[0];
+            assert!(chrono::DateTime::parse_from_rfc3339(
+                first.get("timestamp").unwrap().as_str().unwrap()
+            )
+            .is_ok());
+            assert_eq!(first.get("level").unwrap().as_str(), Some("info"));
+            assert_eq!(first.get("message").unwrap().as_str(), Some("answered"));
+            assert_eq!(first.get("thread").unwrap().as_str(), Some("worker"));
+            assert_eq!(first.get("request_id").unwrap().as_str(), Some("abc"));
+            assert_eq!(first.get("status").unwrap().as_str(), Some("200"));
+            assert_eq!(lines[1].get("level").unwrap().as_str(), Some("warn"));
+            assert_eq!(
+                lines[1].get("message").unwrap().as_str(),
+                Some("line\nbreak")
+            );
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+
+    mod logfile {
+        use crate::logfile::{request_reopen, rotated_files, LogFile, RotateInterval, Rotation};
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+        use std::path::PathBuf;
+        use std::time::{Duration, SystemTime};
+
+        fn temp_dir(name: &str) -> PathBuf {
+            let dir = std::env::temp_dir().join(format!("rsweb-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            dir
+        }
+
+        fn gunzip(path: &PathBuf) -> String {
+            let mut content = String::new();
+            GzDecoder::new(std::fs::File::open(path).unwrap())
+                .read_to_string(&mut content)
+                .unwrap();
+            content
+        }
+
+        #[test]
+        fn size_rotation() {
+            let dir = temp_dir("rotate-size");
+            let path = dir.join("latest.log");
+            let rotation = Rotation {
+                max_size: Some(10),
+                compress: true,
+                keep: Some(2),
+                ..Rotation::default()
+            };
+            let mut file = LogFile::open(path.to_str().unwrap(), rotation).unwrap();
+            file.write(b"line 1\n").unwrap();
+            // rotates before exceeding the size
+            file.write(b"line 2\n").unwrap();
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), "line 2\n");
+            file.rotate().unwrap();
+            file.rotate().unwrap();
+            file.write(b"line 3\n").unwrap();
+            // wait for the background compression
+            file.wait();
+
+            let rotated = rotated_files(&path);
+            assert_eq!(rotated.len(), 2);
+            assert!(rotated.iter().all(|n| n.to_string_lossy().ends_with(".gz")));
+            // the newest file comes first, the one containing line 1 was removed
+            assert_eq!(gunzip(&rotated[0]), "");
+            assert_eq!(gunzip(&rotated[1]), "line 2\n");
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), "line 3\n");
+            let _ = std::fs::remove_dir_all(&dir);
+        }
+
+        #[test]
+        fn time_rotation() {
+            let dir = temp_dir("rotate-time");
+            let path = dir.join("access.log");
+            std::fs::write(&path, "yesterday\n").unwrap();
+            let yesterday = SystemTime::now() - Duration::from_secs(25 * 60 * 60);
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(yesterday)
+                .unwrap();
+            let rotation = Rotation {
+                interval: Some(RotateInterval::Daily),
+                ..Rotation::default()
+            };
+            let mut file = LogFile::open(path.to_str().unwrap(), rotation).unwrap();
+            file.write(b"today\n").unwrap();
+            file.write(b"still today\n").unwrap();
+            let rotated = rotated_files(&path);
+            assert_eq!(rotated.len(), 1);
+            assert_eq!(std::fs::read_to_string(&rotated[0]).unwrap(), "yesterday\n");
+            assert_eq!(
+                std::fs::read_to_string(&path).unwrap(),
+                "today\nstill today\n"
+            );
+            let _ = std::fs::remove_dir_all(&dir);
+        }
+
+        #[test]
+        fn reopen() {
+            let dir = temp_dir("reopen");
+            let path = dir.join("latest.log");
+            let mut file = LogFile::open(path.to_str().unwrap(), Rotation::default()).unwrap();
+            file.write(b"before\n").unwrap();
+            // logrotate moves the file and tells the server to reopen it
+            std::fs::rename(&path, dir.join("latest.log.1")).unwrap();
+            request_reopen();
+            file.write(b"after\n").unwrap();
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), "after\n");
+            assert_eq!(
+                std::fs::read_to_string(dir.join("latest.log.1")).unwrap(),
+                "before\n"
+            );
+            let _ = std::fs::remove_dir_all(&dir);
+        }
+    }
+
+    #[cfg(unix)]
+    mod syslog {
+        use crate::log::{Filter, Level, Logger};
+        use crate::syslog::{facility, Journald, Syslog};
+        use crate::{error, info};
+        use std::os::unix::net::UnixDatagram;
+        use std::path::PathBuf;
+        use std::time::Duration;
+
+        fn listen(name: &str) -> (PathBuf, UnixDatagram) {
+            let path =
+                std::env::temp_dir().join(format!("rsweb-{}-{}.sock", name, std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let socket = UnixDatagram::bind(&path).unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            (path, socket)
+        }
+
+        fn receive(socket: &UnixDatagram) -> Vec<u8> {
+            let mut buf = vec![0u8; 4096];
+            let n = socket.recv(&mut buf).unwrap();
+            buf.truncate(n);
+            buf
+        }
+
+        #[test]
+        fn syslog() {
+            let (path, socket) = listen("syslog");
+            let mut syslog =
+                Syslog::new(path.to_str().unwrap(), facility("local3").unwrap()).unwrap();
+            syslog.set_app_name("my app");
+            let logger = Logger::new();
+            logger.set_filter(Filter::new(Level::Debug));
+            logger.set_syslog(Some(syslog));
+            error!(logger, {"path": "/a\"]", "status": 500}, "failed: {}", 1);
+            let msg = String::from_utf8(receive(&socket)).unwrap();
+            // local3 (19) * 8 + err (3)
+            assert!(msg.starts_with("<155>1 "), "{}", msg);
+            let parts: Vec<&str> = msg.splitn(7, ' ').collect();
+            assert_eq!(parts[3], "myapp");
+            assert_eq!(parts[4], std::process::id().to_string());
+            assert_eq!(parts[5], "-");
+            assert_eq!(
+                parts[6],
+                "[rsweb@32473 module=\"rsweb::tests::syslog\" path=\"/a\\\"\\]\" status=\"500\"] failed: 1"
+            );
+            let _ = std::fs::remove_file(&path);
+        }
+
+        #[test]
+        fn journald() {
+            let (path, socket) = listen("journald");
+            let logger = Logger::new();
+            logger.set_journald(Some(Journald::new(path.to_str().unwrap()).unwrap()));
+            info!(logger, {"request-id": "abc", "message": "ignored"}, "two\nlines");
+            let msg = receive(&socket);
+            let mut expected = b"MESSAGE\n".to_vec();
+            expected.extend(9u64.to_le_bytes());
+            expected.extend(b"two\nlines\n");
+            expected.extend(b"PRIORITY=6\nSYSLOG_IDENTIFIER=rsweb\n");
+            expected.extend(b"CODE_MODULE=rsweb::tests::syslog\nREQUEST_ID=abc\n");
+            assert_eq!(
+                String::from_utf8_lossy(&msg),
+                String::from_utf8_lossy(&expected)
+            );
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+
     mod dbuffer {
 	use crate::dbuffer::DBuffer;
 
@@ -121,5 +456,2148 @@ mod tests {
 	    assert!(dbuffer.read_until_zero(&mut s.as_bytes()).is_ok());
 	    assert!(dbuffer.to_string().is_ok());
 	}
+
+        #[test]
+        fn dbuffer_pipelined_requests() {
+            let mut dbuffer = DBuffer::new();
+            let mut reqs: &[u8] =
+                b"POST /a HTTP/1.1\r\nContent-Length: 2\r\n\r\nhiGET /b HTTP/1.1\r\n\r\n";
+            assert!(dbuffer.read_http_request(&mut reqs).is_ok());
+            assert_eq!(dbuffer.body(), b"hi");
+            assert_eq!(dbuffer.leftover(), b"GET /b HTTP/1.1\r\n\r\n");
+            assert!(dbuffer.read_http_request(&mut reqs).is_ok());
+            assert_eq!(dbuffer.head(), b"GET /b HTTP/1.1\r\n\r\n");
+            assert!(dbuffer.leftover().is_empty());
+        }
+
+        /// a reader that fails once its data is used up
+        struct Reset<'a>(&'a [u8]);
+
+        impl std::io::Read for Reset<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if self.0.is_empty() {
+                    return Err(std::io::ErrorKind::ConnectionReset.into());
+                }
+                self.0.read(buf)
+            }
+        }
+
+        #[test]
+        fn dbuffer_truncated_body() {
+            use std::io::ErrorKind;
+
+            let req: &[u8] = b"POST /a HTTP/1.1\r\nContent-Length: 5\r\n\r\nhi";
+            let err = DBuffer::new().read_http_request(&mut &req[..]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+            let err = DBuffer::new()
+                .read_http_request(&mut Reset(req))
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+        }
+    }
+
+    mod timeout {
+        use crate::dbuffer::DBuffer;
+        use crate::timeout::{Deadline, SetTimeout, Timeouts};
+        use std::io::{ErrorKind, Write};
+        use std::net::{TcpListener, TcpStream};
+        use std::time::Duration;
+
+        #[test]
+        fn header_read_timeout() {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let (mut server, _) = listener.accept().unwrap();
+            client.write_all(b"GET / HTTP/1.1\r\nHost: loc").unwrap();
+            let mut timeouts = Timeouts::none();
+            timeouts.header_read = Some(Duration::from_millis(200));
+            let mut buf = DBuffer::new();
+            let err = buf
+                .read_http_request_timeout(&mut server, &timeouts)
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::TimedOut);
+        }
+
+        #[test]
+        fn complete_request_in_time() {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let (mut server, _) = listener.accept().unwrap();
+            client
+                .write_all(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello")
+                .unwrap();
+            let mut buf = DBuffer::new();
+            assert!(buf
+                .read_http_request_timeout(&mut server, &Timeouts::default())
+                .is_ok());
+            assert!(buf.to_string().unwrap().ends_with("hello"));
+        }
+
+        struct CountingStream(std::cell::Cell<usize>);
+
+        impl SetTimeout for CountingStream {
+            fn set_read_timeout(&self, _dur: Option<Duration>) -> std::io::Result<()> {
+                self.0.set(self.0.get() + 1);
+                Ok(())
+            }
+
+            fn set_write_timeout(&self, _dur: Option<Duration>) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        #[test]
+        fn unchanged_timeout_is_set_once() {
+            let stream = CountingStream(std::cell::Cell::new(0));
+            let mut deadline = Deadline::new(&Timeouts::default());
+            for _ in 0..10 {
+                deadline.apply(&stream).unwrap();
+                deadline.record(100);
+            }
+            assert_eq!(stream.0.get(), 1);
+
+            let mut timeouts = Timeouts::none();
+            timeouts.header_read = Some(Duration::from_secs(20));
+            let mut deadline = Deadline::new(&timeouts);
+            deadline.apply(&stream).unwrap();
+            std::thread::sleep(Duration::from_millis(5));
+            deadline.apply(&stream).unwrap();
+            assert_eq!(stream.0.get(), 3);
+        }
+    }
+
+    mod limits {
+        use crate::dbuffer::DBuffer;
+        use crate::http::StatusCode;
+        use crate::limits::{limit_exceeded, LimitExceeded, Limits};
+        use crate::timeout::Timeouts;
+        use std::io::Write;
+        use std::net::{TcpListener, TcpStream};
+
+        fn read_with_limits(request: &[u8], limits: &Limits) -> std::io::Result<usize> {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let (mut server, _) = listener.accept().unwrap();
+            client.write_all(request).unwrap();
+            DBuffer::new().read_http_request_limited(&mut server, &Timeouts::default(), limits)
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn func_server() {
+            use crate::http::body::Body;
+            use crate::http::response::HTTPResponse;
+            use crate::listener::Listener;
+            use crate::server::FuncServer;
+            use std::io::Read;
+            use std::os::unix::net::UnixStream;
+
+            let dir = std::env::temp_dir().join(format!("rsweb-func-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("rsweb.sock");
+            let log = dir.join("rsweb.log");
+            let ip = "127.0.0.1".parse().unwrap();
+            let mut server = FuncServer::new(1, 0, ip, log.to_str().unwrap());
+            server.set_listeners(vec![Listener::unix(path.clone())]);
+            let mut limits = Limits::none();
+            limits.request_line = Some(32);
+            server.set_limits(limits);
+            std::thread::spawn(move || {
+                server.run(|_| {
+                    HTTPResponse::new(StatusCode::Ok, vec![], Body::new(String::from("func")))
+                })
+            });
+            let request = |req: &[u8]| -> String {
+                // the server binds its socket in the background
+                let mut client = (0..500)
+                    .find_map(|_| {
+                        let client = UnixStream::connect(&path).ok();
+                        if client.is_none() {
+                            std::thread::sleep(std::time::Duration::from_millis(10));
+                        }
+                        client
+                    })
+                    .unwrap();
+                client.write_all(req).unwrap();
+                let mut resp = String::new();
+                client.read_to_string(&mut resp).unwrap();
+                resp
+            };
+
+            let resp = request(b"GET / HTTP/1.1\r\nX-Request-Id: abc\r\n\r\n");
+            assert!(resp.starts_with(&StatusCode::Ok.to_string()));
+            assert!(resp.contains("X-Request-Id: abc\r\n"));
+            assert!(resp.ends_with("func"));
+            // limits are enforced by the same code as for the static servers
+            let resp = request(b"GET /a/very/long/path/to/something HTTP/1.1\r\n\r\n");
+            assert!(resp.starts_with(&StatusCode::URITooLong.to_string()));
+            let _ = std::fs::remove_dir_all(dir);
+        }
+
+        #[test]
+        fn request_line_too_long() {
+            let mut limits = Limits::none();
+            limits.request_line = Some(16);
+            let err =
+                read_with_limits(b"GET /a/very/long/path HTTP/1.1\r\n\r\n", &limits).unwrap_err();
+            assert_eq!(limit_exceeded(&err), Some(LimitExceeded::RequestLine));
+            assert_eq!(LimitExceeded::RequestLine.status(), StatusCode::URITooLong);
+        }
+
+        #[test]
+        fn too_many_headers() {
+            let mut limits = Limits::none();
+            limits.header_count = Some(1);
+            let err =
+                read_with_limits(b"GET / HTTP/1.1\r\nA: b\r\nC: d\r\n\r\n", &limits).unwrap_err();
+            assert_eq!(limit_exceeded(&err), Some(LimitExceeded::HeaderCount));
+        }
+
+        #[test]
+        fn body_limit_per_route() {
+            let mut limits = Limits::none();
+            limits.body_size = Some(4);
+            limits.route(String::from("/upload/*"), Some(16));
+            let req = b"POST /upload/a HTTP/1.1\r\nContent-Length: 8\r\n\r\n12345678";
+            assert!(read_with_limits(req, &limits).is_ok());
+            let req = b"POST /other HTTP/1.1\r\nContent-Length: 8\r\n\r\n12345678";
+            let err = read_with_limits(req, &limits).unwrap_err();
+            assert_eq!(limit_exceeded(&err), Some(LimitExceeded::Body));
+        }
+    }
+
+    mod methods {
+        use crate::cors::Cors;
+        use crate::http::request::{HTTPMethod, HTTPRequest};
+        use crate::http::StatusCode;
+        use crate::methods::{MethodPolicy, STATIC_METHODS};
+        use crate::resource::ResourceLoader;
+        use crate::route::{Router, Rule};
+        use crate::server::handle_static;
+
+        fn respond(req: &str, methods: &MethodPolicy) -> String {
+            let req = HTTPRequest::from_string(req.to_string()).unwrap();
+            let router = Router::new(String::from("/index.html"));
+            let mut rl = ResourceLoader::new(1, String::from("."), false);
+            handle_static(&req, &router, &mut rl, None, methods, &Cors::new())
+                .try_to_string()
+                .unwrap()
+        }
+
+        #[test]
+        fn policy_per_path() {
+            let mut policy = MethodPolicy::new(Some(vec![HTTPMethod::Get, HTTPMethod::Head]));
+            policy.path(String::from("/private/*"), vec![HTTPMethod::Head]);
+            assert_eq!(
+                policy.allowed("/index.html", &STATIC_METHODS),
+                vec![HTTPMethod::Get, HTTPMethod::Head, HTTPMethod::Options]
+            );
+            assert_eq!(
+                policy.allowed("/private/a", &STATIC_METHODS),
+                vec![HTTPMethod::Head, HTTPMethod::Options]
+            );
+        }
+
+        #[test]
+        fn unsupported_method() {
+            let resp = respond(
+                "DELETE /Cargo.toml HTTP/1.1\r\n\r\n",
+                &MethodPolicy::default(),
+            );
+            assert!(resp.starts_with(&StatusCode::MethodNotAllowed.to_string()));
+            assert!(resp.contains("Allow: GET, HEAD, OPTIONS\r\n"));
+        }
+
+        #[test]
+        fn options_asterisk() {
+            let policy = MethodPolicy::new(Some(vec![HTTPMethod::Get]));
+            let resp = respond("OPTIONS * HTTP/1.1\r\n\r\n", &policy);
+            assert!(resp.starts_with(&StatusCode::NoContent.to_string()));
+            assert!(resp.contains("Allow: GET, OPTIONS\r\n"));
+            // only methods the server can answer are advertised
+            let resp = respond("OPTIONS * HTTP/1.1\r\n\r\n", &MethodPolicy::default());
+            assert!(resp.contains("Allow: GET, HEAD, OPTIONS\r\n"));
+            let mut router = Router::new(String::from("/index.html"));
+            let mut rule = Rule::new(String::from("/form"), String::from("/sent"));
+            rule.methods = Some(vec![HTTPMethod::Post]);
+            router.add_route(rule);
+            let req = HTTPRequest::from_string(String::from("OPTIONS * HTTP/1.1\r\n\r\n")).unwrap();
+            let mut rl = ResourceLoader::new(1, String::from("."), false);
+            let resp = handle_static(
+                &req,
+                &router,
+                &mut rl,
+                None,
+                &MethodPolicy::default(),
+                &Cors::new(),
+            );
+            assert!(resp
+                .try_to_string()
+                .unwrap()
+                .contains("Allow: GET, HEAD, POST, OPTIONS\r\n"));
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn unknown_method() {
+            use crate::listener::Listener;
+            use crate::server::Server;
+            use std::io::{Read, Write};
+            use std::os::unix::net::UnixStream;
+
+            let dir = std::env::temp_dir().join(format!("rsweb-methods-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("rsweb.sock");
+            let mut server = Server::new(
+                1,
+                ResourceLoader::new(1, String::from("."), false),
+                Router::new(String::from("/index.html")),
+                0,
+                "127.0.0.1".parse().unwrap(),
+                toml::from_str("").unwrap(),
+            );
+            server.set_listeners(vec![Listener::unix(path.clone())]);
+            let (bound, all_bound) = std::sync::mpsc::channel();
+            server.set_bound_notifier(Some(bound));
+            let log = dir.join("rsweb.log").to_str().unwrap().to_string();
+            std::thread::spawn(move || server.run(log.as_str()));
+            all_bound.recv().unwrap();
+            let request = |req: &[u8]| -> String {
+                let mut client = UnixStream::connect(&path).unwrap();
+                client.write_all(req).unwrap();
+                let mut resp = String::new();
+                client.read_to_string(&mut resp).unwrap();
+                resp
+            };
+
+            // WebDAV methods are answered instead of dropping the connection
+            let resp = request(b"PROPFIND / HTTP/1.1\r\nHost: localhost\r\nDepth: 1\r\n\r\n");
+            assert!(resp.starts_with(&StatusCode::MethodNotAllowed.to_string()));
+            assert!(resp.contains("Allow: GET, HEAD, OPTIONS\r\n"));
+            let resp = request(b"GET\r\n\r\n");
+            assert!(resp.starts_with(&StatusCode::BadRequest.to_string()));
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+
+    mod route {
+        use crate::config::HTTPConfig;
+        use crate::cors::Cors;
+        use crate::http::request::{HTTPMethod, HTTPRequest};
+        use crate::http::StatusCode;
+        use crate::methods::MethodPolicy;
+        use crate::resource::ResourceLoader;
+        use crate::route::{Route, Router};
+        use crate::server::handle_static;
+
+        fn router() -> Router {
+            let conf: HTTPConfig = toml::from_str(
+                r#"
+                port = 8080
+                ip = "127.0.0.1"
+                [resources]
+                root = "."
+                routes = ["/docs/*:https://docs.example.com:8443/"]
+                aliases = ["/:/index.html", "/broken"]
+                [[routes]]
+                from = "/login"
+                to = "https://example.com/login"
+                status = 307
+                methods = ["post"]
+                headers = { "Cache-Control" = "no-store" }
+                [[aliases]]
+                from = "/login"
+                to = "/Cargo.toml"
+                headers = { "X-Frame-Options" = "DENY" }
+                "#,
+            )
+            .unwrap();
+            Router::from_config(&conf.resources, conf.routes.as_ref(), conf.aliases.as_ref())
+        }
+
+        fn respond(req: &str) -> String {
+            let req = HTTPRequest::from_string(req.to_string()).unwrap();
+            let mut rl = ResourceLoader::new(1, String::from("."), false);
+            handle_static(
+                &req,
+                &router(),
+                &mut rl,
+                None,
+                &MethodPolicy::default(),
+                &Cors::new(),
+            )
+            .try_to_string()
+            .unwrap()
+        }
+
+        #[test]
+        fn strings_and_tables() {
+            let router = router();
+            match router.lookup(String::from("/docs/a")) {
+                Some(Route::Route(resp)) => {
+                    let resp = resp.try_to_string().unwrap();
+                    assert!(resp.starts_with(&StatusCode::MovedPermanently.to_string()));
+                    assert!(resp.contains("Location: https://docs.example.com:8443/\r\n"));
+                }
+                _ => panic!("no route for /docs/a"),
+            }
+            match router.lookup_method("/login", &HTTPMethod::Get) {
+                Some(Route::Alias(to)) => {
+                    assert_eq!(to, "/Cargo.toml");
+                    assert_eq!(router.alias_headers("/login", &HTTPMethod::Get).len(), 1);
+                }
+                _ => panic!("no alias for GET /login"),
+            }
+            assert!(router.lookup("/broken".to_string()).is_none());
+        }
+
+        #[test]
+        fn methods_and_headers() {
+            let resp = respond("POST /login HTTP/1.1\r\n\r\n");
+            assert!(resp.starts_with(&StatusCode::TemporaryRedirect.to_string()));
+            assert!(resp.contains("Location: https://example.com/login\r\n"));
+            assert!(resp.contains("Cache-Control: no-store\r\n"));
+            let resp = respond("GET /login HTTP/1.1\r\n\r\n");
+            assert!(resp.starts_with(&StatusCode::Ok.to_string()));
+            assert!(resp.contains("X-Frame-Options: DENY\r\n"));
+            assert!(resp.contains("[package]"));
+        }
+
+        #[test]
+        fn empty_alias() {
+            // validation rejects an empty `to`, but a router built in code may contain one
+            let mut router = Router::new(String::from("/index.html"));
+            router.alias(String::from("/empty"), String::new());
+            let req =
+                HTTPRequest::from_string(String::from("GET /empty HTTP/1.1\r\n\r\n")).unwrap();
+            let mut rl = ResourceLoader::new(1, String::from("."), false);
+            let resp = handle_static(
+                &req,
+                &router,
+                &mut rl,
+                None,
+                &MethodPolicy::default(),
+                &Cors::new(),
+            );
+            assert!(resp
+                .try_to_string()
+                .unwrap()
+                .starts_with(&StatusCode::NotFound.to_string()));
+        }
+    }
+
+    mod cors {
+        use crate::cors::{Cors, CorsPolicy};
+        use crate::http::body::Body;
+        use crate::http::request::{HTTPMethod, HTTPRequest};
+        use crate::http::response::HTTPResponse;
+        use crate::http::StatusCode;
+
+        fn cors() -> Cors {
+            let mut policy = CorsPolicy::new(vec![String::from("https://*.example.com")]);
+            policy.methods = Some(vec![HTTPMethod::Get, HTTPMethod::Put]);
+            policy.headers = vec![String::from("Content-Type")];
+            policy.credentials = true;
+            policy.max_age = Some(600);
+            let mut cors = Cors::new();
+            cors.route(String::from("/api/*"), policy);
+            cors
+        }
+
+        fn respond(req: &str) -> String {
+            let req = HTTPRequest::from_string(req.to_string()).unwrap();
+            cors()
+                .handle(req, |_| {
+                    HTTPResponse::new(StatusCode::Ok, vec![], Body::new(String::from("ok")))
+                })
+                .try_to_string()
+                .unwrap()
+        }
+
+        #[test]
+        fn preflight() {
+            let resp = respond(
+                "OPTIONS /api/items HTTP/1.1\r\nOrigin: https://app.example.com\r\n\
+                 Access-Control-Request-Method: PUT\r\n\
+                 Access-Control-Request-Headers: content-type\r\n\r\n",
+            );
+            assert!(resp.starts_with(&StatusCode::NoContent.to_string()));
+            assert!(resp.contains("Access-Control-Allow-Methods: GET, PUT\r\n"));
+            assert!(resp.contains("Access-Control-Allow-Headers: Content-Type\r\n"));
+            assert!(resp.contains("Access-Control-Max-Age: 600\r\n"));
+            assert!(resp.contains("Access-Control-Allow-Origin: https://app.example.com\r\n"));
+            assert!(resp.contains("Access-Control-Allow-Credentials: true\r\n"));
+            assert!(resp.contains("Vary: Origin\r\n"));
+        }
+
+        #[test]
+        fn preflight_rejected() {
+            let resp = respond(
+                "OPTIONS /api/items HTTP/1.1\r\nOrigin: https://evil.org\r\n\
+                 Access-Control-Request-Method: PUT\r\n\r\n",
+            );
+            assert!(!resp.contains("Access-Control-Allow-Origin"));
+            let resp = respond(
+                "OPTIONS /api/items HTTP/1.1\r\nOrigin: https://app.example.com\r\n\
+                 Access-Control-Request-Method: DELETE\r\n\r\n",
+            );
+            assert!(!resp.contains("Access-Control-Allow-Origin"));
+        }
+
+        #[test]
+        fn simple_request() {
+            let resp =
+                respond("GET /api/items HTTP/1.1\r\nOrigin: https://app.example.com\r\n\r\n");
+            assert!(resp.ends_with("ok"));
+            assert!(resp.contains("Access-Control-Allow-Origin: https://app.example.com\r\n"));
+            assert!(resp.contains("Vary: Origin\r\n"));
+            let resp = respond("GET /other HTTP/1.1\r\nOrigin: https://app.example.com\r\n\r\n");
+            assert!(!resp.contains("Vary"));
+        }
+
+        #[test]
+        fn lowercase_headers() {
+            let resp = respond(
+                "OPTIONS /api/items HTTP/1.1\r\norigin: https://app.example.com\r\n\
+                 access-control-request-method: PUT\r\n\
+                 access-control-request-headers: content-type\r\n\r\n",
+            );
+            assert!(resp.starts_with(&StatusCode::NoContent.to_string()));
+            assert!(resp.contains("Access-Control-Allow-Methods: GET, PUT\r\n"));
+            assert!(resp.contains("Access-Control-Allow-Origin: https://app.example.com\r\n"));
+        }
+
+        #[test]
+        fn any_origin_with_credentials() {
+            let mut policy = CorsPolicy::new(vec![String::from("*")]);
+            let mut resp = HTTPResponse::new(StatusCode::Ok, vec![], Body::new(String::new()));
+            policy.apply("https://evil.org", &mut resp);
+            let resp = resp.try_to_string().unwrap();
+            assert!(resp.contains("Access-Control-Allow-Origin: *\r\n"));
+
+            // `*` must never turn into a reflected origin with credentials
+            policy.credentials = true;
+            let mut resp = HTTPResponse::new(StatusCode::Ok, vec![], Body::new(String::new()));
+            policy.apply("https://evil.org", &mut resp);
+            let resp = resp.try_to_string().unwrap();
+            assert!(!resp.contains("Access-Control-Allow-Origin"));
+            assert!(!resp.contains("Access-Control-Allow-Credentials"));
+        }
+    }
+
+    mod https {
+        use crate::http::request::HTTPRequest;
+        use crate::http::StatusCode;
+        use crate::https::{Hsts, HttpsRedirect};
+
+        fn request(req: &str) -> HTTPRequest {
+            HTTPRequest::from_string(req.to_string()).unwrap()
+        }
+
+        #[test]
+        fn redirect() {
+            let mut redirect = HttpsRedirect::new(443);
+            let req = request("GET /a?b=c HTTP/1.1\r\nHost: example.com:8080\r\n\r\n");
+            let resp = redirect
+                .response(&req, "127.0.0.1")
+                .unwrap()
+                .try_to_string()
+                .unwrap();
+            assert!(resp.starts_with(&StatusCode::MovedPermanently.to_string()));
+            assert!(resp.contains("Location: https://example.com/a?b=c\r\n"));
+
+            redirect.port = 8443;
+            redirect.status = StatusCode::PermanentRedirect;
+            let req = request("POST /a HTTP/1.1\r\nHost: [::1]:8080\r\n\r\n");
+            let resp = redirect
+                .response(&req, "127.0.0.1")
+                .unwrap()
+                .try_to_string()
+                .unwrap();
+            assert!(resp.starts_with(&StatusCode::PermanentRedirect.to_string()));
+            assert!(resp.contains("Location: https://[::1]:8443/a\r\n"));
+
+            let req = request("GET /a HTTP/1.1\r\n\r\n");
+            assert_eq!(
+                redirect.location(&req, "127.0.0.1"),
+                "https://127.0.0.1:8443/a"
+            );
+
+            let req = request("GET /.well-known/acme-challenge/token HTTP/1.1\r\n\r\n");
+            assert!(redirect.response(&req, "127.0.0.1").is_none());
+        }
+
+        #[test]
+        fn hsts() {
+            let mut hsts = Hsts::new(31536000);
+            assert_eq!(
+                hsts.header().to_string(),
+                "Strict-Transport-Security: max-age=31536000"
+            );
+            hsts.include_subdomains = true;
+            hsts.preload = true;
+            assert_eq!(
+                hsts.header().to_string(),
+                "Strict-Transport-Security: max-age=31536000; includeSubDomains; preload"
+            );
+        }
+    }
+
+    mod access {
+        use crate::access::{AccessEntry, AccessLog, AccessLogFormat, FormatToken};
+        use crate::http::body::Body;
+        use crate::http::header::HTTPResponseHeaders;
+        use crate::http::response::HTTPResponse;
+        use crate::http::StatusCode;
+        use crate::json::JsonValue;
+        use chrono::prelude::*;
+        use std::time::{Duration, Instant};
+
+        const REQUEST: &str = "GET /index.html?q=1 HTTP/1.1\r\nHost: example.com\r\n\
+                               Referer: https://example.com/\r\nUser-Agent: curl/7.88\r\n\r\n";
+
+        fn entry(body: &str) -> AccessEntry {
+            let resp = HTTPResponse::new(
+                StatusCode::Ok,
+                vec![HTTPResponseHeaders::Server(String::from("rsweb"))],
+                Body::new(body.to_string()),
+            );
+            let mut entry = AccessEntry::new(
+                Some("10.0.0.1".parse().unwrap()),
+                REQUEST,
+                &resp,
+                Instant::now(),
+            );
+            entry.time = Local.ymd(2000, 10, 10).and_hms(13, 55, 36);
+            entry.duration = Duration::from_micros(1500);
+            entry
+        }
+
+        fn time() -> String {
+            entry("").time.format("%d/%b/%Y:%H:%M:%S %z").to_string()
+        }
+
+        #[test]
+        fn common() {
+            assert_eq!(
+                entry("hello").format(&AccessLogFormat::Common),
+                format!(
+                    "10.0.0.1 - - [{}] \"GET /index.html?q=1 HTTP/1.1\" 200 5",
+                    time()
+                )
+            );
+            // empty bodies are logged as `-`
+            assert!(entry("")
+                .format(&AccessLogFormat::Common)
+                .ends_with(" 200 -"));
+        }
+
+        #[test]
+        fn combined() {
+            assert_eq!(
+                entry("hello").format(&AccessLogFormat::Combined),
+                format!(
+                    "10.0.0.1 - - [{}] \"GET /index.html?q=1 HTTP/1.1\" 200 5 \
+                     \"https://example.com/\" \"curl/7.88\"",
+                    time()
+                )
+            );
+        }
+
+        #[test]
+        fn custom() {
+            let format = AccessLogFormat::from_string(String::from(
+                "%m %U %>s %B %D %T %{Host}i %{server}o %{Origin}i 100%% %q",
+            ));
+            assert_eq!(
+                entry("").format(&format),
+                "GET /index.html?q=1 200 0 1500 0 example.com rsweb - 100% %q"
+            );
+            assert_eq!(
+                AccessLogFormat::from_string(String::from("%h:%s")),
+                AccessLogFormat::Custom(vec![
+                    FormatToken::Client,
+                    FormatToken::Literal(String::from(":")),
+                    FormatToken::Status,
+                ])
+            );
+            assert_eq!(
+                AccessLogFormat::from_string(String::from("json")),
+                AccessLogFormat::Json
+            );
+        }
+
+        #[test]
+        fn json() {
+            let mut entry = entry("hello");
+            entry.user = Some(String::from("CN=client"));
+            let json = JsonValue::parse(&entry.format(&AccessLogFormat::Json)).unwrap();
+            assert_eq!(json.get("client").unwrap().as_str(), Some("10.0.0.1"));
+            assert_eq!(json.get("user").unwrap().as_str(), Some("CN=client"));
+            assert_eq!(json.get("method").unwrap().as_str(), Some("GET"));
+            assert_eq!(json.get("path").unwrap().as_str(), Some("/index.html?q=1"));
+            assert_eq!(json.get("status"), Some(&JsonValue::Number(200.0)));
+            assert_eq!(json.get("bytes"), Some(&JsonValue::Number(5.0)));
+            assert_eq!(json.get("duration_ms"), Some(&JsonValue::Number(1.5)));
+            assert_eq!(
+                json.get("referer").unwrap().as_str(),
+                Some("https://example.com/")
+            );
+            assert_eq!(json.get("user_agent").unwrap().as_str(), Some("curl/7.88"));
+        }
+
+        #[test]
+        fn write() {
+            let path =
+                std::env::temp_dir().join(format!("rsweb-access-{}.log", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let mut log = AccessLog::new(path.to_str().unwrap(), AccessLogFormat::Common);
+            log.open().unwrap();
+            let resp = HTTPResponse::new(StatusCode::NotFound, vec![], Body::new(String::new()));
+            log.record(None, None, REQUEST, &resp, Instant::now());
+            log.clone()
+                .record(None, Some(String::from("CN=a")), "", &resp, Instant::now());
+            let content = std::fs::read_to_string(&path).unwrap();
+            let lines: Vec<&str> = content.lines().collect();
+            assert_eq!(lines.len(), 2);
+            assert!(lines[0].starts_with("- - - ["));
+            assert!(lines[0].ends_with("\"GET /index.html?q=1 HTTP/1.1\" 404 -"));
+            assert!(lines[1].starts_with("- - CN=a ["));
+            assert!(lines[1].ends_with("\"-\" 404 -"));
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+
+    mod metrics {
+        use crate::http::request::{HTTPMethod, HTTPRequest};
+        use crate::http::StatusCode;
+        use crate::metrics::Metrics;
+        use crate::route::Router;
+        use crate::ThreadPool;
+        use std::time::Duration;
+
+        #[test]
+        fn render() {
+            let metrics = Metrics::new();
+            let pool = ThreadPool::new(2);
+            metrics.register_pool("http", pool.stats());
+            let connection = metrics.connection("http");
+            metrics.record_request(
+                "http",
+                Some(&HTTPMethod::Get),
+                StatusCode::Ok,
+                "/docs/*",
+                100,
+                250,
+                Duration::from_millis(30),
+            );
+            metrics.record_request(
+                "http",
+                None,
+                StatusCode::RequestTimeout,
+                "-",
+                0,
+                50,
+                Duration::from_secs(20),
+            );
+            metrics.tls_handshake_failed("https");
+            metrics.cache_miss();
+            let text = metrics.render();
+            let has = |line: &str| text.lines().any(|n| n == line);
+            assert!(has("# TYPE rsweb_requests_total counter"));
+            assert!(has(
+                "rsweb_requests_total{server=\"http\",method=\"GET\",status=\"200\",route=\"/docs/*\"} 1"
+            ));
+            assert!(has(
+                "rsweb_requests_total{server=\"http\",method=\"-\",status=\"408\",route=\"-\"} 1"
+            ));
+            assert!(has(
+                "rsweb_request_duration_seconds_bucket{server=\"http\",le=\"0.025\"} 0"
+            ));
+            assert!(has(
+                "rsweb_request_duration_seconds_bucket{server=\"http\",le=\"0.05\"} 1"
+            ));
+            assert!(has(
+                "rsweb_request_duration_seconds_bucket{server=\"http\",le=\"+Inf\"} 2"
+            ));
+            assert!(has(
+                "rsweb_request_duration_seconds_count{server=\"http\"} 2"
+            ));
+            assert!(has("rsweb_received_bytes_total{server=\"http\"} 100"));
+            assert!(has("rsweb_sent_bytes_total{server=\"http\"} 300"));
+            assert!(has("rsweb_connections_active{server=\"http\"} 1"));
+            assert!(has("rsweb_threadpool_workers{server=\"http\"} 2"));
+            assert!(has("rsweb_threadpool_queued_jobs{server=\"http\"} 0"));
+            assert!(has(
+                "rsweb_tls_handshake_failures_total{server=\"https\"} 1"
+            ));
+            assert!(has("rsweb_resource_cache_misses_total 1"));
+            drop(connection);
+            assert!(metrics
+                .render()
+                .lines()
+                .any(|n| n == "rsweb_connections_active{server=\"http\"} 0"));
+        }
+
+        #[test]
+        fn thread_pool_stats() {
+            let pool = ThreadPool::new(1);
+            let stats = pool.stats();
+            let (started_tx, started_rx) = std::sync::mpsc::channel();
+            let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+            pool.execute(move || {
+                started_tx.send(()).unwrap();
+                release_rx.recv().unwrap();
+            });
+            pool.execute(|| {});
+            started_rx.recv().unwrap();
+            assert_eq!(stats.busy(), 1);
+            assert_eq!(stats.queued(), 1);
+            release_tx.send(()).unwrap();
+            drop(pool);
+            assert_eq!(stats.busy(), 0);
+            assert_eq!(stats.queued(), 0);
+        }
+
+        #[test]
+        fn handle() {
+            let metrics = Metrics::new();
+            let req = |n: &str| HTTPRequest::from_string(String::from(n)).unwrap();
+            let resp = metrics.handle(&req("GET /metrics HTTP/1.1")).unwrap();
+            assert_eq!(resp.get_status(), StatusCode::Ok);
+            assert!(metrics.handle(&req("GET /index.html HTTP/1.1")).is_none());
+            let resp = metrics.handle(&req("POST /metrics HTTP/1.1")).unwrap();
+            assert_eq!(resp.get_status(), StatusCode::MethodNotAllowed);
+            let mut separate = metrics.clone();
+            separate.set_listen(Some(String::from("127.0.0.1:9100")));
+            assert!(separate.handle(&req("GET /metrics HTTP/1.1")).is_none());
+
+            let mut router = Router::new(String::from("/index.html"));
+            router.route(String::from("/old/*"), String::from("/new"));
+            router.alias(String::from("/"), String::from("/index.html"));
+            assert_eq!(router.matching("/old/a"), Some(String::from("/old/*")));
+            assert_eq!(router.matching("/"), Some(String::from("/")));
+            assert_eq!(router.matching("/other"), None);
+        }
+    }
+
+    mod health {
+        use crate::health::Health;
+        use crate::http::request::HTTPRequest;
+        use crate::http::StatusCode;
+        use crate::json::JsonValue;
+        use crate::ThreadPool;
+
+        fn body(resp: &crate::http::response::HTTPResponse) -> JsonValue {
+            JsonValue::parse(resp.get_body().try_to_string().unwrap().as_str()).unwrap()
+        }
+
+        #[test]
+        fn readiness() {
+            let health = Health::new();
+            let pool = ThreadPool::new(2);
+            health.register_listener("http");
+            health.register_root("http", std::env::temp_dir().to_str().unwrap());
+            health.register_pool("http", pool.stats());
+            let resp = health.readiness();
+            assert_eq!(resp.get_status(), StatusCode::ServiceUnavailable);
+            let json = body(&resp);
+            assert_eq!(json.get("status").unwrap().as_str(), Some("not ready"));
+            let listener = json.get("checks").unwrap().get("http_listener").unwrap();
+            assert_eq!(listener.get("ok"), Some(&JsonValue::Bool(false)));
+
+            health.listener_bound("http", "127.0.0.1:8080");
+            let resp = health.readiness();
+            assert_eq!(resp.get_status(), StatusCode::Ok);
+            let checks = body(&resp);
+            let checks = checks.get("checks").unwrap();
+            assert_eq!(
+                checks
+                    .get("http_listener")
+                    .unwrap()
+                    .get("detail")
+                    .unwrap()
+                    .as_str(),
+                Some("bound to 127.0.0.1:8080")
+            );
+            assert_eq!(
+                checks.get("http_thread_pool").unwrap().get("ok"),
+                Some(&JsonValue::Bool(true))
+            );
+
+            health.register_root("https", "/nonexistent/rsweb");
+            assert!(health
+                .checks()
+                .iter()
+                .any(|n| n.name == "https_resource_root" && !n.ok));
+            assert_eq!(
+                health.readiness().get_status(),
+                StatusCode::ServiceUnavailable
+            );
+        }
+
+        #[test]
+        fn saturated_pool() {
+            let mut health = Health::new();
+            health.set_max_queued(Some(0));
+            let pool = ThreadPool::new(1);
+            health.register_pool("http", pool.stats());
+            let (started_tx, started_rx) = std::sync::mpsc::channel();
+            let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+            pool.execute(move || {
+                started_tx.send(()).unwrap();
+                release_rx.recv().unwrap();
+            });
+            pool.execute(|| {});
+            started_rx.recv().unwrap();
+            assert_eq!(
+                health.readiness().get_status(),
+                StatusCode::ServiceUnavailable
+            );
+            release_tx.send(()).unwrap();
+        }
+
+        #[test]
+        fn endpoints() {
+            let mut health = Health::new();
+            let req = |n: &str| HTTPRequest::from_string(String::from(n)).unwrap();
+            let resp = health.handle(&req("GET /healthz HTTP/1.1")).unwrap();
+            assert_eq!(resp.get_status(), StatusCode::Ok);
+            assert_eq!(body(&resp).get("status").unwrap().as_str(), Some("ok"));
+            let resp = health.handle(&req("HEAD /readyz HTTP/1.1")).unwrap();
+            assert_eq!(resp.get_status(), StatusCode::Ok);
+            assert!(resp.get_body().is_empty());
+            let resp = health.handle(&req("DELETE /readyz HTTP/1.1")).unwrap();
+            assert_eq!(resp.get_status(), StatusCode::MethodNotAllowed);
+            assert!(health.handle(&req("GET /index.html HTTP/1.1")).is_none());
+
+            health.set_paths("/live", "/ready");
+            assert!(health.handle(&req("GET /healthz HTTP/1.1")).is_none());
+            assert!(health.handle(&req("GET /live HTTP/1.1")).is_some());
+            health.set_listen(Some(String::from("127.0.0.1:9101")));
+            assert!(health.handle(&req("GET /live HTTP/1.1")).is_none());
+        }
+
+        #[test]
+        fn several_listeners() {
+            let health = Health::new();
+            health.register_listener("http");
+            health.register_listeners("http", 2);
+            health.register_listeners("https", 1);
+            health.listener_bound("http", "0.0.0.0:80");
+            let checks = health.checks();
+            let names: Vec<&str> = checks.iter().map(|n| n.name.as_str()).collect();
+            assert_eq!(
+                names,
+                vec!["http_listener", "http_listener_2", "https_listener"]
+            );
+            assert!(checks[0].ok);
+            assert!(!checks[1].ok);
+            health.listener_bound("http", "[::]:80");
+            health.listener_bound("https", "0.0.0.0:443");
+            assert!(health.checks().iter().all(|n| n.ok));
+        }
+    }
+
+    mod listener {
+        use crate::config::ListenConfig;
+        use crate::listener::{bind_all, from_section, Address, Listener};
+        use std::net::{SocketAddr, TcpStream};
+
+        fn listen(address: &str) -> ListenConfig {
+            ListenConfig {
+                address: address.to_string(),
+                tls: None,
+                reuse_address: None,
+                reuse_port: None,
+                ipv6_only: None,
+                mode: None,
+                owner: None,
+                group: None,
+            }
+        }
+
+        #[test]
+        fn sections() {
+            let mut tls = listen("127.0.0.1:8443");
+            tls.tls = Some(true);
+            let listeners = from_section(
+                None,
+                Some(8080),
+                Some(&vec![listen("[::]:8080"), tls, listen("[::1]:8081")]),
+                false,
+            )
+            .unwrap();
+            let addrs: Vec<String> = listeners.iter().map(|n| n.addr.to_string()).collect();
+            assert_eq!(
+                addrs,
+                vec!["0.0.0.0:8080", "[::]:8080", "127.0.0.1:8443", "[::1]:8081"]
+            );
+            let tls: Vec<bool> = listeners.iter().map(|n| n.tls).collect();
+            assert_eq!(tls, vec![false, false, true, false]);
+            // only the IPv6 listener sharing the port with 0.0.0.0 is made IPv6 only
+            assert_eq!(listeners[1].ipv6_only, Some(true));
+            assert_eq!(listeners[3].ipv6_only, None);
+            assert!(!listeners[0].conflicts(&listeners[1]));
+
+            let listeners =
+                from_section(None, None, Some(&vec![listen("[::]:443")]), true).unwrap();
+            assert!(listeners[0].tls);
+            assert!(from_section(None, None, None, false).is_err());
+            assert!(from_section(Some(&String::from("x")), Some(80), None, false).is_err());
+            assert!(from_section(None, Some(70000), None, false).is_err());
+            assert!(from_section(None, None, Some(&vec![listen("localhost:80")]), false).is_err());
+
+            let mut unix = listen("unix:/run/rsweb.sock");
+            unix.mode = Some(String::from("0660"));
+            let listeners = from_section(None, None, Some(&vec![unix]), false).unwrap();
+            assert_eq!(
+                listeners[0].addr,
+                Address::Unix(std::path::PathBuf::from("/run/rsweb.sock"))
+            );
+            assert_eq!(listeners[0].to_string(), "unix:/run/rsweb.sock");
+            assert_eq!(listeners[0].mode, Some(0o660));
+            assert!(listeners[0].tcp_addr().is_none());
+            assert!(listeners[0].conflicts(&listeners[0].clone()));
+            assert!(Address::from_string("unix:").is_err());
+            let mut unix = listen("unix:/run/rsweb.sock");
+            unix.mode = Some(String::from("rw"));
+            assert!(from_section(None, None, Some(&vec![unix]), false).is_err());
+        }
+
+        #[test]
+        fn constructor_ports() {
+            use crate::server::FuncServer;
+            use crate::ssl::SSLFuncServer;
+
+            let ip = "127.0.0.1".parse().unwrap();
+            let log = std::env::temp_dir().join(format!("rsweb-ports-{}.log", std::process::id()));
+            let log = log.to_str().unwrap();
+            // 70000 must not wrap around to port 4464
+            let err = FuncServer::new(1, 70000, ip, log)
+                .run(|_| unreachable!())
+                .unwrap_err();
+            assert!(err.to_string().contains("port 70000"));
+            let err = SSLFuncServer::new(1, 70000, ip, log, "key.pem", "cert.pem")
+                .err()
+                .unwrap();
+            assert!(err.to_string().contains("port 70000"));
+            let _ = std::fs::remove_file(log);
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn unix_socket() {
+            use crate::listener::Connection;
+            use std::io::{Read, Write};
+            use std::os::unix::fs::{MetadataExt, PermissionsExt};
+            use std::os::unix::net::UnixStream;
+
+            let dir = std::env::temp_dir().join(format!("rsweb-unix-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("rsweb.sock");
+            let _ = std::fs::remove_file(&path);
+            let mut listener = Listener::unix(path.clone());
+            listener.mode = Some(0o600);
+            let uid = std::fs::metadata(&dir).unwrap().uid();
+            listener.owner = Some(uid.to_string());
+            let socket = listener.bind().unwrap();
+            assert_eq!(
+                socket.local_addr().unwrap(),
+                format!("unix:{}", path.display())
+            );
+            let metadata = std::fs::metadata(&path).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o7777, 0o600);
+
+            let mut client = UnixStream::connect(&path).unwrap();
+            let mut conn = socket.accept().unwrap();
+            assert!(matches!(conn, Connection::Unix(_)));
+            assert!(conn.peer_ip().is_none());
+            let credentials = conn.peer_credentials().unwrap();
+            assert_eq!(credentials.uid, uid);
+            #[cfg(target_os = "linux")]
+            assert_eq!(credentials.pid, Some(std::process::id() as i32));
+            client.write_all(b"ping").unwrap();
+            let mut buf = [0; 4];
+            conn.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"ping");
+
+            // the socket is in use while the listener accepts connections
+            assert!(listener.bind().is_err());
+            drop(conn);
+            drop(socket);
+            // the file left behind is stale now and gets replaced
+            assert!(path.exists());
+            assert!(listener.bind().is_ok());
+
+            let mut tls = listener.clone();
+            tls.tls = true;
+            assert!(tls.bind().is_err());
+            // the owner is looked up before binding, so no socket is left behind
+            std::fs::remove_file(&path).unwrap();
+            let mut unknown = listener.clone();
+            unknown.owner = Some(String::from("rsweb-no-such-user"));
+            assert!(unknown.bind().is_err());
+            assert!(!path.exists());
+            // without a mode the socket gets the permissions of the umask
+            let mut owned = listener.clone();
+            owned.mode = None;
+            drop(owned.bind().unwrap());
+            let umask = unsafe { libc::umask(0o022) };
+            unsafe { libc::umask(umask) };
+            #[allow(clippy::useless_conversion)]
+            let umask = u32::from(umask);
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode, 0o777 & !umask);
+
+            std::fs::remove_file(&path).unwrap();
+            std::fs::write(&path, "not a socket").unwrap();
+            assert!(listener.bind().is_err());
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        #[test]
+        fn conflicts() {
+            let addr = |n: &str| n.parse::<SocketAddr>().unwrap();
+            let any = Listener::new(addr("0.0.0.0:80"), false);
+            let mut any6 = Listener::new(addr("[::]:80"), false);
+            assert!(any.conflicts(&Listener::new(addr("127.0.0.1:80"), true)));
+            assert!(!any.conflicts(&Listener::new(addr("127.0.0.1:81"), false)));
+            assert!(any.conflicts(&any6));
+            any6.ipv6_only = Some(true);
+            assert!(!any6.conflicts(&any));
+            let mut a = Listener::new(addr("127.0.0.1:80"), false);
+            let mut b = a.clone();
+            a.reuse_port = true;
+            assert!(a.conflicts(&b));
+            b.reuse_port = true;
+            assert!(!a.conflicts(&b));
+        }
+
+        #[test]
+        fn bind() {
+            let addr = "127.0.0.1:0".parse::<SocketAddr>().unwrap();
+            let listeners = vec![Listener::new(addr, false), Listener::new(addr, false)];
+            let bound = bind_all(&listeners).unwrap();
+            assert_eq!(bound.len(), 2);
+            for (_, socket) in &bound {
+                TcpStream::connect(socket.local_addr().unwrap()).unwrap();
+                assert!(socket.accept().is_ok());
+            }
+            // a second socket on a used address needs SO_REUSEPORT on both
+            let used = bound[0].1.local_addr().unwrap().parse().unwrap();
+            assert!(Listener::new(used, false).bind().is_err());
+            #[cfg(unix)]
+            {
+                let mut shared = Listener::new(addr, false);
+                shared.reuse_port = true;
+                let first = shared.bind().unwrap();
+                shared.addr = Address::from_string(&first.local_addr().unwrap()).unwrap();
+                assert!(shared.bind().is_ok());
+            }
+        }
+    }
+
+    mod request_id {
+        use crate::http::body::Body;
+        use crate::http::header::HTTPResponseHeaders;
+        use crate::http::request::HTTPRequest;
+        use crate::http::response::HTTPResponse;
+        use crate::http::StatusCode;
+        use crate::info;
+        use crate::log::{Logger, RequestScope};
+        use crate::request_id::{apply, assign, generate, is_valid};
+
+        #[test]
+        fn assign_and_echo() {
+            let id = generate();
+            assert_eq!(id.len(), 36);
+            assert_eq!(&id[14..15], "4");
+            assert!(is_valid(id.as_str()));
+            assert_ne!(id, generate());
+            assert!(!is_valid(""));
+            assert!(!is_valid("a b"));
+            assert!(!is_valid(&"a".repeat(129)));
+
+            let mut req =
+                HTTPRequest::from_string(String::from("GET / HTTP/1.1\r\nX-Request-Id: abc-123"))
+                    .unwrap();
+            assert_eq!(req.get_request_id(), Some(String::from("abc-123")));
+            assert_eq!(assign(&mut req), "abc-123");
+            let mut req =
+                HTTPRequest::from_string(String::from("GET / HTTP/1.1\r\nX-Request-Id: <script>"))
+                    .unwrap();
+            let id = assign(&mut req);
+            assert_ne!(id, "<script>");
+            assert_eq!(req.get_request_id(), Some(id.clone()));
+
+            let mut resp = HTTPResponse::new(StatusCode::Ok, Vec::new(), Body::new(String::new()));
+            apply(&id, &mut resp);
+            apply("other", &mut resp);
+            let ids: Vec<String> = resp
+                .get_header()
+                .iter()
+                .filter_map(|n| match n {
+                    HTTPResponseHeaders::RequestId(n) => Some(n.clone()),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(ids, vec![id]);
+        }
+
+        #[test]
+        fn lowercase_header() {
+            let mut req =
+                HTTPRequest::from_string(String::from("GET / HTTP/1.1\r\nx-request-id: abc"))
+                    .unwrap();
+            assert_eq!(assign(&mut req), "abc");
+        }
+
+        #[test]
+        fn log_scope() {
+            let path =
+                std::env::temp_dir().join(format!("rsweb-request-id-{}.log", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let logger = Logger::new();
+            logger.set_logfile(path.to_str().unwrap()).unwrap();
+            {
+                let _scope = RequestScope::enter("abc-123");
+                info!(logger, "handling");
+            }
+            info!(logger, "idle");
+            logger.flush();
+            let content = std::fs::read_to_string(&path).unwrap();
+            let lines: Vec<&str> = content
+                .lines()
+                .map(|n| n.split_once("]: ").unwrap().1)
+                .collect();
+            assert_eq!(lines, vec!["handling request_id=abc-123", "idle"]);
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+
+    mod validate {
+        use crate::validate::{validate, Severity};
+
+        /// positions and severities of the diagnostics for `source`
+        fn found(source: &str) -> Vec<(Option<(usize, usize)>, Severity)> {
+            validate(source)
+                .diagnostics
+                .iter()
+                .map(|n| (n.position, n.severity))
+                .collect()
+        }
+
+        #[test]
+        fn syntax() {
+            let validation = validate("[http]\nport = \"x\"\n");
+            assert!(validation.has_errors());
+            assert!(validation.config.is_none());
+            let diagnostic = &validation.diagnostics[0];
+            assert_eq!(diagnostic.position, Some((2, 8)));
+            assert!(!diagnostic.msg.contains("at line"));
+            assert!(diagnostic.to_string().starts_with("2:8: error: "));
+        }
+
+        #[test]
+        fn unknown_keys() {
+            let source = "[http]\nport = 8080\nip = \"127.0.0.1\"\ncolour = true\n\n[http.resources]\nroot = \".\"\n\n[[http.cors]]\npath = \"/\"\norigins = [\n  \"*\",\n]\nmax_ag = 5\n";
+            let validation = validate(source);
+            assert!(!validation.has_errors());
+            assert!(validation.config.is_some());
+            assert_eq!(
+                found(source),
+                vec![
+                    (Some((4, 1)), Severity::Warning),
+                    (Some((14, 1)), Severity::Warning)
+                ]
+            );
+            assert!(validation.diagnostics[1].msg.contains("[[http.cors]]"));
+        }
+
+        #[test]
+        fn values() {
+            let source = "[http]\nport = 8080\nip = \"1.2.3\"\nthreads = 0\n\n[http.resources]\nroot = \"/nonexistent-rsweb-root\"\nroutes = [\"/a:/b\", \"/broken\"]\n";
+            assert_eq!(
+                found(source),
+                vec![
+                    (Some((3, 1)), Severity::Error),
+                    (Some((4, 1)), Severity::Error),
+                    (Some((7, 1)), Severity::Warning),
+                    (Some((8, 1)), Severity::Error)
+                ]
+            );
+        }
+
+        #[test]
+        fn logfiles() {
+            let source = "[http]\nport = 8080\nlogfile = \"http.log\"\n[http.resources]\nroot = \".\"\n\n[ssl]\nport = 8443\nlogfile = \"https.log\"\n[ssl.resources]\nroot = \".\"\n";
+            assert_eq!(found(source), vec![(Some((9, 1)), Severity::Warning)]);
+            let source = source.replace("https.log", "http.log");
+            assert_eq!(found(source.as_str()), vec![]);
+        }
+
+        #[test]
+        fn cors() {
+            let source = "[http]\nport = 8080\n[http.resources]\nroot = \".\"\n\n[[http.cors]]\npath = \"/\"\norigins = [\"*\"]\ncredentials = true\n";
+            assert_eq!(found(source), vec![(Some((8, 1)), Severity::Error)]);
+            let source = source.replace("true", "false");
+            assert_eq!(found(source.as_str()), vec![]);
+        }
+
+        #[test]
+        fn route_tables() {
+            let source = "[http]\nport = 8080\nip = \"127.0.0.1\"\n[http.resources]\nroot = \".\"\n\n[[http.routes]]\nfrom = \"/a\"\nto = \"/b\"\nstatus = 200\n\n[[http.aliases]]\nfrom = \"/c\"\nto = \"/d\"\nmethods = [\"FETCH\"]\nheaders = { \"Bad Name\" = \"x\" }\n";
+            assert_eq!(
+                found(source),
+                vec![
+                    (Some((10, 1)), Severity::Error),
+                    (Some((15, 1)), Severity::Error),
+                    (Some((16, 1)), Severity::Error)
+                ]
+            );
+        }
+
+        #[test]
+        fn vhosts() {
+            let source = "[[vhost]]\nnames = [\"example.com\", \"~(\"]\ndefault = true\nerror_pages = { 600 = \"/x.html\" }\n[vhost.resources]\nroot = \".\"\n\n[[vhost]]\nnames = [\"Example.com\"]\ndefault = true\n[vhost.resources]\nroot = \".\"\nroutes = [\"/broken\"]\n";
+            assert_eq!(
+                found(source),
+                vec![
+                    (Some((2, 1)), Severity::Error),
+                    (Some((4, 1)), Severity::Error),
+                    (Some((9, 1)), Severity::Warning),
+                    (Some((10, 1)), Severity::Error),
+                    (Some((13, 1)), Severity::Error)
+                ]
+            );
+        }
+
+        #[test]
+        fn conflicting_ports() {
+            let source = "[http]\nport = 8080\nip = \"127.0.0.1\"\n[http.resources]\nroot = \".\"\n\n[metrics]\nlisten = \"0.0.0.0:8080\"\n\n[health]\nlisten = \"0.0.0.0:8080\"\n";
+            // both conflict with the server, but metrics and health checks share their listener
+            assert_eq!(
+                found(source),
+                vec![
+                    (Some((8, 1)), Severity::Error),
+                    (Some((11, 1)), Severity::Error)
+                ]
+            );
+        }
+
+        #[test]
+        fn listeners() {
+            let source = "[http]\nip = \"127.0.0.1\"\n[http.resources]\nroot = \".\"\n\n[[http.listen]]\naddress = \"0.0.0.0:8080\"\n\n[[http.listen]]\naddress = \"[::]:8080\"\n\n[[http.listen]]\naddress = \"127.0.0.1:8080\"\n\n[[http.listen]]\naddress = \"localhost:8081\"\n\n[[http.listen]]\naddress = \"[::1]:8443\"\ntls = true\n";
+            // dual-stack listeners don't conflict, TLS listeners need the ssl section
+            assert_eq!(
+                found(source),
+                vec![
+                    (Some((2, 1)), Severity::Warning),
+                    (Some((12, 1)), Severity::Error),
+                    (Some((16, 1)), Severity::Error),
+                    (Some((20, 1)), Severity::Error)
+                ]
+            );
+            assert!(found("[http]\n[http.resources]\nroot = \".\"\n")
+                .contains(&(Some((1, 1)), Severity::Error)));
+        }
+
+        #[test]
+        fn unix_listeners() {
+            let source = "[http]\nport = 8080\n[http.resources]\nroot = \".\"\n\n[[http.listen]]\naddress = \"unix:/run/rsweb.sock\"\nmode = \"0999\"\n\n[[http.listen]]\naddress = \"unix:/run/rsweb.sock\"\nowner = \"rsweb-no-such-user\"\n\n[[http.listen]]\naddress = \"127.0.0.1:8081\"\ngroup = \"www-data\"\n";
+            assert_eq!(
+                found(source),
+                vec![
+                    (Some((8, 1)), Severity::Error),
+                    (Some((12, 1)), Severity::Error),
+                    (Some((14, 1)), Severity::Warning)
+                ]
+            );
+            // two listeners can't use the same socket
+            let source = "[http]\nport = 8080\n[http.resources]\nroot = \".\"\n\n[[http.listen]]\naddress = \"unix:/run/rsweb.sock\"\n\n[[http.listen]]\naddress = \"unix:/run/rsweb.sock\"\n";
+            assert_eq!(found(source), vec![(Some((9, 1)), Severity::Error)]);
+        }
+    }
+
+    mod vhost {
+        use crate::config::Config;
+        use crate::cors::Cors;
+        use crate::http::request::HTTPRequest;
+        use crate::http::StatusCode;
+        use crate::methods::MethodPolicy;
+        use crate::resource::ResourceLoader;
+        use crate::vhost::{host_name, VirtualHosts};
+
+        fn vhosts(root: &str) -> VirtualHosts {
+            let conf: Config = toml::from_str(
+                format!(
+                    r#"
+                    [[vhost]]
+                    names = ["example.com", "www.example.com"]
+                    headers = {{ "X-Site" = "example" }}
+                    [vhost.resources]
+                    root = "{root}"
+                    aliases = ["/:/a.html"]
+
+                    [[vhost]]
+                    names = ["*.example.com"]
+                    error_pages = {{ 404 = "/missing.html" }}
+                    [vhost.resources]
+                    root = "{root}"
+                    aliases = ["/:/b.html"]
+
+                    [[vhost]]
+                    names = ["~^api[0-9]+\\.example\\.org$"]
+                    default = true
+                    [vhost.resources]
+                    root = "{root}"
+                    aliases = ["/:/c.html"]
+                    "#
+                )
+                .as_str(),
+            )
+            .unwrap();
+            VirtualHosts::from_config(conf.vhost.as_ref()).unwrap()
+        }
+
+        fn respond(vhosts: &VirtualHosts, req: &str) -> String {
+            let req = HTTPRequest::from_string(req.to_string()).unwrap();
+            let mut vhost = vhosts.select(req.get_host().as_deref()).unwrap().clone();
+            vhost
+                .respond(&req, &MethodPolicy::default(), &Cors::new())
+                .try_to_string()
+                .unwrap()
+        }
+
+        #[test]
+        fn select() {
+            assert_eq!(host_name("WWW.Example.com:8080"), "www.example.com");
+            assert_eq!(host_name("example.com."), "example.com");
+            assert_eq!(host_name("[::1]:8443"), "[::1]");
+            assert_eq!(host_name("[::1]"), "[::1]");
+            let vhosts = vhosts(".");
+            let name = |host: Option<&str>| vhosts.select(host).map(|n| n.name());
+            let exact = Some(String::from("example.com"));
+            let wildcard = Some(String::from("*.example.com"));
+            let regex = Some(String::from("~^api[0-9]+\\.example\\.org$"));
+            // exact names win over wildcards
+            assert_eq!(name(Some("www.example.com")), exact);
+            assert_eq!(name(Some("Example.com:80")), exact);
+            assert_eq!(name(Some("blog.example.com")), wildcard);
+            assert_eq!(name(Some("api1.example.org")), regex);
+            // unknown hosts and requests without a host get the default
+            assert_eq!(name(Some("other.net")), regex);
+            assert_eq!(name(None), regex);
+            assert!(VirtualHosts::new().select(Some("example.com")).is_none());
+        }
+
+        #[test]
+        fn resources() {
+            let root = std::env::temp_dir().join(format!("rsweb-vhost-{}", std::process::id()));
+            std::fs::create_dir_all(&root).unwrap();
+            for (name, content) in [("a.html", "A"), ("b.html", "B"), ("missing.html", "gone")] {
+                std::fs::write(root.join(name), content).unwrap();
+            }
+            let vhosts = vhosts(root.to_str().unwrap());
+            let resp = respond(&vhosts, "GET / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+            assert!(resp.ends_with("\r\n\r\nA"));
+            assert!(resp.contains("X-Site: example\r\n"));
+            let resp = respond(&vhosts, "GET / HTTP/1.1\r\nHost: blog.example.com\r\n\r\n");
+            assert!(resp.ends_with("\r\n\r\nB"));
+            assert!(!resp.contains("X-Site"));
+            // header names are case-insensitive
+            let resp = respond(&vhosts, "GET / HTTP/1.1\r\nhost: example.com\r\n\r\n");
+            assert!(resp.ends_with("\r\n\r\nA"));
+            let resp = respond(&vhosts, "GET /x HTTP/1.1\r\nHost: blog.example.com\r\n\r\n");
+            assert!(resp.starts_with(&StatusCode::NotFound.to_string()));
+            assert!(resp.contains("Content-Length: 4\r\n"));
+            assert!(resp.ends_with("\r\n\r\ngone"));
+            let resp = respond(
+                &vhosts,
+                "GET //etc/passwd HTTP/1.1\r\nHost: example.com\r\n\r\n",
+            );
+            assert!(resp.starts_with(&StatusCode::NotFound.to_string()));
+            let _ = std::fs::remove_dir_all(&root);
+        }
+
+        #[test]
+        fn resource_root() {
+            let mut rl = ResourceLoader::new(1, String::from("src"), false);
+            assert!(rl.load(String::from("lib.rs")).is_some());
+            assert!(rl.load(String::from("Cargo.toml")).is_none());
+            assert!(rl.load(String::from("../Cargo.toml")).is_none());
+            // a request for `//etc/passwd` asks for the absolute path `/etc/passwd`
+            let absolute = std::env::current_dir().unwrap().join("src/lib.rs");
+            assert!(rl.load(absolute.to_string_lossy().to_string()).is_none());
+            assert!(rl.load(String::from("/etc/passwd")).is_none());
+            let mut rl = ResourceLoader::new(1, String::from("src"), true);
+            assert!(rl.preload(String::from("/etc/passwd")).is_empty());
+            assert!(!rl.preload(String::from("./lib.rs")).is_empty());
+        }
+    }
+
+    mod tls {
+        use crate::acme::{challenge_acceptor, parse_client_hello, Acme, AcmeChallenges};
+        use crate::certgen::{ephemeral_certificate, generate_certificates};
+        use crate::http::request::HTTPRequest;
+        use crate::json::JsonValue;
+        use crate::mtls::{
+            ClientAccess, ClientAccessRule, ClientAuth, ClientAuthMode, PeerIdentity,
+        };
+        use crate::tls::{
+            build_acceptor, certificate_names, name_matches, pending_file, version_from_string,
+            ReloadableAcceptor, SniCertificates, TlsOptions,
+        };
+        use openssl::asn1::Asn1Time;
+        use openssl::bn::BigNum;
+        use openssl::ec::{EcGroup, EcKey};
+        use openssl::ecdsa::EcdsaSig;
+        use openssl::hash::MessageDigest;
+        use openssl::nid::Nid;
+        use openssl::pkey::{PKey, Private};
+        use openssl::rsa::Rsa;
+        use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode, SslVersion, StatusType};
+        use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+        use openssl::x509::{X509NameBuilder, X509Req, X509};
+        use std::io::{Read, Write};
+        use std::net::{TcpListener, TcpStream};
+        use std::path::PathBuf;
+
+        /// write a self-signed certificate for `name` and its key to a temporary directory
+        fn self_signed(name: &str) -> (String, String) {
+            self_signed_as(name, &name.replace('*', "wildcard"))
+        }
+
+        /// write a self-signed certificate for `name` and its key to `file`.pem and `file`.key in
+        /// a temporary directory
+        fn self_signed_as(name: &str, file: &str) -> (String, String) {
+            let (key, cert) = make_cert(name, None);
+            write_pem(file, &key, &cert)
+        }
+
+        /// create a certificate for `name` signed by `issuer` (self-signed if `None`)
+        fn make_cert(name: &str, issuer: Option<(&PKey<Private>, &X509)>) -> (PKey<Private>, X509) {
+            let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+            let mut subject = X509NameBuilder::new().unwrap();
+            subject.append_entry_by_nid(Nid::COMMONNAME, name).unwrap();
+            let subject = subject.build();
+            let mut cert = X509::builder().unwrap();
+            cert.set_version(2).unwrap();
+            cert.set_subject_name(&subject).unwrap();
+            match issuer {
+                Some((_, ca)) => cert.set_issuer_name(ca.subject_name()).unwrap(),
+                None => cert.set_issuer_name(&subject).unwrap(),
+            }
+            cert.set_pubkey(&key).unwrap();
+            cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+                .unwrap();
+            cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+                .unwrap();
+            let san = SubjectAlternativeName::new()
+                .dns(name)
+                .build(&cert.x509v3_context(issuer.map(|n| n.1.as_ref()), None))
+                .unwrap();
+            cert.append_extension(san).unwrap();
+            if issuer.is_none() {
+                cert.append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+                    .unwrap();
+            }
+            cert.sign(issuer.map(|n| n.0).unwrap_or(&key), MessageDigest::sha256())
+                .unwrap();
+            (key, cert.build())
+        }
+
+        /// write `key` and `cert` to `file`.key and `file`.pem in a temporary directory
+        fn write_pem(file: &str, key: &PKey<Private>, cert: &X509) -> (String, String) {
+            let dir: PathBuf =
+                std::env::temp_dir().join(format!("rsweb-tls-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let keyfile = dir.join(format!("{}.key", file));
+            let certfile = dir.join(format!("{}.pem", file));
+            std::fs::write(&keyfile, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+            std::fs::write(&certfile, cert.to_pem().unwrap()).unwrap();
+            (
+                keyfile.to_str().unwrap().to_string(),
+                certfile.to_str().unwrap().to_string(),
+            )
+        }
+
+        /// connect to `port` asking for `host` and return the common name of the certificate
+        /// the server presented
+        fn served_name(port: u16, host: Option<&str>) -> String {
+            let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+            connector.set_verify(SslVerifyMode::NONE);
+            let mut conf = connector.build().configure().unwrap();
+            conf.set_use_server_name_indication(host.is_some());
+            conf.set_verify_hostname(false);
+            let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let stream = conf.connect(host.unwrap_or("localhost"), stream).unwrap();
+            let cert = stream.ssl().peer_certificate().unwrap();
+            let cn = cert
+                .subject_name()
+                .entries_by_nid(Nid::COMMONNAME)
+                .next()
+                .unwrap();
+            cn.data().as_utf8().unwrap().to_string()
+        }
+
+        #[test]
+        fn wildcard_names() {
+            assert!(name_matches("*.example.com", "a.example.com"));
+            assert!(name_matches("*.example.com", "A.Example.COM"));
+            assert!(!name_matches("*.example.com", "example.com"));
+            assert!(!name_matches("*.example.com", "a.b.example.com"));
+            assert!(name_matches("example.com", "example.com."));
+        }
+
+        #[test]
+        fn sni_certificates() {
+            let (key, cert) = self_signed("default.test");
+            let mut certs = SniCertificates::new();
+            for name in ["a.example.test", "*.example.test"] {
+                let (key, cert) = self_signed(name);
+                certs.load(None, &key, &cert).unwrap();
+            }
+            let acceptor = build_acceptor(&key, &cert, &certs, &TlsOptions::default()).unwrap();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let handle = std::thread::spawn(move || {
+                for stream in listener.incoming().take(4) {
+                    let _ = acceptor.accept(stream.unwrap());
+                }
+            });
+            assert_eq!(served_name(port, Some("a.example.test")), "a.example.test");
+            assert_eq!(served_name(port, Some("b.example.test")), "*.example.test");
+            assert_eq!(served_name(port, Some("other.test")), "default.test");
+            assert_eq!(served_name(port, None), "default.test");
+            handle.join().unwrap();
+        }
+
+        #[test]
+        fn generated_certificates() {
+            let dir = std::env::temp_dir().join(format!("rsweb-certgen-{}", std::process::id()));
+            let names = vec![String::from("localhost"), String::from("127.0.0.1")];
+            let files = generate_certificates(&dir, &names).unwrap();
+            let ca = std::fs::read(&files.ca_certificate).unwrap();
+            // the CA is reused when issuing another certificate
+            let files = generate_certificates(&dir, &names).unwrap();
+            assert_eq!(std::fs::read(&files.ca_certificate).unwrap(), ca);
+
+            let acceptor = build_acceptor(
+                files.private_key.to_str().unwrap(),
+                files.certificate_chain.to_str().unwrap(),
+                &SniCertificates::new(),
+                &TlsOptions::default(),
+            )
+            .unwrap();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let handle = std::thread::spawn(move || {
+                let _ = acceptor.accept(listener.incoming().next().unwrap().unwrap());
+            });
+            let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+            connector.set_ca_file(&files.ca_certificate).unwrap();
+            let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            assert!(connector.build().connect("localhost", stream).is_ok());
+            handle.join().unwrap();
+
+            let (key, cert) = ephemeral_certificate(&names).unwrap();
+            assert!(
+                build_acceptor(&key, &cert, &SniCertificates::new(), &TlsOptions::default())
+                    .is_ok()
+            );
+            let _ = std::fs::remove_dir_all(dir);
+        }
+
+        /// decode unpadded base64url
+        fn unbase64url(data: &str) -> Vec<u8> {
+            let mut data = data.replace('-', "+").replace('_', "/");
+            while !data.len().is_multiple_of(4) {
+                data.push('=');
+            }
+            openssl::base64::decode_block(data.as_str()).unwrap()
+        }
+
+        /// check the signature of the JWS `body` made with `jwk` (or the key in its protected
+        /// header) and return the protected header, the payload and the key
+        fn verify_jws(body: &str, jwk: Option<&JsonValue>) -> (JsonValue, String, JsonValue) {
+            let jws = JsonValue::parse(body).unwrap();
+            let field = |name| jws.get(name).unwrap().as_str().unwrap().to_string();
+            let protected = JsonValue::parse(
+                String::from_utf8(unbase64url(field("protected").as_str()))
+                    .unwrap()
+                    .as_str(),
+            )
+            .unwrap();
+            let jwk = jwk.or_else(|| protected.get("jwk")).unwrap().clone();
+            let coordinate = |name| {
+                BigNum::from_slice(&unbase64url(jwk.get(name).unwrap().as_str().unwrap())).unwrap()
+            };
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+            let key = EcKey::from_public_key_affine_coordinates(
+                &group,
+                &coordinate("x"),
+                &coordinate("y"),
+            )
+            .unwrap();
+            let signature = unbase64url(field("signature").as_str());
+            let signature = EcdsaSig::from_private_components(
+                BigNum::from_slice(&signature[..32]).unwrap(),
+                BigNum::from_slice(&signature[32..]).unwrap(),
+            )
+            .unwrap();
+            let input = format!("{}.{}", field("protected"), field("payload"));
+            let digest = openssl::hash::hash(MessageDigest::sha256(), input.as_bytes()).unwrap();
+            assert!(signature.verify(&digest, &key).unwrap());
+            let payload = String::from_utf8(unbase64url(field("payload").as_str())).unwrap();
+            (protected, payload, jwk)
+        }
+
+        /// a tiny ACME CA that issues one certificate for `localhost` using HTTP-01
+        fn mock_acme(listener: TcpListener, challenges: AcmeChallenges, ca_dir: PathBuf) {
+            let files = generate_certificates(&ca_dir, &[String::from("localhost")]).unwrap();
+            let acceptor = build_acceptor(
+                files.private_key.to_str().unwrap(),
+                files.certificate_chain.to_str().unwrap(),
+                &SniCertificates::new(),
+                &TlsOptions::default(),
+            )
+            .unwrap();
+            let (ca_key, ca_cert) = make_cert("acme.test", None);
+            let base = format!(
+                "https://localhost:{}",
+                listener.local_addr().unwrap().port()
+            );
+            let mut account: Option<JsonValue> = None;
+            let mut nonce = 0;
+            let mut authorized = false;
+            let mut issued: Option<X509> = None;
+            let mut rejected_nonce = false;
+            // when the client was told to wait before polling the processing order again
+            let mut processing: Option<std::time::Instant> = None;
+            for stream in listener.incoming() {
+                let mut stream = acceptor.accept(stream.unwrap()).unwrap();
+                let mut data = Vec::new();
+                let mut buf = [0; 4096];
+                let (head, body) = loop {
+                    let n = stream.read(&mut buf).unwrap();
+                    data.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&data).to_string();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|n| n.strip_prefix("Content-Length: "))
+                            .map(|n| n.parse::<usize>().unwrap())
+                            .unwrap_or(0);
+                        if body.len() >= length {
+                            break (head.to_string(), body.to_string());
+                        }
+                    }
+                };
+                let mut request = head.split(' ');
+                let method = request.next().unwrap().to_string();
+                let path = request.next().unwrap().to_string();
+
+                let mut location = None;
+                let mut retry_after = None;
+                let mut status = 200;
+                let response = if method == "POST" {
+                    let (protected, payload, jwk) = verify_jws(&body, account.as_ref());
+                    assert_eq!(
+                        protected.get("nonce").unwrap().as_str().unwrap(),
+                        format!("nonce-{}", nonce)
+                    );
+                    assert_eq!(
+                        protected.get("url").unwrap().as_str().unwrap(),
+                        format!("{}{}", base, path)
+                    );
+                    let order = |status: &str| {
+                        format!(
+                            "{{\"status\":\"{}\",\"authorizations\":[\"{}/authz\"],\"finalize\":\"{}/finalize\",\"certificate\":\"{}/cert\"}}",
+                            status, base, base, base
+                        )
+                    };
+                    match path.as_str() {
+                        "/account" => {
+                            assert!(payload.contains("\"termsOfServiceAgreed\":true"));
+                            account = Some(jwk);
+                            location = Some(format!("{}/account/1", base));
+                            status = 201;
+                            String::from("{\"status\":\"valid\"}")
+                        }
+                        // the client has to retry with the fresh nonce of the error
+                        "/order" if !rejected_nonce => {
+                            rejected_nonce = true;
+                            status = 400;
+                            String::from(
+                                "{\"type\":\"urn:ietf:params:acme:error:badNonce\",\"detail\":\"stale\"}",
+                            )
+                        }
+                        "/order" => {
+                            assert!(protected.get("kid").is_some());
+                            assert!(payload.contains("\"value\":\"localhost\""));
+                            location = Some(format!("{}/order/1", base));
+                            status = 201;
+                            order("pending")
+                        }
+                        "/authz" => format!(
+                            "{{\"status\":\"{}\",\"identifier\":{{\"type\":\"dns\",\"value\":\"localhost\"}},\"challenges\":[{{\"type\":\"http-01\",\"url\":\"{}/challenge\",\"token\":\"token\"}}]}}",
+                            if authorized { "valid" } else { "pending" },
+                            base
+                        ),
+                        "/challenge" => {
+                            // fetch the key authorization like a CA would over HTTP
+                            let req = HTTPRequest::from_string(String::from(
+                                "GET /.well-known/acme-challenge/token HTTP/1.1\r\n\r\n",
+                            ))
+                            .unwrap();
+                            let resp = challenges.http_response(&req).unwrap();
+                            let thumbprint = openssl::hash::hash(
+                                MessageDigest::sha256(),
+                                account.as_ref().unwrap().to_string().as_bytes(),
+                            )
+                            .unwrap();
+                            let expected = format!(
+                                "token.{}",
+                                openssl::base64::encode_block(&thumbprint)
+                                    .trim_end_matches('=')
+                                    .replace('+', "-")
+                                    .replace('/', "_")
+                            );
+                            assert!(resp.try_to_string().unwrap().ends_with(&expected));
+                            authorized = true;
+                            String::from("{}")
+                        }
+                        "/finalize" => {
+                            assert!(authorized);
+                            let csr = JsonValue::parse(&payload).unwrap();
+                            let csr = X509Req::from_der(&unbase64url(
+                                csr.get("csr").unwrap().as_str().unwrap(),
+                            ))
+                            .unwrap();
+                            let mut cert = X509::builder().unwrap();
+                            cert.set_version(2).unwrap();
+                            cert.set_subject_name(csr.subject_name()).unwrap();
+                            cert.set_issuer_name(ca_cert.subject_name()).unwrap();
+                            cert.set_pubkey(&csr.public_key().unwrap()).unwrap();
+                            cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+                                .unwrap();
+                            cert.set_not_after(&Asn1Time::days_from_now(90).unwrap())
+                                .unwrap();
+                            let san = SubjectAlternativeName::new()
+                                .dns("localhost")
+                                .build(&cert.x509v3_context(Some(&ca_cert), None))
+                                .unwrap();
+                            cert.append_extension(san).unwrap();
+                            cert.sign(&ca_key, MessageDigest::sha256()).unwrap();
+                            issued = Some(cert.build());
+                            order("processing")
+                        }
+                        "/order/1" => match (&issued, processing) {
+                            (None, _) => order(if authorized { "ready" } else { "pending" }),
+                            (Some(_), None) => {
+                                processing = Some(std::time::Instant::now());
+                                retry_after = Some(2);
+                                order("processing")
+                            }
+                            (Some(_), Some(since)) => {
+                                assert!(since.elapsed() >= std::time::Duration::from_secs(2));
+                                order("valid")
+                            }
+                        },
+                        "/cert" => {
+                            let mut chain = issued.as_ref().unwrap().to_pem().unwrap();
+                            chain.extend(ca_cert.to_pem().unwrap());
+                            String::from_utf8(chain).unwrap()
+                        }
+                        _ => panic!("unexpected request to {}", path),
+                    }
+                } else if path == "/dir" {
+                    format!(
+                        "{{\"newNonce\":\"{}/nonce\",\"newAccount\":\"{}/account\",\"newOrder\":\"{}/order\"}}",
+                        base, base, base
+                    )
+                } else {
+                    String::new()
+                };
+                nonce += 1;
+                let mut head = format!(
+                    "HTTP/1.1 {} OK\r\nReplay-Nonce: nonce-{}\r\nContent-Length: {}\r\nConnection: close\r\n",
+                    status,
+                    nonce,
+                    response.len()
+                );
+                if let Some(n) = retry_after {
+                    head.push_str(format!("Retry-After: {}\r\n", n).as_str());
+                }
+                if let Some(n) = location {
+                    head.push_str(format!("Location: {}\r\n", n).as_str());
+                }
+                stream
+                    .write_all(format!("{}\r\n{}", head, response).as_bytes())
+                    .unwrap();
+                let _ = stream.shutdown();
+                if path == "/cert" {
+                    return;
+                }
+            }
+        }
+
+        #[test]
+        fn acme_issuance() {
+            let dir = std::env::temp_dir().join(format!("rsweb-acme-{}", std::process::id()));
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let mut acme = Acme::new(
+                format!("https://localhost:{}/dir", port).as_str(),
+                vec![String::from("localhost")],
+                &dir.join("storage"),
+            );
+            acme.ca_file = Some(dir.join("ca").join("ca.pem").to_str().unwrap().to_string());
+            let challenges = acme.challenges();
+            let ca_dir = dir.join("ca");
+            // create the CA of the mock server before the client needs it
+            generate_certificates(&ca_dir, &[String::from("localhost")]).unwrap();
+            let handle = std::thread::spawn(move || mock_acme(listener, challenges, ca_dir));
+
+            // the placeholder certificate has to be replaced
+            let (key, cert) = acme.certificate_files().unwrap();
+            assert!(acme.needs_renewal());
+            acme.obtain().unwrap();
+            handle.join().unwrap();
+            assert!(!acme.needs_renewal());
+            assert!(
+                build_acceptor(&key, &cert, &SniCertificates::new(), &TlsOptions::default())
+                    .is_ok()
+            );
+            let _ = std::fs::remove_dir_all(dir);
+        }
+
+        /// issue a certificate from a running Pebble. `PEBBLE_DIRECTORY` is its directory URL,
+        /// `PEBBLE_CA` the CA of its API (`test/certs/pebble.minica.pem`) and the http-01
+        /// challenges are answered on `PEBBLE_HTTP_PORT` (5002 like in Pebble's test config).
+        /// Pebble rejects some nonces and delays validations by default, which exercises nonce
+        /// retries and polling (see `PEBBLE_WFE_NONCEREJECT` and `PEBBLE_VA_SLEEPTIME`)
+        #[test]
+        #[ignore]
+        fn acme_pebble() {
+            use crate::resource::ResourceLoader;
+            use crate::route::Router;
+            use crate::server::Server;
+
+            let directory = std::env::var("PEBBLE_DIRECTORY").expect("PEBBLE_DIRECTORY is not set");
+            let domain =
+                std::env::var("PEBBLE_DOMAIN").unwrap_or_else(|_| String::from("localhost"));
+            let port = std::env::var("PEBBLE_HTTP_PORT")
+                .map(|n| n.parse::<usize>().unwrap())
+                .unwrap_or(5002);
+            let dir = std::env::temp_dir().join(format!("rsweb-pebble-{}", std::process::id()));
+            let mut acme = Acme::new(directory.as_str(), vec![domain], &dir.join("storage"));
+            acme.ca_file = std::env::var("PEBBLE_CA").ok();
+            let mut server = Server::new(
+                1,
+                ResourceLoader::new(1, String::from("."), false),
+                Router::new(String::from("/index.html")),
+                port,
+                "0.0.0.0".parse().unwrap(),
+                toml::from_str("").unwrap(),
+            );
+            server.set_acme_challenges(Some(acme.challenges()));
+            let (bound, all_bound) = std::sync::mpsc::channel();
+            server.set_bound_notifier(Some(bound));
+            let log = dir.join("rsweb.log").to_str().unwrap().to_string();
+            std::fs::create_dir_all(&dir).unwrap();
+            std::thread::spawn(move || server.run(log.as_str()));
+            all_bound.recv().unwrap();
+
+            let (key, cert) = acme.certificate_files().unwrap();
+            assert!(acme.needs_renewal());
+            acme.obtain().unwrap();
+            assert!(!acme.needs_renewal());
+            assert!(
+                build_acceptor(&key, &cert, &SniCertificates::new(), &TlsOptions::default())
+                    .is_ok()
+            );
+            // an existing account is reused for renewals
+            acme.obtain().unwrap();
+            let _ = std::fs::remove_dir_all(dir);
+        }
+
+        #[test]
+        fn acme_tls_alpn() {
+            let challenges = AcmeChallenges::new();
+            challenges.add_tls(
+                "example.test",
+                challenge_acceptor("example.test", "token.thumbprint").unwrap(),
+            );
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let server = challenges.clone();
+            let handle = std::thread::spawn(move || {
+                let stream = listener.incoming().next().unwrap().unwrap();
+                let acceptor = server.tls_acceptor(&stream).unwrap();
+                let _ = acceptor.accept(stream);
+            });
+            let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+            connector.set_verify(SslVerifyMode::NONE);
+            connector.set_alpn_protos(b"\x0aacme-tls/1").unwrap();
+            let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let stream = connector.build().connect("example.test", stream).unwrap();
+            assert_eq!(
+                stream.ssl().selected_alpn_protocol(),
+                Some(&b"acme-tls/1"[..])
+            );
+            let cert = stream.ssl().peer_certificate().unwrap();
+            assert_eq!(certificate_names(&cert), vec!["example.test"]);
+            handle.join().unwrap();
+
+            let hello = parse_client_hello(b"\x16\x03\x01\x00\x02\x01\x00");
+            assert!(hello.is_none());
+        }
+
+        /// get the common name of the default certificate of `acceptor`
+        fn default_name(acceptor: &ReloadableAcceptor) -> String {
+            let acceptor = acceptor.acceptor();
+            let cert = acceptor.context().certificate().unwrap();
+            let cn = cert
+                .subject_name()
+                .entries_by_nid(Nid::COMMONNAME)
+                .next()
+                .unwrap();
+            cn.data().as_utf8().unwrap().to_string()
+        }
+
+        #[test]
+        fn reload_certificates() {
+            let (key, cert) = self_signed_as("old.test", "reload");
+            let acceptor =
+                ReloadableAcceptor::new(&key, &cert, SniCertificates::new(), TlsOptions::default())
+                    .unwrap();
+            assert!(acceptor.reload_if_changed().is_none());
+
+            self_signed_as("new.test", "reload");
+            let later = std::time::SystemTime::now() + std::time::Duration::from_secs(10);
+            let file = std::fs::File::options().write(true).open(&cert).unwrap();
+            file.set_modified(later).unwrap();
+            assert!(acceptor.reload_if_changed().unwrap().is_ok());
+            assert_eq!(default_name(&acceptor), "new.test");
+
+            // nothing is reloaded while the chain waits to replace the old one
+            let pending = pending_file(std::path::Path::new(&cert));
+            std::fs::write(&pending, "pending").unwrap();
+            std::fs::write(&key, "replaced").unwrap();
+            assert!(acceptor.reload_if_changed().is_none());
+            std::fs::remove_file(&pending).unwrap();
+            assert!(acceptor.reload_if_changed().unwrap().is_err());
+            assert_eq!(default_name(&acceptor), "new.test");
+
+            // a broken certificate keeps the old one in use
+            std::fs::write(&cert, "broken").unwrap();
+            assert!(acceptor.reload_if_changed().unwrap().is_err());
+            assert_eq!(default_name(&acceptor), "new.test");
+            assert!(acceptor.reload_if_changed().is_none());
+        }
+
+        #[test]
+        fn client_certificates() {
+            let (ca_key, ca) = make_cert("Test CA", None);
+            let (_, ca_file) = write_pem("client-ca", &ca_key, &ca);
+            let (client_key, client) = make_cert("client.test", Some((&ca_key, &ca)));
+            let (key, cert) = self_signed("mtls.test");
+            let options = TlsOptions {
+                client_auth: Some(ClientAuth::new(ClientAuthMode::Required, &ca_file)),
+                ..TlsOptions::default()
+            };
+            let acceptor = build_acceptor(&key, &cert, &SniCertificates::new(), &options).unwrap();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let handle = std::thread::spawn(move || {
+                let mut peers = Vec::new();
+                for stream in listener.incoming().take(2) {
+                    peers.push(
+                        acceptor
+                            .accept(stream.unwrap())
+                            .ok()
+                            .and_then(|n| PeerIdentity::from_ssl(n.ssl())),
+                    );
+                }
+                peers
+            });
+            for with_cert in [false, true] {
+                let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+                connector.set_verify(SslVerifyMode::NONE);
+                if with_cert {
+                    connector.set_private_key(&client_key).unwrap();
+                    connector.set_certificate(&client).unwrap();
+                }
+                let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+                // with TLS 1.3 the client only learns about the rejection on its first read
+                if let Ok(mut stream) = connector.build().connect("mtls.test", stream) {
+                    let _ = std::io::Read::read(&mut stream, &mut [0; 1]);
+                }
+            }
+            let peers = handle.join().unwrap();
+            assert!(peers[0].is_none());
+            let peer = peers[1].clone().unwrap();
+            assert_eq!(peer.subject, "CN=client.test");
+            assert_eq!(peer.sans, vec![String::from("client.test")]);
+            assert_eq!(peer.fingerprint.len(), 64);
+
+            let mut access = ClientAccess::new();
+            access.rule(ClientAccessRule {
+                path: String::from("/admin/*"),
+                subjects: vec![String::from("CN=admin.*")],
+                sans: Vec::new(),
+                fingerprints: vec![peer.fingerprint.to_uppercase()],
+            });
+            assert!(access.allowed("/index.html", None));
+            assert!(!access.allowed("/admin/users", None));
+            assert!(access.allowed("/admin/users", Some(&peer)));
+            let other = PeerIdentity {
+                subject: String::from("CN=other"),
+                sans: Vec::new(),
+                fingerprint: String::new(),
+            };
+            assert!(!access.allowed("/admin/users", Some(&other)));
+        }
+
+        #[test]
+        fn tls_policy() {
+            let (key, cert) = self_signed("policy.test");
+            let ocsp = format!("{}.ocsp", cert);
+            std::fs::write(&ocsp, b"stapled").unwrap();
+            let options = TlsOptions {
+                max_version: version_from_string("TLSv1.2"),
+                alpn: vec![String::from("http/1.1")],
+                session_tickets: false,
+                ocsp_response: Some(ocsp),
+                ..TlsOptions::default()
+            };
+            let acceptor = build_acceptor(&key, &cert, &SniCertificates::new(), &options).unwrap();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let handle = std::thread::spawn(move || {
+                let stream = listener.incoming().next().unwrap().unwrap();
+                let _ = acceptor.accept(stream);
+            });
+            let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+            connector.set_verify(SslVerifyMode::NONE);
+            connector.set_alpn_protos(b"\x02h2\x08http/1.1").unwrap();
+            connector
+                .set_status_callback(|ssl| Ok(ssl.ocsp_status().is_some()))
+                .unwrap();
+            let mut conf = connector.build().configure().unwrap();
+            conf.set_status_type(StatusType::OCSP).unwrap();
+            let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let stream = conf.connect("policy.test", stream).unwrap();
+            assert_eq!(stream.ssl().version2(), Some(SslVersion::TLS1_2));
+            assert_eq!(
+                stream.ssl().selected_alpn_protocol(),
+                Some(&b"http/1.1"[..])
+            );
+            assert_eq!(stream.ssl().ocsp_status(), Some(&b"stapled"[..]));
+            handle.join().unwrap();
+        }
     }
 }
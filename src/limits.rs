@@ -0,0 +1,147 @@
+use crate::config::LimitConfig;
+use crate::http::StatusCode;
+use wildmatch::WildMatch;
+
+/// the limit a request exceeded
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LimitExceeded {
+    /// the request line is too long (`414 URI Too Long`)
+    RequestLine,
+
+    /// the request has too many header fields (`431 Request Header Fields Too Large`)
+    HeaderCount,
+
+    /// the header of the request is too large (`431 Request Header Fields Too Large`)
+    HeaderSize,
+
+    /// the body of the request is too large (`413 Payload Too Large`)
+    Body,
+}
+
+impl LimitExceeded {
+    /// get the status code to respond with
+    pub fn status(&self) -> StatusCode {
+        match self {
+            LimitExceeded::RequestLine => StatusCode::URITooLong,
+            LimitExceeded::HeaderCount | LimitExceeded::HeaderSize => {
+                StatusCode::RequestHeaderFieldsTooLarge
+            }
+            LimitExceeded::Body => StatusCode::PayloadTooLarge,
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let string = match self {
+            LimitExceeded::RequestLine => "request line too long",
+            LimitExceeded::HeaderCount => "too many header fields",
+            LimitExceeded::HeaderSize => "request header too large",
+            LimitExceeded::Body => "request body too large",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+impl From<LimitExceeded> for std::io::Error {
+    fn from(e: LimitExceeded) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// size limits for requests. A `None` value disables the corresponding limit
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// maximum length of the request line in bytes
+    pub request_line: Option<usize>,
+
+    /// maximum amount of header fields
+    pub header_count: Option<usize>,
+
+    /// maximum size of all header fields together in bytes
+    pub header_size: Option<usize>,
+
+    /// maximum size of a request body in bytes
+    pub body_size: Option<usize>,
+
+    /// body size limits for paths matching a wildcard pattern. These take precedence over
+    /// `body_size`
+    pub routes: Vec<(String, Option<usize>)>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            request_line: Some(8192),
+            header_count: Some(100),
+            header_size: Some(32768),
+            body_size: Some(1048576),
+            routes: Vec::new(),
+        }
+    }
+}
+
+impl Limits {
+    /// limits that allow requests of any size
+    pub fn none() -> Limits {
+        Limits {
+            request_line: None,
+            header_count: None,
+            header_size: None,
+            body_size: None,
+            routes: Vec::new(),
+        }
+    }
+
+    /// create limits from a config section. Values that aren't set use the defaults, values set
+    /// to `0` disable the limit
+    pub fn from_config(conf: Option<&LimitConfig>) -> Limits {
+        let default = Limits::default();
+        let conf = match conf {
+            Some(n) => n,
+            None => return default,
+        };
+        let limit = |value: Option<usize>, default: Option<usize>| match value {
+            Some(0) => None,
+            Some(n) => Some(n),
+            None => default,
+        };
+        Limits {
+            request_line: limit(conf.request_line, default.request_line),
+            header_count: limit(conf.header_count, default.header_count),
+            header_size: limit(conf.header_size, default.header_size),
+            body_size: limit(conf.body_size, default.body_size),
+            routes: conf
+                .routes
+                .clone()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|n| (n.path, limit(Some(n.body_size), None)))
+                .collect(),
+        }
+    }
+
+    /// add a body size limit for all paths matching `pattern`
+    pub fn route(&mut self, pattern: String, body_size: Option<usize>) {
+        self.routes.push((pattern, body_size));
+    }
+
+    /// get the maximum body size for a request to `path`
+    pub fn body_limit(&self, path: &str) -> Option<usize> {
+        for (pattern, limit) in &self.routes {
+            if WildMatch::new(pattern.as_str()).matches(path) {
+                return *limit;
+            }
+        }
+        self.body_size
+    }
+}
+
+/// get the [`LimitExceeded`] an io error was created from (if any)
+pub fn limit_exceeded(e: &std::io::Error) -> Option<LimitExceeded> {
+    e.get_ref()
+        .and_then(|n| n.downcast_ref::<LimitExceeded>())
+        .copied()
+}
@@ -0,0 +1,772 @@
+use crate::config::{HTTPConfig, ListenConfig, SslConfig};
+use crate::error;
+use crate::error::ServerError;
+use crate::log::Logger;
+use crate::mtls::PeerIdentity;
+use crate::timeout::SetTimeout;
+use openssl::ssl::SslStream;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+#[cfg(unix)]
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// prefix of unix domain socket addresses, e.g. `unix:/run/rsweb.sock`
+pub const UNIX_PREFIX: &str = "unix:";
+
+/// held while the umask is changed to bind a unix socket
+#[cfg(unix)]
+static UMASK: Mutex<()> = Mutex::new(());
+
+/// the address of a [`Listener`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Address {
+    Tcp(SocketAddr),
+    /// the path of a unix domain socket
+    Unix(PathBuf),
+}
+
+impl Address {
+    /// read an address like `0.0.0.0:80`, `[::]:80` or `unix:/run/rsweb.sock`
+    pub fn from_string(address: &str) -> Result<Address, ServerError> {
+        if let Some(path) = address.strip_prefix(UNIX_PREFIX) {
+            return match path.is_empty() {
+                true => Err(ServerError::new("unix socket addresses need a path")),
+                false => Ok(Address::Unix(PathBuf::from(path))),
+            };
+        }
+        match address.parse::<SocketAddr>() {
+            Ok(n) => Ok(Address::Tcp(n)),
+            Err(_) => Err(ServerError::new(
+                format!(
+                    "'{}' is not an address like '0.0.0.0:80', '[::]:80' or 'unix:/run/rsweb.sock'",
+                    address
+                )
+                .as_str(),
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            Address::Tcp(n) => write!(f, "{}", n),
+            Address::Unix(n) => write!(f, "{}{}", UNIX_PREFIX, n.display()),
+        }
+    }
+}
+
+/// an address a server listens on together with the options of its socket
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Listener {
+    pub addr: Address,
+    /// connections are TLS connections. Not supported on unix sockets
+    pub tls: bool,
+    /// set `SO_REUSEADDR`
+    pub reuse_address: bool,
+    /// set `SO_REUSEPORT`
+    pub reuse_port: bool,
+    /// set `IPV6_V6ONLY` on IPv6 addresses. `None` keeps the system setting
+    pub ipv6_only: Option<bool>,
+    /// permissions of a unix socket. `None` leaves them to the umask
+    pub mode: Option<u32>,
+    /// user owning a unix socket, by name or id
+    pub owner: Option<String>,
+    /// group owning a unix socket, by name or id
+    pub group: Option<String>,
+}
+
+impl Listener {
+    /// create a listener on `addr` with `SO_REUSEADDR` set
+    pub fn new(addr: SocketAddr, tls: bool) -> Listener {
+        Listener {
+            addr: Address::Tcp(addr),
+            tls,
+            reuse_address: true,
+            reuse_port: false,
+            ipv6_only: None,
+            mode: None,
+            owner: None,
+            group: None,
+        }
+    }
+
+    /// create a listener on the unix socket at `path`. A socket left over from a previous run
+    /// is replaced when it is bound
+    pub fn unix(path: PathBuf) -> Listener {
+        Listener {
+            addr: Address::Unix(path),
+            ..Listener::new(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)), false)
+        }
+    }
+
+    /// create a listener from a `listen` entry. `tls` is used if the entry doesn't set it
+    pub fn from_config(conf: &ListenConfig, tls: bool) -> Result<Listener, ServerError> {
+        let mut listener = match Address::from_string(conf.address.as_str())? {
+            Address::Tcp(n) => Listener::new(n, tls),
+            Address::Unix(n) => Listener::unix(n),
+        };
+        listener.tls = conf.tls.unwrap_or(tls);
+        listener.reuse_address = conf.reuse_address.unwrap_or(true);
+        listener.reuse_port = conf.reuse_port.unwrap_or(false);
+        listener.ipv6_only = conf.ipv6_only;
+        listener.mode = match &conf.mode {
+            Some(n) => Some(parse_mode(n)?),
+            None => None,
+        };
+        listener.owner = conf.owner.clone();
+        listener.group = conf.group.clone();
+        Ok(listener)
+    }
+
+    /// get the address of a TCP listener
+    pub fn tcp_addr(&self) -> Option<SocketAddr> {
+        match &self.addr {
+            Address::Tcp(n) => Some(*n),
+            Address::Unix(_) => None,
+        }
+    }
+
+    /// check whether binding both listeners would fail because their addresses overlap
+    pub fn conflicts(&self, other: &Listener) -> bool {
+        let (a, b) = match (&self.addr, &other.addr) {
+            (Address::Tcp(a), Address::Tcp(b)) => (a, b),
+            (Address::Unix(a), Address::Unix(b)) => return a == b,
+            _ => return false,
+        };
+        if a.port() != b.port() || (self.reuse_port && other.reuse_port) {
+            return false;
+        }
+        match (a.ip(), b.ip()) {
+            (IpAddr::V4(_), IpAddr::V6(n)) => n.is_unspecified() && other.ipv6_only != Some(true),
+            (IpAddr::V6(n), IpAddr::V4(_)) => n.is_unspecified() && self.ipv6_only != Some(true),
+            (a, b) => a == b || a.is_unspecified() || b.is_unspecified(),
+        }
+    }
+
+    /// bind a socket to the address using the options of the listener
+    pub fn bind(&self) -> std::io::Result<Socket> {
+        match &self.addr {
+            Address::Tcp(n) => Ok(Socket::Tcp(self.bind_tcp(*n)?)),
+            #[cfg(unix)]
+            Address::Unix(n) => Ok(Socket::Unix(self.bind_unix(n)?)),
+            #[cfg(not(unix))]
+            Address::Unix(_) => Err(std::io::Error::new(
+                ErrorKind::Unsupported,
+                "unix sockets aren't supported on this system",
+            )),
+        }
+    }
+
+    /// bind the unix socket at `path`, replacing a stale one, and set its permissions and
+    /// ownership
+    #[cfg(unix)]
+    fn bind_unix(&self, path: &Path) -> std::io::Result<UnixListener> {
+        use std::os::unix::fs::PermissionsExt;
+
+        if self.tls {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "TLS isn't supported on unix sockets",
+            ));
+        }
+        let uid = match &self.owner {
+            Some(n) => Some(user_id(n)?),
+            None => None,
+        };
+        let gid = match &self.group {
+            Some(n) => Some(group_id(n)?),
+            None => None,
+        };
+        remove_stale(path)?;
+        let restrict = self.mode.is_some() || uid.is_some() || gid.is_some();
+        let (socket, umask) = if restrict {
+            // only the owner may connect until the configured owner and mode are set. The umask
+            // is shared by all threads, so servers binding at the same time take turns
+            let _lock = UMASK.lock().unwrap_or_else(|e| e.into_inner());
+            // SAFETY: umask has no memory safety requirements
+            let umask = unsafe { libc::umask(0o177) };
+            let socket = UnixListener::bind(path);
+            // SAFETY: see above
+            unsafe { libc::umask(umask) };
+            // mode_t is smaller than u32 on some systems
+            #[allow(clippy::useless_conversion)]
+            let umask = u32::from(umask);
+            (socket?, Some(umask))
+        } else {
+            (UnixListener::bind(path)?, None)
+        };
+        let permissions = || -> std::io::Result<()> {
+            if uid.is_some() || gid.is_some() {
+                std::os::unix::fs::chown(path, uid, gid)?;
+            }
+            if let Some(umask) = umask {
+                let mode = self.mode.unwrap_or(0o777 & !umask);
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+            }
+            Ok(())
+        };
+        // don't leave a socket with the wrong owner or permissions behind
+        if let Err(e) = permissions() {
+            let _ = std::fs::remove_file(path);
+            return Err(e);
+        }
+        Ok(socket)
+    }
+
+    #[cfg(unix)]
+    fn bind_tcp(&self, addr: SocketAddr) -> std::io::Result<TcpListener> {
+        use std::mem::size_of;
+        use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+
+        let family = match addr {
+            SocketAddr::V4(_) => libc::AF_INET,
+            SocketAddr::V6(_) => libc::AF_INET6,
+        };
+        // SAFETY: socket has no memory safety requirements
+        let fd = unsafe { libc::socket(family, libc::SOCK_STREAM, 0) };
+        if fd == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // SAFETY: the descriptor was just created and isn't owned by anything else
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+        // SAFETY: the descriptor is open
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        set_option(fd, libc::SOL_SOCKET, libc::SO_REUSEADDR, self.reuse_address)?;
+        if self.reuse_port {
+            set_option(fd, libc::SOL_SOCKET, libc::SO_REUSEPORT, true)?;
+        }
+        let result = match addr {
+            SocketAddr::V4(addr) => {
+                // SAFETY: sockaddr_in is plain old data
+                let mut sin: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+                sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                sin.sin_port = addr.port().to_be();
+                sin.sin_addr = libc::in_addr {
+                    s_addr: u32::from_ne_bytes(addr.ip().octets()),
+                };
+                // SAFETY: sin is a valid sockaddr_in of the given length
+                unsafe {
+                    libc::bind(
+                        fd,
+                        &sin as *const libc::sockaddr_in as *const libc::sockaddr,
+                        size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                    )
+                }
+            }
+            SocketAddr::V6(addr) => {
+                if let Some(n) = self.ipv6_only {
+                    set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, n)?;
+                }
+                // SAFETY: sockaddr_in6 is plain old data
+                let mut sin6: libc::sockaddr_in6 = unsafe { std::mem::zeroed() };
+                sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sin6.sin6_port = addr.port().to_be();
+                sin6.sin6_flowinfo = addr.flowinfo();
+                sin6.sin6_addr = libc::in6_addr {
+                    s6_addr: addr.ip().octets(),
+                };
+                sin6.sin6_scope_id = addr.scope_id();
+                // SAFETY: sin6 is a valid sockaddr_in6 of the given length
+                unsafe {
+                    libc::bind(
+                        fd,
+                        &sin6 as *const libc::sockaddr_in6 as *const libc::sockaddr,
+                        size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+                    )
+                }
+            }
+        };
+        if result == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // SAFETY: the socket is bound
+        if unsafe { libc::listen(socket.as_raw_fd(), 128) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(TcpListener::from(socket))
+    }
+
+    /// bind a socket to `addr`. The socket options are only supported on unix
+    #[cfg(not(unix))]
+    fn bind_tcp(&self, addr: SocketAddr) -> std::io::Result<TcpListener> {
+        TcpListener::bind(addr)
+    }
+}
+
+impl std::fmt::Display for Listener {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self.tls {
+            true => write!(f, "{} (TLS)", self.addr),
+            false => write!(f, "{}", self.addr),
+        }
+    }
+}
+
+/// read the permissions of a unix socket written in octal, e.g. `"0660"`
+pub fn parse_mode(mode: &str) -> Result<u32, ServerError> {
+    match u32::from_str_radix(mode.trim_start_matches("0o"), 8) {
+        Ok(n) if n <= 0o7777 => Ok(n),
+        _ => Err(ServerError::new(
+            format!("'{}' is not an octal mode like '0660'", mode).as_str(),
+        )),
+    }
+}
+
+/// remove the unix socket at `path` if it was left over by a process that isn't running
+/// anymore. Fails if a process still accepts connections on it or if `path` isn't a socket
+#[cfg(unix)]
+fn remove_stale(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(n) => n,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(std::io::Error::new(
+            ErrorKind::AlreadyExists,
+            "the path exists and isn't a socket",
+        ));
+    }
+    match UnixStream::connect(path) {
+        Ok(_) => Err(std::io::Error::new(
+            ErrorKind::AddrInUse,
+            "another process is listening on the socket",
+        )),
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => std::fs::remove_file(path),
+        Err(e) => Err(e),
+    }
+}
+
+/// get the id of the user `name`, which may also be an id
+#[cfg(unix)]
+pub(crate) fn user_id(name: &str) -> std::io::Result<u32> {
+    if let Ok(n) = name.parse::<u32>() {
+        return Ok(n);
+    }
+    let cname = match std::ffi::CString::new(name) {
+        Ok(n) => n,
+        Err(_) => return Err(std::io::Error::new(ErrorKind::InvalidInput, "invalid name")),
+    };
+    // SAFETY: passwd is plain old data
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16384];
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    // SAFETY: all pointers are valid for the duration of the call and buf.len() is its size
+    let ret = unsafe {
+        libc::getpwnam_r(
+            cname.as_ptr(),
+            &mut entry,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::from_raw_os_error(ret));
+    }
+    match result.is_null() {
+        true => Err(std::io::Error::new(
+            ErrorKind::NotFound,
+            format!("unknown user '{}'", name),
+        )),
+        false => Ok(entry.pw_uid),
+    }
+}
+
+/// get the id of the group `name`, which may also be an id
+#[cfg(unix)]
+pub(crate) fn group_id(name: &str) -> std::io::Result<u32> {
+    if let Ok(n) = name.parse::<u32>() {
+        return Ok(n);
+    }
+    let cname = match std::ffi::CString::new(name) {
+        Ok(n) => n,
+        Err(_) => return Err(std::io::Error::new(ErrorKind::InvalidInput, "invalid name")),
+    };
+    // SAFETY: group is plain old data
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 16384];
+    let mut result: *mut libc::group = std::ptr::null_mut();
+    // SAFETY: all pointers are valid for the duration of the call and buf.len() is its size
+    let ret = unsafe {
+        libc::getgrnam_r(
+            cname.as_ptr(),
+            &mut entry,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::from_raw_os_error(ret));
+    }
+    match result.is_null() {
+        true => Err(std::io::Error::new(
+            ErrorKind::NotFound,
+            format!("unknown group '{}'", name),
+        )),
+        false => Ok(entry.gr_gid),
+    }
+}
+
+/// set the boolean socket option `name`
+#[cfg(unix)]
+fn set_option(
+    fd: libc::c_int,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: bool,
+) -> std::io::Result<()> {
+    let value = value as libc::c_int;
+    // SAFETY: value lives until the call returns and its size is passed along
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    match result {
+        -1 => Err(std::io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// get the address passed to a server constructor as `ip` and `port`. Unlike in the config, port
+/// `0` is allowed and binds an ephemeral port
+pub(crate) fn server_addr(ip: IpAddr, port: usize) -> Result<SocketAddr, ServerError> {
+    match u16::try_from(port) {
+        Ok(n) => Ok(SocketAddr::new(ip, n)),
+        Err(_) => Err(ServerError::new(
+            format!("port {} is not between 0 and 65535", port).as_str(),
+        )),
+    }
+}
+
+/// get the listeners of a config section: `ip` and `port` (if set) followed by the `listen`
+/// entries. `tls` is the default of their TLS flag. Unspecified IPv6 addresses sharing a port
+/// with `0.0.0.0` only accept IPv6 connections so both can be bound
+pub fn from_section(
+    ip: Option<&String>,
+    port: Option<usize>,
+    listen: Option<&Vec<ListenConfig>>,
+    tls: bool,
+) -> Result<Vec<Listener>, ServerError> {
+    let mut listeners = Vec::new();
+    if let Some(port) = port {
+        let ip = match ip {
+            Some(n) => match n.parse::<IpAddr>() {
+                Ok(n) => n,
+                Err(_) => {
+                    return Err(ServerError::new(
+                        format!("'{}' is not an IP address", n).as_str(),
+                    ))
+                }
+            },
+            None => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        };
+        let port = match u16::try_from(port) {
+            Ok(n) if n > 0 => n,
+            _ => {
+                return Err(ServerError::new(
+                    format!("port {} is not between 1 and 65535", port).as_str(),
+                ))
+            }
+        };
+        listeners.push(Listener::new(SocketAddr::new(ip, port), tls));
+    }
+    for n in listen.into_iter().flatten() {
+        listeners.push(Listener::from_config(n, tls)?);
+    }
+    if listeners.is_empty() {
+        return Err(ServerError::new("a port or listen address is required"));
+    }
+    dual_stack(&mut listeners);
+    Ok(listeners)
+}
+
+/// get the listeners of the `http` section
+pub fn http_listeners(conf: &HTTPConfig) -> Result<Vec<Listener>, ServerError> {
+    from_section(conf.ip.as_ref(), conf.port, conf.listen.as_ref(), false)
+}
+
+/// get the listeners of the `ssl` section
+pub fn ssl_listeners(conf: &SslConfig) -> Result<Vec<Listener>, ServerError> {
+    from_section(conf.ip.as_ref(), conf.port, conf.listen.as_ref(), true)
+}
+
+/// get the port of the first TLS listener of the `ssl` section, e.g. to redirect to it
+pub fn tls_port(conf: &SslConfig) -> Option<usize> {
+    ssl_listeners(conf)
+        .ok()?
+        .iter()
+        .filter(|n| n.tls)
+        .find_map(|n| n.tcp_addr())
+        .map(|n| n.port() as usize)
+}
+
+/// make unspecified IPv6 listeners that don't set `ipv6_only` only accept IPv6 connections if
+/// there is a `0.0.0.0` listener with the same port. Otherwise binding the second one fails on
+/// systems accepting IPv4 connections on IPv6 sockets
+pub fn dual_stack(listeners: &mut [Listener]) {
+    let ipv4: Vec<u16> = listeners
+        .iter()
+        .filter_map(|n| n.tcp_addr())
+        .filter(|n| n.ip() == IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+        .map(|n| n.port())
+        .collect();
+    for n in listeners.iter_mut() {
+        if let Some(SocketAddr::V6(addr)) = n.tcp_addr() {
+            if addr.ip().is_unspecified() && n.ipv6_only.is_none() && ipv4.contains(&addr.port()) {
+                n.ipv6_only = Some(true);
+            }
+        }
+    }
+}
+
+/// bind all `listeners`. Fails if any of them can't be bound
+pub fn bind_all(listeners: &[Listener]) -> std::io::Result<Vec<(Listener, Socket)>> {
+    let mut bound = Vec::new();
+    for n in listeners {
+        match n.bind() {
+            Ok(socket) => bound.push((n.clone(), socket)),
+            Err(e) => {
+                return Err(std::io::Error::new(
+                    e.kind(),
+                    format!("failed to bind {}: {}", n.addr, e),
+                ))
+            }
+        }
+    }
+    Ok(bound)
+}
+
+/// accept connections on all `bound` listeners in background threads. The connections are
+/// received together with the listener that accepted them
+pub(crate) fn accept_all(
+    bound: Vec<(Listener, Socket)>,
+    logger: &Logger,
+) -> Receiver<(Connection, Listener)> {
+    let (sender, receiver) = channel();
+    for (listener, socket) in bound {
+        let sender = sender.clone();
+        let logger = logger.clone();
+        std::thread::spawn(move || loop {
+            match socket.accept() {
+                Ok(n) => {
+                    if sender.send((n, listener.clone())).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => error!(logger, "{}", e),
+            }
+        });
+    }
+    receiver
+}
+
+/// a bound [`Listener`]
+pub enum Socket {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Socket {
+    /// get the address the socket is bound to, e.g. `127.0.0.1:8080` or
+    /// `unix:/run/rsweb.sock`
+    pub fn local_addr(&self) -> std::io::Result<String> {
+        match self {
+            Socket::Tcp(n) => Ok(n.local_addr()?.to_string()),
+            #[cfg(unix)]
+            Socket::Unix(n) => match n.local_addr()?.as_pathname() {
+                Some(path) => Ok(format!("{}{}", UNIX_PREFIX, path.display())),
+                None => Ok(format!("{}(unnamed)", UNIX_PREFIX)),
+            },
+        }
+    }
+
+    /// wait for the next connection
+    pub fn accept(&self) -> std::io::Result<Connection> {
+        match self {
+            Socket::Tcp(n) => Ok(Connection::Plain(n.accept()?.0)),
+            #[cfg(unix)]
+            Socket::Unix(n) => Ok(Connection::Unix(n.accept()?.0)),
+        }
+    }
+}
+
+/// the credentials of the process on the other end of a unix socket
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PeerCredentials {
+    pub uid: u32,
+    pub gid: u32,
+    /// only available on some systems (e.g. Linux)
+    pub pid: Option<i32>,
+}
+
+impl PeerCredentials {
+    /// get the credentials of the peer of `stream` (`SO_PEERCRED`)
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn from_stream(stream: &UnixStream) -> std::io::Result<PeerCredentials> {
+        use std::os::unix::io::AsRawFd;
+
+        // SAFETY: ucred is plain old data
+        let mut cred: libc::ucred = unsafe { std::mem::zeroed() };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        // SAFETY: cred and len are valid for the duration of the call and len is the size of cred
+        let result = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut cred as *mut libc::ucred as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if result == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(PeerCredentials {
+            uid: cred.uid,
+            gid: cred.gid,
+            pid: Some(cred.pid),
+        })
+    }
+
+    /// get the credentials of the peer of `stream` (`getpeereid`)
+    #[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+    pub fn from_stream(stream: &UnixStream) -> std::io::Result<PeerCredentials> {
+        use std::os::unix::io::AsRawFd;
+
+        let mut uid: libc::uid_t = 0;
+        let mut gid: libc::gid_t = 0;
+        // SAFETY: uid and gid are valid for the duration of the call
+        if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(PeerCredentials {
+            uid,
+            gid,
+            pid: None,
+        })
+    }
+}
+
+/// a connection accepted by a [`Listener`]
+pub enum Connection {
+    Plain(TcpStream),
+    Tls(Box<SslStream<TcpStream>>),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Connection {
+    /// check whether the connection uses TLS
+    pub fn is_tls(&self) -> bool {
+        matches!(self, Connection::Tls(_))
+    }
+
+    /// get the IP address of the client. Unix socket connections don't have one
+    pub fn peer_ip(&self) -> Option<IpAddr> {
+        match self {
+            Connection::Plain(n) => n.peer_addr().ok().map(|n| n.ip()),
+            Connection::Tls(n) => n.get_ref().peer_addr().ok().map(|n| n.ip()),
+            #[cfg(unix)]
+            Connection::Unix(_) => None,
+        }
+    }
+
+    /// get the verified client certificate of a TLS connection
+    pub fn peer_identity(&self) -> Option<PeerIdentity> {
+        match self {
+            Connection::Tls(n) => PeerIdentity::from_ssl(n.ssl()),
+            _ => None,
+        }
+    }
+
+    /// get the credentials of the client process of a unix socket connection
+    pub fn peer_credentials(&self) -> Option<PeerCredentials> {
+        match self {
+            #[cfg(unix)]
+            Connection::Unix(n) => PeerCredentials::from_stream(n).ok(),
+            _ => None,
+        }
+    }
+
+    /// close the connection
+    pub fn shutdown(&mut self) -> std::io::Result<()> {
+        match self {
+            Connection::Plain(n) => n.shutdown(std::net::Shutdown::Both),
+            Connection::Tls(n) => match n.shutdown() {
+                Ok(_) => Ok(()),
+                Err(e) => Err(std::io::Error::other(e.to_string())),
+            },
+            #[cfg(unix)]
+            Connection::Unix(n) => n.shutdown(std::net::Shutdown::Both),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Connection::Plain(n) => n.read(buf),
+            Connection::Tls(n) => n.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(n) => n.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Connection::Plain(n) => n.write(buf),
+            Connection::Tls(n) => n.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(n) => n.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Connection::Plain(n) => n.flush(),
+            Connection::Tls(n) => n.flush(),
+            #[cfg(unix)]
+            Connection::Unix(n) => n.flush(),
+        }
+    }
+}
+
+impl SetTimeout for Connection {
+    fn set_read_timeout(&self, dur: Option<Duration>) -> std::io::Result<()> {
+        match self {
+            Connection::Plain(n) => SetTimeout::set_read_timeout(n, dur),
+            Connection::Tls(n) => n.set_read_timeout(dur),
+            #[cfg(unix)]
+            Connection::Unix(n) => SetTimeout::set_read_timeout(n, dur),
+        }
+    }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> std::io::Result<()> {
+        match self {
+            Connection::Plain(n) => SetTimeout::set_write_timeout(n, dur),
+            Connection::Tls(n) => n.set_write_timeout(dur),
+            #[cfg(unix)]
+            Connection::Unix(n) => SetTimeout::set_write_timeout(n, dur),
+        }
+    }
+}
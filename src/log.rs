@@ -1,157 +1,630 @@
+use crate::config::LogConfig;
+use crate::error::ServerError;
+use crate::json::JsonValue;
+use crate::logfile::{LogFile, Rotation};
+#[cfg(unix)]
+use crate::syslog::{Journald, Syslog};
 use btui::Terminal;
 use btui::{
     effects::*,
     print::{fg, sp},
 };
 use chrono::prelude::*;
-use std::fs::File;
-use std::fs::OpenOptions;
-use std::io::Write;
-use std::path::Path;
-
-/// the type of log
-pub enum LogType {
-    Warning,
+use serde_derive::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// messages that can be queued before new ones are dropped
+pub const DEFAULT_QUEUE_SIZE: usize = 1024;
+
+/// the severity of a log message
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
     Error,
-    Log,
 }
 
-/// A logger that logs to stderr and/or a file
-pub struct Logger {
+impl Level {
+    /// get the level called `string` (`trace`, `debug`, `info`, `warn` or `error`)
+    pub fn from_string(string: String) -> Option<Level> {
+        match string.to_lowercase().as_str() {
+            "trace" => Some(Level::Trace),
+            "debug" => Some(Level::Debug),
+            "info" => Some(Level::Info),
+            "warn" | "warning" => Some(Level::Warn),
+            "error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+
+    /// get the label written in front of messages. Info messages have none
+    fn label(&self) -> Option<(&'static str, Color)> {
+        match self {
+            Level::Trace => Some(("Trace", Color::Magenta)),
+            Level::Debug => Some(("Debug", Color::Cyan)),
+            Level::Info => None,
+            Level::Warn => Some(("Warning", Color::Yellow)),
+            Level::Error => Some(("Error", Color::Red)),
+        }
+    }
+}
+
+impl std::fmt::Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let s = match self {
+            Level::Trace => "trace",
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// the minimum level of messages that get logged, overridable per module
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Filter {
+    level: Level,
+    modules: Vec<(String, Level)>,
+}
+
+impl Filter {
+    /// create a filter that logs messages of `level` and above from all modules
+    pub fn new(level: Level) -> Filter {
+        Filter {
+            level,
+            modules: Vec::new(),
+        }
+    }
+
+    /// log messages of `level` and above from `module` and its submodules (e.g. `rsweb::tls`)
+    pub fn module(&mut self, module: &str, level: Level) {
+        self.modules.retain(|(n, _)| n != module);
+        self.modules.push((module.to_string(), level));
+    }
+
+    /// parse a filter like `info,rsweb::acme=debug`: a default level followed by levels of
+    /// modules. Entries are separated by commas
+    pub fn from_string(string: &str) -> Result<Filter, ServerError> {
+        let mut filter = Filter::new(Level::Info);
+        for part in string
+            .split(',')
+            .map(|n| n.trim())
+            .filter(|n| !n.is_empty())
+        {
+            let (module, level) = match part.split_once('=') {
+                Some((m, l)) => (Some(m.trim()), l.trim()),
+                None => (None, part),
+            };
+            let level = match Level::from_string(level.to_string()) {
+                Some(n) => n,
+                None => {
+                    return Err(ServerError::new(
+                        format!("invalid log level: '{}'", level).as_str(),
+                    ))
+                }
+            };
+            match module {
+                Some(m) => filter.module(m, level),
+                None => filter.level = level,
+            }
+        }
+        Ok(filter)
+    }
+
+    /// check whether messages of `level` from `module` get logged. The most specific module
+    /// filter applies
+    pub fn enabled(&self, level: Level, module: &str) -> bool {
+        let min = self
+            .modules
+            .iter()
+            .filter(|(n, _)| {
+                module == n
+                    || (module.starts_with(n.as_str()) && module[n.len()..].starts_with("::"))
+            })
+            .max_by_key(|(n, _)| n.len())
+            .map(|(_, l)| *l)
+            .unwrap_or(self.level);
+        level >= min
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::new(Level::Info)
+    }
+}
+
+/// how messages are written to the terminal and log file
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `[dd-mm-YYYY HH:MM:SS]: Level: message key=value`
+    #[default]
+    Text,
+    /// one JSON object per line
+    Json,
+}
+
+/// a logged message
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub time: DateTime<Local>,
+    pub level: Level,
+    /// the module the message was logged from, e.g. `rsweb::server`
+    pub module: String,
+    /// name of the thread the message was logged from, e.g. `rsweb-worker-3`
+    pub thread: String,
+    pub msg: String,
+    /// additional information as (key, value), e.g. `("path", "/index.html")`
+    pub fields: Vec<(String, String)>,
+}
+
+impl Record {
+    /// get the record as a JSON object. The fields are members of the object unless their
+    /// names are taken by the record itself
+    pub fn to_json(&self) -> JsonValue {
+        let mut members = vec![
+            (
+                String::from("timestamp"),
+                JsonValue::from(self.time.to_rfc3339_opts(SecondsFormat::Millis, false)),
+            ),
+            (
+                String::from("level"),
+                JsonValue::from(self.level.to_string()),
+            ),
+            (String::from("message"), JsonValue::from(self.msg.as_str())),
+            (
+                String::from("module"),
+                JsonValue::from(self.module.as_str()),
+            ),
+            (
+                String::from("thread"),
+                JsonValue::from(self.thread.as_str()),
+            ),
+        ];
+        for (k, v) in &self.fields {
+            if !members.iter().any(|(n, _)| n == k) {
+                members.push((k.clone(), JsonValue::from(v.as_str())));
+            }
+        }
+        JsonValue::Object(members)
+    }
+}
+
+thread_local! {
+    /// id of the request the current thread is handling
+    static REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// while it exists, messages logged from the current thread get the id of the request being
+/// handled as `request_id` field
+#[derive(Debug)]
+pub struct RequestScope {
+    previous: Option<String>,
+}
+
+impl RequestScope {
+    /// attach `id` to the messages logged from the current thread until the scope is dropped
+    pub fn enter(id: &str) -> RequestScope {
+        let previous = REQUEST_ID.with(|n| n.replace(Some(id.to_string())));
+        RequestScope { previous }
+    }
+
+    /// get the id of the request the current thread is handling
+    pub fn current() -> Option<String> {
+        REQUEST_ID.with(|n| n.borrow().clone())
+    }
+}
+
+impl Drop for RequestScope {
+    fn drop(&mut self) {
+        REQUEST_ID.with(|n| *n.borrow_mut() = self.previous.take());
+    }
+}
+
+/// get a name for the current thread
+fn thread_name() -> String {
+    let thread = std::thread::current();
+    match thread.name() {
+        Some(n) => n.to_string(),
+        None => format!("{:?}", thread.id()),
+    }
+}
+
+enum Entry {
+    Record(Record),
+    /// answered once all earlier messages were written
+    Flush(SyncSender<()>),
+}
+
+/// where messages are written to. Only used by the writer thread and the setters
+#[derive(Default)]
+struct Sinks {
     term: Option<Terminal>,
-    file: Option<File>,
+    term_format: LogFormat,
+    file: Option<LogFile>,
+    file_format: LogFormat,
+    rotation: Rotation,
+    #[cfg(unix)]
+    syslog: Option<Syslog>,
+    #[cfg(unix)]
+    journald: Option<Journald>,
+}
+
+impl Sinks {
+    fn write(&mut self, record: &Record) {
+        let time = record.time.format("%d-%m-%Y %H:%M:%S");
+        // debug output names the module it comes from to make filtering it easier
+        let mut msg = match record.level {
+            Level::Trace | Level::Debug => format!("{}: {}", record.module, record.msg),
+            _ => record.msg.clone(),
+        };
+        for (k, v) in &record.fields {
+            msg.push_str(format!(" {}={}", k, v).as_str());
+        }
+        let json = match (self.term_format, self.file_format) {
+            (LogFormat::Text, LogFormat::Text) => String::new(),
+            _ => record.to_json().to_string(),
+        };
+        if let Some(n) = &self.term {
+            let _ = match record.level.label() {
+                _ if self.term_format == LogFormat::Json => n.eprintln(json.as_str()),
+                Some((label, color)) => n.eprintln(format!(
+                    "[{}]: {}{}: {}{}{}",
+                    time,
+                    fg(color),
+                    label,
+                    fg(Color::White),
+                    msg,
+                    sp(Special::Reset)
+                )),
+                None => n.eprintln(format!("[{}]: {}", time, msg)),
+            };
+        }
+        if let Some(n) = &mut self.file {
+            let line = match record.level.label() {
+                _ if self.file_format == LogFormat::Json => format!("{}\n", json),
+                Some((label, _)) => format!("[{}]: {}: {}\n", time, label, msg),
+                None => format!("[{}]: {}\n", time, msg),
+            };
+            let _ = n.write(line.as_bytes());
+        }
+        #[cfg(unix)]
+        if let Some(n) = &mut self.syslog {
+            n.send(record);
+        }
+        #[cfg(unix)]
+        if let Some(n) = &mut self.journald {
+            n.send(record);
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(n) = &mut self.file {
+            let _ = n.flush();
+        }
+    }
+}
+
+struct Shared {
+    sender: SyncSender<Entry>,
+    filter: RwLock<Filter>,
+    sinks: Arc<Mutex<Sinks>>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl Drop for Shared {
+    /// write all queued messages before the last handle is gone
+    fn drop(&mut self) {
+        let (tx, rx) = mpsc::sync_channel(1);
+        if self.sender.send(Entry::Flush(tx)).is_ok() {
+            let _ = rx.recv();
+        }
+    }
+}
+
+/// write queued messages until all handles of the logger are dropped
+fn writer(receiver: Receiver<Entry>, sinks: Arc<Mutex<Sinks>>, dropped: Arc<AtomicUsize>) {
+    for entry in receiver {
+        let mut sinks = match sinks.lock() {
+            Ok(n) => n,
+            Err(e) => e.into_inner(),
+        };
+        match entry {
+            Entry::Record(record) => {
+                let n = dropped.swap(0, Ordering::Relaxed);
+                if n > 0 {
+                    sinks.write(&Record {
+                        time: record.time,
+                        level: Level::Warn,
+                        module: String::from(module_path!()),
+                        thread: thread_name(),
+                        msg: format!("log queue full, dropped {} messages", n),
+                        fields: Vec::new(),
+                    });
+                }
+                sinks.write(&record);
+            }
+            Entry::Flush(done) => {
+                sinks.flush();
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+/// a logger that writes to stderr, a file, syslog and/or the systemd journal. Handles are cheap to
+/// clone and share the same filter, outputs and background writer thread, so logging never waits
+/// for the outputs. If more messages are queued than the writer can keep up with, new ones are
+/// dropped and the amount is reported later
+#[derive(Clone)]
+pub struct Logger {
+    shared: Arc<Shared>,
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Logger {
-    /// create a new logger
+    /// create a new logger without outputs that logs messages of level info and above
     pub fn new() -> Logger {
+        Logger::with_queue_size(DEFAULT_QUEUE_SIZE)
+    }
+
+    /// create a new logger that queues up to `size` messages
+    pub fn with_queue_size(size: usize) -> Logger {
+        let (sender, receiver) = mpsc::sync_channel(size.max(1));
+        let sinks = Arc::new(Mutex::new(Sinks::default()));
+        let dropped = Arc::new(AtomicUsize::new(0));
+        {
+            let sinks = sinks.clone();
+            let dropped = dropped.clone();
+            std::thread::spawn(move || writer(receiver, sinks, dropped));
+        }
         Logger {
-            term: None,
-            file: None,
+            shared: Arc::new(Shared {
+                sender,
+                filter: RwLock::new(Filter::default()),
+                sinks,
+                dropped,
+            }),
+        }
+    }
+
+    /// create a logger writing to the terminal and `logfile`. Failing to open the file is
+    /// logged to the terminal
+    pub fn open(logfile: &str) -> Logger {
+        let logger = Logger::new();
+        logger.set_term(Terminal::default());
+        if let Err(e) = logger.set_logfile(logfile) {
+            logger.log(
+                Level::Error,
+                module_path!(),
+                format!("couldn't open log file '{}': {}", logfile, e),
+            );
+        }
+        logger
+    }
+
+    /// create a logger from the `log` config section that writes to the terminal and `logfile`
+    pub fn from_config(conf: Option<&LogConfig>, logfile: &str) -> Result<Logger, ServerError> {
+        let filter = match conf.and_then(|n| n.level.as_ref()) {
+            Some(n) => Filter::from_string(n)?,
+            None => Filter::default(),
+        };
+        let logger = Logger::with_queue_size(
+            conf.and_then(|n| n.queue_size)
+                .unwrap_or(DEFAULT_QUEUE_SIZE),
+        );
+        logger.set_filter(filter);
+        if let Some(n) = conf.and_then(|n| n.rotation.as_ref()) {
+            logger.set_rotation(Rotation::from_config(n));
+        }
+        if conf.and_then(|n| n.terminal).unwrap_or(true) {
+            logger.set_term(Terminal::default());
+        }
+        if let Some(n) = conf.and_then(|n| n.terminal_format) {
+            logger.set_term_format(n);
+        }
+        if let Some(n) = conf.and_then(|n| n.file_format) {
+            logger.set_file_format(n);
+        }
+        #[cfg(unix)]
+        if let Some(n) = conf.and_then(|n| n.syslog.as_ref()) {
+            logger.set_syslog(Some(Syslog::from_config(n)?));
+        }
+        #[cfg(unix)]
+        if let Some(n) = conf.and_then(|n| n.journald.as_ref()) {
+            logger.set_journald(Some(Journald::from_config(n)?));
+        }
+        if let Err(e) = logger.set_logfile(logfile) {
+            return Err(ServerError::new(
+                format!("couldn't open log file '{}': {}", logfile, e).as_str(),
+            ));
+        }
+        Ok(logger)
+    }
+
+    fn sinks(&self) -> std::sync::MutexGuard<'_, Sinks> {
+        match self.shared.sinks.lock() {
+            Ok(n) => n,
+            Err(e) => e.into_inner(),
         }
     }
 
     /// set the log file to log to for the logger
     /// # Returns
     /// A result that is an error variant if the file opening process fails
-    pub fn set_logfile(&mut self, fname: &str) -> Result<(), std::io::Error> {
-        let path = Path::new(fname);
-        if let Some(parent) = path.parent() {
-            if !parent.exists() {
-                if let Err(e) = std::fs::create_dir_all(parent) {
-                    return Err(e);
-                }
-            }
-        }
-        self.file = match OpenOptions::new().append(true).create(true).open(fname) {
-            Ok(n) => Some(n),
-            Err(e) => {
-                return Err(e);
-            }
-        };
+    pub fn set_logfile(&self, fname: &str) -> Result<(), std::io::Error> {
+        let mut sinks = self.sinks();
+        sinks.file = Some(LogFile::open(fname, sinks.rotation.clone())?);
         Ok(())
     }
 
+    /// set when the log file is rotated
+    pub fn set_rotation(&self, rotation: Rotation) {
+        let mut sinks = self.sinks();
+        if let Some(n) = &mut sinks.file {
+            n.set_rotation(rotation.clone());
+        }
+        sinks.rotation = rotation;
+    }
+
     /// set the terminal to log to for a logger
-    pub fn set_term(&mut self, term: Terminal) {
-        self.term = Some(term);
-    }
-
-    /// log a message with a specific type
-    pub fn log<T: std::fmt::Display>(&mut self, msg: T, msgtype: LogType) {
-        match msgtype {
-            LogType::Warning => {
-                // TODO: make time be a string formatted in the way used internally
-                let cur_time: DateTime<Local> = Local::now();
-                if let Some(n) = &self.term {
-                    let _ = n.eprintln(format!(
-                        "[{}]: {}Warning: {}{}{}",
-                        cur_time.format("%d-%m-%Y %H:%M:%S").to_string(),
-                        fg(Color::Yellow),
-                        fg(Color::White),
-                        msg,
-                        sp(Special::Reset)
-                    ));
-                }
-                if let Some(n) = &mut self.file {
-                    let _ = n.write(
-                        format!(
-                            "[{}]: Warning: {}\n",
-                            cur_time.format("%d-%m-%Y %H:%M:%S").to_string(),
-                            msg
-                        )
-                        .as_str()
-                        .as_bytes(),
-                    );
-                    let _ = n.flush();
-                }
-            }
-            LogType::Error => {
-                let cur_time: DateTime<Local> = Local::now();
-                if let Some(n) = &self.term {
-                    let _ = n.eprintln(format!(
-                        "[{}]: {}Error: {}{}{}",
-                        cur_time.format("%d-%m-%Y %H:%M:%S").to_string(),
-                        fg(Color::Red),
-                        fg(Color::White),
-                        msg,
-                        sp(Special::Reset)
-                    ));
-                }
-                if let Some(n) = &mut self.file {
-                    let _ = n.write(
-                        format!(
-                            "[{}]: Error: {}\n",
-                            cur_time.format("%d-%m-%Y %H:%M:%S").to_string(),
-                            msg
-                        )
-                        .as_str()
-                        .as_bytes(),
-                    );
-                    let _ = n.flush();
-                }
-            }
-            LogType::Log => {
-                let cur_time: DateTime<Local> = Local::now();
-                if let Some(n) = &self.term {
-                    let _ = n.eprintln(format!(
-                        "[{}]: {}",
-                        cur_time.format("%d-%m-%Y %H:%M:%S").to_string(),
-                        msg
-                    ));
-                }
-                if let Some(n) = &mut self.file {
-                    let _ = n.write(
-                        format!(
-                            "[{}]: {}\n",
-                            cur_time.format("%d-%m-%Y %H:%M:%S").to_string(),
-                            msg
-                        )
-                        .as_str()
-                        .as_bytes(),
-                    );
-                    let _ = n.flush();
-                }
+    pub fn set_term(&self, term: Terminal) {
+        self.sinks().term = Some(term);
+    }
+
+    /// set how messages are written to the terminal
+    pub fn set_term_format(&self, format: LogFormat) {
+        self.sinks().term_format = format;
+    }
+
+    /// set how messages are written to the log file
+    pub fn set_file_format(&self, format: LogFormat) {
+        self.sinks().file_format = format;
+    }
+
+    /// send messages to syslog. `None` stops sending them
+    #[cfg(unix)]
+    pub fn set_syslog(&self, syslog: Option<Syslog>) {
+        self.sinks().syslog = syslog;
+    }
+
+    /// send messages to the systemd journal. `None` stops sending them
+    #[cfg(unix)]
+    pub fn set_journald(&self, journald: Option<Journald>) {
+        self.sinks().journald = journald;
+    }
+
+    /// set which messages get logged
+    pub fn set_filter(&self, filter: Filter) {
+        match self.shared.filter.write() {
+            Ok(mut n) => *n = filter,
+            Err(e) => *e.into_inner() = filter,
+        }
+    }
+
+    /// check whether messages of `level` from `module` get logged
+    pub fn enabled(&self, level: Level, module: &str) -> bool {
+        match self.shared.filter.read() {
+            Ok(n) => n.enabled(level, module),
+            Err(e) => e.into_inner().enabled(level, module),
+        }
+    }
+
+    /// log a message of `level` from `module`. Usually called through the macros, e.g.
+    /// [`info!`](crate::info)
+    pub fn log<T: std::fmt::Display>(&self, level: Level, module: &str, msg: T) {
+        self.log_with_fields(level, module, msg, Vec::new());
+    }
+
+    /// log a message with additional information as (key, value). Outputs that support it (e.g.
+    /// syslog and the journal) keep the fields separate from the message
+    pub fn log_with_fields<T: std::fmt::Display>(
+        &self,
+        level: Level,
+        module: &str,
+        msg: T,
+        mut fields: Vec<(String, String)>,
+    ) {
+        if !self.enabled(level, module) {
+            return;
+        }
+        if let Some(id) = RequestScope::current() {
+            if !fields.iter().any(|(k, _)| k == "request_id") {
+                fields.push((String::from("request_id"), id));
             }
         }
+        let record = Record {
+            time: Local::now(),
+            level,
+            module: module.to_string(),
+            thread: thread_name(),
+            msg: msg.to_string(),
+            fields,
+        };
+        if let Err(TrySendError::Full(_)) = self.shared.sender.try_send(Entry::Record(record)) {
+            self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// wait until all queued messages are written
+    pub fn flush(&self) {
+        let (tx, rx) = mpsc::sync_channel(1);
+        if self.shared.sender.send(Entry::Flush(tx)).is_ok() {
+            let _ = rx.recv();
+        }
     }
 }
 
+/// log a message of a [`Level`] with a [`Logger`]. The message is only formatted if the level
+/// is enabled for the calling module. Fields can be given in braces before the message, e.g.
+/// `info!(logger, {"path": path, "status": 200}, "request answered")`
 #[macro_export]
-macro_rules! msg {
-    ($logger:expr, $($arg:expr),*) => {{
-        $logger.log(format!($($arg,)*), $crate::log::LogType::Log);
+macro_rules! log {
+    ($logger:expr, $level:expr, { $($key:literal : $value:expr),* $(,)? }, $($arg:tt)+) => {{
+        let logger: &$crate::log::Logger = &$logger;
+        if logger.enabled($level, module_path!()) {
+            logger.log_with_fields(
+                $level,
+                module_path!(),
+                format!($($arg)+),
+                vec![$((String::from($key), $value.to_string())),*],
+            );
+        }
     }};
+    ($logger:expr, $level:expr, $($arg:tt)+) => {{
+        let logger: &$crate::log::Logger = &$logger;
+        if logger.enabled($level, module_path!()) {
+            logger.log($level, module_path!(), format!($($arg)+));
+        }
+    }};
+}
+#[macro_export]
+macro_rules! trace {
+    ($logger:expr, $($arg:tt)+) => {
+        $crate::log!($logger, $crate::log::Level::Trace, $($arg)+)
+    };
+}
+#[macro_export]
+macro_rules! debug {
+    ($logger:expr, $($arg:tt)+) => {
+        $crate::log!($logger, $crate::log::Level::Debug, $($arg)+)
+    };
+}
+#[macro_export]
+macro_rules! info {
+    ($logger:expr, $($arg:tt)+) => {
+        $crate::log!($logger, $crate::log::Level::Info, $($arg)+)
+    };
+}
+/// same as [`info!`](crate::info)
+#[macro_export]
+macro_rules! msg {
+    ($logger:expr, $($arg:tt)+) => {
+        $crate::log!($logger, $crate::log::Level::Info, $($arg)+)
+    };
 }
 #[macro_export]
 macro_rules! warn {
-    ($logger:expr, $($arg:expr),*) => {{
-        $logger.log(format!($($arg,)*), $crate::log::LogType::Warn);
-    }};
+    ($logger:expr, $($arg:tt)+) => {
+        $crate::log!($logger, $crate::log::Level::Warn, $($arg)+)
+    };
 }
 #[macro_export]
 macro_rules! error {
-    ($logger:expr, $($arg:expr),*) => {{
-        $logger.log(format!($($arg,)*), $crate::log::LogType::Error);
-    }};
+    ($logger:expr, $($arg:tt)+) => {
+        $crate::log!($logger, $crate::log::Level::Error, $($arg)+)
+    };
 }
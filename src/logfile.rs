@@ -0,0 +1,308 @@
+use crate::config::RotationConfig;
+use chrono::prelude::*;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_derive::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+/// incremented to make all log files reopen their path before the next write
+static REOPEN_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// make all open log files reopen their path before they are written to the next time, e.g.
+/// after an external tool like logrotate moved them
+pub fn request_reopen() {
+    REOPEN_GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+extern "C" fn reopen_handler(_: libc::c_int) {
+    request_reopen();
+}
+
+/// reopen all log files when the process receives `SIGUSR1` (see [`request_reopen`])
+#[cfg(unix)]
+pub fn reopen_on_sigusr1() -> Result<(), std::io::Error> {
+    let handler = reopen_handler as extern "C" fn(libc::c_int);
+    // SAFETY: the handler only increments an atomic counter, which is async-signal-safe
+    if unsafe { libc::signal(libc::SIGUSR1, handler as libc::sighandler_t) } == libc::SIG_ERR {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// how often a log file is rotated regardless of its size
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RotateInterval {
+    Hourly,
+    Daily,
+    Weekly,
+}
+
+impl RotateInterval {
+    /// get the period `time` falls into. Files are rotated when the period changes
+    fn period(&self, time: DateTime<Local>) -> String {
+        match self {
+            RotateInterval::Hourly => time.format("%Y%m%d%H").to_string(),
+            RotateInterval::Daily => time.format("%Y%m%d").to_string(),
+            RotateInterval::Weekly => time.format("%G%V").to_string(),
+        }
+    }
+}
+
+/// when a log file is rotated and how many rotated files are kept. Rotated files are named
+/// after the time they were rotated, e.g. `latest.log.20230102-150405.gz`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Rotation {
+    /// rotate once the file grows larger than this many bytes
+    pub max_size: Option<u64>,
+
+    /// rotate when the hour, day or week changes
+    pub interval: Option<RotateInterval>,
+
+    /// compress rotated files with gzip
+    pub compress: bool,
+
+    /// number of rotated files to keep
+    pub keep: Option<usize>,
+
+    /// remove rotated files older than this
+    pub max_age: Option<Duration>,
+}
+
+impl Rotation {
+    /// create a rotation from the `rotation` setting of a log file. Rotated files are compressed
+    /// by default
+    pub fn from_config(conf: &RotationConfig) -> Rotation {
+        Rotation {
+            max_size: conf.max_size,
+            interval: conf.interval,
+            compress: conf.compress.unwrap_or(true),
+            keep: conf.keep,
+            max_age: conf.max_age.map(|n| Duration::from_secs(n * 24 * 60 * 60)),
+        }
+    }
+}
+
+/// a log file that is appended to and rotated according to a [`Rotation`]
+#[derive(Debug)]
+pub struct LogFile {
+    path: PathBuf,
+    rotation: Rotation,
+    file: File,
+    size: u64,
+    /// period the content of the file belongs to, used for time based rotation
+    period: Option<String>,
+    generation: usize,
+    /// compression and removal of old files after the last rotation
+    pending: Option<JoinHandle<()>>,
+}
+
+impl LogFile {
+    /// open `path` for appending. Missing parent directories are created
+    pub fn open(path: &str, rotation: Rotation) -> Result<LogFile, std::io::Error> {
+        let generation = REOPEN_GENERATION.load(Ordering::SeqCst);
+        let path = PathBuf::from(path);
+        let (file, size, modified) = LogFile::open_file(&path)?;
+        let mut logfile = LogFile {
+            path,
+            rotation,
+            file,
+            size,
+            period: None,
+            generation,
+            pending: None,
+        };
+        logfile.period = logfile.period_of(modified);
+        Ok(logfile)
+    }
+
+    fn open_file(path: &Path) -> Result<(File, u64, Option<SystemTime>), std::io::Error> {
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() && !parent.exists() {
+                std::fs::create_dir_all(parent)?
+            }
+        }
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        let meta = file.metadata()?;
+        // an empty file has no content of an earlier period to rotate
+        let modified = match meta.len() {
+            0 => None,
+            _ => meta.modified().ok(),
+        };
+        Ok((file, meta.len(), modified))
+    }
+
+    fn period_of(&self, time: Option<SystemTime>) -> Option<String> {
+        let interval = self.rotation.interval?;
+        Some(interval.period(time.map(DateTime::from).unwrap_or_else(Local::now)))
+    }
+
+    /// get the path of the file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// change when the file is rotated
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.rotation = rotation;
+        self.period = self.period_of(None);
+    }
+
+    /// open the path of the file again, e.g. after it was moved by logrotate
+    pub fn reopen(&mut self) -> Result<(), std::io::Error> {
+        let _ = self.file.flush();
+        let (file, size, modified) = LogFile::open_file(&self.path)?;
+        self.file = file;
+        self.size = size;
+        self.period = self.period_of(modified);
+        self.generation = REOPEN_GENERATION.load(Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// get the path a rotated file is moved to
+    fn rotated_path(&self) -> PathBuf {
+        let name = self
+            .path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let base = format!("{}.{}", name, Local::now().format("%Y%m%d-%H%M%S"));
+        let mut rotated = self.path.with_file_name(&base);
+        let mut i = 1;
+        while rotated.exists() || Path::new(&format!("{}.gz", rotated.display())).exists() {
+            rotated = self.path.with_file_name(format!("{}.{}", base, i));
+            i += 1;
+        }
+        rotated
+    }
+
+    /// move the current file aside and start a new one. Compression of the rotated file and
+    /// removal of old files happen in the background (see [`LogFile::wait`])
+    pub fn rotate(&mut self) -> Result<(), std::io::Error> {
+        // only one rotation at a time works on the rotated files
+        self.wait();
+        let _ = self.file.flush();
+        let rotated = self.rotated_path();
+        std::fs::rename(&self.path, &rotated)?;
+        self.reopen()?;
+        let path = self.path.clone();
+        let rotation = self.rotation.clone();
+        self.pending = Some(std::thread::spawn(move || {
+            if rotation.compress {
+                let _ = compress(&rotated);
+            }
+            remove_old(&path, &rotation);
+        }));
+        Ok(())
+    }
+
+    /// wait until compression and removal of old files after the last rotation are done
+    pub fn wait(&mut self) {
+        if let Some(n) = self.pending.take() {
+            let _ = n.join();
+        }
+    }
+
+    /// check whether writing `len` more bytes requires rotating the file first
+    fn needs_rotation(&self, len: usize) -> bool {
+        if let Some(max) = self.rotation.max_size {
+            if self.size > 0 && self.size + len as u64 > max {
+                return true;
+            }
+        }
+        match (&self.period, self.period_of(None)) {
+            (Some(old), Some(now)) => *old != now,
+            _ => false,
+        }
+    }
+
+    /// append `data` to the file, rotating or reopening it first if necessary
+    pub fn write(&mut self, data: &[u8]) -> Result<(), std::io::Error> {
+        if self.generation != REOPEN_GENERATION.load(Ordering::SeqCst) {
+            self.reopen()?;
+        }
+        if self.needs_rotation(data.len()) {
+            self.rotate()?;
+        }
+        self.file.write_all(data)?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.file.flush()
+    }
+}
+
+/// replace `path` with a gzip compressed copy called `path.gz`
+fn compress(path: &Path) -> Result<(), std::io::Error> {
+    let target = PathBuf::from(format!("{}.gz", path.display()));
+    let mut input = File::open(path)?;
+    let mut encoder = GzEncoder::new(File::create(&target)?, Compression::default());
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = input.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        encoder.write_all(&buf[..n])?;
+    }
+    encoder.finish()?;
+    std::fs::remove_file(path)
+}
+
+/// get the rotated files of the log file at `path`, newest first
+pub fn rotated_files(path: &Path) -> Vec<PathBuf> {
+    let name = match path.file_name() {
+        Some(n) => format!("{}.", n.to_string_lossy()),
+        None => return Vec::new(),
+    };
+    let dir = match path.parent() {
+        Some(n) if !n.as_os_str().is_empty() => n.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let mut files: Vec<PathBuf> = match std::fs::read_dir(&dir) {
+        Ok(n) => n
+            .filter_map(|n| n.ok())
+            .map(|n| n.path())
+            .filter(|n| {
+                n.file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .and_then(|n| n.strip_prefix(name.as_str()).map(|n| n.to_string()))
+                    .map(|n| n.starts_with(|c: char| c.is_ascii_digit()))
+                    .unwrap_or(false)
+            })
+            .collect(),
+        Err(_) => return Vec::new(),
+    };
+    // the timestamps in the names sort chronologically
+    files.sort_by(|a, b| {
+        let key = |n: &PathBuf| n.to_string_lossy().trim_end_matches(".gz").to_string();
+        key(b).cmp(&key(a))
+    });
+    files
+}
+
+/// remove rotated files of `path` exceeding the retention limits of `rotation`
+fn remove_old(path: &Path, rotation: &Rotation) {
+    let now = SystemTime::now();
+    for (i, file) in rotated_files(path).iter().enumerate() {
+        let too_many = rotation.keep.map(|n| i >= n).unwrap_or(false);
+        let too_old = match (
+            rotation.max_age,
+            std::fs::metadata(file).and_then(|n| n.modified()),
+        ) {
+            (Some(max), Ok(modified)) => now.duration_since(modified).unwrap_or_default() > max,
+            _ => false,
+        };
+        if too_many || too_old {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use crate::config::PathMethodsConfig;
+use crate::http::request::HTTPMethod;
+use wildmatch::WildMatch;
+
+/// all HTTP methods
+pub const ALL_METHODS: [HTTPMethod; 9] = [
+    HTTPMethod::Get,
+    HTTPMethod::Head,
+    HTTPMethod::Post,
+    HTTPMethod::Put,
+    HTTPMethod::Delete,
+    HTTPMethod::Connect,
+    HTTPMethod::Options,
+    HTTPMethod::Trace,
+    HTTPMethod::Patch,
+];
+
+/// methods the static file server is able to answer
+pub const STATIC_METHODS: [HTTPMethod; 3] =
+    [HTTPMethod::Get, HTTPMethod::Head, HTTPMethod::Options];
+
+/// an allowlist of HTTP methods for the whole server and for paths matching wildcard patterns.
+///
+/// `OPTIONS` is always allowed so clients can find out which methods they may use.
+#[derive(Clone, Debug)]
+pub struct MethodPolicy {
+    default: Option<Vec<HTTPMethod>>,
+    paths: Vec<(String, Vec<HTTPMethod>)>,
+}
+
+impl Default for MethodPolicy {
+    fn default() -> Self {
+        MethodPolicy::new(None)
+    }
+}
+
+impl MethodPolicy {
+    /// create a new policy allowing `default` on all paths. `None` allows every method
+    pub fn new(default: Option<Vec<HTTPMethod>>) -> MethodPolicy {
+        MethodPolicy {
+            default,
+            paths: Vec::new(),
+        }
+    }
+
+    /// create a policy from the `allowed_methods` and `path_methods` settings of a config
+    /// section. Unknown method names are ignored
+    pub fn from_config(
+        allowed_methods: Option<&Vec<String>>,
+        path_methods: Option<&Vec<PathMethodsConfig>>,
+    ) -> MethodPolicy {
+        let parse = |methods: &Vec<String>| -> Vec<HTTPMethod> {
+            methods
+                .iter()
+                .filter_map(|n| HTTPMethod::from_string(n.to_uppercase()))
+                .collect()
+        };
+        let mut policy = MethodPolicy::new(allowed_methods.map(parse));
+        for rule in path_methods.into_iter().flatten() {
+            policy.path(rule.path.clone(), parse(&rule.allowed_methods));
+        }
+        policy
+    }
+
+    /// allow only `methods` on paths matching `pattern`. The first matching pattern wins
+    pub fn path(&mut self, pattern: String, methods: Vec<HTTPMethod>) {
+        self.paths.push((pattern, methods));
+    }
+
+    /// get the methods allowed on `path` out of the `supported` ones
+    pub fn allowed(&self, path: &str, supported: &[HTTPMethod]) -> Vec<HTTPMethod> {
+        let configured = self
+            .paths
+            .iter()
+            .find(|(pattern, _)| WildMatch::new(pattern.as_str()).matches(path))
+            .map(|(_, methods)| methods)
+            .or(self.default.as_ref());
+        supported
+            .iter()
+            .filter(|n| {
+                **n == HTTPMethod::Options || configured.is_none_or(|methods| methods.contains(n))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// get the methods allowed somewhere on the server (used for `OPTIONS *`)
+    pub fn server_allowed(&self, supported: &[HTTPMethod]) -> Vec<HTTPMethod> {
+        supported
+            .iter()
+            .filter(|n| {
+                **n == HTTPMethod::Options
+                    || self.default.as_ref().is_none_or(|m| m.contains(n))
+                    || self.paths.iter().any(|(_, m)| m.contains(n))
+            })
+            .cloned()
+            .collect()
+    }
+}
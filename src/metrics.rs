@@ -0,0 +1,423 @@
+use crate::config::MetricsConfig;
+use crate::http::body::Body;
+use crate::http::header::HTTPResponseHeaders;
+use crate::http::request::{HTTPMethod, HTTPRequest};
+use crate::http::response::HTTPResponse;
+use crate::http::{MimeType, StatusCode};
+use crate::log::Logger;
+use crate::server::{read_only_response, serve_admin};
+use crate::tp::PoolStats;
+use crate::RSWEB_SERVER_STR;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// path metrics are served on by default
+pub const METRICS_PATH: &str = "/metrics";
+
+/// upper bounds of the buckets of the request duration histogram in seconds
+pub const DURATION_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// name, type and help text of the exposed metrics in the order they are written
+const METRICS: [(&str, &str, &str); 12] = [
+    (
+        "rsweb_requests_total",
+        "counter",
+        "Answered requests by server, method, status and route.",
+    ),
+    (
+        "rsweb_request_duration_seconds",
+        "histogram",
+        "Time from accepting a request to sending the response.",
+    ),
+    (
+        "rsweb_received_bytes_total",
+        "counter",
+        "Bytes of requests read from clients.",
+    ),
+    (
+        "rsweb_sent_bytes_total",
+        "counter",
+        "Bytes of responses sent to clients.",
+    ),
+    (
+        "rsweb_connections_active",
+        "gauge",
+        "Connections currently being handled.",
+    ),
+    (
+        "rsweb_threadpool_workers",
+        "gauge",
+        "Worker threads of the thread pool.",
+    ),
+    (
+        "rsweb_threadpool_queued_jobs",
+        "gauge",
+        "Connections waiting for a worker.",
+    ),
+    (
+        "rsweb_threadpool_busy_workers",
+        "gauge",
+        "Workers handling a connection.",
+    ),
+    (
+        "rsweb_resource_cache_hits_total",
+        "counter",
+        "Resources served from the cache.",
+    ),
+    (
+        "rsweb_resource_cache_misses_total",
+        "counter",
+        "Resources that had to be read from disk.",
+    ),
+    (
+        "rsweb_resource_cache_bytes",
+        "gauge",
+        "Size of the cached resources.",
+    ),
+    (
+        "rsweb_tls_handshake_failures_total",
+        "counter",
+        "TLS handshakes that failed.",
+    ),
+];
+
+/// escape a label value of the Prometheus text format
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// format `labels` as `name="value",...`
+fn labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, escape(v)))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// write the sample `name{labels} value`
+fn sample(out: &mut String, name: &str, labels: &str, value: f64) {
+    if labels.is_empty() {
+        out.push_str(format!("{} {}\n", name, value).as_str());
+    } else {
+        out.push_str(format!("{}{{{}}} {}\n", name, labels, value).as_str());
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+    /// observations per bucket of [`DURATION_BUCKETS`], not cumulative
+    buckets: [u64; DURATION_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if let Some(i) = DURATION_BUCKETS.iter().position(|n| value <= *n) {
+            self.buckets[i] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn write(&self, out: &mut String, name: &str, labels: &str) {
+        let prefix = match labels {
+            "" => String::new(),
+            n => format!("{},", n),
+        };
+        let mut cumulative = 0;
+        for (i, bound) in DURATION_BUCKETS.iter().enumerate() {
+            cumulative += self.buckets[i];
+            let labels = format!("{}le=\"{}\"", prefix, bound);
+            sample(
+                out,
+                format!("{}_bucket", name).as_str(),
+                &labels,
+                cumulative as f64,
+            );
+        }
+        let labels_inf = format!("{}le=\"+Inf\"", prefix);
+        sample(
+            out,
+            format!("{}_bucket", name).as_str(),
+            &labels_inf,
+            self.count as f64,
+        );
+        sample(out, format!("{}_sum", name).as_str(), labels, self.sum);
+        sample(
+            out,
+            format!("{}_count", name).as_str(),
+            labels,
+            self.count as f64,
+        );
+    }
+}
+
+#[derive(Debug, Default)]
+struct Values {
+    /// counters and gauges by name and labels
+    samples: BTreeMap<(&'static str, String), f64>,
+    histograms: BTreeMap<(&'static str, String), Histogram>,
+    /// thread pools by the server using them
+    pools: Vec<(String, PoolStats)>,
+}
+
+/// counters, gauges and histograms about the servers, exposed in the Prometheus text format.
+/// Clones share their values, so one instance can collect the metrics of several servers
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    values: Arc<Mutex<Values>>,
+    path: String,
+    listen: Option<String>,
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics::new()
+    }
+}
+
+/// decrements the active connections of a server when dropped
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    metrics: Metrics,
+    server: String,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let labels = labels(&[("server", self.server.as_str())]);
+        self.metrics.add("rsweb_connections_active", labels, -1.0);
+    }
+}
+
+impl Metrics {
+    /// create metrics served on [`METRICS_PATH`] of the servers using them
+    pub fn new() -> Metrics {
+        Metrics {
+            values: Arc::new(Mutex::new(Values::default())),
+            path: String::from(METRICS_PATH),
+            listen: None,
+        }
+    }
+
+    /// create metrics as configured in the `metrics` section
+    pub fn from_config(conf: &MetricsConfig) -> Metrics {
+        let mut metrics = Metrics::new();
+        if let Some(n) = &conf.path {
+            metrics.set_path(n);
+        }
+        metrics.set_listen(conf.listen.clone());
+        metrics
+    }
+
+    /// set the path metrics are served on
+    pub fn set_path(&mut self, path: &str) {
+        self.path = path.to_string();
+    }
+
+    /// serve metrics on a separate listener (e.g. `127.0.0.1:9100`, see [`Metrics::serve`])
+    /// instead of the servers using them. `None` serves them on the servers
+    pub fn set_listen(&mut self, listen: Option<String>) {
+        self.listen = listen;
+    }
+
+    fn values(&self) -> MutexGuard<'_, Values> {
+        match self.values.lock() {
+            Ok(n) => n,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    fn add(&self, name: &'static str, labels: String, value: f64) {
+        *self.values().samples.entry((name, labels)).or_insert(0.0) += value;
+    }
+
+    fn set(&self, name: &'static str, labels: String, value: f64) {
+        self.values().samples.insert((name, labels), value);
+    }
+
+    /// count a connection accepted by `server` (e.g. `http`) as active until the returned guard
+    /// is dropped
+    pub fn connection(&self, server: &str) -> ConnectionGuard {
+        self.add(
+            "rsweb_connections_active",
+            labels(&[("server", server)]),
+            1.0,
+        );
+        ConnectionGuard {
+            metrics: self.clone(),
+            server: server.to_string(),
+        }
+    }
+
+    /// record a request answered by `server`
+    /// # Arguments
+    /// * `method`: the method of the request. `None` if it couldn't be read
+    /// * `route`: the pattern of the route or alias the request matched, `-` if none did. Paths
+    ///   aren't used as labels because every requested path would create new series
+    /// * `received`, `sent`: the size of the request and the response in bytes
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_request(
+        &self,
+        server: &str,
+        method: Option<&HTTPMethod>,
+        status: StatusCode,
+        route: &str,
+        received: usize,
+        sent: usize,
+        duration: Duration,
+    ) {
+        // unknown methods share one label, clients could create any number of them
+        let method = match method {
+            Some(HTTPMethod::Other(_)) => String::from("other"),
+            Some(n) => n.to_string(),
+            None => String::from("-"),
+        };
+        let code = status.code().to_string();
+        let by_server = labels(&[("server", server)]);
+        let mut values = self.values();
+        *values
+            .samples
+            .entry((
+                "rsweb_requests_total",
+                labels(&[
+                    ("server", server),
+                    ("method", method.as_str()),
+                    ("status", code.as_str()),
+                    ("route", route),
+                ]),
+            ))
+            .or_insert(0.0) += 1.0;
+        *values
+            .samples
+            .entry(("rsweb_received_bytes_total", by_server.clone()))
+            .or_insert(0.0) += received as f64;
+        *values
+            .samples
+            .entry(("rsweb_sent_bytes_total", by_server.clone()))
+            .or_insert(0.0) += sent as f64;
+        values
+            .histograms
+            .entry(("rsweb_request_duration_seconds", by_server))
+            .or_default()
+            .observe(duration.as_secs_f64());
+    }
+
+    /// record a failed TLS handshake of `server`
+    pub fn tls_handshake_failed(&self, server: &str) {
+        self.add(
+            "rsweb_tls_handshake_failures_total",
+            labels(&[("server", server)]),
+            1.0,
+        );
+    }
+
+    /// record a resource served from the cache
+    pub fn cache_hit(&self) {
+        self.add("rsweb_resource_cache_hits_total", String::new(), 1.0);
+    }
+
+    /// record a resource that wasn't cached
+    pub fn cache_miss(&self) {
+        self.add("rsweb_resource_cache_misses_total", String::new(), 1.0);
+    }
+
+    /// set the size of the cached resources in bytes
+    pub fn set_cache_size(&self, bytes: usize) {
+        self.set("rsweb_resource_cache_bytes", String::new(), bytes as f64);
+    }
+
+    /// expose the queue depth and busy workers of the thread pool of `server`
+    pub fn register_pool(&self, server: &str, stats: PoolStats) {
+        self.values().pools.push((server.to_string(), stats));
+    }
+
+    /// get all metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let values = self.values();
+        let mut samples = values.samples.clone();
+        for (server, stats) in &values.pools {
+            let labels = labels(&[("server", server.as_str())]);
+            // servers sharing a label are summed up
+            for (name, value) in [
+                ("rsweb_threadpool_workers", stats.size()),
+                ("rsweb_threadpool_queued_jobs", stats.queued()),
+                ("rsweb_threadpool_busy_workers", stats.busy()),
+            ] {
+                *samples.entry((name, labels.clone())).or_insert(0.0) += value as f64;
+            }
+        }
+        let mut out = String::new();
+        for (name, kind, help) in METRICS {
+            out.push_str(format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind).as_str());
+            for ((n, labels), value) in &samples {
+                if *n == name {
+                    sample(&mut out, name, labels, *value);
+                }
+            }
+            for ((n, labels), histogram) in &values.histograms {
+                if *n == name {
+                    histogram.write(&mut out, name, labels);
+                }
+            }
+        }
+        out
+    }
+
+    /// create a response containing all metrics
+    pub fn response(&self) -> HTTPResponse {
+        let body = Body::new(self.render());
+        HTTPResponse::new(
+            StatusCode::Ok,
+            vec![
+                HTTPResponseHeaders::Server(RSWEB_SERVER_STR.to_string()),
+                HTTPResponseHeaders::ContentType(MimeType::Plaintext),
+                HTTPResponseHeaders::ContentLength(body.len()),
+            ],
+            body,
+        )
+    }
+
+    /// get the address of the separate listener, if any
+    pub fn listen(&self) -> Option<&str> {
+        self.listen.as_deref()
+    }
+
+    /// answer `req` if it asks for the metrics
+    pub(crate) fn answer(&self, req: &HTTPRequest) -> Option<HTTPResponse> {
+        if req.get_path() != self.path {
+            return None;
+        }
+        Some(read_only_response(&req.get_method(), || self.response()))
+    }
+
+    /// answer `req` if it asks for the metrics and they are served by the servers instead of a
+    /// separate listener
+    pub fn handle(&self, req: &HTTPRequest) -> Option<HTTPResponse> {
+        match self.listen {
+            Some(_) => None,
+            None => self.answer(req),
+        }
+    }
+
+    /// serve the metrics on the separate listener set with [`Metrics::set_listen`] in a
+    /// background thread. Does nothing if none is set
+    pub fn serve(&self, logger: Logger) -> Result<(), std::io::Error> {
+        let addr = match &self.listen {
+            Some(n) => n.clone(),
+            None => return Ok(()),
+        };
+        let metrics = self.clone();
+        serve_admin(addr.as_str(), "metrics", logger, move |req| {
+            metrics.answer(req)
+        })
+    }
+}
@@ -0,0 +1,268 @@
+use crate::config::{ClientAccessConfig, ClientAuthConfig};
+use crate::error::ServerError;
+use openssl::hash::MessageDigest;
+use openssl::ssl::{SslAcceptorBuilder, SslFiletype, SslRef, SslVerifyMode};
+use openssl::x509::store::X509Lookup;
+use openssl::x509::verify::X509VerifyFlags;
+use openssl::x509::{X509Name, X509VerifyResult};
+use wildmatch::WildMatch;
+
+/// whether clients have to present a certificate
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ClientAuthMode {
+    /// clients are asked for a certificate but may connect without one. Certificates that are
+    /// presented have to be valid
+    Optional,
+
+    /// clients without a valid certificate are rejected during the handshake
+    Required,
+}
+
+impl ClientAuthMode {
+    pub fn from_string(string: String) -> Option<ClientAuthMode> {
+        match string.as_str() {
+            "optional" => Some(ClientAuthMode::Optional),
+            "required" => Some(ClientAuthMode::Required),
+            _ => None,
+        }
+    }
+}
+
+/// verification of client certificates
+#[derive(Clone, Debug)]
+pub struct ClientAuth {
+    /// whether clients have to present a certificate
+    pub mode: ClientAuthMode,
+
+    /// PEM file containing the certificates of the CAs client certificates have to be issued by
+    pub ca_file: String,
+
+    /// directory containing certificate revocation lists named by the hash of their issuer (as
+    /// created by `openssl rehash`). If set, client certificates are checked against them
+    pub crl_dir: Option<String>,
+}
+
+impl ClientAuth {
+    /// create a client certificate verification trusting the CAs in `ca_file`
+    pub fn new(mode: ClientAuthMode, ca_file: &str) -> ClientAuth {
+        ClientAuth {
+            mode,
+            ca_file: ca_file.to_string(),
+            crl_dir: None,
+        }
+    }
+
+    /// read the verification settings from a config section
+    pub fn from_config(conf: &ClientAuthConfig) -> Result<ClientAuth, ServerError> {
+        let mode = match &conf.mode {
+            Some(n) => match ClientAuthMode::from_string(n.to_lowercase()) {
+                Some(n) => n,
+                None => {
+                    return Err(ServerError::new(
+                        format!("invalid client certificate mode: '{}'", n).as_str(),
+                    ));
+                }
+            },
+            None => ClientAuthMode::Required,
+        };
+        Ok(ClientAuth {
+            mode,
+            ca_file: conf.ca_file.clone(),
+            crl_dir: conf.crl_dir.clone(),
+        })
+    }
+
+    /// get the files the verification depends on
+    pub fn files(&self) -> Vec<&str> {
+        vec![self.ca_file.as_str()]
+    }
+
+    /// ask clients connecting to `builder` for a certificate and verify it
+    pub fn apply(&self, builder: &mut SslAcceptorBuilder) -> Result<(), ServerError> {
+        let ca_error = || {
+            ServerError::new(format!("error loading client CA file '{}'", self.ca_file).as_str())
+        };
+        if builder.set_ca_file(self.ca_file.as_str()).is_err() {
+            return Err(ca_error());
+        }
+        match X509Name::load_client_ca_file(self.ca_file.as_str()) {
+            Ok(n) => builder.set_client_ca_list(n),
+            Err(_) => return Err(ca_error()),
+        }
+        if let Some(dir) = &self.crl_dir {
+            let store = builder.cert_store_mut();
+            let res = store
+                .add_lookup(X509Lookup::hash_dir())
+                .and_then(|n| n.add_dir(dir.as_str(), SslFiletype::PEM))
+                .and_then(|_| store.set_flags(X509VerifyFlags::CRL_CHECK));
+            if res.is_err() {
+                return Err(ServerError::new(
+                    format!("error loading CRL directory '{}'", dir).as_str(),
+                ));
+            }
+        }
+        // sessions of verified clients may only be resumed by this server
+        if builder.set_session_id_context(b"rsweb").is_err() {
+            return Err(ServerError::new("error setting session id context"));
+        }
+        builder.set_verify(match self.mode {
+            ClientAuthMode::Optional => SslVerifyMode::PEER,
+            ClientAuthMode::Required => SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+        });
+        Ok(())
+    }
+}
+
+/// the identity of a client that presented a verified certificate
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PeerIdentity {
+    /// subject of the certificate, e.g. `C=DE, O=Example, CN=client`
+    pub subject: String,
+
+    /// subject alternative names (DNS names, email addresses, IP addresses and URIs)
+    pub sans: Vec<String>,
+
+    /// SHA-256 fingerprint of the certificate as lowercase hex
+    pub fingerprint: String,
+}
+
+impl PeerIdentity {
+    /// get the identity of the client of `ssl`
+    ///
+    /// # Returns
+    /// `None` if the client didn't present a certificate or it couldn't be verified
+    pub fn from_ssl(ssl: &SslRef) -> Option<PeerIdentity> {
+        if ssl.verify_result() != X509VerifyResult::OK {
+            return None;
+        }
+        let cert = ssl.peer_certificate()?;
+        let subject = cert
+            .subject_name()
+            .entries()
+            .filter_map(|n| {
+                let key = n.object().nid().short_name().ok()?;
+                let value = n.data().as_utf8().ok()?;
+                Some(format!("{}={}", key, value))
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+        let sans = cert
+            .subject_alt_names()
+            .map(|names| {
+                names
+                    .iter()
+                    .filter_map(|n| {
+                        if let Some(n) = n.dnsname().or_else(|| n.email()).or_else(|| n.uri()) {
+                            return Some(n.to_string());
+                        }
+                        match n.ipaddress()? {
+                            [a, b, c, d] => {
+                                Some(std::net::Ipv4Addr::new(*a, *b, *c, *d).to_string())
+                            }
+                            n => <[u8; 16]>::try_from(n)
+                                .ok()
+                                .map(|n| std::net::Ipv6Addr::from(n).to_string()),
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let fingerprint = cert
+            .digest(MessageDigest::sha256())
+            .ok()?
+            .iter()
+            .map(|n| format!("{:02x}", n))
+            .collect();
+        Some(PeerIdentity {
+            subject,
+            sans,
+            fingerprint,
+        })
+    }
+}
+
+/// clients allowed to access paths matching a wildcard pattern
+#[derive(Clone, Debug)]
+pub struct ClientAccessRule {
+    /// wildcard pattern of the paths the rule applies to
+    pub path: String,
+
+    /// wildcard patterns the subject of the client certificate may match
+    pub subjects: Vec<String>,
+
+    /// wildcard patterns one of the subject alternative names may match
+    pub sans: Vec<String>,
+
+    /// allowed certificate fingerprints (SHA-256, hex, colons are ignored)
+    pub fingerprints: Vec<String>,
+}
+
+impl ClientAccessRule {
+    /// check whether `peer` satisfies the rule. A rule without any subjects, names or
+    /// fingerprints admits every client with a verified certificate
+    pub fn admits(&self, peer: Option<&PeerIdentity>) -> bool {
+        let peer = match peer {
+            Some(n) => n,
+            None => return false,
+        };
+        if self.subjects.is_empty() && self.sans.is_empty() && self.fingerprints.is_empty() {
+            return true;
+        }
+        self.subjects
+            .iter()
+            .any(|n| WildMatch::new(n.as_str()).matches(peer.subject.as_str()))
+            || self.sans.iter().any(|n| {
+                let pattern = WildMatch::new(n.as_str());
+                peer.sans.iter().any(|m| pattern.matches(m.as_str()))
+            })
+            || self.fingerprints.iter().any(|n| {
+                n.replace(':', "")
+                    .eq_ignore_ascii_case(peer.fingerprint.as_str())
+            })
+    }
+}
+
+/// access rules based on client certificates. The first rule matching a path is used, paths
+/// without a rule are open to everyone
+#[derive(Clone, Debug, Default)]
+pub struct ClientAccess {
+    rules: Vec<ClientAccessRule>,
+}
+
+impl ClientAccess {
+    /// create an empty set of rules
+    pub fn new() -> ClientAccess {
+        ClientAccess { rules: Vec::new() }
+    }
+
+    /// create the rules from the `rules` setting of the `client_auth` config section
+    pub fn from_config(conf: Option<&Vec<ClientAccessConfig>>) -> ClientAccess {
+        let mut access = ClientAccess::new();
+        for n in conf.into_iter().flatten() {
+            access.rule(ClientAccessRule {
+                path: n.path.clone(),
+                subjects: n.subjects.clone().unwrap_or_default(),
+                sans: n.sans.clone().unwrap_or_default(),
+                fingerprints: n.fingerprints.clone().unwrap_or_default(),
+            });
+        }
+        access
+    }
+
+    /// add a rule
+    pub fn rule(&mut self, rule: ClientAccessRule) {
+        self.rules.push(rule);
+    }
+
+    /// check whether the client `peer` may access `path`
+    pub fn allowed(&self, path: &str, peer: Option<&PeerIdentity>) -> bool {
+        match self
+            .rules
+            .iter()
+            .find(|n| WildMatch::new(n.path.as_str()).matches(path))
+        {
+            Some(rule) => rule.admits(peer),
+            None => true,
+        }
+    }
+}
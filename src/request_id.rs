@@ -0,0 +1,70 @@
+use crate::http::header::HTTPResponseHeaders;
+use crate::http::request::HTTPRequest;
+use crate::http::response::HTTPResponse;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+/// name of the header carrying the id of a request
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// longest id accepted from a client
+const MAX_LENGTH: usize = 128;
+
+/// check whether the id a client sent can be used for its request. Ids are limited to 128
+/// characters that can't break log lines or headers
+pub fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_LENGTH
+        && id.chars().all(|c| {
+            c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':' | '+' | '/' | '=')
+        })
+}
+
+/// generate a random id in the format of a version 4 UUID, e.g.
+/// `0b0e5b4e-8c4f-4f6a-9d1e-3f2c8a7b6d5e`
+pub fn generate() -> String {
+    let mut bytes = [0u8; 16];
+    if openssl::rand::rand_bytes(&mut bytes).is_err() {
+        // ids only have to be unique, so the time and a counter are good enough
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|n| n.as_nanos() as u64)
+            .unwrap_or_default();
+        bytes[..8].copy_from_slice(&nanos.to_be_bytes());
+        bytes[8..].copy_from_slice(&COUNTER.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+    }
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|n| format!("{:02x}", n)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// give `req` an id and return it. A valid `X-Request-Id` sent by the client is kept, otherwise
+/// a new id is generated
+pub fn assign(req: &mut HTTPRequest) -> String {
+    let id = match req.get_request_id() {
+        Some(n) if is_valid(n.as_str()) => n,
+        _ => generate(),
+    };
+    req.set_request_id(Some(id.clone()));
+    id
+}
+
+/// echo `id` in the `X-Request-Id` header of `resp` unless the handler already set one
+pub fn apply(id: &str, resp: &mut HTTPResponse) {
+    let set = resp
+        .get_header()
+        .iter()
+        .any(|n| matches!(n, HTTPResponseHeaders::RequestId(_)));
+    if !set {
+        resp.add_header(HTTPResponseHeaders::RequestId(id.to_string()));
+    }
+}
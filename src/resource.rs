@@ -1,8 +1,9 @@
 use crate::http::MimeType;
+use crate::metrics::Metrics;
 use std::collections::HashMap;
 use std::fs::{metadata, OpenOptions};
 use std::io::Read;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 use std::time::SystemTime;
 
 /// a resource loader and cacher
@@ -11,6 +12,7 @@ pub struct ResourceLoader {
     resource_cache: HashMap<String, Resource>,
     resource_root: String,
     use_cache: bool,
+    metrics: Option<Metrics>,
 }
 
 /// a resource loaded by the resource loader
@@ -60,9 +62,7 @@ impl Resource {
             }
         };
         let mut content: Vec<u8> = Vec::new();
-        if let Err(e) = file.read_to_end(&mut content) {
-            return Err(e);
-        }
+        file.read_to_end(&mut content)?;
         let accessed = SystemTime::now();
 
         Ok(Resource {
@@ -80,7 +80,7 @@ impl Resource {
 
     /// get the time when the file was accessed
     pub fn get_accessed(&self) -> SystemTime {
-        self.accessed.clone()
+        self.accessed
     }
 
     /// get the path where the resource is located
@@ -101,7 +101,30 @@ impl ResourceLoader {
             resource_cache: HashMap::with_capacity(capacity),
             resource_root: root,
             use_cache,
+            metrics: None,
+        }
+    }
+
+    /// get the directory resources are loaded from
+    pub fn get_root(&self) -> String {
+        self.resource_root.clone()
+    }
+
+    /// record cache hits and misses in `metrics`
+    pub fn set_metrics(&mut self, metrics: Option<Metrics>) {
+        self.metrics = metrics;
+    }
+
+    /// join `path` to the resource root. Returns `None` for paths that could leave the root, i.e.
+    /// absolute paths and paths containing `..`
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        if !Path::new(path)
+            .components()
+            .all(|n| matches!(n, Component::Normal(_) | Component::CurDir))
+        {
+            return None;
         }
+        Some(Path::new(self.resource_root.as_str()).join(path))
     }
 
     /// load a resource from cache or file system
@@ -121,34 +144,45 @@ impl ResourceLoader {
             },
             None => MimeType::Plaintext,
         };
+        // paths leaving the resource root are never served
+        let file = self.resolve(path.as_str())?;
         if self.use_cache {
             // TODO: wtf? This needs refactoring
             if let Some(n) = self.resource_cache.clone().get(&path) {
-                if let Ok(md) = metadata(path.as_str()) {
+                if let Ok(md) = metadata(&file) {
                     if let Ok(time) = md.modified() {
                         if let Ok(elapsed) = time.elapsed() {
                             if let Ok(res_elapsed) = n.get_accessed().elapsed() {
                                 if elapsed < res_elapsed {
-                                    let new = match Resource::load(path.clone()) {
-                                        Ok(n) => n,
-                                        Err(_) => {
-                                            return None;
-                                        }
-                                    };
+                                    let new =
+                                        match Resource::load(file.to_string_lossy().to_string()) {
+                                            Ok(n) => n,
+                                            Err(_) => {
+                                                return None;
+                                            }
+                                        };
                                     let _ = self.resource_cache.insert(path, new);
                                 }
                             }
                         }
                     }
                 }
+                if let Some(metrics) = &self.metrics {
+                    metrics.cache_hit();
+                    metrics.set_cache_size(
+                        self.resource_cache.values().map(|n| n.content.len()).sum(),
+                    );
+                }
                 return Some(n.clone());
             }
+            if let Some(metrics) = &self.metrics {
+                metrics.cache_miss();
+            }
         }
-        let p = Path::new(path.as_str());
-        if !p.exists() {
-            return None;
+        if !file.exists() {
+            None
         } else {
-            let mut f = match OpenOptions::new().read(true).open(p.to_str().unwrap()) {
+            let mut f = match OpenOptions::new().read(true).open(&file) {
                 Ok(n) => n,
                 Err(_) => {
                     return None;
@@ -169,9 +203,10 @@ impl ResourceLoader {
         if !self.use_cache {
             return String::new();
         }
-        let p = Path::new(path.as_str());
-        //let p = p.join(Path::new(self.resource_root.as_str()));
-        let p = Path::new(self.resource_root.as_str()).join(p);
+        let p = match self.resolve(path.as_str()) {
+            Some(n) => n,
+            None => return String::new(),
+        };
         if !p.exists() {
             return String::new();
         }
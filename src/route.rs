@@ -1,26 +1,112 @@
+use crate::config::{Resource, RouteConfig};
 use crate::http::header::HTTPResponseHeaders;
+use crate::http::request::HTTPMethod;
 use crate::http::response::HTTPResponse;
 use crate::http::Body;
 use crate::http::StatusCode;
 use crate::RSWEB_SERVER_STR;
-use std::collections::HashMap;
 use wildmatch::WildMatch;
 
 /// an enum for the two types of routes:
 ///
-/// - `Route`: returns a redirect (status 301) to the actual address
+/// - `Route`: returns a redirect (status 301 by default) to the actual address
 /// - `Alias`: opens a different file than the one requested (e.g if `/` was requested that might
-/// be an alias to `/index`)
+///   be an alias to `/index`). Its headers are returned by [`Router::alias_headers`]
 pub enum Route {
     Route(HTTPResponse),
     Alias(String),
 }
 
-/// a router for routing http traffic
+/// a route or alias from all paths matching the wildcard pattern `from` to `to`
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub from: String,
+    pub to: String,
+    /// status of the redirect. Only used by routes
+    pub status: StatusCode,
+    /// methods the rule applies to. `None` applies it to all methods
+    pub methods: Option<Vec<HTTPMethod>>,
+    /// headers added to the response
+    pub headers: Vec<(String, String)>,
+}
+
+impl Rule {
+    /// create a rule from `from` to `to` for all methods
+    pub fn new(from: String, to: String) -> Rule {
+        Rule {
+            from,
+            to,
+            status: StatusCode::MovedPermanently,
+            methods: None,
+            headers: Vec::new(),
+        }
+    }
+
+    /// create a rule from a `from:to` string. Only the first `:` separates the two, so `to` may
+    /// contain more (e.g. `/docs:https://docs.example.com`)
+    pub fn from_string(string: &str) -> Option<Rule> {
+        match string.split_once(':') {
+            Some((from, to)) if !from.is_empty() && !to.is_empty() => {
+                Some(Rule::new(from.to_string(), to.to_string()))
+            }
+            _ => None,
+        }
+    }
+
+    /// create a rule from a `[[routes]]` or `[[aliases]]` table. Unknown method names are
+    /// ignored and an invalid status falls back to 301
+    pub fn from_config(conf: &RouteConfig) -> Rule {
+        let mut rule = Rule::new(conf.from.clone(), conf.to.clone());
+        if let Some(n) = conf.status.and_then(StatusCode::redirect) {
+            rule.status = n;
+        }
+        rule.methods = conf.methods.as_ref().map(|methods| {
+            methods
+                .iter()
+                .filter_map(|n| HTTPMethod::from_string(n.to_uppercase()))
+                .collect()
+        });
+        rule.headers = conf
+            .headers
+            .iter()
+            .flatten()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        rule
+    }
+
+    /// check whether the rule applies to a request for `path` using `method`. Without a method
+    /// only the path is checked
+    fn matches(&self, path: &str, method: Option<&HTTPMethod>) -> bool {
+        let method = match (method, &self.methods) {
+            (Some(method), Some(methods)) => methods.contains(method),
+            _ => true,
+        };
+        method && WildMatch::new(self.from.as_str()).matches(path)
+    }
+
+    fn headers(&self) -> Vec<HTTPResponseHeaders> {
+        self.headers
+            .iter()
+            .map(|(name, value)| HTTPResponseHeaders::Other(name.clone(), value.clone()))
+            .collect()
+    }
+}
+
+/// a router for routing http traffic. Routes and aliases are checked in the order they were
+/// added, routes before aliases
 #[derive(Clone)]
 pub struct Router {
-    routemap: HashMap<String, String>,
-    aliasmap: HashMap<String, String>,
+    routes: Vec<Rule>,
+    aliases: Vec<Rule>,
+}
+
+/// add `rule` to `rules`, replacing the rule with the same pattern
+fn insert(rules: &mut Vec<Rule>, rule: Rule) {
+    match rules.iter_mut().find(|n| n.from == rule.from) {
+        Some(n) => *n = rule,
+        None => rules.push(rule),
+    }
 }
 
 impl Router {
@@ -28,48 +114,118 @@ impl Router {
     #[allow(unused_variables)]
     pub fn new(index: String) -> Router {
         Router {
-            routemap: HashMap::new(),
-            aliasmap: HashMap::new(),
+            routes: Vec::new(),
+            aliases: Vec::new(),
         }
     }
 
+    /// create a router from the `routes` and `aliases` strings of `resources` followed by the
+    /// `[[routes]]` and `[[aliases]]` tables of a config section. Strings without `:` are ignored
+    pub fn from_config(
+        resources: &Resource,
+        routes: Option<&Vec<RouteConfig>>,
+        aliases: Option<&Vec<RouteConfig>>,
+    ) -> Router {
+        let index = resources
+            .index
+            .clone()
+            .unwrap_or_else(|| String::from("/index.html"));
+        let mut router = Router::new(index);
+        let strings = |n: &Option<Vec<String>>| -> Vec<Rule> {
+            n.iter()
+                .flatten()
+                .filter_map(|n| Rule::from_string(n))
+                .collect()
+        };
+        let tables = |n: Option<&Vec<RouteConfig>>| -> Vec<Rule> {
+            n.into_iter().flatten().map(Rule::from_config).collect()
+        };
+        for rule in strings(&resources.routes).into_iter().chain(tables(routes)) {
+            router.add_route(rule);
+        }
+        for rule in strings(&resources.aliases)
+            .into_iter()
+            .chain(tables(aliases))
+        {
+            router.add_alias(rule);
+        }
+        router
+    }
+
     /// add a new route
     pub fn route(&mut self, from: String, to: String) {
-        self.routemap.insert(from, to);
+        self.add_route(Rule::new(from, to));
     }
 
     /// add a new alias
     pub fn alias(&mut self, key: String, alias: String) {
-        self.aliasmap.insert(key, alias);
+        self.add_alias(Rule::new(key, alias));
+    }
+
+    /// add a new route with a status, methods and headers
+    pub fn add_route(&mut self, rule: Rule) {
+        insert(&mut self.routes, rule);
+    }
+
+    /// add a new alias with methods and headers. Its status is ignored
+    pub fn add_alias(&mut self, rule: Rule) {
+        insert(&mut self.aliases, rule);
+    }
+
+    /// get the pattern of the route or alias matching `path`
+    pub fn matching(&self, path: &str) -> Option<String> {
+        self.routes
+            .iter()
+            .chain(self.aliases.iter())
+            .find(|n| n.matches(path, None))
+            .map(|n| n.from.clone())
+    }
+
+    /// check whether any route applies to `method`
+    pub fn routes_method(&self, method: &HTTPMethod) -> bool {
+        self.routes.iter().any(|n| {
+            n.methods
+                .as_ref()
+                .is_none_or(|methods| methods.contains(method))
+        })
     }
 
-    /// lookup and return a response if a route was found.
-    /// If `pattern` matches multiple keys the first one found gets returned
+    /// lookup and return a response if a route was found, regardless of the methods it applies
+    /// to. If `pattern` matches multiple routes the first one added gets returned
     pub fn lookup(&self, pattern: String) -> Option<Route> {
-        let mut resp: Option<Route> = None;
-        for key in self.routemap.keys() {
-            if WildMatch::new(key.as_str()).matches(pattern.as_str()) {
-                let body = Body::new(String::new());
-                let loc = self.routemap.get(key.as_str()).unwrap();
-                resp = Some(Route::Route(HTTPResponse::new(
-                    StatusCode::MovedPermanently,
-                    vec![
-                        HTTPResponseHeaders::Location(loc.to_string()),
-                        HTTPResponseHeaders::Server(RSWEB_SERVER_STR.to_string()),
-                    ],
-                    body,
-                )));
-                break;
-            }
-        }
-        if resp.is_none() {
-            for alias in self.aliasmap.keys() {
-                if WildMatch::new(alias.as_str()).matches(pattern.as_str()) {
-                    resp = Some(Route::Alias(self.aliasmap.get(alias).unwrap().to_string()));
-                    break;
-                }
-            }
+        self.find(pattern.as_str(), None)
+    }
+
+    /// lookup and return a response if a route applying to `method` was found
+    pub fn lookup_method(&self, pattern: &str, method: &HTTPMethod) -> Option<Route> {
+        self.find(pattern, Some(method))
+    }
+
+    fn find(&self, pattern: &str, method: Option<&HTTPMethod>) -> Option<Route> {
+        if let Some(rule) = self.routes.iter().find(|n| n.matches(pattern, method)) {
+            let mut headers = vec![
+                HTTPResponseHeaders::Location(rule.to.clone()),
+                HTTPResponseHeaders::Server(RSWEB_SERVER_STR.to_string()),
+            ];
+            headers.extend(rule.headers());
+            return Some(Route::Route(HTTPResponse::new(
+                rule.status.clone(),
+                headers,
+                Body::new(String::new()),
+            )));
         }
-        resp
+        self.aliases
+            .iter()
+            .find(|n| n.matches(pattern, method))
+            .map(|n| Route::Alias(n.to.clone()))
+    }
+
+    /// get the headers of the alias applying to a request for `pattern` using `method`
+    pub fn alias_headers(&self, pattern: &str, method: &HTTPMethod) -> Vec<HTTPResponseHeaders> {
+        self.aliases
+            .iter()
+            .find(|n| n.matches(pattern, Some(method)))
+            .map(|n| n.headers())
+            .unwrap_or_default()
     }
 }
@@ -1,27 +1,575 @@
 #![allow(unused_assignments)]
+use crate::access::AccessLog;
+use crate::acme::AcmeChallenges;
 use crate::config;
+use crate::cors::Cors;
 use crate::dbuffer::DBuffer;
+use crate::error::ServerError;
+use crate::health::Health;
 use crate::http::MimeType;
 use crate::http::{body::*, header::*, request::*, response::*, StatusCode};
-use crate::log;
+use crate::https::{Hsts, HttpsRedirect};
+use crate::limits::{limit_exceeded, Limits};
+use crate::listener::{accept_all, bind_all, server_addr, tls_port, Connection, Listener};
+use crate::log::{Logger, RequestScope};
+use crate::methods::{MethodPolicy, ALL_METHODS, STATIC_METHODS};
+use crate::metrics::Metrics;
+use crate::mtls::ClientAccess;
+use crate::request_id;
 use crate::resource::ResourceLoader;
 use crate::route::*;
+use crate::timeout::{SetTimeout, Timeouts};
+use crate::tls::ReloadableAcceptor;
+use crate::vhost::{Vhost, VirtualHosts};
 use crate::ThreadPool;
 use crate::RSWEB_SERVER_STR;
 use crate::RSWEB_VERSION;
-use crate::{error, msg};
-use std::io::Write;
-use std::net::IpAddr;
-use std::net::TcpListener;
+use crate::{debug, error, msg};
+use std::io::{ErrorKind, Write};
+use std::net::{IpAddr, TcpListener};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 /// a rsweb server using a resource loader and router
 pub struct Server {
     tp: ThreadPool,
     rl: ResourceLoader,
-    port: usize,
-    ip: IpAddr,
+    listeners: Vec<Listener>,
+    /// why the address passed to [`Server::new`] is invalid, reported by [`Server::run`]
+    port_error: Option<ServerError>,
+    acceptor: Option<Arc<ReloadableAcceptor>>,
     router: Router,
     config: config::Config,
+    timeouts: Timeouts,
+    limits: Limits,
+    methods: MethodPolicy,
+    cors: Cors,
+    redirect: Option<HttpsRedirect>,
+    acme: Option<AcmeChallenges>,
+    access_log: Option<AccessLog>,
+    logger: Option<Logger>,
+    metrics: Option<Metrics>,
+    health: Option<Health>,
+    vhosts: VirtualHosts,
+    bound: Option<Sender<()>>,
+}
+
+/// create a response that only consists of `status` and a short html body describing it. The
+/// connection is marked to be closed afterwards
+pub(crate) fn status_response(status: StatusCode) -> HTTPResponse {
+    let body = Body::new(format!("<h1>{} {}</h1>", status.code(), status.reason()));
+    let headers = vec![
+        HTTPResponseHeaders::Server(RSWEB_SERVER_STR.to_string()),
+        HTTPResponseHeaders::ContentType(MimeType::Html),
+        HTTPResponseHeaders::ContentLength(body.get_bytes().len()),
+        HTTPResponseHeaders::Connection(String::from("close")),
+    ];
+    HTTPResponse::new(status, headers, body)
+}
+
+/// get the status code to answer a request with that couldn't be read because of `e`
+fn read_error_status(e: &std::io::Error) -> Option<StatusCode> {
+    if e.kind() == ErrorKind::TimedOut {
+        return Some(StatusCode::RequestTimeout);
+    }
+    match limit_exceeded(e) {
+        Some(n) => Some(n.status()),
+        // the request head was read completely but isn't valid
+        None if e.kind() == ErrorKind::InvalidData => Some(StatusCode::BadRequest),
+        None => None,
+    }
+}
+
+/// create the response to an `OPTIONS` request listing the `allowed` methods
+fn options_response(allowed: Vec<HTTPMethod>) -> HTTPResponse {
+    HTTPResponse::new(
+        StatusCode::NoContent,
+        vec![
+            HTTPResponseHeaders::Server(RSWEB_SERVER_STR.to_string()),
+            HTTPResponseHeaders::Allow(allowed),
+            HTTPResponseHeaders::ContentLength(0),
+        ],
+        Body::new(String::new()),
+    )
+}
+
+/// answer a request for a resource that only supports `GET` and `HEAD` with the response created
+/// by `response`. Other methods are answered with `405 Method Not Allowed`
+pub(crate) fn read_only_response<F>(method: &HTTPMethod, response: F) -> HTTPResponse
+where
+    F: FnOnce() -> HTTPResponse,
+{
+    match method {
+        HTTPMethod::Get => response(),
+        HTTPMethod::Head => {
+            let resp = response();
+            HTTPResponse::new(
+                resp.get_status(),
+                resp.get_header(),
+                Body::new(String::new()),
+            )
+        }
+        _ => {
+            let mut resp = status_response(StatusCode::MethodNotAllowed);
+            resp.add_header(HTTPResponseHeaders::Allow(vec![
+                HTTPMethod::Get,
+                HTTPMethod::Head,
+            ]));
+            resp
+        }
+    }
+}
+
+/// serve administrative endpoints like metrics on `addr` in a background thread. Requests
+/// `handler` doesn't answer get `404 Not Found`. `name` describes the endpoints in the log
+pub(crate) fn serve_admin<F>(
+    addr: &str,
+    name: &str,
+    logger: Logger,
+    handler: F,
+) -> Result<(), std::io::Error>
+where
+    F: Fn(&HTTPRequest) -> Option<HTTPResponse> + Send + 'static,
+{
+    let listener = TcpListener::bind(addr)?;
+    msg!(logger, "serving {} on {}", name, addr);
+    std::thread::spawn(move || {
+        let timeouts = Timeouts::default();
+        let limits = Limits::default();
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(n) => n,
+                Err(e) => {
+                    error!(logger, "{}", e);
+                    continue;
+                }
+            };
+            let mut buf = DBuffer::new();
+            if buf
+                .read_http_request_limited(&mut stream, &timeouts, &limits)
+                .is_err()
+            {
+                continue;
+            }
+            let resp = match buf
+                .to_string()
+                .ok()
+                .and_then(|n| HTTPRequest::from_string(n).ok())
+                .and_then(|req| handler(&req))
+            {
+                Some(n) => n,
+                None => status_response(StatusCode::NotFound),
+            };
+            let _ = stream.set_write_timeout(timeouts.write);
+            if stream.write_all(&resp.to_bytes()).is_err() {
+                error!(logger, "failed to write to stream");
+            }
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        }
+    });
+    Ok(())
+}
+
+/// load the resource at `path` (relative to the resource root) and create a response containing
+/// it. If it can't be found `notfound_page` (or a default page) is sent with status 404
+fn resource_response(
+    path: &str,
+    method: &HTTPMethod,
+    resload: &mut ResourceLoader,
+    notfound_page: Option<&str>,
+) -> HTTPResponse {
+    let mut headers = vec![HTTPResponseHeaders::Server(RSWEB_SERVER_STR.to_string())];
+    let mut status = StatusCode::InternalServerError;
+    let mut body = Body::new(String::new());
+    // paths are relative to the resource root. An empty path (e.g. an alias to "") loads nothing
+    let relative = |path: &str| path.strip_prefix('/').unwrap_or(path).to_string();
+    match resload.load(relative(path)) {
+        Some(n) => {
+            headers.push(HTTPResponseHeaders::ContentType(n.get_mime()));
+            body = Body::from_bytes(n.get_content());
+            status = StatusCode::Ok;
+        }
+        None => {
+            headers.push(HTTPResponseHeaders::ContentType(MimeType::Html));
+            status = StatusCode::NotFound;
+            body = match notfound_page.and_then(|page| resload.load(relative(page))) {
+                Some(n) => Body::from_bytes(n.get_content()),
+                None => Body::new(String::from("<h1>404 Not Found</h1>")),
+            };
+        }
+    }
+    headers.push(HTTPResponseHeaders::ContentLength(body.get_bytes().len()));
+    if *method == HTTPMethod::Head {
+        body = Body::new(String::new());
+    }
+    HTTPResponse::new(status, headers, body)
+}
+
+/// answer `req` using the routes of `router` and the resources of `resload`. Methods not allowed
+/// by `methods` or not supported for the requested path are answered with `405 Method Not
+/// Allowed` and `OPTIONS` requests (including CORS preflight requests) are answered automatically
+pub(crate) fn handle_static(
+    req: &HTTPRequest,
+    router: &Router,
+    resload: &mut ResourceLoader,
+    notfound_page: Option<&str>,
+    methods: &MethodPolicy,
+    cors: &Cors,
+) -> HTTPResponse {
+    let path = req.get_path();
+    let method = req.get_method();
+    if path == "*" {
+        if method != HTTPMethod::Options {
+            return status_response(StatusCode::BadRequest);
+        }
+        // static resources and aliases only answer the static methods, routes may answer more
+        let supported: Vec<HTTPMethod> = ALL_METHODS
+            .iter()
+            .filter(|n| STATIC_METHODS.contains(n) || router.routes_method(n))
+            .cloned()
+            .collect();
+        return options_response(methods.server_allowed(&supported));
+    }
+    let route = router.lookup_method(path.as_str(), &method);
+    let supported: &[HTTPMethod] = match route {
+        Some(Route::Route(_)) => &ALL_METHODS,
+        _ => &STATIC_METHODS,
+    };
+    let allowed = methods.allowed(path.as_str(), supported);
+    if let Some(resp) = cors.preflight(req, &allowed) {
+        return resp;
+    }
+    let mut resp = if !allowed.contains(&method) {
+        let mut resp = status_response(StatusCode::MethodNotAllowed);
+        resp.add_header(HTTPResponseHeaders::Allow(allowed));
+        resp
+    } else if method == HTTPMethod::Options {
+        options_response(allowed)
+    } else {
+        match route {
+            Some(Route::Route(resp)) => resp,
+            Some(Route::Alias(alias)) => {
+                let mut resp = resource_response(&alias, &method, resload, notfound_page);
+                for header in router.alias_headers(path.as_str(), &method) {
+                    resp.add_header(header);
+                }
+                resp
+            }
+            None => resource_response(&path, &method, resload, notfound_page),
+        }
+    };
+    cors.apply(req, &mut resp);
+    resp
+}
+
+/// check that the resource roots of `vhosts` are readable for the readiness of `server`
+pub(crate) fn register_vhost_roots(health: &Health, server: &str, vhosts: &VirtualHosts) {
+    for vhost in vhosts.hosts() {
+        let name = format!("{}_vhost_{}", server, vhost.name());
+        health.register_root(name.as_str(), vhost.get_root().as_str());
+    }
+}
+
+/// the host HTTPS redirects of requests without a `Host` header accepted by `listener` point
+/// to. IPv6 addresses are put in brackets and unix sockets use `localhost`
+fn fallback_host(listener: &Listener) -> String {
+    match listener.tcp_addr().map(|n| n.ip()) {
+        Some(IpAddr::V4(n)) => n.to_string(),
+        Some(IpAddr::V6(n)) => format!("[{}]", n),
+        None => String::from("localhost"),
+    }
+}
+
+/// send a [`status_response`] for `status` to `stream` while respecting the write timeout
+fn send_status<T: Write + SetTimeout>(
+    stream: &mut T,
+    status: StatusCode,
+    timeouts: &Timeouts,
+) -> std::io::Result<()> {
+    stream.set_write_timeout(timeouts.write)?;
+    stream.write_all(&status_response(status).to_bytes())?;
+    stream.flush()
+}
+
+/// answers the requests of [`FuncServer`] and [`SSLFuncServer`] instead of virtual hosts
+///
+/// [`SSLFuncServer`]: crate::ssl::SSLFuncServer
+pub(crate) type Handler = Arc<dyn Fn(HTTPRequest) -> HTTPResponse + Send + Sync>;
+
+/// create a [`Handler`] calling `func`. It only has to be `Send`, so every request uses a copy
+/// taken under a lock
+pub(crate) fn func_handler<F>(func: F) -> Handler
+where
+    F: FnOnce(HTTPRequest) -> HTTPResponse + Send + Copy + 'static,
+{
+    let func = Mutex::new(func);
+    Arc::new(move |req| {
+        let func = *func.lock().unwrap_or_else(|e| e.into_inner());
+        func(req)
+    })
+}
+
+/// the request handling shared by all listeners of all servers. It answers plain and TLS
+/// connections alike, only HTTPS redirects and ACME HTTP-01 challenges are limited to plain and
+/// HSTS to TLS connections
+///
+/// [`SSLServer`]: crate::ssl::SSLServer
+#[derive(Clone)]
+pub(crate) struct Core {
+    /// the name of the server in metrics, e.g. `http`
+    pub name: &'static str,
+    pub logger: Logger,
+    pub vhosts: Arc<VirtualHosts>,
+    pub fallback: Arc<Vhost>,
+    pub timeouts: Timeouts,
+    pub limits: Limits,
+    pub methods: MethodPolicy,
+    pub cors: Cors,
+    pub redirect: Option<HttpsRedirect>,
+    pub access: ClientAccess,
+    pub hsts: Option<Hsts>,
+    pub acme: Option<AcmeChallenges>,
+    pub access_log: Option<AccessLog>,
+    pub metrics: Option<Metrics>,
+    pub health: Option<Health>,
+    /// the certificates of TLS listeners
+    pub acceptor: Option<Arc<ReloadableAcceptor>>,
+    /// answers requests instead of `vhosts` and `fallback` if set
+    pub handler: Option<Handler>,
+}
+
+impl Core {
+    /// create a core answering every request with `handler`. The other settings are disabled
+    pub fn with_handler(name: &'static str, logger: Logger, handler: Handler) -> Core {
+        let rl = ResourceLoader::new(0, String::new(), false);
+        Core {
+            name,
+            logger,
+            vhosts: Arc::new(VirtualHosts::new()),
+            fallback: Arc::new(Vhost::new(Vec::new(), rl, Router::new(String::new()))),
+            timeouts: Timeouts::default(),
+            limits: Limits::default(),
+            methods: MethodPolicy::default(),
+            cors: Cors::new(),
+            redirect: None,
+            access: ClientAccess::new(),
+            hsts: None,
+            acme: None,
+            access_log: None,
+            metrics: None,
+            health: None,
+            acceptor: None,
+            handler: Some(handler),
+        }
+    }
+
+    /// bind `listeners`, report them to the health checks and `bound` and answer their
+    /// connections using `tp`. Only returns if binding fails
+    pub fn serve(
+        &self,
+        listeners: &[Listener],
+        tp: &ThreadPool,
+        bound: Option<Sender<()>>,
+    ) -> Result<(), std::io::Error> {
+        if listeners.iter().any(|n| n.tls) && self.acceptor.is_none() {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "TLS listeners require certificates",
+            ));
+        }
+        let sockets = bind_all(listeners)?;
+        for (listener, socket) in &sockets {
+            let addr = socket.local_addr()?;
+            msg!(self.logger, "listening on {}", listener);
+            if let Some(n) = &self.health {
+                n.listener_bound(self.name, addr.to_string().as_str());
+            }
+        }
+        if let Some(n) = bound {
+            let _ = n.send(());
+        }
+        for (stream, listener) in accept_all(sockets, &self.logger) {
+            let core = self.clone();
+            tp.execute(move || core.handle(stream, &listener));
+        }
+        Ok(())
+    }
+
+    /// answer the request on a connection accepted by `listener`
+    fn handle(&self, stream: Connection, listener: &Listener) {
+        let started = Instant::now();
+        let _connection = self.metrics.as_ref().map(|n| n.connection(self.name));
+        let client = stream.peer_ip();
+        let mut stream = match self.accept(stream, listener) {
+            Some(n) => n,
+            None => return,
+        };
+        let logging = &self.logger;
+        let mut buf = DBuffer::new();
+        if let Err(e) = buf.read_http_request_limited(&mut stream, &self.timeouts, &self.limits) {
+            if let Some(status) = read_error_status(&e) {
+                error!(logging, "{}", e);
+                if send_status(&mut stream, status.clone(), &self.timeouts).is_err() {
+                    error!(logging, "failed to write to stream");
+                }
+                let resp = status_response(status);
+                if let Some(n) = &self.access_log {
+                    let user = stream.peer_identity().map(|n| n.subject);
+                    n.record(client, user, "", &resp, started);
+                }
+                if let Some(n) = &self.metrics {
+                    n.record_request(
+                        self.name,
+                        None,
+                        resp.get_status(),
+                        "-",
+                        buf.as_bytes().len(),
+                        resp.to_bytes().len(),
+                        started.elapsed(),
+                    );
+                }
+                let _ = stream.shutdown();
+                return;
+            }
+            error!(logging, "failed to read from stream");
+            let _ = stream.shutdown();
+            return;
+        }
+        if stream.set_write_timeout(self.timeouts.write).is_err() {
+            error!(logging, "failed to set write timeout");
+        }
+        // TODO: only take the headers as string. The body might be non UTF-8
+        let data: String = match buf.to_string() {
+            Ok(n) => n,
+            Err(_) => {
+                error!(logging, "failed to parse data to utf8");
+                String::new()
+            }
+        };
+        if let Ok(mut req) = HTTPRequest::from_string(data.clone()) {
+            req.set_peer_identity(stream.peer_identity());
+            req.set_peer_credentials(stream.peer_credentials());
+            let id = request_id::assign(&mut req);
+            let _scope = RequestScope::enter(&id);
+            let mut vhost = self
+                .vhosts
+                .select(req.get_host().as_deref())
+                .unwrap_or(&self.fallback)
+                .clone();
+            let mut resp = self.respond(&req, &mut vhost, listener, stream.is_tls());
+            if let Some(n) = self.hsts.as_ref().filter(|_| stream.is_tls()) {
+                resp.add_header(n.header());
+            }
+            request_id::apply(&id, &mut resp);
+            let bytes = resp.to_bytes();
+            match stream.write_all(&bytes) {
+                Ok(_) => (),
+                Err(_) => error!(logging, "failed to write to stream"),
+            }
+            match stream.flush() {
+                Ok(_) => (),
+                Err(_) => error!(logging, "failed to flush stream"),
+            }
+            debug!(
+                logging,
+                {
+                    "method": req.get_method(),
+                    "path": req.get_path(),
+                    "status": resp.get_status().code(),
+                },
+                "answered request"
+            );
+            if let Some(n) = &self.access_log {
+                let user = req.get_peer_identity().map(|n| n.subject);
+                n.record(client, user, data.as_str(), &resp, started);
+            }
+            if let Some(n) = &self.metrics {
+                let route = vhost
+                    .router()
+                    .matching(req.get_path().as_str())
+                    .unwrap_or_else(|| String::from("-"));
+                n.record_request(
+                    self.name,
+                    Some(&req.get_method()),
+                    resp.get_status(),
+                    route.as_str(),
+                    buf.as_bytes().len(),
+                    bytes.len(),
+                    started.elapsed(),
+                );
+            }
+        } else {
+            error!(logging, "failed to parse request");
+        }
+        match stream.shutdown() {
+            Ok(_) => (),
+            Err(_) => error!(logging, "failed to shutdown stream"),
+        }
+    }
+
+    /// complete the TLS handshake on connections of TLS listeners. Returns `None` if the
+    /// connection is done afterwards
+    fn accept(&self, stream: Connection, listener: &Listener) -> Option<Connection> {
+        let (stream, acceptor) = match (stream, &self.acceptor) {
+            (Connection::Plain(stream), Some(n)) if listener.tls => (stream, n.acceptor()),
+            (stream, _) => return Some(stream),
+        };
+        let _ = stream.set_read_timeout(self.timeouts.header_read.or(self.timeouts.idle));
+        let _ = stream.set_write_timeout(self.timeouts.write);
+        // validation connections of the CA only need the handshake
+        if let Some(challenge) = self.acme.as_ref().and_then(|n| n.tls_acceptor(&stream)) {
+            if challenge.accept(stream).is_err() {
+                error!(self.logger, "failed ACME TLS-ALPN-01 handshake");
+            }
+            return None;
+        }
+        match acceptor.accept(stream) {
+            Ok(n) => Some(Connection::Tls(Box::new(n))),
+            Err(_) => {
+                error!(self.logger, "failed SSL handshake");
+                if let Some(n) = &self.metrics {
+                    n.tls_handshake_failed(self.name);
+                }
+                None
+            }
+        }
+    }
+
+    /// create the response to `req` using `vhost` unless it is answered by the server itself
+    fn respond(
+        &self,
+        req: &HTTPRequest,
+        vhost: &mut Vhost,
+        listener: &Listener,
+        tls: bool,
+    ) -> HTTPResponse {
+        if !tls {
+            if let Some(n) = self.acme.as_ref().and_then(|n| n.http_response(req)) {
+                return n;
+            }
+        }
+        if let Some(n) = self.health.as_ref().and_then(|n| n.handle(req)) {
+            return n;
+        }
+        let peer = req.get_peer_identity();
+        if !self.access.allowed(req.get_path().as_str(), peer.as_ref()) {
+            return vhost.finish(&req.get_method(), status_response(StatusCode::Forbidden));
+        }
+        if let Some(n) = self.metrics.as_ref().and_then(|n| n.handle(req)) {
+            return n;
+        }
+        if !tls {
+            let host = fallback_host(listener);
+            if let Some(n) = self.redirect.as_ref().and_then(|n| n.response(req, &host)) {
+                return n;
+            }
+        }
+        match &self.handler {
+            Some(handler) => self.cors.handle(req.clone(), |req| handler(req)),
+            None => vhost.respond(req, &self.methods, &self.cors),
+        }
+    }
 }
 
 impl Server {
@@ -31,7 +579,10 @@ impl Server {
     /// * `rl`: the resource loader to use
     /// * `router`: the router to use
     /// * `port`: the port to use
-    /// * `ip`: the ip address to run on
+    /// * `ip`: the ip address to run on. More addresses can be set with
+    ///   [`Server::set_listeners`]
+    /// * `config`: the config to use. Timeouts, limits, allowed methods, CORS policies, the
+    ///   redirect to HTTPS and the access log are taken from its `http` section
     pub fn new(
         capacity: usize,
         rl: ResourceLoader,
@@ -40,192 +591,215 @@ impl Server {
         ip: IpAddr,
         config: config::Config,
     ) -> Server {
+        let timeouts =
+            Timeouts::from_config(config.http.as_ref().and_then(|n| n.timeouts.as_ref()));
+        let limits = Limits::from_config(config.http.as_ref().and_then(|n| n.limits.as_ref()));
+        let methods = MethodPolicy::from_config(
+            config
+                .http
+                .as_ref()
+                .and_then(|n| n.allowed_methods.as_ref()),
+            config.http.as_ref().and_then(|n| n.path_methods.as_ref()),
+        );
+        let cors = Cors::from_config(config.http.as_ref().and_then(|n| n.cors.as_ref()));
+        let redirect = config
+            .http
+            .as_ref()
+            .and_then(|n| n.https_redirect.as_ref())
+            .map(|n| HttpsRedirect::from_config(n, config.ssl.as_ref().and_then(tls_port)));
+        let access_log = config
+            .http
+            .as_ref()
+            .and_then(|n| n.access_log.as_ref())
+            .map(AccessLog::from_config);
+        let (listeners, port_error) = match server_addr(ip, port) {
+            Ok(n) => (vec![Listener::new(n, false)], None),
+            Err(e) => (Vec::new(), Some(e)),
+        };
         Server {
             tp: ThreadPool::new(capacity),
             rl,
+            listeners,
+            port_error,
+            acceptor: None,
             router,
-            port,
-            ip,
             config,
+            timeouts,
+            limits,
+            methods,
+            cors,
+            redirect,
+            acme: None,
+            access_log,
+            logger: None,
+            metrics: None,
+            health: None,
+            vhosts: VirtualHosts::new(),
+            bound: None,
+        }
+    }
+
+    /// send `()` to `bound` once all listeners are bound, e.g. to start obtaining certificates
+    /// whose validation needs them. `None` disables the notification
+    pub fn set_bound_notifier(&mut self, bound: Option<Sender<()>>) {
+        self.bound = bound;
+    }
+
+    /// listen on `listeners` instead of the address passed to [`Server::new`]. Listeners with
+    /// TLS need certificates (see [`Server::set_certificates`])
+    pub fn set_listeners(&mut self, listeners: Vec<Listener>) {
+        self.listeners = listeners;
+        self.port_error = None;
+        if let Some(n) = &self.health {
+            n.register_listeners("http", self.listeners.len());
         }
     }
 
+    /// serve TLS listeners with the certificates of `acceptor`, e.g. the ones of an
+    /// [`SSLServer`](crate::ssl::SSLServer) to share them
+    pub fn set_certificates(&mut self, acceptor: Option<Arc<ReloadableAcceptor>>) {
+        self.acceptor = acceptor;
+    }
+
+    /// set the timeouts used for client connections
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    /// set the size limits for requests
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// set the methods clients are allowed to use
+    pub fn set_allowed_methods(&mut self, methods: MethodPolicy) {
+        self.methods = methods;
+    }
+
+    /// set the CORS policies
+    pub fn set_cors(&mut self, cors: Cors) {
+        self.cors = cors;
+    }
+
+    /// redirect requests to HTTPS instead of serving them. `None` disables the redirect
+    pub fn set_https_redirect(&mut self, redirect: Option<HttpsRedirect>) {
+        self.redirect = redirect;
+    }
+
+    /// answer the pending ACME HTTP-01 challenges of `challenges`
+    pub fn set_acme_challenges(&mut self, challenges: Option<AcmeChallenges>) {
+        self.acme = challenges;
+    }
+
+    /// write every answered request to `access_log`. `None` disables the access log
+    pub fn set_access_log(&mut self, access_log: Option<AccessLog>) {
+        self.access_log = access_log;
+    }
+
+    /// log to `logger` (e.g. to share it with other servers) instead of creating a logger when
+    /// the server is started
+    pub fn set_logger(&mut self, logger: Logger) {
+        self.logger = Some(logger);
+    }
+
+    /// record requests, connections and resource cache usage in `metrics` and serve them unless
+    /// they have a separate listener. `None` disables metrics
+    pub fn set_metrics(&mut self, metrics: Option<Metrics>) {
+        self.rl.set_metrics(metrics.clone());
+        self.vhosts.set_metrics(metrics.clone());
+        self.metrics = metrics;
+    }
+
+    /// report liveness and readiness of the server (listeners, resource roots and thread pool)
+    /// to `health` and answer its endpoints unless it has a separate admin listener. `None`
+    /// disables them
+    pub fn set_health(&mut self, health: Option<Health>) {
+        if let Some(n) = &health {
+            n.register_listeners("http", self.listeners.len());
+            n.register_root("http", self.rl.get_root().as_str());
+            register_vhost_roots(n, "http", &self.vhosts);
+            n.register_pool("http", self.tp.stats());
+        }
+        self.health = health;
+    }
+
+    /// answer requests for the hosts of `vhosts` with their resources and routes. Requests for
+    /// other hosts are answered with the resources and routes of the server
+    pub fn set_vhosts(&mut self, vhosts: VirtualHosts) {
+        self.vhosts = vhosts;
+        self.vhosts.set_metrics(self.metrics.clone());
+        if let Some(n) = &self.health {
+            register_vhost_roots(n, "http", &self.vhosts);
+        }
+    }
+
+    /// the virtual host answering requests for hosts without one of their own
+    fn fallback_vhost(&self) -> Vhost {
+        let mut vhost = Vhost::new(Vec::new(), self.rl.clone(), self.router.clone());
+        vhost.set_notfound_page(
+            self.config
+                .http
+                .as_ref()
+                .and_then(|n| n.resources.notfound_page.clone()),
+        );
+        vhost
+    }
+
     /// run the server
     /// # Arguments
-    /// `lf`: the logfile to log to
+    /// `lf`: the logfile to log to if no logger was set with [`Server::set_logger`]
     pub fn run(&mut self, lf: &str) -> Result<(), std::io::Error> {
-        let listener = match self.ip {
-            IpAddr::V4(addr) => match TcpListener::bind(format!("{}:{}", addr, self.port)) {
-                Ok(n) => n,
-                Err(e) => {
-                    return Err(e);
-                }
-            },
-            IpAddr::V6(addr) => match TcpListener::bind(format!("{}:{}", addr, self.port)) {
-                Ok(n) => n,
-                Err(e) => {
-                    return Err(e);
-                }
-            },
+        if let Some(e) = &self.port_error {
+            return Err(std::io::Error::new(ErrorKind::InvalidInput, e.to_string()));
+        }
+        if let Some(n) = &mut self.access_log {
+            n.open()?;
+        }
+        if let Some(n) = &self.metrics {
+            n.register_pool("http", self.tp.stats());
+        }
+        let logger = match &self.logger {
+            Some(n) => n.clone(),
+            None => Logger::open(lf),
         };
-
-        let mut logger = log::Logger::new();
-        let _ = logger.set_term(btui::Terminal::default());
-        let _ = logger.set_logfile(lf);
         msg!(logger, "starting HTTP server (rsweb {})", RSWEB_VERSION);
-        for stream in listener.incoming() {
-            match stream {
-                Ok(mut stream) => {
-                    // TODO: make the executing thread mutate the resource loader of the main
-                    // thread
-                    let router = self.router.clone();
-                    let logfile = lf.to_string();
-                    let mut resload = self.rl.clone();
-                    let config = self.config.clone();
-                    self.tp.execute(move || {
-                        let mut logging = log::Logger::new();
-                        logging.set_term(btui::Terminal::new());
-                        let _ = logging.set_logfile(logfile.as_str());
-                        let mut buf = DBuffer::new();
-                        if let Err(_) = buf.read_http_request(&mut stream) {
-                            error!(logging, "failed to read from stream");
-                        }
-                        // TODO: only take the headers as string. The body might be non UTF-8
-                        let data: String = match buf.to_string() {
-                            Ok(n) => n,
-                            Err(_) => {
-                                error!(logging, "failed to parse data to utf8");
-                                return;
-                            }
-                        };
-                        if let Ok(req) = HTTPRequest::from_string(data) {
-                            msg!(logging, "request: {} {}", req.get_method(), req.get_path());
-                            if let Some(n) = router.lookup(req.get_path()) {
-                                let resp = match n {
-                                    Route::Route(p) => p,
-                                    Route::Alias(q) => {
-                                        let mut headers = vec![HTTPResponseHeaders::Server(
-                                            RSWEB_SERVER_STR.to_string(),
-                                        )];
-                                        let mut status = StatusCode::InternalServerError;
-                                        let mut body = Body::new(String::new());
-                                        match resload.load(q[1..].to_string()) {
-                                            Some(n) => {
-                                                headers.push(HTTPResponseHeaders::ContentType(
-                                                    n.get_mime(),
-                                                ));
-                                                body = Body::from_bytes(n.get_content());
-                                                status = StatusCode::Ok;
-                                            }
-                                            None => {
-                                                headers.push(HTTPResponseHeaders::ContentType(
-                                                    MimeType::Html,
-                                                ));
-                                                status = StatusCode::NotFound;
-						body = match config.http {
-						    Some(http_conf) => {
-							match http_conf.resources.notfound_page {
-							    Some(page) => {
-								match resload.load(page[1..].to_string()) {
-								    Some(n) => Body::from_bytes(n.get_content()),
-								    None => Body::new(String::from("<h1>494 Not Found</h1>"))
-								}
-							    },
-							    None => Body::new(String::from("<h1>404 Not Found</h1>"))
-							}
-						    },
-						    None => Body::new(String::from("<h1>404 Not Found</h1>"))
-						};
-                                            }
-                                        }
-                                        headers.push(HTTPResponseHeaders::ContentLength(
-                                            body.get_bytes().len(),
-                                        ));
-                                        if req.get_method() == HTTPMethod::Head {
-                                            body = Body::new(String::new());
-                                        }
-                                        HTTPResponse::new(status, headers, body)
-                                    }
-                                };
-                                match stream.write(&resp.to_bytes()) {
-                                    Ok(_) => (),
-                                    Err(_) => error!(logging, "failed to write to stream"),
-                                }
-                                match stream.flush() {
-                                    Ok(_) => (),
-                                    Err(_) => error!(logging, "failed to flush stream"),
-                                }
-                            } else {
-                                let mut header =
-                                    vec![HTTPResponseHeaders::Server(RSWEB_SERVER_STR.to_string())];
-                                let mut body = Body::new(String::new());
-                                let mut status = StatusCode::InternalServerError;
-                                match resload.load(req.get_path()[1..].to_string()) {
-                                    Some(n) => {
-                                        header.push(HTTPResponseHeaders::ContentType(n.get_mime()));
-                                        body = Body::from_bytes(n.get_content());
-                                        status = StatusCode::Ok;
-                                    }
-                                    None => {
-                                        header
-                                            .push(HTTPResponseHeaders::ContentType(MimeType::Html));
-                                        status = StatusCode::NotFound;
-					body = match config.http {
-					    Some(http_conf) => {
-						match http_conf.resources.notfound_page {
-						    Some(page) => {
-							match resload.load(page[1..].to_string()) {
-							    Some(n) => Body::from_bytes(n.get_content()),
-							    None => Body::new(String::from("<h1>494 Not Found</h1>"))
-							}
-						    },
-						    None => Body::new(String::from("<h1>404 Not Found</h1>"))
-						}
-					    },
-					    None => Body::new(String::from("<h1>404 Not Found</h1>"))
-					};
-                                    }
-                                }
-                                header.push(HTTPResponseHeaders::ContentLength(
-                                    body.get_bytes().len(),
-                                ));
-                                if req.get_method() == HTTPMethod::Head {
-                                    body = Body::new(String::new());
-                                }
-
-                                let response = HTTPResponse::new(status, header, body);
-                                match stream.write(&response.to_bytes()) {
-                                    Ok(_) => (),
-                                    Err(_) => error!(logging, "failed to write to stream"),
-                                }
-                                match stream.flush() {
-                                    Ok(_) => (),
-                                    Err(_) => error!(logging, "failed to flush stream"),
-                                }
-                            }
-                        } else {
-                            error!(logging, "failed to parse request");
-                        }
-                        match stream.shutdown(std::net::Shutdown::Both) {
-                            Ok(_) => (),
-                            Err(_) => error!(logging, "failed to shutdown stream"),
-                        }
-                    });
-                }
-                Err(_) => (),
-            }
-        }
-
-        Ok(())
+        let core = Core {
+            name: "http",
+            logger,
+            vhosts: Arc::new(self.vhosts.clone()),
+            fallback: Arc::new(self.fallback_vhost()),
+            timeouts: self.timeouts.clone(),
+            limits: self.limits.clone(),
+            methods: self.methods.clone(),
+            cors: self.cors.clone(),
+            redirect: self.redirect.clone(),
+            access: ClientAccess::new(),
+            hsts: None,
+            acme: self.acme.clone(),
+            access_log: self.access_log.clone(),
+            metrics: self.metrics.clone(),
+            health: self.health.clone(),
+            acceptor: self.acceptor.clone(),
+            handler: None,
+        };
+        core.serve(&self.listeners, &self.tp, self.bound.take())
     }
 }
 
 /// server using a function to deal with requests
 pub struct FuncServer {
     tp: ThreadPool,
-    port: usize,
-    ip: IpAddr,
-    logfile: String,
+    listeners: Vec<Listener>,
+    /// why the address passed to [`FuncServer::new`] is invalid, reported by [`FuncServer::run`]
+    port_error: Option<ServerError>,
+    logger: Logger,
+    timeouts: Timeouts,
+    limits: Limits,
+    cors: Cors,
+    redirect: Option<HttpsRedirect>,
+    access_log: Option<AccessLog>,
+    metrics: Option<Metrics>,
+    health: Option<Health>,
 }
 
 impl FuncServer {
@@ -233,64 +807,122 @@ impl FuncServer {
     /// # Arguments
     /// * `capacity`: the capacity of the thread pool
     /// * `port`: the port to use
-    /// * `ip`: the ip address to bind to
+    /// * `ip`: the ip address to bind to. More addresses can be set with
+    ///   [`FuncServer::set_listeners`]
     /// * `logfile`: the logfile to use
     pub fn new(capacity: usize, port: usize, ip: IpAddr, logfile: &str) -> FuncServer {
+        let (listeners, port_error) = match server_addr(ip, port) {
+            Ok(n) => (vec![Listener::new(n, false)], None),
+            Err(e) => (Vec::new(), Some(e)),
+        };
         FuncServer {
             tp: ThreadPool::new(capacity),
-            port,
-            ip,
-            logfile: logfile.to_string(),
+            listeners,
+            port_error,
+            logger: Logger::open(logfile),
+            timeouts: Timeouts::default(),
+            limits: Limits::default(),
+            cors: Cors::new(),
+            redirect: None,
+            access_log: None,
+            metrics: None,
+            health: None,
         }
     }
 
+    /// set the timeouts used for client connections
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    /// set the size limits for requests
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// set the CORS policies. Preflight requests to paths with a policy are answered without
+    /// calling the function and the CORS headers are added to its responses
+    pub fn set_cors(&mut self, cors: Cors) {
+        self.cors = cors;
+    }
+
+    /// listen on `listeners` instead of the address passed to [`FuncServer::new`]. They can't
+    /// use TLS, see [`SSLFuncServer`](crate::ssl::SSLFuncServer) for that
+    pub fn set_listeners(&mut self, listeners: Vec<Listener>) {
+        self.listeners = listeners;
+        self.port_error = None;
+        if let Some(n) = &self.health {
+            n.register_listeners("http", self.listeners.len());
+        }
+    }
+
+    /// redirect requests to HTTPS instead of calling the function. `None` disables the redirect
+    pub fn set_https_redirect(&mut self, redirect: Option<HttpsRedirect>) {
+        self.redirect = redirect;
+    }
+
+    /// write every answered request to `access_log`. `None` disables the access log
+    pub fn set_access_log(&mut self, access_log: Option<AccessLog>) {
+        self.access_log = access_log;
+    }
+
+    /// log to `logger` (e.g. to share it with other servers) instead of the logfile passed to
+    /// [`FuncServer::new`]
+    pub fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
+
+    /// record requests and connections in `metrics` and serve them unless they have a separate
+    /// listener. `None` disables metrics
+    pub fn set_metrics(&mut self, metrics: Option<Metrics>) {
+        self.metrics = metrics;
+    }
+
+    /// report liveness and readiness of the server (listeners and thread pool) to `health` and
+    /// answer its endpoints unless it has a separate admin listener. `None` disables them
+    pub fn set_health(&mut self, health: Option<Health>) {
+        if let Some(n) = &health {
+            n.register_listeners("http", self.listeners.len());
+            n.register_pool("http", self.tp.stats());
+        }
+        self.health = health;
+    }
+
     /// run the server using `func` as the function
-    pub fn run<F: 'static>(&mut self, func: F) -> Result<(), std::io::Error>
+    pub fn run<F>(&mut self, func: F) -> Result<(), std::io::Error>
     where
-        F: FnOnce(HTTPRequest) -> HTTPResponse + std::marker::Send + Copy,
+        F: FnOnce(HTTPRequest) -> HTTPResponse + std::marker::Send + Copy + 'static,
     {
-        let listener = match self.ip {
-            addr => match TcpListener::bind(format!("{}:{}", addr, self.port)) {
-                Ok(n) => n,
-                Err(e) => return Err(e),
-            },
-        };
-
-        let mut logger = log::Logger::new();
-        let _ = logger.set_logfile(self.logfile.as_str());
-        let _ = logger.set_term(btui::Terminal::new());
-        msg!(logger, "starting HTTP server (rsweb {})", RSWEB_VERSION);
-        for stream in listener.incoming() {
-            match stream {
-                Ok(mut stream) => {
-                    let mut log = log::Logger::new();
-                    let _ = log.set_logfile(self.logfile.as_str());
-                    let _ = log.set_term(btui::Terminal::new());
-                    self.tp.execute(move || {
-                        let mut buf = DBuffer::new();
-                        if let Err(_) = buf.read_http_request(&mut stream) {
-                            error!(log, "failed to read from stream");
-                            return;
-                        }
-                        if let Ok(data) = buf.to_string() {
-                            match HTTPRequest::from_string(data) {
-                                Ok(req) => {
-                                    let resp = func(req);
-                                    if let Err(_) = stream.write(&resp.to_bytes()) {
-                                        error!(log, "failed to write to stream");
-                                    }
-                                    if let Err(_) = stream.flush() {
-                                        error!(log, "failed to flush stream");
-                                    }
-                                }
-                                Err(_) => error!(log, "failed to parse HTTP request"),
-                            }
-                        }
-                    });
-                }
-                Err(e) => error!(logger, "{}", e),
-            }
+        if let Some(e) = &self.port_error {
+            return Err(std::io::Error::new(ErrorKind::InvalidInput, e.to_string()));
         }
-        Ok(())
+        if self.listeners.iter().any(|n| n.tls) {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "a FuncServer can't serve TLS listeners",
+            ));
+        }
+        if let Some(n) = &mut self.access_log {
+            n.open()?;
+        }
+        if let Some(n) = &self.metrics {
+            n.register_pool("http", self.tp.stats());
+        }
+        msg!(
+            self.logger,
+            "starting HTTP server (rsweb {})",
+            RSWEB_VERSION
+        );
+        let core = Core {
+            timeouts: self.timeouts.clone(),
+            limits: self.limits.clone(),
+            cors: self.cors.clone(),
+            redirect: self.redirect.clone(),
+            access_log: self.access_log.clone(),
+            metrics: self.metrics.clone(),
+            health: self.health.clone(),
+            ..Core::with_handler("http", self.logger.clone(), func_handler(func))
+        };
+        core.serve(&self.listeners, &self.tp, None)
     }
 }
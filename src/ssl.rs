@@ -1,40 +1,68 @@
 #![allow(unused_assignments)]
-use crate::dbuffer::DBuffer;
+use crate::access::AccessLog;
+use crate::acme::AcmeChallenges;
+use crate::config::Config;
+use crate::cors::Cors;
 use crate::error::ServerError;
-use crate::http::body::Body;
-use crate::http::header::HTTPResponseHeaders;
-use crate::http::request::HTTPMethod;
+use crate::health::Health;
 use crate::http::request::HTTPRequest;
 use crate::http::response::HTTPResponse;
-use crate::http::MimeType;
-use crate::http::StatusCode;
-use crate::log;
+use crate::https::Hsts;
+use crate::limits::Limits;
+use crate::listener::{server_addr, Listener};
+use crate::log::Logger;
+use crate::methods::MethodPolicy;
+use crate::metrics::Metrics;
+use crate::msg;
+use crate::mtls::ClientAccess;
 use crate::resource::ResourceLoader;
 use crate::route::*;
-use crate::config::Config;
+use crate::server::{func_handler, register_vhost_roots, Core};
+use crate::timeout::Timeouts;
+use crate::tls::{ReloadableAcceptor, SniCertificates, TlsOptions};
+use crate::vhost::{Vhost, VirtualHosts};
 use crate::ThreadPool;
-use crate::RSWEB_SERVER_STR;
 use crate::RSWEB_VERSION;
-use crate::{error, msg};
-use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
-use std::io::Write;
+use std::io::ErrorKind;
 use std::net::IpAddr;
-use std::net::TcpListener;
+use std::sync::mpsc::Sender;
 use std::sync::Arc;
+use std::time::Duration;
+
+/// default time between checks of the certificate files for changes
+const DEFAULT_RELOAD_INTERVAL: Duration = Duration::from_secs(60);
 
 /// an SSL/TLS server using a resource loader and router
 pub struct SSLServer {
     tp: ThreadPool,
     rl: ResourceLoader,
-    port: usize,
-    ip: IpAddr,
+    listeners: Vec<Listener>,
     router: Router,
-    sslacceptor: Arc<SslAcceptor>,
+    sslacceptor: Arc<ReloadableAcceptor>,
+    reload_interval: Option<Duration>,
     config: Config,
+    timeouts: Timeouts,
+    limits: Limits,
+    methods: MethodPolicy,
+    cors: Cors,
+    access: ClientAccess,
+    hsts: Option<Hsts>,
+    acme: Option<AcmeChallenges>,
+    access_log: Option<AccessLog>,
+    logger: Option<Logger>,
+    metrics: Option<Metrics>,
+    health: Option<Health>,
+    vhosts: VirtualHosts,
+    bound: Option<Sender<()>>,
 }
 
 impl SSLServer {
-    /// create a new SSL server
+    /// create a new SSL server. `privkeyfile` and `certchainfile` hold the default certificate,
+    /// additional certificates selected by SNI are taken from the `certificates` setting, client
+    /// certificate verification from the `client_auth` setting and the access log from the
+    /// `access_log` setting of the `ssl` section of `config`. More addresses than `ip` and
+    /// `port` can be set with [`SSLServer::set_listeners`]
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         capacity: usize,
         rl: ResourceLoader,
@@ -43,231 +71,250 @@ impl SSLServer {
         ip: IpAddr,
         privkeyfile: String,
         certchainfile: String,
-	config: Config,
+        config: Config,
     ) -> Result<SSLServer, ServerError> {
-        let mut acceptor = match SslAcceptor::mozilla_intermediate(SslMethod::tls()) {
-            Ok(n) => n,
-            Err(_) => {
-                return Err(ServerError::new("error creating SSL Acceptor"));
-            }
+        let certs = SniCertificates::from_config(
+            config.ssl.as_ref().and_then(|n| n.certificates.as_ref()),
+        )?;
+        let options = TlsOptions::from_config(config.ssl.as_ref())?;
+        let acceptor = Arc::new(ReloadableAcceptor::new(
+            privkeyfile.as_str(),
+            certchainfile.as_str(),
+            certs,
+            options,
+        )?);
+        let reload_interval = match config.ssl.as_ref().and_then(|n| n.reload_interval) {
+            Some(0) => None,
+            Some(n) => Some(Duration::from_secs(n)),
+            None => Some(DEFAULT_RELOAD_INTERVAL),
         };
-        match acceptor.set_private_key_file(privkeyfile.as_str(), SslFiletype::PEM) {
-            Ok(n) => n,
-            Err(_) => {
-                return Err(ServerError::new("error creating SSL Acceptor"));
-            }
-        }
-        match acceptor.set_certificate_chain_file(certchainfile.as_str()) {
-            Ok(n) => n,
-            Err(_) => {
-                return Err(ServerError::new("error creating SSL Acceptor"));
-            }
-        }
-        match acceptor.check_private_key() {
-            Ok(n) => n,
-            Err(_) => {
-                return Err(ServerError::new(
-                    format!("problem with private key: '{}'", privkeyfile).as_str(),
-                ));
-            }
-        }
-        let acceptor = Arc::new(acceptor.build());
+        let timeouts = Timeouts::from_config(config.ssl.as_ref().and_then(|n| n.timeouts.as_ref()));
+        let limits = Limits::from_config(config.ssl.as_ref().and_then(|n| n.limits.as_ref()));
+        let methods = MethodPolicy::from_config(
+            config.ssl.as_ref().and_then(|n| n.allowed_methods.as_ref()),
+            config.ssl.as_ref().and_then(|n| n.path_methods.as_ref()),
+        );
+        let cors = Cors::from_config(config.ssl.as_ref().and_then(|n| n.cors.as_ref()));
+        let access = ClientAccess::from_config(
+            config
+                .ssl
+                .as_ref()
+                .and_then(|n| n.client_auth.as_ref())
+                .and_then(|n| n.rules.as_ref()),
+        );
+        let hsts = config
+            .ssl
+            .as_ref()
+            .and_then(|n| n.hsts.as_ref())
+            .map(Hsts::from_config);
+        let access_log = config
+            .ssl
+            .as_ref()
+            .and_then(|n| n.access_log.as_ref())
+            .map(AccessLog::from_config);
+        let addr = server_addr(ip, port)?;
         Ok(SSLServer {
             tp: ThreadPool::new(capacity),
             rl,
+            listeners: vec![Listener::new(addr, true)],
             router,
-            port,
-            ip,
             sslacceptor: acceptor,
-	    config,
+            reload_interval,
+            config,
+            timeouts,
+            limits,
+            methods,
+            cors,
+            access,
+            hsts,
+            acme: None,
+            access_log,
+            logger: None,
+            metrics: None,
+            health: None,
+            vhosts: VirtualHosts::new(),
+            bound: None,
         })
     }
 
+    /// send `()` to `bound` once all listeners are bound like
+    /// [`Server::set_bound_notifier`](crate::server::Server::set_bound_notifier)
+    pub fn set_bound_notifier(&mut self, bound: Option<Sender<()>>) {
+        self.bound = bound;
+    }
+
+    /// listen on `listeners` instead of the address passed to [`SSLServer::new`]. Listeners
+    /// without TLS answer plain HTTP requests like the TLS ones, except for HSTS
+    pub fn set_listeners(&mut self, listeners: Vec<Listener>) {
+        self.listeners = listeners;
+        if let Some(n) = &self.health {
+            n.register_listeners("https", self.listeners.len());
+        }
+    }
+
+    /// set the timeouts used for client connections
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    /// set the size limits for requests
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// set the methods clients are allowed to use
+    pub fn set_allowed_methods(&mut self, methods: MethodPolicy) {
+        self.methods = methods;
+    }
+
+    /// set the CORS policies
+    pub fn set_cors(&mut self, cors: Cors) {
+        self.cors = cors;
+    }
+
+    /// set the paths only clients with certain certificates may access
+    pub fn set_client_access(&mut self, access: ClientAccess) {
+        self.access = access;
+    }
+
+    /// set the `Strict-Transport-Security` policy sent with every response. `None` disables it
+    pub fn set_hsts(&mut self, hsts: Option<Hsts>) {
+        self.hsts = hsts;
+    }
+
+    /// answer the pending ACME TLS-ALPN-01 challenges of `challenges`
+    pub fn set_acme_challenges(&mut self, challenges: Option<AcmeChallenges>) {
+        self.acme = challenges;
+    }
+
+    /// write every answered request to `access_log`. `None` disables the access log
+    pub fn set_access_log(&mut self, access_log: Option<AccessLog>) {
+        self.access_log = access_log;
+    }
+
+    /// log to `logger` (e.g. to share it with other servers) instead of creating a logger when
+    /// the server is started
+    pub fn set_logger(&mut self, logger: Logger) {
+        self.logger = Some(logger);
+    }
+
+    /// record requests, connections, failed handshakes and resource cache usage in `metrics`
+    /// and serve them unless they have a separate listener. `None` disables metrics
+    pub fn set_metrics(&mut self, metrics: Option<Metrics>) {
+        self.rl.set_metrics(metrics.clone());
+        self.vhosts.set_metrics(metrics.clone());
+        self.metrics = metrics;
+    }
+
+    /// report liveness and readiness of the server (listeners, certificate, resource roots and
+    /// thread pool) to `health` and answer its endpoints unless it has a separate admin listener.
+    /// `None` disables them
+    pub fn set_health(&mut self, health: Option<Health>) {
+        if let Some(n) = &health {
+            n.register_listeners("https", self.listeners.len());
+            n.register_tls("https", self.sslacceptor.clone());
+            n.register_root("https", self.rl.get_root().as_str());
+            register_vhost_roots(n, "https", &self.vhosts);
+            n.register_pool("https", self.tp.stats());
+        }
+        self.health = health;
+    }
+
+    /// answer requests for the hosts of `vhosts` with their resources and routes. Requests for
+    /// other hosts are answered with the resources and routes of the server. The certificate is
+    /// still selected by SNI (see the `certificates` setting)
+    pub fn set_vhosts(&mut self, vhosts: VirtualHosts) {
+        self.vhosts = vhosts;
+        self.vhosts.set_metrics(self.metrics.clone());
+        if let Some(n) = &self.health {
+            register_vhost_roots(n, "https", &self.vhosts);
+        }
+    }
+
+    /// the virtual host answering requests for hosts without one of their own
+    fn fallback_vhost(&self) -> Vhost {
+        let mut vhost = Vhost::new(Vec::new(), self.rl.clone(), self.router.clone());
+        vhost.set_notfound_page(
+            self.config
+                .ssl
+                .as_ref()
+                .and_then(|n| n.resources.notfound_page.clone()),
+        );
+        vhost
+    }
+
+    /// set how often the certificate files are checked for changes while the server is running.
+    /// `None` disables the check
+    pub fn set_reload_interval(&mut self, interval: Option<Duration>) {
+        self.reload_interval = interval;
+    }
+
+    /// get a handle to the certificates of the server. It can be used to reload them (e.g.
+    /// after they were renewed) while the server is running
+    pub fn certificates(&self) -> Arc<ReloadableAcceptor> {
+        self.sslacceptor.clone()
+    }
+
     /// run the SSL server
     /// # Arguments
-    /// `lf`: the logfile to log to
+    /// `lf`: the logfile to log to if no logger was set with [`SSLServer::set_logger`]
     pub fn run(&mut self, lf: &str) -> Result<(), std::io::Error> {
-        let listener = match self.ip {
-            IpAddr::V4(addr) => match TcpListener::bind(format!("{}:{}", addr, self.port)) {
-                Ok(n) => n,
-                Err(e) => {
-                    return Err(e);
-                }
-            },
-            IpAddr::V6(addr) => match TcpListener::bind(format!("{}:{}", addr, self.port)) {
-                Ok(n) => n,
-                Err(e) => {
-                    return Err(e);
-                }
-            },
-        };
-        let mut logger = log::Logger::new();
-        logger.set_term(btui::Terminal::default());
-        if let Err(_) = logger.set_logfile(lf) {
-            error!(logger, "couldn't open log file");
+        if let Some(n) = &mut self.access_log {
+            n.open()?;
+        }
+        if let Some(n) = &self.metrics {
+            n.register_pool("https", self.tp.stats());
         }
+        let logger = match &self.logger {
+            Some(n) => n.clone(),
+            None => Logger::open(lf),
+        };
         msg!(logger, "starting HTTPS server (rsweb {})", RSWEB_VERSION);
-        for stream in listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    let acceptor = self.sslacceptor.clone();
-                    let mut resload = self.rl.clone();
-                    let router = self.router.clone();
-                    let logfile = lf.to_string();
-		    let config = self.config.clone();
-
-                    self.tp.execute(move || {
-                        let mut logging = log::Logger::new();
-                        logging.set_term(btui::Terminal::new());
-                        let _ = logging.set_logfile(logfile.as_str());
-                        let mut buf = DBuffer::new();
-                        let mut stream = match acceptor.accept(stream) {
-                            Ok(n) => n,
-                            Err(_) => {
-                                error!(logging, "failed SSL handshake");
-                                return;
-                            }
-                        };
-                        if let Err(_) = buf.read_http_request(&mut stream) {
-                            error!(logging, "failed to read from stream");
-                        }
-                        let data: String = match buf.to_string() {
-                            Ok(n) => n,
-                            Err(_) => {
-                                error!(logging, "failed to parse data to utf8");
-                                String::new()
-                            }
-                        };
-                        if let Ok(req) = HTTPRequest::from_string(data) {
-                            msg!(logging, "request: {} {}", req.get_method(), req.get_path());
-                            if let Some(n) = router.lookup(req.get_path()) {
-                                let resp = match n {
-                                    Route::Route(p) => p,
-                                    Route::Alias(q) => {
-                                        let mut headers = vec![HTTPResponseHeaders::Server(
-                                            RSWEB_SERVER_STR.to_string(),
-                                        )];
-                                        let mut body = Body::new(String::new());
-                                        let mut status = StatusCode::InternalServerError;
-                                        match resload.load(q[1..].to_string()) {
-                                            Some(n) => {
-                                                headers.push(HTTPResponseHeaders::ContentType(
-                                                    n.get_mime(),
-                                                ));
-                                                body = Body::from_bytes(n.get_content());
-                                                status = StatusCode::Ok;
-                                            }
-                                            None => {
-                                                headers.push(HTTPResponseHeaders::ContentType(
-                                                    MimeType::Html,
-                                                ));
-						body = match config.ssl {
-						    Some(ssl_conf) => {
-							match ssl_conf.resources.notfound_page {
-							    Some(page) => {
-								match resload.load(page[1..].to_string()) {
-								    Some(n) => Body::from_bytes(n.get_content()),
-								    None => Body::new(String::from("<h1>494 Not Found</h1>"))
-								}
-							    },
-							    None => Body::new(String::from("<h1>404 Not Found</h1>"))
-							}
-						    },
-						    None => Body::new(String::from("<h1>404 Not Found</h1>"))
-						};
-                                                status = StatusCode::NotFound;
-                                            }
-                                        }
-                                        headers.push(HTTPResponseHeaders::ContentLength(
-                                            body.get_bytes().len(),
-                                        ));
-                                        if req.get_method() == HTTPMethod::Head {
-                                            body = Body::new(String::new());
-                                        }
-                                        HTTPResponse::new(status, headers, body)
-                                    }
-                                };
-                                match stream.write(&resp.to_bytes()) {
-                                    Ok(_) => (),
-                                    Err(_) => error!(logging, "failed to write to stream"),
-                                }
-                                match stream.flush() {
-                                    Ok(_) => (),
-                                    Err(_) => error!(logging, "failed to flush stream"),
-                                }
-                            } else {
-                                let mut headers =
-                                    vec![HTTPResponseHeaders::Server(RSWEB_SERVER_STR.to_string())];
-                                let mut body = Body::new(String::new());
-                                let mut status = StatusCode::InternalServerError;
-                                match resload.load(req.get_path()[1..].to_string()) {
-                                    Some(n) => {
-                                        headers
-                                            .push(HTTPResponseHeaders::ContentType(n.get_mime()));
-                                        body = Body::from_bytes(n.get_content());
-                                        status = StatusCode::Ok;
-                                    }
-                                    None => {
-                                        headers
-                                            .push(HTTPResponseHeaders::ContentType(MimeType::Html));
-					body = match config.ssl {
-					    Some(ssl_conf) => {
-						match ssl_conf.resources.notfound_page {
-						    Some(page) => {
-							match resload.load(page[1..].to_string()) {
-							    Some(n) => Body::from_bytes(n.get_content()),
-							    None => Body::new(String::from("<h1>494 Not Found</h1>"))
-							}
-						    },
-						    None => Body::new(String::from("<h1>404 Not Found</h1>"))
-						}
-					    },
-					    None => Body::new(String::from("<h1>404 Not Found</h1>"))
-					};
-                                        status = StatusCode::NotFound;
-                                    }
-                                }
-                                headers.push(HTTPResponseHeaders::ContentLength(
-                                    body.get_bytes().len(),
-                                ));
-                                if req.get_method() == HTTPMethod::Head {
-                                    body = Body::new(String::new());
-                                }
-                                let response = HTTPResponse::new(status, headers, body);
-                                match stream.write(&response.to_bytes()) {
-                                    Ok(_) => (),
-                                    Err(_) => error!(logging, "failed to write to stream"),
-                                }
-                                match stream.flush() {
-                                    Ok(_) => (),
-                                    Err(_) => error!(logging, "failed to flush stream"),
-                                }
-                            }
-                        } else {
-                            error!(logging, "failed to parse request");
-                        }
-                        match stream.shutdown() {
-                            Ok(_) => (),
-                            Err(_) => error!(logging, "failed to shutdown stream"),
-                        }
-                    });
-                }
-                Err(_) => {
-                    error!(logger, "connection failed");
-                }
-            }
+        if let Some(interval) = self.reload_interval {
+            ReloadableAcceptor::watch(self.sslacceptor.clone(), interval, logger.clone());
         }
-        Ok(())
+        let core = Core {
+            name: "https",
+            logger,
+            vhosts: Arc::new(self.vhosts.clone()),
+            fallback: Arc::new(self.fallback_vhost()),
+            timeouts: self.timeouts.clone(),
+            limits: self.limits.clone(),
+            methods: self.methods.clone(),
+            cors: self.cors.clone(),
+            redirect: None,
+            access: self.access.clone(),
+            hsts: self.hsts.clone(),
+            acme: self.acme.clone(),
+            access_log: self.access_log.clone(),
+            metrics: self.metrics.clone(),
+            health: self.health.clone(),
+            acceptor: Some(self.sslacceptor.clone()),
+            handler: None,
+        };
+        core.serve(&self.listeners, &self.tp, self.bound.take())
     }
 }
 
 /// server using a function to deal with requests
 pub struct SSLFuncServer {
     tp: ThreadPool,
-    port: usize,
-    ip: IpAddr,
-    logfile: String,
-    acceptor: Arc<SslAcceptor>,
+    listeners: Vec<Listener>,
+    logger: Logger,
+    privkeyfile: String,
+    certchainfile: String,
+    certs: SniCertificates,
+    options: TlsOptions,
+    acceptor: Arc<ReloadableAcceptor>,
+    reload_interval: Option<Duration>,
+    timeouts: Timeouts,
+    limits: Limits,
+    cors: Cors,
+    access: ClientAccess,
+    hsts: Option<Hsts>,
+    access_log: Option<AccessLog>,
+    metrics: Option<Metrics>,
+    health: Option<Health>,
 }
 
 impl SSLFuncServer {
@@ -275,7 +322,8 @@ impl SSLFuncServer {
     /// # Arguments
     /// * `capacity`: the capacity of the thread pool
     /// * `port`: the port to use
-    /// * `ip`: the ip address to bind to
+    /// * `ip`: the ip address to bind to. More addresses can be set with
+    ///   [`SSLFuncServer::set_listeners`]
     /// * `logfile`: the logfile to use
     /// * `privkeyfile`: file that contains the private key
     /// * `certchainfile`: file that contains a certificate chain (or single certificate)
@@ -287,99 +335,175 @@ impl SSLFuncServer {
         privkeyfile: &str,
         certchainfile: &str,
     ) -> Result<SSLFuncServer, ServerError> {
-        let mut acceptor = match SslAcceptor::mozilla_intermediate(SslMethod::tls()) {
-            Ok(n) => n,
-            Err(_) => {
-                return Err(ServerError::new("error creating SSL Acceptor"));
-            }
-        };
-        match acceptor.set_private_key_file(privkeyfile, SslFiletype::PEM) {
-            Ok(_) => (),
-            Err(_) => {
-                return Err(ServerError::new("error setting private key file"));
-            }
-        }
-        match acceptor.set_certificate_chain_file(certchainfile) {
-            Ok(_) => (),
-            Err(_) => {
-                return Err(ServerError::new("error setting certificate chain file"));
-            }
-        }
-        match acceptor.check_private_key() {
-            Ok(_) => (),
-            Err(_) => {
-                return Err(ServerError::new(
-                    format!("problem with private key: '{}'", privkeyfile).as_str(),
-                ));
-            }
-        }
-        let acceptor = Arc::new(acceptor.build());
+        let addr = server_addr(ip, port)?;
+        let acceptor = Arc::new(ReloadableAcceptor::new(
+            privkeyfile,
+            certchainfile,
+            SniCertificates::new(),
+            TlsOptions::default(),
+        )?);
         Ok(SSLFuncServer {
             tp: ThreadPool::new(capacity),
-            port,
-            ip,
-            logfile: logfile.to_string(),
+            listeners: vec![Listener::new(addr, true)],
+            logger: Logger::open(logfile),
+            privkeyfile: privkeyfile.to_string(),
+            certchainfile: certchainfile.to_string(),
+            certs: SniCertificates::new(),
+            options: TlsOptions::default(),
             acceptor,
+            reload_interval: Some(DEFAULT_RELOAD_INTERVAL),
+            timeouts: Timeouts::default(),
+            limits: Limits::default(),
+            cors: Cors::new(),
+            access: ClientAccess::new(),
+            hsts: None,
+            access_log: None,
+            metrics: None,
+            health: None,
         })
     }
 
+    /// set the timeouts used for client connections
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    /// set the size limits for requests
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// set the CORS policies. Preflight requests to paths with a policy are answered without
+    /// calling the function and the CORS headers are added to its responses
+    pub fn set_cors(&mut self, cors: Cors) {
+        self.cors = cors;
+    }
+
+    /// listen on `listeners` instead of the address passed to [`SSLFuncServer::new`]. All of
+    /// them have to use TLS, see [`FuncServer`](crate::server::FuncServer) for plain HTTP
+    pub fn set_listeners(&mut self, listeners: Vec<Listener>) {
+        self.listeners = listeners;
+        if let Some(n) = &self.health {
+            n.register_listeners("https", self.listeners.len());
+        }
+    }
+
+    /// serve `certs` to clients asking for one of their names (SNI). All other clients get the
+    /// certificate passed to [`SSLFuncServer::new`]
+    pub fn set_certificates(&mut self, certs: SniCertificates) -> Result<(), ServerError> {
+        self.certs = certs;
+        self.rebuild_acceptor()
+    }
+
+    /// set the options applied to all certificates (e.g. client certificate verification)
+    pub fn set_tls_options(&mut self, options: TlsOptions) -> Result<(), ServerError> {
+        self.options = options;
+        self.rebuild_acceptor()
+    }
+
+    /// set the paths only clients with certain certificates may access. Requests the rules
+    /// deny are answered with `403 Forbidden` without calling the function
+    pub fn set_client_access(&mut self, access: ClientAccess) {
+        self.access = access;
+    }
+
+    /// set the `Strict-Transport-Security` policy sent with every response. `None` disables it
+    pub fn set_hsts(&mut self, hsts: Option<Hsts>) {
+        self.hsts = hsts;
+    }
+
+    /// write every answered request to `access_log`. `None` disables the access log
+    pub fn set_access_log(&mut self, access_log: Option<AccessLog>) {
+        self.access_log = access_log;
+    }
+
+    /// log to `logger` (e.g. to share it with other servers) instead of the logfile passed to
+    /// [`SSLFuncServer::new`]
+    pub fn set_logger(&mut self, logger: Logger) {
+        self.logger = logger;
+    }
+
+    /// record requests, connections and failed handshakes in `metrics` and serve them unless
+    /// they have a separate listener. `None` disables metrics
+    pub fn set_metrics(&mut self, metrics: Option<Metrics>) {
+        self.metrics = metrics;
+    }
+
+    /// report liveness and readiness of the server (listeners, certificate and thread pool) to
+    /// `health` and answer its endpoints unless it has a separate admin listener. `None`
+    /// disables them
+    pub fn set_health(&mut self, health: Option<Health>) {
+        if let Some(n) = &health {
+            n.register_listeners("https", self.listeners.len());
+            n.register_pool("https", self.tp.stats());
+        }
+        self.health = health;
+    }
+
+    fn rebuild_acceptor(&mut self) -> Result<(), ServerError> {
+        let acceptor = ReloadableAcceptor::new(
+            self.privkeyfile.as_str(),
+            self.certchainfile.as_str(),
+            self.certs.clone(),
+            self.options.clone(),
+        )?;
+        self.acceptor = Arc::new(acceptor);
+        Ok(())
+    }
+
+    /// set how often the certificate files are checked for changes while the server is running.
+    /// `None` disables the check
+    pub fn set_reload_interval(&mut self, interval: Option<Duration>) {
+        self.reload_interval = interval;
+    }
+
+    /// get a handle to the certificates of the server. It can be used to reload them (e.g.
+    /// after they were renewed) while the server is running
+    pub fn certificates(&self) -> Arc<ReloadableAcceptor> {
+        self.acceptor.clone()
+    }
+
     /// run the server using `func` as the function
-    pub fn run<F: 'static>(&mut self, func: F) -> Result<(), std::io::Error>
+    pub fn run<F>(&mut self, func: F) -> Result<(), std::io::Error>
     where
-        F: FnOnce(HTTPRequest) -> HTTPResponse + std::marker::Send + Copy,
+        F: FnOnce(HTTPRequest) -> HTTPResponse + std::marker::Send + Copy + 'static,
     {
-        let listener = match self.ip {
-            addr => match TcpListener::bind(format!("{}:{}", addr, self.port)) {
-                Ok(n) => n,
-                Err(e) => return Err(e),
-            },
-        };
-
-        let mut logger = log::Logger::new();
-        let _ = logger.set_logfile(self.logfile.as_str());
-        let _ = logger.set_term(btui::Terminal::new());
-        msg!(logger, "starting HTTP server (rsweb {})", RSWEB_VERSION);
-        for stream in listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    let mut log = log::Logger::new();
-                    let _ = log.set_logfile(self.logfile.as_str());
-                    let _ = log.set_term(btui::Terminal::new());
-                    let acceptor = self.acceptor.clone();
-                    self.tp.execute(move || {
-                        let mut stream = match acceptor.accept(stream) {
-                            Ok(n) => n,
-                            Err(_) => {
-                                error!(log, "failed SSL handshake");
-                                return;
-                            }
-                        };
-                        let mut buf = DBuffer::new();
-                        if let Err(_) = buf.read_http_request(&mut stream) {
-                            error!(log, "failed to read from stream");
-                            return;
-                        }
-                        if let Ok(data) = buf.to_string() {
-                            match HTTPRequest::from_string(data) {
-                                Ok(req) => {
-                                    let resp = func(req);
-                                    if let Err(_) = stream.write(&resp.to_bytes()) {
-                                        error!(log, "failed to write to stream");
-                                    }
-                                    if let Err(_) = stream.flush() {
-                                        error!(log, "failed to flush stream");
-                                    }
-                                }
-                                Err(_) => {
-                                    error!(log, "failed to parse HTTP request")
-                                }
-                            }
-                        }
-                    });
-                }
-                Err(e) => error!(logger, "{}", e),
-            }
+        if self.listeners.iter().any(|n| !n.tls) {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "an SSLFuncServer can't serve plain listeners",
+            ));
         }
-        Ok(())
+        if let Some(n) = &mut self.access_log {
+            n.open()?;
+        }
+        if let Some(n) = &self.metrics {
+            n.register_pool("https", self.tp.stats());
+        }
+        msg!(
+            self.logger,
+            "starting HTTPS server (rsweb {})",
+            RSWEB_VERSION
+        );
+        if let Some(n) = &self.health {
+            // the acceptor is rebuilt when certificates or options change before starting
+            n.register_tls("https", self.acceptor.clone());
+        }
+        if let Some(interval) = self.reload_interval {
+            ReloadableAcceptor::watch(self.acceptor.clone(), interval, self.logger.clone());
+        }
+        let core = Core {
+            timeouts: self.timeouts.clone(),
+            limits: self.limits.clone(),
+            cors: self.cors.clone(),
+            access: self.access.clone(),
+            hsts: self.hsts.clone(),
+            access_log: self.access_log.clone(),
+            metrics: self.metrics.clone(),
+            health: self.health.clone(),
+            acceptor: Some(self.acceptor.clone()),
+            ..Core::with_handler("https", self.logger.clone(), func_handler(func))
+        };
+        core.serve(&self.listeners, &self.tp, None)
     }
 }
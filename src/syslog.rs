@@ -0,0 +1,283 @@
+use crate::config::{JournaldConfig, SyslogConfig};
+use crate::error::ServerError;
+use crate::log::{Level, Record};
+use chrono::prelude::*;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+
+/// the socket of the local syslog daemon
+pub const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// the socket of the systemd journal for its native protocol
+pub const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// SD-ID of the structured data element holding the fields of a message. 32473 is the private
+/// enterprise number reserved for documentation (RFC 5612)
+const SD_ID: &str = "rsweb@32473";
+
+/// get the syslog severity of `level`
+pub fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// get the number of the syslog facility called `name` (e.g. `daemon` or `local0`)
+pub fn facility(name: &str) -> Option<u8> {
+    let n = match name {
+        "kern" => 0,
+        "user" => 1,
+        "mail" => 2,
+        "daemon" => 3,
+        "auth" => 4,
+        "syslog" => 5,
+        "lpr" => 6,
+        "news" => 7,
+        "uucp" => 8,
+        "cron" => 9,
+        "authpriv" => 10,
+        "ftp" => 11,
+        n => match n.strip_prefix("local").and_then(|n| n.parse::<u8>().ok()) {
+            Some(n) if n <= 7 => 16 + n,
+            _ => return None,
+        },
+    };
+    Some(n)
+}
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for its whole length
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    let end = buf.iter().position(|n| *n == 0).unwrap_or(buf.len());
+    match ret {
+        0 if end > 0 => String::from_utf8_lossy(&buf[..end]).to_string(),
+        _ => String::from("-"),
+    }
+}
+
+/// turn `string` into a printable ASCII token of at most `max` characters without spaces, as
+/// required for the header fields and parameter names of RFC 5424
+fn token(string: &str, max: usize, forbidden: &[char]) -> String {
+    let token: String = string
+        .chars()
+        .filter(|c| c.is_ascii_graphic() && !forbidden.contains(c))
+        .take(max)
+        .collect();
+    if token.is_empty() {
+        String::from("-")
+    } else {
+        token
+    }
+}
+
+/// sends messages to the local syslog daemon in the format of RFC 5424. Fields of a message
+/// are sent as structured data
+#[derive(Debug)]
+pub struct Syslog {
+    socket: UnixDatagram,
+    path: PathBuf,
+    facility: u8,
+    hostname: String,
+    app_name: String,
+}
+
+impl Syslog {
+    /// connect to the syslog daemon listening on `socket` and send messages with `facility`
+    pub fn new(socket: &str, facility: u8) -> Result<Syslog, ServerError> {
+        let sock = match UnixDatagram::unbound().and_then(|n| n.connect(socket).map(|_| n)) {
+            Ok(n) => n,
+            Err(e) => {
+                return Err(ServerError::new(
+                    format!("failed to connect to syslog at '{}': {}", socket, e).as_str(),
+                ))
+            }
+        };
+        Ok(Syslog {
+            socket: sock,
+            path: PathBuf::from(socket),
+            facility,
+            hostname: token(hostname().as_str(), 255, &[]),
+            app_name: String::from("rsweb"),
+        })
+    }
+
+    /// connect to syslog as configured in the `syslog` setting of the `log` section
+    pub fn from_config(conf: &SyslogConfig) -> Result<Syslog, ServerError> {
+        let facility = match &conf.facility {
+            Some(n) => match facility(n.as_str()) {
+                Some(n) => n,
+                None => {
+                    return Err(ServerError::new(
+                        format!("invalid syslog facility: '{}'", n).as_str(),
+                    ))
+                }
+            },
+            None => facility("daemon").unwrap_or(3),
+        };
+        let mut syslog = Syslog::new(conf.socket.as_deref().unwrap_or(SYSLOG_SOCKET), facility)?;
+        if let Some(n) = &conf.app_name {
+            syslog.set_app_name(n);
+        }
+        Ok(syslog)
+    }
+
+    /// set the name messages are sent with. Defaults to `rsweb`
+    pub fn set_app_name(&mut self, name: &str) {
+        self.app_name = token(name, 48, &[]);
+    }
+
+    /// format `record` as a syslog message
+    pub fn format(&self, record: &Record) -> String {
+        let pri = self.facility as u32 * 8 + severity(record.level) as u32;
+        let mut data = format!("[{} module=\"{}\"", SD_ID, escape(&record.module));
+        for (k, v) in &record.fields {
+            data.push_str(
+                format!(" {}=\"{}\"", token(k, 32, &['=', ']', '"']), escape(v)).as_str(),
+            );
+        }
+        data.push(']');
+        format!(
+            "<{}>1 {} {} {} {} - {} {}",
+            pri,
+            record.time.to_rfc3339_opts(SecondsFormat::Micros, false),
+            self.hostname,
+            self.app_name,
+            std::process::id(),
+            data,
+            record.msg
+        )
+    }
+
+    /// send `record` to syslog. If the daemon was restarted the socket is connected again
+    pub fn send(&mut self, record: &Record) {
+        let msg = self.format(record);
+        if self.socket.send(msg.as_bytes()).is_err() {
+            if let Ok(n) = UnixDatagram::unbound().and_then(|n| n.connect(&self.path).map(|_| n)) {
+                self.socket = n;
+                let _ = self.socket.send(msg.as_bytes());
+            }
+        }
+    }
+}
+
+/// escape a structured data parameter value
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// sends messages to the systemd journal using its native protocol. Fields of a message are
+/// sent as journal fields with uppercase names (e.g. `path` becomes `PATH`)
+#[derive(Debug)]
+pub struct Journald {
+    socket: UnixDatagram,
+    path: PathBuf,
+    identifier: String,
+}
+
+/// turn `name` into a valid journal field name
+fn field_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' => c.to_ascii_uppercase(),
+            'A'..='Z' | '0'..='9' => c,
+            _ => '_',
+        })
+        .skip_while(|c| *c == '_' || c.is_ascii_digit())
+        .take(64)
+        .collect();
+    if name.is_empty() {
+        String::from("FIELD")
+    } else {
+        name
+    }
+}
+
+/// append the journal field `name` with `value` to `buf`
+fn journal_field(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.extend(name.as_bytes());
+    if value.contains('\n') {
+        // values containing line breaks are sent with their length
+        buf.push(b'\n');
+        buf.extend((value.len() as u64).to_le_bytes());
+        buf.extend(value.as_bytes());
+    } else {
+        buf.push(b'=');
+        buf.extend(value.as_bytes());
+    }
+    buf.push(b'\n');
+}
+
+impl Journald {
+    /// connect to the journal listening on `socket`
+    pub fn new(socket: &str) -> Result<Journald, ServerError> {
+        let sock = match UnixDatagram::unbound().and_then(|n| n.connect(socket).map(|_| n)) {
+            Ok(n) => n,
+            Err(e) => {
+                return Err(ServerError::new(
+                    format!("failed to connect to the journal at '{}': {}", socket, e).as_str(),
+                ))
+            }
+        };
+        Ok(Journald {
+            socket: sock,
+            path: PathBuf::from(socket),
+            identifier: String::from("rsweb"),
+        })
+    }
+
+    /// connect to the journal as configured in the `journald` setting of the `log` section
+    pub fn from_config(conf: &JournaldConfig) -> Result<Journald, ServerError> {
+        let mut journald = Journald::new(conf.socket.as_deref().unwrap_or(JOURNALD_SOCKET))?;
+        if let Some(n) = &conf.identifier {
+            journald.identifier = n.clone();
+        }
+        Ok(journald)
+    }
+
+    /// encode `record` for the native protocol of the journal
+    pub fn format(&self, record: &Record) -> Vec<u8> {
+        let mut buf = Vec::new();
+        journal_field(&mut buf, "MESSAGE", &record.msg);
+        journal_field(
+            &mut buf,
+            "PRIORITY",
+            severity(record.level).to_string().as_str(),
+        );
+        journal_field(&mut buf, "SYSLOG_IDENTIFIER", &self.identifier);
+        journal_field(&mut buf, "CODE_MODULE", &record.module);
+        for (k, v) in &record.fields {
+            let name = field_name(k);
+            // fields of the journal itself can't be overwritten
+            if !matches!(
+                name.as_str(),
+                "MESSAGE" | "PRIORITY" | "SYSLOG_IDENTIFIER" | "CODE_MODULE"
+            ) {
+                journal_field(&mut buf, name.as_str(), v);
+            }
+        }
+        buf
+    }
+
+    /// send `record` to the journal. If it was restarted the socket is connected again
+    pub fn send(&mut self, record: &Record) {
+        let msg = self.format(record);
+        if self.socket.send(&msg).is_err() {
+            if let Ok(n) = UnixDatagram::unbound().and_then(|n| n.connect(&self.path).map(|_| n)) {
+                self.socket = n;
+                let _ = self.socket.send(&msg);
+            }
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use crate::config::TimeoutConfig;
+use openssl::ssl::SslStream;
+use std::io::{Error, ErrorKind};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+/// time a client gets before its transfer rate is checked against [`Timeouts::min_rate`]. Small
+/// requests are usually complete long before that
+const MIN_RATE_GRACE: Duration = Duration::from_secs(5);
+
+/// timeouts applied to client connections
+///
+/// A `None` value disables the corresponding timeout.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Timeouts {
+    /// time a client has to send the request line and all headers (measured from accepting the
+    /// connection)
+    pub header_read: Option<Duration>,
+
+    /// time a client has to send the request body
+    pub body_read: Option<Duration>,
+
+    /// time a single write call of the response may block. This isn't a deadline for the whole
+    /// response: a client that keeps accepting data slowly can take longer than this in total
+    pub write: Option<Duration>,
+
+    /// time a connection may stay silent before it gets closed
+    pub idle: Option<Duration>,
+
+    /// minimum amount of bytes per second a client has to send once it started sending a request
+    pub min_rate: Option<usize>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            header_read: Some(Duration::from_secs(20)),
+            body_read: Some(Duration::from_secs(60)),
+            write: Some(Duration::from_secs(30)),
+            idle: Some(Duration::from_secs(10)),
+            min_rate: Some(500),
+        }
+    }
+}
+
+impl Timeouts {
+    /// timeouts that never expire
+    pub fn none() -> Timeouts {
+        Timeouts {
+            header_read: None,
+            body_read: None,
+            write: None,
+            idle: None,
+            min_rate: None,
+        }
+    }
+
+    /// create timeouts from a config section. Values that aren't set use the defaults, values set
+    /// to `0` disable the timeout
+    pub fn from_config(conf: Option<&TimeoutConfig>) -> Timeouts {
+        let default = Timeouts::default();
+        let conf = match conf {
+            Some(n) => n,
+            None => return default,
+        };
+        let secs = |value: Option<u64>, default: Option<Duration>| match value {
+            Some(0) => None,
+            Some(n) => Some(Duration::from_secs(n)),
+            None => default,
+        };
+        Timeouts {
+            header_read: secs(conf.header_read, default.header_read),
+            body_read: secs(conf.body_read, default.body_read),
+            write: secs(conf.write, default.write),
+            idle: secs(conf.idle, default.idle),
+            min_rate: match conf.min_rate {
+                Some(0) => None,
+                Some(n) => Some(n),
+                None => default.min_rate,
+            },
+        }
+    }
+}
+
+/// a stream whose read and write timeouts can be changed
+pub trait SetTimeout {
+    /// set the time a read may block. `None` blocks forever
+    fn set_read_timeout(&self, dur: Option<Duration>) -> std::io::Result<()>;
+
+    /// set the time a write may block. `None` blocks forever
+    fn set_write_timeout(&self, dur: Option<Duration>) -> std::io::Result<()>;
+}
+
+impl SetTimeout for TcpStream {
+    fn set_read_timeout(&self, dur: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_read_timeout(self, dur)
+    }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_write_timeout(self, dur)
+    }
+}
+
+#[cfg(unix)]
+impl SetTimeout for std::os::unix::net::UnixStream {
+    fn set_read_timeout(&self, dur: Option<Duration>) -> std::io::Result<()> {
+        std::os::unix::net::UnixStream::set_read_timeout(self, dur)
+    }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> std::io::Result<()> {
+        std::os::unix::net::UnixStream::set_write_timeout(self, dur)
+    }
+}
+
+impl<S: SetTimeout> SetTimeout for SslStream<S> {
+    fn set_read_timeout(&self, dur: Option<Duration>) -> std::io::Result<()> {
+        self.get_ref().set_read_timeout(dur)
+    }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> std::io::Result<()> {
+        self.get_ref().set_write_timeout(dur)
+    }
+}
+
+/// keeps track of the deadlines while reading a single request
+pub struct Deadline {
+    timeouts: Timeouts,
+    deadline: Option<Instant>,
+    first_byte: Option<Instant>,
+    bytes: usize,
+    /// the read timeout that was last set on the stream
+    applied: Option<Option<Duration>>,
+}
+
+impl Deadline {
+    /// start tracking a new request. The header timeout starts running immediately
+    pub fn new(timeouts: &Timeouts) -> Deadline {
+        Deadline {
+            timeouts: timeouts.clone(),
+            deadline: timeouts.header_read.map(|n| Instant::now() + n),
+            first_byte: None,
+            bytes: 0,
+            applied: None,
+        }
+    }
+
+    /// switch to reading the body of the request
+    pub fn start_body(&mut self) {
+        self.deadline = self.timeouts.body_read.map(|n| Instant::now() + n);
+        self.first_byte = Some(Instant::now());
+        self.bytes = 0;
+    }
+
+    /// record that `n` bytes were received
+    pub fn record(&mut self, n: usize) {
+        if n > 0 && self.first_byte.is_none() {
+            self.first_byte = Some(Instant::now());
+        }
+        self.bytes += n;
+    }
+
+    /// check the deadlines and return how long the next read may block
+    ///
+    /// # Returns
+    /// an error of kind [`ErrorKind::TimedOut`] if the client ran out of time or sends too slowly
+    pub fn next_timeout(&self) -> std::io::Result<Option<Duration>> {
+        let now = Instant::now();
+        if let (Some(rate), Some(start)) = (self.timeouts.min_rate, self.first_byte) {
+            let elapsed = now.duration_since(start).saturating_sub(MIN_RATE_GRACE);
+            if !elapsed.is_zero() && (self.bytes as f64) < rate as f64 * elapsed.as_secs_f64() {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "client is sending below the minimum transfer rate",
+                ));
+            }
+        }
+        let remaining = match self.deadline {
+            Some(deadline) => match deadline.checked_duration_since(now) {
+                Some(n) if !n.is_zero() => Some(n),
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::TimedOut,
+                        "client took too long to send the request",
+                    ))
+                }
+            },
+            None => None,
+        };
+        Ok(match (remaining, self.timeouts.idle) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        })
+    }
+
+    /// set the read timeout of `stream` for the next read. The timeout is only changed if it
+    /// differs from the one set before, so reads limited by the idle timeout don't cost an extra
+    /// system call each
+    pub fn apply<T: SetTimeout>(&mut self, stream: &T) -> std::io::Result<()> {
+        let timeout = self.next_timeout()?;
+        if self.applied != Some(timeout) {
+            stream.set_read_timeout(timeout)?;
+            self.applied = Some(timeout);
+        }
+        Ok(())
+    }
+}
+
+/// convert errors produced by an expired socket timeout to [`ErrorKind::TimedOut`]
+pub fn map_timeout_error(e: Error) -> Error {
+    match e.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+            Error::new(ErrorKind::TimedOut, "connection timed out")
+        }
+        _ => e,
+    }
+}
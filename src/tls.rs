@@ -0,0 +1,595 @@
+use crate::config::{CertificateConfig, SslConfig};
+use crate::error::ServerError;
+use crate::log::Logger;
+use crate::mtls::ClientAuth;
+use crate::{error, msg};
+use openssl::nid::Nid;
+use openssl::ssl::{
+    select_next_proto, AlpnError, NameType, SniError, SslAcceptor, SslAcceptorBuilder, SslContext,
+    SslFiletype, SslMethod, SslOptions, SslSessionCacheMode, SslVersion,
+};
+use openssl::x509::X509Ref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+/// check whether the certificate name `pattern` covers `host`. A leading `*.` matches exactly one
+/// label, so `*.example.com` matches `a.example.com` but neither `example.com` nor
+/// `a.b.example.com`. Names are compared case insensitively
+pub fn name_matches(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.trim_end_matches('.');
+    let host = host.trim_end_matches('.');
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => match host.split_once('.') {
+            Some((label, rest)) => !label.is_empty() && rest.eq_ignore_ascii_case(suffix),
+            None => false,
+        },
+        None => pattern.eq_ignore_ascii_case(host),
+    }
+}
+
+/// read the host names a certificate is valid for. These are the DNS names of its subject
+/// alternative name extension or, if it doesn't have any, its common name
+pub fn certificate_names(cert: &X509Ref) -> Vec<String> {
+    let names: Vec<String> = cert
+        .subject_alt_names()
+        .map(|names| {
+            names
+                .iter()
+                .filter_map(|n| n.dnsname().map(|n| n.to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+    if !names.is_empty() {
+        return names;
+    }
+    cert.subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .filter_map(|n| n.data().as_utf8().ok().map(|n| n.to_string()))
+        .collect()
+}
+
+/// a TLS configuration profile recommended by Mozilla
+/// (<https://wiki.mozilla.org/Security/Server_Side_TLS>)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TlsProfile {
+    /// TLS 1.3 only
+    Modern,
+
+    /// TLS 1.2 and 1.3 with ciphers supported by almost all clients
+    Intermediate,
+}
+
+impl TlsProfile {
+    pub fn from_string(string: String) -> Option<TlsProfile> {
+        match string.as_str() {
+            "modern" => Some(TlsProfile::Modern),
+            "intermediate" => Some(TlsProfile::Intermediate),
+            _ => None,
+        }
+    }
+}
+
+/// parse a TLS version like `1.2` or `TLSv1.2`
+pub fn version_from_string(string: &str) -> Option<SslVersion> {
+    match string.trim_start_matches("TLSv") {
+        "1" | "1.0" => Some(SslVersion::TLS1),
+        "1.1" => Some(SslVersion::TLS1_1),
+        "1.2" => Some(SslVersion::TLS1_2),
+        "1.3" => Some(SslVersion::TLS1_3),
+        _ => None,
+    }
+}
+
+/// settings applied to every certificate of a server
+#[derive(Clone)]
+pub struct TlsOptions {
+    /// the profile the other settings are applied on top of
+    pub profile: TlsProfile,
+
+    /// oldest TLS version clients may use. `None` uses the one of the profile
+    pub min_version: Option<SslVersion>,
+
+    /// newest TLS version clients may use. `None` allows the newest supported one
+    pub max_version: Option<SslVersion>,
+
+    /// OpenSSL cipher list for TLS 1.2 and older
+    pub ciphers: Option<String>,
+
+    /// OpenSSL ciphersuites for TLS 1.3
+    pub ciphersuites: Option<String>,
+
+    /// colon separated list of curves (groups) used for key exchange, e.g. `X25519:P-256`
+    pub curves: Option<String>,
+
+    /// protocols offered with ALPN in order of preference, e.g. `http/1.1`
+    pub alpn: Vec<String>,
+
+    /// whether sessions are cached so clients can resume them
+    pub session_cache: bool,
+
+    /// maximum amount of cached sessions
+    pub session_cache_size: Option<usize>,
+
+    /// whether session tickets are issued to clients
+    pub session_tickets: bool,
+
+    /// file containing a DER encoded OCSP response that is stapled to the handshake of clients
+    /// asking for the certificate status
+    pub ocsp_response: Option<String>,
+
+    /// verification of client certificates (mutual TLS). `None` doesn't ask clients for a
+    /// certificate
+    pub client_auth: Option<ClientAuth>,
+}
+
+impl Default for TlsOptions {
+    fn default() -> Self {
+        TlsOptions {
+            profile: TlsProfile::Intermediate,
+            min_version: None,
+            max_version: None,
+            ciphers: None,
+            ciphersuites: None,
+            curves: None,
+            alpn: Vec::new(),
+            session_cache: true,
+            session_cache_size: None,
+            session_tickets: true,
+            ocsp_response: None,
+            client_auth: None,
+        }
+    }
+}
+
+impl TlsOptions {
+    /// read the options from the `ssl` section of a config
+    pub fn from_config(conf: Option<&SslConfig>) -> Result<TlsOptions, ServerError> {
+        let default = TlsOptions::default();
+        let conf = match conf {
+            Some(n) => n,
+            None => return Ok(default),
+        };
+        let profile = match &conf.profile {
+            Some(n) => match TlsProfile::from_string(n.to_lowercase()) {
+                Some(n) => n,
+                None => {
+                    return Err(ServerError::new(
+                        format!("invalid TLS profile: '{}'", n).as_str(),
+                    ));
+                }
+            },
+            None => default.profile,
+        };
+        let version = |value: &Option<String>| match value {
+            Some(n) => match version_from_string(n) {
+                Some(n) => Ok(Some(n)),
+                None => Err(ServerError::new(
+                    format!("invalid TLS version: '{}'", n).as_str(),
+                )),
+            },
+            None => Ok(None),
+        };
+        let client_auth = match &conf.client_auth {
+            Some(n) => Some(ClientAuth::from_config(n)?),
+            None => None,
+        };
+        Ok(TlsOptions {
+            profile,
+            min_version: version(&conf.min_version)?,
+            max_version: version(&conf.max_version)?,
+            ciphers: conf.ciphers.clone(),
+            ciphersuites: conf.ciphersuites.clone(),
+            curves: conf.curves.clone(),
+            alpn: conf.alpn.clone().unwrap_or_default(),
+            session_cache: conf.session_cache.unwrap_or(default.session_cache),
+            session_cache_size: conf.session_cache_size,
+            session_tickets: conf.session_tickets.unwrap_or(default.session_tickets),
+            ocsp_response: conf.ocsp_response.clone(),
+            client_auth,
+        })
+    }
+
+    /// get the files the options depend on
+    pub fn files(&self) -> Vec<&str> {
+        let mut files: Vec<&str> = self.ocsp_response.iter().map(|n| n.as_str()).collect();
+        if let Some(n) = &self.client_auth {
+            files.extend(n.files());
+        }
+        files
+    }
+
+    /// create a builder using the profile
+    fn builder(&self) -> Result<SslAcceptorBuilder, ServerError> {
+        let res = match self.profile {
+            TlsProfile::Modern => SslAcceptor::mozilla_modern_v5(SslMethod::tls()),
+            TlsProfile::Intermediate => SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()),
+        };
+        match res {
+            Ok(n) => Ok(n),
+            Err(_) => Err(ServerError::new("error creating SSL Acceptor")),
+        }
+    }
+
+    /// apply the options to `builder`
+    pub fn apply(&self, builder: &mut SslAcceptorBuilder) -> Result<(), ServerError> {
+        let invalid = |setting: &str, value: &str| {
+            ServerError::new(format!("invalid {}: '{}'", setting, value).as_str())
+        };
+        if self.min_version.is_some() && builder.set_min_proto_version(self.min_version).is_err() {
+            return Err(ServerError::new("error setting minimum TLS version"));
+        }
+        if self.max_version.is_some() && builder.set_max_proto_version(self.max_version).is_err() {
+            return Err(ServerError::new("error setting maximum TLS version"));
+        }
+        if let Some(n) = &self.ciphers {
+            if builder.set_cipher_list(n).is_err() {
+                return Err(invalid("cipher list", n));
+            }
+        }
+        if let Some(n) = &self.ciphersuites {
+            if builder.set_ciphersuites(n).is_err() {
+                return Err(invalid("TLS 1.3 ciphersuites", n));
+            }
+        }
+        if let Some(n) = &self.curves {
+            if builder.set_groups_list(n).is_err() {
+                return Err(invalid("curve list", n));
+            }
+        }
+        if !self.alpn.is_empty() {
+            // protocols are sent as a list of length prefixed strings
+            let mut protocols: Vec<u8> = Vec::new();
+            for n in &self.alpn {
+                if n.is_empty() || n.len() > 255 {
+                    return Err(invalid("ALPN protocol", n));
+                }
+                protocols.push(n.len() as u8);
+                protocols.extend(n.bytes());
+            }
+            builder.set_alpn_select_callback(move |_, client| {
+                select_next_proto(&protocols, client).ok_or(AlpnError::NOACK)
+            });
+        }
+        if !self.session_cache {
+            builder.set_session_cache_mode(SslSessionCacheMode::OFF);
+        } else if let Some(n) = self.session_cache_size {
+            builder.set_session_cache_size(n.min(i32::MAX as usize) as i32);
+        }
+        if !self.session_tickets {
+            builder.set_options(SslOptions::NO_TICKET);
+        }
+        if let Some(file) = &self.ocsp_response {
+            let response = match std::fs::read(file) {
+                Ok(n) => n,
+                Err(_) => {
+                    return Err(ServerError::new(
+                        format!("error reading OCSP response '{}'", file).as_str(),
+                    ));
+                }
+            };
+            let res = builder.set_status_callback(move |ssl| {
+                ssl.set_ocsp_status(&response)?;
+                Ok(true)
+            });
+            if res.is_err() {
+                return Err(ServerError::new("error enabling OCSP stapling"));
+            }
+        }
+        if let Some(n) = &self.client_auth {
+            n.apply(builder)?;
+        }
+        Ok(())
+    }
+}
+
+/// create an acceptor builder using the private key in `privkeyfile` and the certificate chain in
+/// `certchainfile` (both PEM encoded) and `options`
+pub fn acceptor_builder(
+    privkeyfile: &str,
+    certchainfile: &str,
+    options: &TlsOptions,
+) -> Result<SslAcceptorBuilder, ServerError> {
+    let mut acceptor = options.builder()?;
+    match acceptor.set_private_key_file(privkeyfile, SslFiletype::PEM) {
+        Ok(_) => (),
+        Err(_) => {
+            return Err(ServerError::new(
+                format!("error setting private key file '{}'", privkeyfile).as_str(),
+            ));
+        }
+    }
+    match acceptor.set_certificate_chain_file(certchainfile) {
+        Ok(_) => (),
+        Err(_) => {
+            return Err(ServerError::new(
+                format!("error setting certificate chain file '{}'", certchainfile).as_str(),
+            ));
+        }
+    }
+    match acceptor.check_private_key() {
+        Ok(_) => (),
+        Err(_) => {
+            return Err(ServerError::new(
+                format!("problem with private key: '{}'", privkeyfile).as_str(),
+            ));
+        }
+    }
+    options.apply(&mut acceptor)?;
+    Ok(acceptor)
+}
+
+/// load the certificate described by `conf` and get the names it is used for
+fn load_context(
+    conf: &CertificateConfig,
+    options: &TlsOptions,
+) -> Result<(Vec<String>, SslContext), ServerError> {
+    let ctx = acceptor_builder(
+        conf.private_key.as_str(),
+        conf.certificate_chain.as_str(),
+        options,
+    )?
+    .build()
+    .into_context();
+    let names = match &conf.names {
+        Some(n) => n.clone(),
+        None => ctx.certificate().map(certificate_names).unwrap_or_default(),
+    };
+    if names.is_empty() {
+        return Err(ServerError::new(
+            format!("no host names for certificate '{}'", conf.certificate_chain).as_str(),
+        ));
+    }
+    Ok((names, ctx))
+}
+
+/// get the certificate for `host` out of `certs`. Exact names take precedence over wildcard names
+fn lookup<'a>(certs: &'a [(Vec<String>, SslContext)], host: &str) -> Option<&'a SslContext> {
+    let exact = certs
+        .iter()
+        .find(|(names, _)| names.iter().any(|n| n.eq_ignore_ascii_case(host)));
+    exact
+        .or_else(|| {
+            certs
+                .iter()
+                .find(|(names, _)| names.iter().any(|n| name_matches(n, host)))
+        })
+        .map(|(_, ctx)| ctx)
+}
+
+/// certificates selected by the server name a client sends (SNI)
+///
+/// The certificates are loaded from their files every time they are installed into an acceptor,
+/// so renewed certificates get picked up when the acceptor is rebuilt.
+#[derive(Clone, Default)]
+pub struct SniCertificates {
+    sources: Vec<CertificateConfig>,
+}
+
+impl SniCertificates {
+    /// create an empty set of certificates
+    pub fn new() -> SniCertificates {
+        SniCertificates {
+            sources: Vec::new(),
+        }
+    }
+
+    /// load the certificates of the `certificates` setting of a config section
+    pub fn from_config(
+        conf: Option<&Vec<CertificateConfig>>,
+    ) -> Result<SniCertificates, ServerError> {
+        let mut certs = SniCertificates::new();
+        for n in conf.into_iter().flatten() {
+            certs.load(
+                n.names.clone(),
+                n.private_key.as_str(),
+                n.certificate_chain.as_str(),
+            )?;
+        }
+        Ok(certs)
+    }
+
+    /// load a certificate and its private key and use it for `names`. If `names` is `None` the
+    /// names the certificate is valid for are used
+    pub fn load(
+        &mut self,
+        names: Option<Vec<String>>,
+        privkeyfile: &str,
+        certchainfile: &str,
+    ) -> Result<(), ServerError> {
+        let conf = CertificateConfig {
+            names,
+            private_key: privkeyfile.to_string(),
+            certificate_chain: certchainfile.to_string(),
+        };
+        load_context(&conf, &TlsOptions::default())?;
+        self.sources.push(conf);
+        Ok(())
+    }
+
+    /// check whether there are no certificates
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// get the files the certificates are loaded from
+    pub fn files(&self) -> Vec<&str> {
+        self.sources
+            .iter()
+            .flat_map(|n| [n.private_key.as_str(), n.certificate_chain.as_str()])
+            .collect()
+    }
+
+    /// switch to the matching certificate during the handshake of connections to `builder`.
+    /// Clients that don't send a server name or ask for an unknown one get the certificate of
+    /// `builder` (the default certificate)
+    pub fn install(
+        &self,
+        builder: &mut SslAcceptorBuilder,
+        options: &TlsOptions,
+    ) -> Result<(), ServerError> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let mut certs = Vec::new();
+        for n in &self.sources {
+            certs.push(load_context(n, options)?);
+        }
+        builder.set_servername_callback(move |ssl, _alert| {
+            let ctx = ssl
+                .servername(NameType::HOST_NAME)
+                .and_then(|host| lookup(&certs, host));
+            if let Some(ctx) = ctx {
+                if ssl.set_ssl_context(ctx).is_err() {
+                    return Err(SniError::ALERT_FATAL);
+                }
+            }
+            Ok(())
+        });
+        Ok(())
+    }
+}
+
+/// create an acceptor using the default certificate in `privkeyfile` and `certchainfile` and
+/// `certs` for clients asking for one of their names
+pub fn build_acceptor(
+    privkeyfile: &str,
+    certchainfile: &str,
+    certs: &SniCertificates,
+    options: &TlsOptions,
+) -> Result<SslAcceptor, ServerError> {
+    let mut builder = acceptor_builder(privkeyfile, certchainfile, options)?;
+    certs.install(&mut builder, options)?;
+    Ok(builder.build())
+}
+
+/// get the file a new version of `file` is written to before it replaces `file`. Certificates
+/// aren't reloaded while the one of their key or chain exists, so a key and its certificate can
+/// be replaced one after the other (see [`crate::acme::Acme::obtain`])
+pub fn pending_file(file: &Path) -> PathBuf {
+    let mut name = file.as_os_str().to_owned();
+    name.push(".new");
+    PathBuf::from(name)
+}
+
+/// an acceptor whose certificates can be replaced while the server is running
+///
+/// Connections that are already established keep using the certificates they were accepted with.
+/// If loading the new certificates fails the old ones stay in use.
+pub struct ReloadableAcceptor {
+    privkeyfile: String,
+    certchainfile: String,
+    certs: SniCertificates,
+    options: TlsOptions,
+    acceptor: RwLock<Arc<SslAcceptor>>,
+    /// modification times of the certificate files when they were last loaded
+    modified: Mutex<Vec<Option<SystemTime>>>,
+}
+
+impl ReloadableAcceptor {
+    /// create an acceptor like [`build_acceptor`] that remembers the files the certificates were
+    /// loaded from
+    pub fn new(
+        privkeyfile: &str,
+        certchainfile: &str,
+        certs: SniCertificates,
+        options: TlsOptions,
+    ) -> Result<ReloadableAcceptor, ServerError> {
+        let acceptor = build_acceptor(privkeyfile, certchainfile, &certs, &options)?;
+        let mut acceptor = ReloadableAcceptor {
+            privkeyfile: privkeyfile.to_string(),
+            certchainfile: certchainfile.to_string(),
+            certs,
+            options,
+            acceptor: RwLock::new(Arc::new(acceptor)),
+            modified: Mutex::new(Vec::new()),
+        };
+        acceptor.modified = Mutex::new(acceptor.modification_times());
+        Ok(acceptor)
+    }
+
+    /// get the acceptor to use for a new connection
+    pub fn acceptor(&self) -> Arc<SslAcceptor> {
+        match self.acceptor.read() {
+            Ok(n) => n.clone(),
+            Err(e) => e.into_inner().clone(),
+        }
+    }
+
+    fn modification_times(&self) -> Vec<Option<SystemTime>> {
+        let mut files = vec![self.privkeyfile.as_str(), self.certchainfile.as_str()];
+        files.extend(self.certs.files());
+        files.extend(self.options.files());
+        files
+            .into_iter()
+            .map(|n| std::fs::metadata(n).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+
+    /// load all certificates from their files and use them for new connections. If a
+    /// certificate or key can't be loaded or they don't match the old certificates are kept
+    pub fn reload(&self) -> Result<(), ServerError> {
+        let modified = self.modification_times();
+        let res = build_acceptor(
+            self.privkeyfile.as_str(),
+            self.certchainfile.as_str(),
+            &self.certs,
+            &self.options,
+        );
+        // remember the times even if loading failed so a broken pair is only reported once
+        match self.modified.lock() {
+            Ok(mut n) => *n = modified,
+            Err(e) => *e.into_inner() = modified,
+        }
+        let acceptor = res?;
+        match self.acceptor.write() {
+            Ok(mut n) => *n = Arc::new(acceptor),
+            Err(e) => *e.into_inner() = Arc::new(acceptor),
+        }
+        Ok(())
+    }
+
+    /// reload the certificates if one of their files changed since they were last loaded and
+    /// the key and the chain aren't being replaced (see [`pending_file`])
+    ///
+    /// # Returns
+    /// `None` if nothing changed, otherwise the result of [`ReloadableAcceptor::reload`]
+    pub fn reload_if_changed(&self) -> Option<Result<(), ServerError>> {
+        if [&self.privkeyfile, &self.certchainfile]
+            .iter()
+            .any(|n| pending_file(Path::new(n)).exists())
+        {
+            return None;
+        }
+        let changed = match self.modified.lock() {
+            Ok(n) => *n != self.modification_times(),
+            Err(e) => *e.into_inner() != self.modification_times(),
+        };
+        if changed {
+            Some(self.reload())
+        } else {
+            None
+        }
+    }
+
+    /// check the certificate files for changes every `interval` in a background thread and
+    /// reload them when they changed. Results are logged to `logger`
+    pub fn watch(
+        acceptor: Arc<ReloadableAcceptor>,
+        interval: Duration,
+        logger: Logger,
+    ) -> JoinHandle<()> {
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            match acceptor.reload_if_changed() {
+                Some(Ok(())) => msg!(logger, "reloaded TLS certificates"),
+                Some(Err(e)) => error!(
+                    logger,
+                    "failed to reload TLS certificates, keeping the old ones: {}", e
+                ),
+                None => (),
+            }
+        })
+    }
+}
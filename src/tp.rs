@@ -1,5 +1,5 @@
 // Note: this threadpool implementation is pretty much taken from the rust language book
-use crate::log::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
@@ -24,17 +24,19 @@ pub struct Worker {
 impl Worker {
     /// construct a new worker
     /// # Arguments
-    /// * `id`: the id this worker uses for logging purposes
+    /// * `id`: the id of this worker
     /// * `reciever`: the recieving end of a channel
-    pub fn new(id: usize, reciever: Arc<Mutex<mpsc::Receiver<Msg>>>) -> Worker {
-        let thread = thread::spawn(move || loop {
+    /// * `stats`: the statistics of the pool to update
+    pub fn new(id: usize, reciever: Arc<Mutex<mpsc::Receiver<Msg>>>, stats: PoolStats) -> Worker {
+        let builder = thread::Builder::new().name(format!("rsweb-worker-{}", id));
+        let thread = builder.spawn(move || loop {
             let job = reciever.lock().unwrap().recv().unwrap();
-            let mut logger: Logger = Logger::new();
-            logger.set_term(btui::Terminal::default());
             match job {
                 Msg::Exec(task) => {
-                    logger.log(format!("Worker {}: executing task...", id), LogType::Log);
+                    stats.queued.fetch_sub(1, Ordering::Relaxed);
+                    stats.busy.fetch_add(1, Ordering::Relaxed);
                     task();
+                    stats.busy.fetch_sub(1, Ordering::Relaxed);
                 }
                 Msg::Terminate => {
                     break;
@@ -43,15 +45,41 @@ impl Worker {
         });
         Worker {
             id,
-            thread: Some(thread),
+            thread: Some(thread.expect("failed to spawn worker thread")),
         }
     }
 }
 
+/// the number of jobs waiting for a worker and the number of workers executing a job
+#[derive(Clone, Debug, Default)]
+pub struct PoolStats {
+    size: usize,
+    queued: Arc<AtomicUsize>,
+    busy: Arc<AtomicUsize>,
+}
+
+impl PoolStats {
+    /// get the number of workers
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// get the number of jobs waiting for a worker
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// get the number of workers executing a job
+    pub fn busy(&self) -> usize {
+        self.busy.load(Ordering::Relaxed)
+    }
+}
+
 /// a thread pool with a set amount of threads
 pub struct ThreadPool {
     workers: Vec<Worker>,
     sender: mpsc::Sender<Msg>,
+    stats: PoolStats,
 }
 
 impl ThreadPool {
@@ -63,11 +91,24 @@ impl ThreadPool {
         let reciever = Arc::new(Mutex::new(reciever));
 
         let mut workers: Vec<Worker> = Vec::with_capacity(size);
+        let stats = PoolStats {
+            size,
+            ..PoolStats::default()
+        };
 
         for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&reciever)));
+            workers.push(Worker::new(id, Arc::clone(&reciever), stats.clone()));
         }
-        ThreadPool { workers, sender }
+        ThreadPool {
+            workers,
+            sender,
+            stats,
+        }
+    }
+
+    /// get a handle to the statistics of the pool
+    pub fn stats(&self) -> PoolStats {
+        self.stats.clone()
     }
 
     /// give a job to execute to the threadpool
@@ -76,9 +117,11 @@ impl ThreadPool {
         F: FnOnce() + Send + 'static,
     {
         let job = Box::new(f);
+        self.stats.queued.fetch_add(1, Ordering::Relaxed);
         match self.sender.send(Msg::Exec(job)) {
             Ok(_) => (),
             Err(e) => {
+                self.stats.queued.fetch_sub(1, Ordering::Relaxed);
                 eprintln!("failed to execute task: {}", e);
             }
         }
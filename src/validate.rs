@@ -0,0 +1,748 @@
+use crate::config::{
+    Config, CorsConfig, ListenConfig, Resource, RouteConfig, SslConfig, VhostConfig,
+};
+use crate::http::request::HTTPMethod;
+use crate::http::StatusCode;
+use crate::listener::{dual_stack, parse_mode, Listener};
+#[cfg(unix)]
+use crate::listener::{group_id, user_id};
+use crate::log::Filter;
+use crate::route::Rule;
+use crate::tls::TlsOptions;
+use crate::vhost::ServerName;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs::{read_to_string, File};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+
+/// how bad a problem in the config is
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Severity {
+    /// `rsweb` can't start with this config
+    Error,
+    /// the config works but probably not as intended
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// a problem found in the config, located by line and column (both starting at 1) if possible
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub position: Option<(usize, usize)>,
+    pub msg: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    /// format the diagnostic as `line:column: severity: message`
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self.position {
+            Some((line, column)) => {
+                write!(f, "{}:{}: {}: {}", line, column, self.severity, self.msg)
+            }
+            None => write!(f, "{}: {}", self.severity, self.msg),
+        }
+    }
+}
+
+/// the result of validating a config
+#[derive(Clone)]
+pub struct Validation {
+    /// the config if it could be parsed at all
+    pub config: Option<Config>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Validation {
+    /// check whether `rsweb` can't start with the config
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|n| n.severity == Severity::Error)
+    }
+}
+
+/// count the brackets `value` leaves open, ignoring strings and comments
+fn open_brackets(value: &str) -> i32 {
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for c in value.chars() {
+        match quote {
+            Some(q) => {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' && q == '"' {
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '[' | '{' => depth += 1,
+                ']' | '}' => depth -= 1,
+                '#' => break,
+                _ => (),
+            },
+        }
+    }
+    depth
+}
+
+/// split a dotted TOML key into its parts without quotes
+fn key_parts(key: &str) -> Vec<String> {
+    key.split('.')
+        .map(|n| n.trim().trim_matches('"').trim_matches('\'').to_string())
+        .collect()
+}
+
+/// positions of the keys and table headers of a TOML document by their path, e.g.
+/// `http.cors[0].path`
+struct Positions {
+    keys: HashMap<String, (usize, usize)>,
+}
+
+impl Positions {
+    fn new(source: &str) -> Positions {
+        let mut keys = HashMap::new();
+        // number of tables of each array of tables seen so far
+        let mut arrays: HashMap<String, usize> = HashMap::new();
+        let mut table = String::new();
+        let mut depth = 0;
+        // resolve the path of a table header, using the last table of arrays of tables
+        let resolve = |parts: &[String], arrays: &HashMap<String, usize>| {
+            let mut path = String::new();
+            for part in parts {
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(part);
+                if let Some(n) = arrays.get(&path) {
+                    path = format!("{}[{}]", path, n - 1);
+                }
+            }
+            path
+        };
+        for (i, line) in source.lines().enumerate() {
+            let trimmed = line.trim_start();
+            let position = (i + 1, line.len() - trimmed.len() + 1);
+            if depth > 0 {
+                // continuation of a multi-line array or inline table
+                depth += open_brackets(trimmed);
+                continue;
+            }
+            if let Some(rest) = trimmed.strip_prefix("[[") {
+                let parts = key_parts(rest.split("]]").next().unwrap_or(""));
+                let (last, parents) = match parts.split_last() {
+                    Some(n) => n,
+                    None => continue,
+                };
+                let mut name = resolve(parents, &arrays);
+                if !name.is_empty() {
+                    name.push('.');
+                }
+                name.push_str(last);
+                let count = arrays.entry(name.clone()).or_insert(0);
+                table = format!("{}[{}]", name, count);
+                *count += 1;
+                keys.entry(table.clone()).or_insert(position);
+            } else if let Some(rest) = trimmed.strip_prefix('[') {
+                table = resolve(&key_parts(rest.split(']').next().unwrap_or("")), &arrays);
+                keys.entry(table.clone()).or_insert(position);
+            } else if let Some((key, value)) = trimmed.split_once('=') {
+                if trimmed.starts_with('#') {
+                    continue;
+                }
+                let key = key_parts(key).join(".");
+                let path = match table.as_str() {
+                    "" => key,
+                    n => format!("{}.{}", n, key),
+                };
+                keys.insert(path, position);
+                depth = open_brackets(value).max(0);
+            }
+        }
+        Positions { keys }
+    }
+
+    /// get the position of `path` or of the closest table containing it
+    fn find(&self, path: &str) -> Option<(usize, usize)> {
+        let mut path = path;
+        loop {
+            if let Some(n) = self.keys.get(path) {
+                return Some(*n);
+            }
+            let parent = match (path.rfind('.'), path.rfind('[')) {
+                (Some(a), Some(b)) => a.max(b),
+                (Some(a), None) => a,
+                (None, Some(b)) => b,
+                (None, None) => return None,
+            };
+            path = &path[..parent];
+        }
+    }
+}
+
+/// collects diagnostics located in one document
+struct Checker {
+    positions: Positions,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn report(&mut self, severity: Severity, path: &str, msg: String) {
+        let position = self.positions.find(path);
+        self.diagnostics.push(Diagnostic {
+            severity,
+            position,
+            msg,
+        });
+    }
+
+    fn error(&mut self, path: &str, msg: String) {
+        self.report(Severity::Error, path, msg);
+    }
+
+    fn warning(&mut self, path: &str, msg: String) {
+        self.report(Severity::Warning, path, msg);
+    }
+
+    /// report keys of `raw` that aren't part of `known`, the config written back
+    fn unknown_keys(&mut self, raw: &toml::Value, known: Option<&toml::Value>, path: &str) {
+        match raw {
+            toml::Value::Table(table) => {
+                for (key, value) in table {
+                    let sub = match path {
+                        "" => key.clone(),
+                        n => format!("{}.{}", n, key),
+                    };
+                    match known.and_then(|n| n.get(key)) {
+                        Some(n) => self.unknown_keys(value, Some(n), sub.as_str()),
+                        None => {
+                            let section = match path {
+                                "" => String::from("the top level"),
+                                n if n.ends_with(']') => match n.rfind('[') {
+                                    Some(i) => format!("[[{}]]", &n[..i]),
+                                    None => format!("[{}]", n),
+                                },
+                                n => format!("[{}]", n),
+                            };
+                            self.warning(
+                                sub.as_str(),
+                                format!("unknown key '{}' in {} is ignored", key, section),
+                            );
+                        }
+                    }
+                }
+            }
+            toml::Value::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    let known = known.and_then(|n| n.as_array()).and_then(|n| n.get(i));
+                    self.unknown_keys(item, known, format!("{}[{}]", path, i).as_str());
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// check a file the config refers to can be read
+    fn readable(&mut self, path: &str, name: &str, file: &str) {
+        if let Err(e) = File::open(file) {
+            self.error(path, format!("can't read {} '{}': {}", name, file, e));
+        }
+    }
+
+    /// check the settings common to the `http` and `ssl` sections. Returns the valid listeners
+    /// of the section together with the paths of their settings. `tls` is the default of their
+    /// TLS flag
+    #[allow(clippy::too_many_arguments)]
+    fn server(
+        &mut self,
+        section: &str,
+        ip: Option<&String>,
+        port: Option<usize>,
+        listen: Option<&Vec<ListenConfig>>,
+        tls: bool,
+        threads: Option<usize>,
+        resources: &Resource,
+    ) -> Vec<(String, Listener)> {
+        let ip_path = format!("{}.ip", section);
+        let has_ip = ip.is_some();
+        let ip = match ip.map(|n| (n, n.parse::<IpAddr>())) {
+            Some((_, Ok(n))) => Some(n),
+            Some((n, Err(_))) => {
+                self.error(ip_path.as_str(), format!("'{}' is not an IP address", n));
+                None
+            }
+            None => Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+        };
+        let mut paths = Vec::new();
+        let mut listeners = Vec::new();
+        match port {
+            Some(port) if port == 0 || port > u16::MAX as usize => self.error(
+                format!("{}.port", section).as_str(),
+                format!("port {} is not between 1 and 65535", port),
+            ),
+            Some(port) => {
+                if let Some(ip) = ip {
+                    paths.push(format!("{}.port", section));
+                    listeners.push(Listener::new(SocketAddr::new(ip, port as u16), tls));
+                }
+            }
+            None if has_ip => {
+                self.warning(ip_path.as_str(), String::from("ip is ignored without port"))
+            }
+            None => (),
+        }
+        for (i, n) in listen.into_iter().flatten().enumerate() {
+            let path = format!("{}.listen[{}]", section, i);
+            if let Some(Err(e)) = n.mode.as_deref().map(parse_mode) {
+                self.error(format!("{}.mode", path).as_str(), e.to_string());
+                continue;
+            }
+            match Listener::from_config(n, tls) {
+                Ok(n) => {
+                    self.socket(path.as_str(), &n);
+                    paths.push(path);
+                    listeners.push(n);
+                }
+                Err(e) => self.error(format!("{}.address", path).as_str(), e.to_string()),
+            }
+        }
+        if port.is_none() && listen.is_none_or(|n| n.is_empty()) {
+            self.error(
+                section,
+                String::from("a port or listen address is required"),
+            );
+        }
+        if threads == Some(0) {
+            self.error(
+                format!("{}.threads", section).as_str(),
+                String::from("at least one thread is required"),
+            );
+        }
+        self.root(section, resources);
+        dual_stack(&mut listeners);
+        paths.into_iter().zip(listeners).collect()
+    }
+
+    /// check the settings of unix sockets at `path` and that TCP listeners don't use them
+    fn socket(&mut self, path: &str, listener: &Listener) {
+        if listener.tcp_addr().is_some() {
+            if listener.mode.is_some() || listener.owner.is_some() || listener.group.is_some() {
+                self.warning(
+                    path,
+                    String::from("mode, owner and group only apply to unix sockets"),
+                );
+            }
+            return;
+        }
+        if cfg!(not(unix)) {
+            self.error(
+                format!("{}.address", path).as_str(),
+                String::from("unix sockets aren't supported on this system"),
+            );
+        }
+        if listener.tls {
+            self.error(
+                format!("{}.tls", path).as_str(),
+                String::from("TLS isn't supported on unix sockets"),
+            );
+        }
+        #[cfg(unix)]
+        {
+            if let Some(Err(e)) = listener.owner.as_deref().map(user_id) {
+                self.error(format!("{}.owner", path).as_str(), e.to_string());
+            }
+            if let Some(Err(e)) = listener.group.as_deref().map(group_id) {
+                self.error(format!("{}.group", path).as_str(), e.to_string());
+            }
+        }
+    }
+
+    /// check the `routes` and `aliases` strings of `resources` and the `[[routes]]` and
+    /// `[[aliases]]` tables of a section
+    fn routes(
+        &mut self,
+        section: &str,
+        resources: &Resource,
+        routes: Option<&Vec<RouteConfig>>,
+        aliases: Option<&Vec<RouteConfig>>,
+    ) {
+        for (key, entries) in [
+            ("routes", &resources.routes),
+            ("aliases", &resources.aliases),
+        ] {
+            for entry in entries.iter().flatten() {
+                if Rule::from_string(entry).is_none() {
+                    self.error(
+                        format!("{}.resources.{}", section, key).as_str(),
+                        format!("'{}' in {} doesn't have the form 'from:to'", entry, key),
+                    );
+                }
+            }
+        }
+        for (key, tables) in [("routes", routes), ("aliases", aliases)] {
+            for (i, table) in tables.into_iter().flatten().enumerate() {
+                let path = format!("{}.{}[{}]", section, key, i);
+                if table.from.is_empty() || table.to.is_empty() {
+                    self.error(
+                        path.as_str(),
+                        format!("'from' and 'to' of {} can't be empty", key),
+                    );
+                }
+                match table.status {
+                    Some(_) if key == "aliases" => self.error(
+                        format!("{}.status", path).as_str(),
+                        String::from("aliases don't have a status"),
+                    ),
+                    Some(n) if StatusCode::redirect(n).is_none() => self.error(
+                        format!("{}.status", path).as_str(),
+                        format!("{} is not a redirect status (301, 302, 303, 307 or 308)", n),
+                    ),
+                    _ => (),
+                }
+                for method in table.methods.iter().flatten() {
+                    if HTTPMethod::from_string(method.to_uppercase()).is_none() {
+                        self.error(
+                            format!("{}.methods", path).as_str(),
+                            format!("'{}' is not an HTTP method", method),
+                        );
+                    }
+                }
+                self.headers(format!("{}.headers", path).as_str(), table.headers.as_ref());
+            }
+        }
+    }
+
+    /// check the `[[cors]]` tables of a section
+    fn cors(&mut self, section: &str, cors: Option<&Vec<CorsConfig>>) {
+        for (i, n) in cors.into_iter().flatten().enumerate() {
+            if n.credentials == Some(true) && n.origins.iter().any(|n| n == "*") {
+                self.error(
+                    format!("{}.cors[{}].origins", section, i).as_str(),
+                    String::from("'*' can't be used with credentials, list the allowed origins"),
+                );
+            }
+        }
+    }
+
+    /// check the resource root of a section is readable
+    fn root(&mut self, section: &str, resources: &Resource) {
+        if let Err(e) = std::fs::read_dir(&resources.root) {
+            self.warning(
+                format!("{}.resources.root", section).as_str(),
+                format!("resource root '{}' is not readable: {}", resources.root, e),
+            );
+        }
+    }
+
+    /// check headers added to responses
+    fn headers(&mut self, path: &str, headers: Option<&BTreeMap<String, String>>) {
+        for (name, value) in headers.into_iter().flatten() {
+            let token = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_graphic() && !"()<>@,;:\\\"/[]?={}".contains(c));
+            if !token || value.contains(['\r', '\n']) {
+                self.error(path, format!("'{}: {}' is not a valid header", name, value));
+            }
+        }
+    }
+
+    /// check the `[[vhost]]` sections
+    fn vhosts(&mut self, vhosts: &[VhostConfig]) {
+        let mut default: Option<usize> = None;
+        let mut names: Vec<String> = Vec::new();
+        for (i, vhost) in vhosts.iter().enumerate() {
+            let section = format!("vhost[{}]", i);
+            let path = format!("{}.names", section);
+            if vhost.names.is_empty() {
+                self.error(
+                    path.as_str(),
+                    String::from("a virtual host needs at least one name"),
+                );
+            }
+            for name in &vhost.names {
+                match ServerName::from_string(name) {
+                    Ok(ServerName::Regex(_)) => (),
+                    Ok(n) => {
+                        let n = n.to_string();
+                        if names.contains(&n) {
+                            self.warning(
+                                path.as_str(),
+                                format!("'{}' is the name of an earlier virtual host", n),
+                            );
+                        }
+                        names.push(n);
+                    }
+                    Err(e) => self.error(path.as_str(), e.to_string()),
+                }
+            }
+            if vhost.default.unwrap_or(false) {
+                match default {
+                    Some(n) => self.error(
+                        format!("{}.default", section).as_str(),
+                        format!(
+                            "only one virtual host can be the default, vhost[{}] already is",
+                            n
+                        ),
+                    ),
+                    None => default = Some(i),
+                }
+            }
+            self.root(section.as_str(), &vhost.resources);
+            self.routes(
+                section.as_str(),
+                &vhost.resources,
+                vhost.routes.as_ref(),
+                vhost.aliases.as_ref(),
+            );
+            for (status, page) in vhost.error_pages.iter().flatten() {
+                let path = format!("{}.error_pages.{}", section, status);
+                match status.parse::<u16>() {
+                    Ok(n) if (400..600).contains(&n) => {
+                        let file =
+                            Path::new(&vhost.resources.root).join(page.trim_start_matches('/'));
+                        if !file.is_file() {
+                            self.warning(
+                                path.as_str(),
+                                format!("error page '{}' doesn't exist", file.display()),
+                            );
+                        }
+                    }
+                    _ => self.error(
+                        path.as_str(),
+                        format!("'{}' is not an error status (400 to 599)", status),
+                    ),
+                }
+            }
+            self.headers(
+                format!("{}.headers", section).as_str(),
+                vhost.headers.as_ref(),
+            );
+        }
+    }
+
+    /// check the certificate and TLS settings of the `ssl` section
+    fn tls(&mut self, ssl: &SslConfig) {
+        match (&ssl.private_key, &ssl.certificate_chain) {
+            (Some(key), Some(chain)) => {
+                self.readable("ssl.private_key", "private key", key);
+                self.readable("ssl.certificate_chain", "certificate chain", chain);
+            }
+            (Some(_), None) | (None, Some(_)) => self.error(
+                "ssl",
+                String::from("private_key and certificate_chain have to be set together"),
+            ),
+            (None, None) => (),
+        }
+        for (i, cert) in ssl.certificates.iter().flatten().enumerate() {
+            let path = format!("ssl.certificates[{}]", i);
+            self.readable(
+                format!("{}.private_key", path).as_str(),
+                "private key",
+                &cert.private_key,
+            );
+            self.readable(
+                format!("{}.certificate_chain", path).as_str(),
+                "certificate chain",
+                &cert.certificate_chain,
+            );
+        }
+        if let Some(n) = &ssl.client_auth {
+            self.readable("ssl.client_auth.ca_file", "CA file", &n.ca_file);
+        }
+        if let Some(n) = &ssl.ocsp_response {
+            self.readable("ssl.ocsp_response", "OCSP response", n);
+        }
+        if let Some(n) = ssl.acme.as_ref().and_then(|n| n.ca_file.as_ref()) {
+            self.readable("ssl.acme.ca_file", "CA file", n);
+        }
+        if let Err(e) = TlsOptions::from_config(Some(ssl)) {
+            self.error("ssl", e.to_string());
+        }
+    }
+
+    /// check the address of a separate listener
+    fn listen(&mut self, path: &str, listen: &Option<String>) -> Option<SocketAddr> {
+        let listen = listen.as_ref()?;
+        match listen.parse::<SocketAddr>() {
+            Ok(n) => Some(n),
+            Err(_) => {
+                self.error(
+                    path,
+                    format!("'{}' is not an address like '127.0.0.1:9100'", listen),
+                );
+                None
+            }
+        }
+    }
+
+    fn config(&mut self, config: &Config) {
+        // (path of the setting, description, listener)
+        let mut addresses: Vec<(String, &str, Listener)> = Vec::new();
+        if let Some(n) = &config.http {
+            let listen = n.listen.as_ref();
+            for (path, listener) in self.server(
+                "http",
+                n.ip.as_ref(),
+                n.port,
+                listen,
+                false,
+                n.threads,
+                &n.resources,
+            ) {
+                if listener.tls && config.ssl.is_none() {
+                    self.error(
+                        format!("{}.tls", path).as_str(),
+                        String::from(
+                            "TLS listeners of the http section need the certificates of the ssl section",
+                        ),
+                    );
+                }
+                addresses.push((path, "the http server", listener));
+            }
+            self.routes("http", &n.resources, n.routes.as_ref(), n.aliases.as_ref());
+            self.cors("http", n.cors.as_ref());
+        }
+        if let Some(n) = &config.ssl {
+            let listen = n.listen.as_ref();
+            for (path, listener) in self.server(
+                "ssl",
+                n.ip.as_ref(),
+                n.port,
+                listen,
+                true,
+                n.threads,
+                &n.resources,
+            ) {
+                addresses.push((path, "the ssl server", listener));
+            }
+            self.routes("ssl", &n.resources, n.routes.as_ref(), n.aliases.as_ref());
+            self.cors("ssl", n.cors.as_ref());
+            self.tls(n);
+        }
+        // all servers share one logger writing to the first configured log file
+        if let (Some(http), Some(ssl)) = (&config.http, &config.ssl) {
+            if let (Some(a), Some(b)) = (&http.logfile, &ssl.logfile) {
+                if a != b {
+                    self.warning(
+                        "ssl.logfile",
+                        format!("'{}' is ignored, all servers log to '{}' of [http]", b, a),
+                    );
+                }
+            }
+        }
+        if let Some(n) = &config.vhost {
+            self.vhosts(n);
+        }
+        let metrics = config.metrics.as_ref().map(|n| n.listen.clone());
+        if let Some(addr) = metrics.and_then(|n| self.listen("metrics.listen", &n)) {
+            let listener = Listener::new(addr, false);
+            addresses.push((
+                String::from("metrics.listen"),
+                "the metrics listener",
+                listener,
+            ));
+        }
+        let health = config.health.as_ref().map(|n| n.listen.clone());
+        if let Some(addr) = health.and_then(|n| self.listen("health.listen", &n)) {
+            let listener = Listener::new(addr, false);
+            addresses.push((
+                String::from("health.listen"),
+                "the health check listener",
+                listener,
+            ));
+        }
+        for (i, (path, name, listener)) in addresses.iter().enumerate() {
+            for (_, other, other_listener) in &addresses[..i] {
+                // metrics and health checks share a listener with the same address
+                let shared = listener.addr == other_listener.addr
+                    && path.ends_with(".listen")
+                    && other.contains("listener");
+                if listener.conflicts(other_listener) && !shared {
+                    self.error(
+                        path,
+                        format!(
+                            "{} on {} conflicts with {} on {}",
+                            name, listener.addr, other, other_listener.addr
+                        ),
+                    );
+                }
+            }
+        }
+        if let Some(log) = &config.log {
+            if let Some(Err(e)) = log.level.as_ref().map(|n| Filter::from_string(n)) {
+                self.error("log.level", e.to_string());
+            }
+            #[cfg(unix)]
+            if let Some(n) = log.syslog.as_ref().and_then(|n| n.facility.as_ref()) {
+                if crate::syslog::facility(n).is_none() {
+                    self.error(
+                        "log.syslog.facility",
+                        format!("'{}' is not a syslog facility", n),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// validate the config in `source`. Syntax errors and invalid values are reported as errors,
+/// unknown keys and unreadable resource roots as warnings
+pub fn validate(source: &str) -> Validation {
+    let mut checker = Checker {
+        positions: Positions::new(source),
+        diagnostics: Vec::new(),
+    };
+    let config: Config = match toml::from_str(source) {
+        Ok(n) => n,
+        Err(e) => {
+            // the position is part of the diagnostic instead of the message
+            let msg = e.to_string();
+            let msg = match msg.rfind(" at line ") {
+                Some(n) => msg[..n].to_string(),
+                None => msg,
+            };
+            checker.diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                position: e.line_col().map(|(line, column)| (line + 1, column + 1)),
+                msg,
+            });
+            return Validation {
+                config: None,
+                diagnostics: checker.diagnostics,
+            };
+        }
+    };
+    if let (Ok(raw), Ok(known)) = (
+        toml::from_str::<toml::Value>(source),
+        toml::Value::try_from(&config),
+    ) {
+        checker.unknown_keys(&raw, Some(&known), "");
+    }
+    checker.config(&config);
+    checker
+        .diagnostics
+        .sort_by_key(|n| n.position.unwrap_or((usize::MAX, 0)));
+    Validation {
+        config: Some(config),
+        diagnostics: checker.diagnostics,
+    }
+}
+
+/// read and validate the config file at `path`
+pub fn validate_file(path: &str) -> Result<Validation, std::io::Error> {
+    Ok(validate(read_to_string(path)?.as_str()))
+}
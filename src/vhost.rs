@@ -0,0 +1,313 @@
+use crate::config::VhostConfig;
+use crate::cors::Cors;
+use crate::error::ServerError;
+use crate::http::body::Body;
+use crate::http::header::HTTPResponseHeaders;
+use crate::http::request::{HTTPMethod, HTTPRequest};
+use crate::http::response::HTTPResponse;
+use crate::methods::MethodPolicy;
+use crate::metrics::Metrics;
+use crate::resource::ResourceLoader;
+use crate::route::Router;
+use crate::server::handle_static;
+use regex::Regex;
+use wildmatch::WildMatch;
+
+/// a name a virtual host answers requests for
+#[derive(Clone, Debug)]
+pub enum ServerName {
+    /// `example.com`
+    Exact(String),
+    /// `*.example.com`, using `*` and `?` like routes
+    Wildcard(String),
+    /// a regular expression, written with a leading `~` in the config
+    Regex(Regex),
+}
+
+impl ServerName {
+    /// read a server name. Names starting with `~` are regular expressions, names containing `*`
+    /// or `?` wildcards and all others exact names. Exact and wildcard names ignore case
+    pub fn from_string(name: &str) -> Result<ServerName, ServerError> {
+        if let Some(n) = name.strip_prefix('~') {
+            return match Regex::new(n) {
+                Ok(n) => Ok(ServerName::Regex(n)),
+                Err(e) => Err(ServerError::new(
+                    format!("invalid server name '{}': {}", name, e).as_str(),
+                )),
+            };
+        }
+        let name = name.to_lowercase();
+        if name.is_empty() {
+            Err(ServerError::new("server names can't be empty"))
+        } else if name.contains(['*', '?']) {
+            Ok(ServerName::Wildcard(name))
+        } else {
+            Ok(ServerName::Exact(name))
+        }
+    }
+
+    /// check whether `host` (see [`host_name`]) matches the name
+    pub fn matches(&self, host: &str) -> bool {
+        match self {
+            ServerName::Exact(n) => n == host,
+            ServerName::Wildcard(n) => WildMatch::new(n.as_str()).matches(host),
+            ServerName::Regex(n) => n.is_match(host),
+        }
+    }
+}
+
+impl std::fmt::Display for ServerName {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            ServerName::Exact(n) | ServerName::Wildcard(n) => write!(f, "{}", n),
+            ServerName::Regex(n) => write!(f, "~{}", n),
+        }
+    }
+}
+
+/// get the host name of the value of a `Host` header: lowercase, without the port and a
+/// trailing dot. IPv6 addresses keep their brackets
+pub fn host_name(host: &str) -> String {
+    let host = match host.rfind(':') {
+        Some(n) if !host[n..].contains(']') => &host[..n],
+        _ => host,
+    };
+    host.trim_end_matches('.').to_lowercase()
+}
+
+/// a site with its own resources, routes, error pages and headers
+#[derive(Clone)]
+pub struct Vhost {
+    names: Vec<ServerName>,
+    rl: ResourceLoader,
+    router: Router,
+    notfound_page: Option<String>,
+    error_pages: Vec<(u16, String)>,
+    headers: Vec<(String, String)>,
+}
+
+impl Vhost {
+    /// create a virtual host for `names` serving the resources of `rl` using `router`
+    pub fn new(names: Vec<ServerName>, rl: ResourceLoader, router: Router) -> Vhost {
+        Vhost {
+            names,
+            rl,
+            router,
+            notfound_page: None,
+            error_pages: Vec::new(),
+            headers: Vec::new(),
+        }
+    }
+
+    /// create a virtual host from a `[[vhost]]` section
+    pub fn from_config(conf: &VhostConfig) -> Result<Vhost, ServerError> {
+        let mut names = Vec::new();
+        for n in &conf.names {
+            names.push(ServerName::from_string(n)?);
+        }
+        let rl = ResourceLoader::new(
+            conf.resources.cache_capacity.unwrap_or(10),
+            conf.resources.root.clone(),
+            conf.resources.resource_cache.unwrap_or(true),
+        );
+        let router =
+            Router::from_config(&conf.resources, conf.routes.as_ref(), conf.aliases.as_ref());
+        let mut vhost = Vhost::new(names, rl, router);
+        vhost.set_notfound_page(conf.resources.notfound_page.clone());
+        for (status, page) in conf.error_pages.iter().flatten() {
+            let status = match status.parse::<u16>() {
+                Ok(n) if (400..600).contains(&n) => n,
+                _ => {
+                    return Err(ServerError::new(
+                        format!("'{}' is not an error status", status).as_str(),
+                    ))
+                }
+            };
+            vhost.set_error_page(status, page.clone());
+        }
+        for (name, value) in conf.headers.iter().flatten() {
+            vhost.add_header(name.clone(), value.clone());
+        }
+        Ok(vhost)
+    }
+
+    /// get the first name of the virtual host, e.g. to name it in logs
+    pub fn name(&self) -> String {
+        match self.names.first() {
+            Some(n) => n.to_string(),
+            None => String::from("default"),
+        }
+    }
+
+    /// get the names of the virtual host
+    pub fn names(&self) -> &[ServerName] {
+        &self.names
+    }
+
+    /// get the directory resources are loaded from
+    pub fn get_root(&self) -> String {
+        self.rl.get_root()
+    }
+
+    /// get the router of the virtual host
+    pub fn router(&self) -> &Router {
+        &self.router
+    }
+
+    /// send the page at `page` (relative to the resource root) if a resource can't be found
+    pub fn set_notfound_page(&mut self, page: Option<String>) {
+        self.notfound_page = page;
+    }
+
+    /// send the page at `page` (relative to the resource root) instead of the default body of
+    /// responses with the status `status`
+    pub fn set_error_page(&mut self, status: u16, page: String) {
+        self.error_pages.retain(|(n, _)| *n != status);
+        self.error_pages.push((status, page));
+    }
+
+    /// add the header `name` with `value` to all responses
+    pub fn add_header(&mut self, name: String, value: String) {
+        self.headers.push((name, value));
+    }
+
+    /// record resource cache hits and misses in `metrics`
+    pub fn set_metrics(&mut self, metrics: Option<Metrics>) {
+        self.rl.set_metrics(metrics);
+    }
+
+    /// answer `req` with the resources and routes of the virtual host. Methods not allowed by
+    /// `methods` are rejected and `cors` is applied like on servers without virtual hosts
+    pub fn respond(
+        &mut self,
+        req: &HTTPRequest,
+        methods: &MethodPolicy,
+        cors: &Cors,
+    ) -> HTTPResponse {
+        let resp = handle_static(
+            req,
+            &self.router,
+            &mut self.rl,
+            self.notfound_page.as_deref(),
+            methods,
+            cors,
+        );
+        self.finish(&req.get_method(), resp)
+    }
+
+    /// replace the body of `resp` with the error page for its status if there is one and add
+    /// the headers of the virtual host
+    pub fn finish(&mut self, method: &HTTPMethod, resp: HTTPResponse) -> HTTPResponse {
+        let status = resp.get_status();
+        let page = self
+            .error_pages
+            .iter()
+            .find(|(n, _)| *n == status.code())
+            .and_then(|(_, page)| self.rl.load(page.trim_start_matches('/').to_string()));
+        let mut resp = match page {
+            Some(page) => {
+                let mut headers: Vec<HTTPResponseHeaders> = resp
+                    .get_header()
+                    .into_iter()
+                    .filter(|n| {
+                        !matches!(
+                            n,
+                            HTTPResponseHeaders::ContentType(_)
+                                | HTTPResponseHeaders::ContentLength(_)
+                        )
+                    })
+                    .collect();
+                let content = page.get_content();
+                headers.push(HTTPResponseHeaders::ContentType(page.get_mime()));
+                headers.push(HTTPResponseHeaders::ContentLength(content.len()));
+                let body = match method {
+                    HTTPMethod::Head => Body::new(String::new()),
+                    _ => Body::from_bytes(content),
+                };
+                HTTPResponse::new(status, headers, body)
+            }
+            None => resp,
+        };
+        for (name, value) in &self.headers {
+            resp.add_header(HTTPResponseHeaders::Other(name.clone(), value.clone()));
+        }
+        resp
+    }
+}
+
+/// virtual hosts selected by the `Host` header of requests
+#[derive(Clone, Default)]
+pub struct VirtualHosts {
+    hosts: Vec<Vhost>,
+    default: Option<usize>,
+}
+
+impl VirtualHosts {
+    /// create an empty set of virtual hosts. Servers answer all requests themselves then
+    pub fn new() -> VirtualHosts {
+        VirtualHosts::default()
+    }
+
+    /// create the virtual hosts of the `[[vhost]]` sections. Only one of them can be the default
+    pub fn from_config(conf: Option<&Vec<VhostConfig>>) -> Result<VirtualHosts, ServerError> {
+        let mut vhosts = VirtualHosts::new();
+        for n in conf.into_iter().flatten() {
+            let vhost = Vhost::from_config(n)?;
+            if n.default.unwrap_or(false) {
+                if vhosts.default.is_some() {
+                    return Err(ServerError::new("only one virtual host can be the default"));
+                }
+                vhosts.set_default(vhost);
+            } else {
+                vhosts.add(vhost);
+            }
+        }
+        Ok(vhosts)
+    }
+
+    /// add a virtual host
+    pub fn add(&mut self, vhost: Vhost) {
+        self.hosts.push(vhost);
+    }
+
+    /// add a virtual host that also answers requests for hosts no virtual host is named after
+    pub fn set_default(&mut self, vhost: Vhost) {
+        self.hosts.push(vhost);
+        self.default = Some(self.hosts.len() - 1);
+    }
+
+    /// get all virtual hosts
+    pub fn hosts(&self) -> &[Vhost] {
+        &self.hosts
+    }
+
+    /// record resource cache hits and misses of all virtual hosts in `metrics`
+    pub fn set_metrics(&mut self, metrics: Option<Metrics>) {
+        for n in self.hosts.iter_mut() {
+            n.set_metrics(metrics.clone());
+        }
+    }
+
+    /// select the virtual host for a request with the `Host` header `host`. Exact names are
+    /// preferred over wildcards and wildcards over regular expressions. Without a match the
+    /// default virtual host is used, if there is one
+    pub fn select(&self, host: Option<&str>) -> Option<&Vhost> {
+        if let Some(host) = host.map(host_name) {
+            let find = |kind: fn(&ServerName) -> bool| {
+                self.hosts.iter().find(|vhost| {
+                    vhost
+                        .names
+                        .iter()
+                        .any(|n| kind(n) && n.matches(host.as_str()))
+                })
+            };
+            let found = find(|n| matches!(n, ServerName::Exact(_)))
+                .or_else(|| find(|n| matches!(n, ServerName::Wildcard(_))))
+                .or_else(|| find(|n| matches!(n, ServerName::Regex(_))));
+            if found.is_some() {
+                return found;
+            }
+        }
+        self.default.map(|n| &self.hosts[n])
+    }
+}